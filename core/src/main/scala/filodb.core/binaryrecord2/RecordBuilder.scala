@@ -1,5 +1,7 @@
 package filodb.core.binaryrecord2
 
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
 import com.typesafe.scalalogging.StrictLogging
 import org.agrona.DirectBuffer
 import scalaxy.loops._
@@ -129,7 +131,7 @@ final class RecordBuilder(memFactory: MemFactory,
   final def addBlob(base: Any, offset: Long, numBytes: Int): Unit = {
     require(numBytes < 65536, s"bytes too large ($numBytes bytes) for addBlob")
     checkFieldAndMemory(numBytes + 2)
-    UnsafeUtils.setShort(curBase, curRecEndOffset, numBytes.toShort) // length of blob
+    UnsafeUtils.setShortLE(curBase, curRecEndOffset, numBytes.toShort) // length of blob
     UnsafeUtils.unsafe.copyMemory(base, offset, curBase, curRecEndOffset + 2, numBytes)
     updateFieldPointerAndLens(numBytes + 2)
     if (fieldNo >= firstPartField) recHash = combineHash(recHash, BinaryRegion.hash32(base, offset, numBytes))
@@ -138,10 +140,10 @@ final class RecordBuilder(memFactory: MemFactory,
 
   final def addBlob(strPtr: ZCUTF8): Unit = addBlob(strPtr.base, strPtr.offset, strPtr.numBytes)
 
-  // Adds a blob from another buffer which already has the length bytes as the first two bytes
+  // Adds a blob from another buffer which already has the length bytes as the first two bytes, little endian
   // For example: buffers created by BinaryHistograms.  OR, a UTF8String medium.
   final def addBlob(buf: DirectBuffer): Unit = {
-    val numBytes = buf.getShort(0).toInt
+    val numBytes = buf.getShort(0, LITTLE_ENDIAN).toInt
     require(numBytes < buf.capacity)
     addBlob(buf.byteArray, buf.addressOffset + 2, numBytes)
   }
@@ -293,7 +295,7 @@ final class RecordBuilder(memFactory: MemFactory,
     val keyValueSize = if (predefKeyNum >= 0) { valueLen + 4 } else { keyLen + valueLen + 4 }
     requireBytes(keyValueSize)
     if (predefKeyNum >= 0) {
-      setShort(curBase, curRecEndOffset, (0xF000 | predefKeyNum).toShort)
+      setShortLE(curBase, curRecEndOffset, (0xF000 | predefKeyNum).toShort)
       curRecEndOffset += 2
     } else {
       UTF8StringMedium.copyByteArrayTo(keyBytes, keyOffset, keyLen, curBase, curRecEndOffset)
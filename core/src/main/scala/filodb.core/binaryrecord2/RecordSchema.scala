@@ -254,14 +254,14 @@ final class RecordSchema(val columns: Seq[ColumnInfo],
     var itemIndex = 0
     while (curOffset < endOffset) {
       // Read key length.  Is it a predefined key?
-      val keyLen = UnsafeUtils.getShort(base, curOffset) & 0x0FFFF
+      val keyLen = UnsafeUtils.getShortLE(base, curOffset) & 0x0FFFF
       val keyIndex = keyLen ^ 0x0F000
       if (keyIndex < 0x1000) {   // predefined key; no key bytes
         consumer.consume(predefKeyBytes, predefKeyOffsets(keyIndex), base, curOffset + 2, itemIndex)
-        curOffset += 4 + (UnsafeUtils.getShort(base, curOffset + 2) & 0x0FFFF)
+        curOffset += 4 + (UnsafeUtils.getShortLE(base, curOffset + 2) & 0x0FFFF)
       } else {
         consumer.consume(base, curOffset, base, curOffset + 2 + keyLen, itemIndex)
-        curOffset += 4 + keyLen + (UnsafeUtils.getShort(base, curOffset + 2 + keyLen) & 0x0FFFF)
+        curOffset += 4 + keyLen + (UnsafeUtils.getShortLE(base, curOffset + 2 + keyLen) & 0x0FFFF)
       }
       itemIndex += 1
     }
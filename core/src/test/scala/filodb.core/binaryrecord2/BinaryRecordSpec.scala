@@ -1,6 +1,7 @@
 package filodb.core.binaryrecord2
 
 import debox.Buffer
+import org.agrona.concurrent.UnsafeBuffer
 import org.scalatest.{BeforeAndAfter, BeforeAndAfterAll, FunSpec, Matchers}
 
 import filodb.core.{MachineMetricsData, Types}
@@ -323,6 +324,32 @@ class BinaryRecordSpec extends FunSpec with Matchers with BeforeAndAfter with Be
       stringSchema.partitionHash(addrStringAdd) shouldEqual stringSchema.partitionHash(addrAddSlowly)
     }
 
+    it("should write blob lengths little endian, as addBlob(buf) and UTF8StringMedium read them") {
+      val builder = new RecordBuilder(MemFactory.onHeapFactory, longStrSchema)
+      builder.startNewRecord()
+      builder.addLong(1L)
+      builder.addString("abc")
+      builder.endRecord()
+
+      // a UTF8StringMedium built from little endian bytes, added with the length bytes it already has
+      val (strBase, strOffset) = UTF8StringMedium("abc")
+      val strBuf = new UnsafeBuffer(Array.empty[Byte])
+      UnsafeUtils.wrapDirectBuf(strBase, strOffset, 5, strBuf)
+      builder.startNewRecord()
+      builder.addLong(2L)
+      builder.addBlob(strBuf)
+      builder.endRecord()
+
+      builder.allContainers.head.consumeRecords(consumer)
+      records should have length (2)
+      records.foreach { case (base, offset) =>
+        val blobOffset = longStrSchema.utf8StringOffset(base, offset, 1)
+        (0 until 5).map(i => UnsafeUtils.getByte(base, blobOffset + i)) shouldEqual
+          Seq(3, 0, 'a', 'b', 'c').map(_.toByte)
+        longStrSchema.asJavaString(base, offset, 1) shouldEqual "abc"
+      }
+    }
+
     it("should add and get map fields with no predefined keys") {
       val builder = new RecordBuilder(MemFactory.onHeapFactory, schema2)
       val data = withMap(linearMultiSeries(), extraTags=extraTags).take(3)
@@ -0,0 +1,49 @@
+package filodb.http
+
+import scala.util.Try
+
+import io.circe.{Decoder, DecodingFailure, Encoder, HCursor, Json}
+
+import filodb.memory.format.vectors.{CustomBuckets, HistogramBuckets, LongHistogram}
+
+/**
+ * JSON codecs for decoded histograms, for debug and tooling endpoints.  A LongHistogram is written as
+ * {{{
+ *   {"buckets": [0.5, 1.0, 2.0, "Infinity"], "counts": [1, 4, 9, 10]}
+ * }}}
+ * Bucket tops are JSON numbers, except for infinite or NaN ones which JSON numbers cannot hold, which are strings.
+ * Bucket schemes are read back as CustomBuckets with the same tops.
+ */
+object HistogramCirceSupport {
+  implicit val encodeBuckets: Encoder[HistogramBuckets] = new Encoder[HistogramBuckets] {
+    final def apply(b: HistogramBuckets): Json = Json.fromValues(b.allBucketTops.map(Json.fromDoubleOrString))
+  }
+
+  implicit val decodeBuckets: Decoder[HistogramBuckets] = new Decoder[HistogramBuckets] {
+    final def apply(c: HCursor): Decoder.Result[HistogramBuckets] =
+      c.as[List[Double]](Decoder.decodeList(decodeBucketTop)).right.map { tops => CustomBuckets(tops.toArray) }
+  }
+
+  implicit val encodeLongHistogram: Encoder[LongHistogram] = new Encoder[LongHistogram] {
+    final def apply(h: LongHistogram): Json =
+      Json.obj("buckets" -> encodeBuckets(h.buckets), "counts" -> Json.fromValues(h.values.map(Json.fromLong)))
+  }
+
+  implicit val decodeLongHistogram: Decoder[LongHistogram] = new Decoder[LongHistogram] {
+    final def apply(c: HCursor): Decoder.Result[LongHistogram] =
+      for { buckets <- c.downField("buckets").as[HistogramBuckets].right
+            counts  <- c.downField("counts").as[List[Long]].right
+            hist    <- (if (counts.size == buckets.numBuckets) Right(LongHistogram(buckets, counts.toArray))
+                        else Left(DecodingFailure(s"${counts.size} counts for ${buckets.numBuckets} buckets",
+                                                  c.history))).right
+      } yield hist
+  }
+
+  // A bucket top is a JSON number, or a string such as "Infinity" for values JSON numbers cannot hold
+  private val decodeBucketTop: Decoder[Double] = new Decoder[Double] {
+    final def apply(c: HCursor): Decoder.Result[Double] =
+      c.as[Double].left.flatMap { err =>
+        c.as[String].right.flatMap { s => Try(s.toDouble).toOption.toRight(err) }
+      }
+  }
+}
@@ -0,0 +1,39 @@
+package filodb.http
+
+import io.circe.parser.decode
+import io.circe.syntax._
+import org.scalatest.{FunSpec, Matchers}
+
+import filodb.memory.format.vectors.{CustomBuckets, GeometricBuckets, HistogramBuckets, LongHistogram}
+
+class HistogramCirceSupportSpec extends FunSpec with Matchers {
+  import HistogramCirceSupport._
+
+  it("should write bucket tops as numbers and counts as an array") {
+    val hist = LongHistogram(GeometricBuckets(2.0, 2.0, 4), Array(1L, 4L, 9L, 10L))
+    hist.asJson.noSpaces shouldEqual """{"buckets":[2.0,4.0,8.0,16.0],"counts":[1,4,9,10]}"""
+  }
+
+  it("should round trip a decoded histogram through JSON") {
+    val hist = LongHistogram(CustomBuckets(Array(0.25, 1.0, 10.0, Double.PositiveInfinity)),
+                             Array(0L, 3L, 3L, Long.MaxValue))
+    val json = hist.asJson.noSpaces
+    json should include (""""Infinity"""")
+    val decoded = decode[LongHistogram](json).right.get
+    decoded.buckets.allBucketTops shouldEqual hist.buckets.allBucketTops
+    decoded.values shouldEqual hist.values
+
+    // bucket schemes other than CustomBuckets come back as CustomBuckets with the same tops
+    val geometric = LongHistogram(HistogramBuckets.binaryBuckets64, Array.tabulate(64)(_.toLong))
+    val decoded2 = decode[LongHistogram](geometric.asJson.noSpaces).right.get
+    decoded2.buckets shouldBe a[CustomBuckets]
+    decoded2.buckets.allBucketTops shouldEqual geometric.buckets.allBucketTops
+    decoded2.values shouldEqual geometric.values
+  }
+
+  it("should refuse histograms whose counts do not match the buckets") {
+    decode[LongHistogram]("""{"buckets":[1.0,2.0],"counts":[5]}""").isLeft shouldEqual true
+    decode[LongHistogram]("""{"buckets":[1.0,"abc"],"counts":[5,6]}""").isLeft shouldEqual true
+    decode[LongHistogram]("""{"counts":[5]}""").isLeft shouldEqual true
+  }
+}
@@ -0,0 +1,46 @@
+package filodb.jmh
+
+/**
+ * Generators of Long vectors shaped like real series, shared by the NibblePack benchmarks so that they compare on
+ * the same data.  Each is seeded, so every run and every benchmark sees the same values.
+ */
+object BenchmarkDatasets {
+  val Names = Seq("counter", "gauge", "sparse")
+
+  /**
+   * Monotonic counter: steady increments with jitter, plus the occasional burst.
+   */
+  def counter(numValues: Int, seed: Long = 13): Array[Long] = {
+    val rand = new scala.util.Random(seed)
+    val values = Array.fill(numValues)(1000L + rand.nextInt(200) + (if (rand.nextInt(100) == 0) 100000L else 0L))
+    for { i <- 1 until numValues } { values(i) += values(i - 1) }
+    values
+  }
+
+  /**
+   * Jittery gauge, eg millis of CPU or bytes of memory: a level which wanders slowly, plus noise on every sample,
+   * so that values go down as often as up.
+   */
+  def gauge(numValues: Int, seed: Long = 17): Array[Long] = {
+    val rand = new scala.util.Random(seed)
+    var level = 500000L
+    Array.fill(numValues) {
+      level = Math.max(0L, level + rand.nextInt(2001) - 1000)
+      level + rand.nextInt(100)
+    }
+  }
+
+  /**
+   * Sparse vector, eg error counts per interval: 95% zeroes, with small values in between.
+   */
+  def sparse(numValues: Int, seed: Long = 19): Array[Long] = {
+    val rand = new scala.util.Random(seed)
+    Array.fill(numValues)(if (rand.nextInt(20) == 0) 1L + rand.nextInt(50) else 0L)
+  }
+
+  def apply(name: String, numValues: Int): Array[Long] = name match {
+    case "counter" => counter(numValues)
+    case "gauge"   => gauge(numValues)
+    case "sparse"  => sparse(numValues)
+  }
+}
@@ -0,0 +1,50 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{CompressedVec, NibblePack}
+
+/**
+ * Measures the latency of fetching a single element from a CompressedVec through its skip table, compared to
+ * unpacking the whole NibblePacked vector and indexing into the output.
+ */
+@State(Scope.Thread)
+class CompressedVecBenchmark {
+  val numValues = 10000
+  val inputs = Array.tabulate(numValues) { i => 1000000L + i * 1000 + util.Random.nextInt(100) }
+  val lookupIndex = numValues - 100
+
+  val vecBuf = new ExpandableArrayBuffer()
+  val vecBytes = CompressedVec.encode(inputs, vecBuf, 0)
+  val vec = CompressedVec(new UnsafeBuffer(vecBuf, 0, vecBytes)).right.get
+
+  val vecBufK1 = new ExpandableArrayBuffer()
+  val vecBytesK1 = CompressedVec.encode(inputs, vecBufK1, 0, 1)
+  val vecK1 = CompressedVec(new UnsafeBuffer(vecBufK1, 0, vecBytesK1)).right.get
+
+  val deltaBuf = new ExpandableArrayBuffer()
+  val deltaBytes = NibblePack.packDelta(inputs, deltaBuf, 0)
+  val deltaSlice = new UnsafeBuffer(deltaBuf, 0, deltaBytes)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.NANOSECONDS)
+  def getWithSkipTable(): Long = vec.get(lookupIndex).get
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.NANOSECONDS)
+  def getWithSkipEveryBlock(): Long = vecK1.get(lookupIndex).get
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.NANOSECONDS)
+  def getWithFullUnpack(): Long = {
+    deltaSlice.wrap(deltaBuf, 0, deltaBytes)
+    NibblePack.unpackDelta(deltaSlice, numValues).right.get(lookupIndex)
+  }
+}
@@ -0,0 +1,48 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.CompressedVec
+
+/**
+ * Sweeps the skip table stride (blocksPerSkip) of a CompressedVec, to weigh get() latency against footer size.
+ * The footer takes 4 bytes for every blocksPerSkip blocks, ie 4 / (8 * blocksPerSkip) bytes per value, while
+ * get() skips over up to blocksPerSkip - 1 block headers.  Lookups are spread over the whole vector so that
+ * the average number of blocks skipped is measured, not just one position.
+ */
+@State(Scope.Thread)
+class CompressedVecStrideBenchmark {
+  val numValues = 10000
+  val inputs = Array.tabulate(numValues) { i => 1000000L + i * 1000 + util.Random.nextInt(100) }
+  val lookups = Array.fill(1024)(util.Random.nextInt(numValues))
+
+  @Param(Array("1", "4", "16", "64"))
+  var blocksPerSkip: Int = 0
+
+  var vec: CompressedVec = _
+
+  @Setup
+  def setup(): Unit = {
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = CompressedVec.encode(inputs, buf, 0, blocksPerSkip)
+    vec = CompressedVec(new UnsafeBuffer(buf, 0, numBytes)).right.get
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.NANOSECONDS)
+  @OperationsPerInvocation(1024)
+  def getSpread(): Long = {
+    var sum = 0L
+    var i = 0
+    while (i < lookups.size) {
+      sum += vec.get(lookups(i)).get
+      i += 1
+    }
+    sum
+  }
+}
@@ -0,0 +1,29 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{DecodeCache, NibblePack}
+
+/**
+ * Measures unpacking the same chunk again and again, as some query plans do, with and without a DecodeCache.
+ */
+@State(Scope.Thread)
+class DecodeCacheBenchmark {
+  val rand = new scala.util.Random(7)
+  val values = Array.fill(1000)(rand.nextInt(5000).toLong).scanLeft(0L)(_ + _).tail
+  val bytes = NibblePack.packDeltaToBytes(values)
+  val cache = new DecodeCache(16)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackEveryTime(): Int = NibblePack.unpackDelta(new UnsafeBuffer(bytes), values.size).right.get.size
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackCached(): Int = cache.unpackDelta(new UnsafeBuffer(bytes), values.size).right.get.size
+}
@@ -0,0 +1,57 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{DoubleXORPack, NibblePack}
+
+/**
+ * Measures the Gorilla-style XOR bit stream of DoubleXORPack, which reuses the previous leading and trailing zero
+ * window whenever an XOR fits in it, against NibblePack.packDoubles, which NibblePacks XORs 8 at a time, on a
+ * slowly varying gauge.  The packed size of each is reported alongside its time, see PackedSize.
+ */
+@State(Scope.Thread)
+class DoubleXORBenchmark {
+  val numValues = 10000
+  val rand = new scala.util.Random(23)
+  // A temperature-like gauge: a random walk in steps of 0.1, which often stays the same
+  val gauge = Array.fill(numValues)(rand.nextInt(5) - 2).scanLeft(200)(_ + _).tail.map(_ / 10.0)
+
+  val xorBuf = new ExpandableArrayBuffer()
+  val xorBytes = DoubleXORPack.pack(gauge, xorBuf, 0)
+  val nibbleBuf = new ExpandableArrayBuffer()
+  val nibbleBytes = NibblePack.packDoubles(gauge, nibbleBuf, 0)
+
+  val out = new Array[Double](numValues)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackXORStream(size: PackedSize): Double = {
+    size.packedBytes = xorBytes
+    DoubleXORPack.unpack(new UnsafeBuffer(xorBuf, 0, xorBytes), out)
+    out(numValues - 1)
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackNibbleXOR(size: PackedSize): Double = {
+    size.packedBytes = nibbleBytes
+    NibblePack.unpackDoubleXOR(new UnsafeBuffer(nibbleBuf, 0, nibbleBytes), out)
+    out(numValues - 1)
+  }
+}
+
+/**
+ * The packed size in bytes of the data a benchmark unpacks, reported by JMH as a secondary result next to each
+ * benchmark's time.
+ */
+@State(Scope.Thread)
+@AuxCounters(AuxCounters.Type.EVENTS)
+class PackedSize {
+  var packedBytes: Long = 0L
+}
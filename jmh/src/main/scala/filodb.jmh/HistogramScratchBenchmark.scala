@@ -0,0 +1,50 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.vectors.{BinaryHistogram, GeometricBuckets}
+
+/**
+ * Measures writing non increasing histograms with a fresh scratch array per histogram, with the default thread
+ * local one, and with one array reused by the caller.  Run with -prof gc to compare allocations per histogram.
+ */
+@State(Scope.Thread)
+class HistogramScratchBenchmark {
+  val buckets = GeometricBuckets(2.0, 2.0, 64)
+  val rand = new scala.util.Random(17)
+  val histograms = Array.fill(100)(Array.fill(64)(rand.nextInt(1000).toLong))
+  val buf = new ExpandableArrayBuffer(4096)
+  val scratch = new Array[Long](8)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def writeFreshScratch(): Int = {
+    var total = 0
+    histograms.foreach { values =>
+      total += BinaryHistogram.writeNonIncreasing(buckets, values, buf, new Array[Long](8))
+    }
+    total
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def writeThreadLocalScratch(): Int = {
+    var total = 0
+    histograms.foreach { values => total += BinaryHistogram.writeNonIncreasing(buckets, values, buf) }
+    total
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def writeReusedScratch(): Int = {
+    var total = 0
+    histograms.foreach { values => total += BinaryHistogram.writeNonIncreasing(buckets, values, buf, scratch) }
+    total
+  }
+}
@@ -0,0 +1,113 @@
+package filodb.jmh
+
+import java.nio.{ByteBuffer, ByteOrder}
+import java.util.concurrent.TimeUnit
+
+import net.jpountz.lz4.LZ4Factory
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{CompressedVec, NibbleAuto}
+
+/**
+ * Puts the NibblePack numbers in context, by measuring the same work on the same data against two baselines:
+ * copying the raw Longs to and from little endian bytes, and LZ4 over those raw bytes.  For each dataset in
+ * BenchmarkDatasets it times encoding, decoding, and fetching randomly chosen values, where NibblePack fetches
+ * through a CompressedVec and LZ4 has to decompress everything first.
+ */
+@State(Scope.Thread)
+class NibbleBaselineBenchmark {
+  val numValues = 100000
+  val lz4 = LZ4Factory.fastestInstance()
+  val compressor = lz4.fastCompressor()
+  val decompressor = lz4.fastDecompressor()
+  val rawBytes = new Array[Byte](numValues * 8)
+  val raw = ByteBuffer.wrap(rawBytes).order(ByteOrder.LITTLE_ENDIAN)
+  val lz4Bytes = new Array[Byte](compressor.maxCompressedLength(rawBytes.size))
+  val out = new Array[Long](numValues)
+  val indices = {
+    val rand = new scala.util.Random(23)
+    Array.fill(1000)(rand.nextInt(numValues))
+  }
+
+  @Param(Array("counter", "gauge", "sparse"))
+  var dataset: String = ""
+
+  var values: Array[Long] = Array.empty
+  val nibbleBuf = new ExpandableArrayBuffer()
+  var nibbleBytes = 0
+  var vec: CompressedVec = _
+
+  @Setup
+  def setup(): Unit = {
+    values = BenchmarkDatasets(dataset, numValues)
+    nibbleBytes = NibbleAuto.packAuto(values, nibbleBuf, 0)
+    raw.asLongBuffer.put(values)
+    compressor.compress(rawBytes, 0, rawBytes.size, lz4Bytes, 0, lz4Bytes.size)
+    val vecBuf = new ExpandableArrayBuffer()
+    val vecBytes = CompressedVec.encode(values, vecBuf, 0)
+    vec = CompressedVec(new UnsafeBuffer(vecBuf, 0, vecBytes)).right.get
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def encodeNibble(): Int = NibbleAuto.packAuto(values, nibbleBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def encodeRaw(): Int = {
+    raw.asLongBuffer.put(values)
+    rawBytes.size
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def encodeLZ4(): Int = {
+    raw.asLongBuffer.put(values)
+    compressor.compress(rawBytes, 0, rawBytes.size, lz4Bytes, 0, lz4Bytes.size)
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def decodeNibble(): Int = NibbleAuto.unpackAuto(new UnsafeBuffer(nibbleBuf, 0, nibbleBytes)).right.get.size
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def decodeRaw(): Long = {
+    raw.asLongBuffer.get(out)
+    out(numValues - 1)
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def decodeLZ4(): Long = {
+    decompressor.decompress(lz4Bytes, 0, rawBytes, 0, rawBytes.size)
+    raw.asLongBuffer.get(out)
+    out(numValues - 1)
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def randomGetNibble(): Long = indices.map(i => vec.get(i).get).sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def randomGetRaw(): Long = indices.map(i => raw.getLong(i * 8)).sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def randomGetLZ4(): Long = {
+    decompressor.decompress(lz4Bytes, 0, rawBytes, 0, rawBytes.size)
+    indices.map(i => raw.getLong(i * 8)).sum
+  }
+}
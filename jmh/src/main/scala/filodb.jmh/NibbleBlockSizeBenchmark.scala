@@ -0,0 +1,41 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.NibbleBlockSize
+
+/**
+ * Compares block sizes of 8, 16 and 32 for NibbleBlockSize.packDelta, on BenchmarkDatasets.counter: steady
+ * increments with jitter, plus the occasional burst.  Larger blocks share one header across more values but widen
+ * more of them with each burst.
+ */
+@State(Scope.Thread)
+class NibbleBlockSizeBenchmark {
+  val numValues = 100000
+  val counter = BenchmarkDatasets.counter(numValues)
+
+  @Param(Array("8", "16", "32"))
+  var blockSize: Int = 0
+
+  val buf = new ExpandableArrayBuffer()
+  var numBytes = 0
+
+  @Setup
+  def setup(): Unit = {
+    numBytes = NibbleBlockSize.packDelta(counter, buf, 0, blockSize)
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def packDelta(): Int = NibbleBlockSize.packDelta(counter, buf, 0, blockSize)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackDelta(): Int = NibbleBlockSize.unpackDelta(new UnsafeBuffer(buf, 0, numBytes)).right.get.size
+}
@@ -0,0 +1,41 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibbleBufferPool, NibblePack}
+
+/**
+ * Measures packing many small vectors into a fresh buffer each time against buffers from a NibbleBufferPool.
+ * Run with -prof gc to compare the bytes allocated per vector.
+ */
+@State(Scope.Thread)
+class NibbleBufferPoolBenchmark {
+  val rand = new scala.util.Random(3)
+  val vectors = Array.fill(100)(Array.fill(300)(rand.nextInt(1000).toLong).scanLeft(0L)(_ + _).tail)
+  val pool = new NibbleBufferPool()
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def packFreshBuffers(): Int = {
+    var total = 0
+    vectors.foreach { v => total += NibblePack.packDelta(v, new ExpandableArrayBuffer(4096), 0) }
+    total
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def packPooledBuffers(): Int = {
+    var total = 0
+    vectors.foreach { v =>
+      val packed = pool.packDelta(v)
+      total += packed.numBytes
+      packed.close()
+    }
+    total
+  }
+}
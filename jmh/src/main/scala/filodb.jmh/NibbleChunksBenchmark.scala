@@ -0,0 +1,37 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import scala.concurrent.Await
+import scala.concurrent.ExecutionContext.Implicits.global
+import scala.concurrent.duration._
+
+import org.agrona.ExpandableArrayBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibbleChunks, NibblePack}
+
+/**
+ * Measures packing a very large vector in parallel chunks on the global ExecutionContext against packing it
+ * with one serial packDelta.
+ */
+@State(Scope.Thread)
+class NibbleChunksBenchmark {
+  val numValues = 20000000
+  val rand = new scala.util.Random(17)
+  val counter = Array.fill(numValues)(rand.nextInt(1000).toLong).scanLeft(0L)(_ + _).drop(1)
+
+  val parallelBuf = new ExpandableArrayBuffer(numValues * 2)
+  val serialBuf = new ExpandableArrayBuffer(numValues * 2)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MILLISECONDS)
+  def packDeltaParallel(): Int =
+    Await.result(NibbleChunks.packDeltaParallel(counter, parallelBuf, 0), 60.seconds)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MILLISECONDS)
+  def packDeltaSerial(): Int = NibblePack.packDelta(counter, serialBuf, 0)
+}
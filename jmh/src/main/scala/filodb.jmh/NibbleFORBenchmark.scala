@@ -0,0 +1,54 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibbleFOR, NibblePackSigned}
+
+/**
+ * Measures frame-of-reference encoding against signed delta encoding, on values which cluster within each block
+ * of 8 but jump between blocks.  forBytes and deltaBytes hold the packed sizes of each.
+ */
+@State(Scope.Thread)
+class NibbleFORBenchmark {
+  val numValues = 100000
+  val rand = new scala.util.Random(11)
+  val clustered = Array.tabulate(numValues) { i =>
+    if (i % 8 == 0) rand.nextInt(1 << 20) * 1000L else 0L
+  }
+  for { i <- 0 until numValues } {
+    if (i % 8 != 0) clustered(i) = clustered(i - i % 8) + rand.nextInt(100)
+  }
+
+  val forBuf = new ExpandableArrayBuffer()
+  val forBytes = NibbleFOR.packFOR(clustered, forBuf, 0)
+  val deltaBuf = new ExpandableArrayBuffer()
+  val deltaBytes = NibblePackSigned.packDelta(clustered, deltaBuf, 0)
+  val outArray = new Array[Long](numValues)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def packFOR(): Int = NibbleFOR.packFOR(clustered, forBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def packDelta(): Int = NibblePackSigned.packDelta(clustered, deltaBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackFOR(): Int = NibbleFOR.unpackFOR(new UnsafeBuffer(forBuf, 0, forBytes)).right.get.size
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackDelta(): Long = {
+    NibblePackSigned.unpackDelta(new UnsafeBuffer(deltaBuf, 0, deltaBytes), outArray)
+    outArray(numValues - 1)
+  }
+}
@@ -0,0 +1,38 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibbleFixedWidth, NibblePack}
+
+/**
+ * Measures decoding deltas all of one width (NibbleFixedWidth) against the variable width blocks of packDelta, on
+ * uniform data where every delta needs about the same width, so that the fixed width costs little in size.
+ * fixedBytes and variableBytes hold the packed sizes of each.
+ */
+@State(Scope.Thread)
+class NibbleFixedWidthBenchmark {
+  val numValues = 100000
+  val rand = new scala.util.Random(17)
+  val uniform = Array.fill(numValues)(1000L + rand.nextInt(3000).toLong)
+  for { i <- 1 until numValues } { uniform(i) += uniform(i - 1) }
+
+  val fixedBuf = new ExpandableArrayBuffer()
+  val fixedBytes = NibbleFixedWidth.packDelta(uniform, fixedBuf, 0)
+  val variableBuf = new ExpandableArrayBuffer()
+  val variableBytes = NibblePack.packDelta(uniform, variableBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackFixedWidth(): Int = NibbleFixedWidth.unpackDelta(new UnsafeBuffer(fixedBuf, 0, fixedBytes)).right.get.size
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackVariableWidth(): Int =
+    NibblePack.unpackDelta(new UnsafeBuffer(variableBuf, 0, variableBytes), numValues).right.get.size
+}
@@ -0,0 +1,43 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.{ExpandableArrayBuffer, ExpandableDirectByteBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibbleAggregations, NibblePack}
+
+/**
+ * Measures unpacking a 10M-element NibblePacked vector, which is far bigger than the CPU caches, so that the
+ * unpack loop is bound by memory latency rather than by decoding.  Compares on-heap and off-heap buffers.
+ */
+@State(Scope.Thread)
+class NibblePackLargeBenchmark {
+  val numValues = 10000000
+  val inputs = Array.tabulate(numValues) { i => 1000000L + i * 1000L + util.Random.nextInt(100) }
+
+  val heapBuf = new ExpandableArrayBuffer()
+  val heapBytes = NibblePack.packDelta(inputs, heapBuf, 0)
+  val heapSlice = new UnsafeBuffer(heapBuf, 0, heapBytes)
+
+  val directBuf = new ExpandableDirectByteBuffer()
+  val directBytes = NibblePack.packDelta(inputs, directBuf, 0)
+  val directSlice = new UnsafeBuffer(directBuf, 0, directBytes)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MILLISECONDS)
+  def unpackLargeOnHeap(): Long = {
+    heapSlice.wrap(heapBuf, 0, heapBytes)
+    NibbleAggregations.maxDelta(heapSlice, numValues).right.get
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MILLISECONDS)
+  def unpackLargeOffHeap(): Long = {
+    directSlice.wrap(directBuf, 0, directBytes)
+    NibbleAggregations.maxDelta(directSlice, numValues).right.get
+  }
+}
@@ -0,0 +1,48 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibblePack, NibbleRuns}
+
+/**
+ * Measures delta encoding with runs of zero deltas shortened against plain delta encoding, on a counter which
+ * stays flat for thousands of samples at a time and then jumps.  runsBytes and deltaBytes hold the packed sizes
+ * of each.
+ */
+@State(Scope.Thread)
+class NibbleRunsBenchmark {
+  val numValues = 100000
+  val rand = new scala.util.Random(13)
+  val flatCounter = Array.fill(numValues)(if (rand.nextInt(5000) == 0) 1L + rand.nextInt(1000) else 0L)
+                         .scanLeft(0L)(_ + _).drop(1)
+
+  val runsBuf = new ExpandableArrayBuffer()
+  val runsBytes = NibbleRuns.packDeltaRuns(flatCounter, runsBuf, 0)
+  val deltaBuf = new ExpandableArrayBuffer()
+  val deltaBytes = NibblePack.packDelta(flatCounter, deltaBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def packDeltaRuns(): Int = NibbleRuns.packDeltaRuns(flatCounter, runsBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def packDelta(): Int = NibblePack.packDelta(flatCounter, deltaBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackDeltaRuns(): Int = NibbleRuns.unpackDeltaRuns(new UnsafeBuffer(runsBuf, 0, runsBytes)).right.get.size
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackDelta(): Int =
+    NibblePack.unpackDelta(new UnsafeBuffer(deltaBuf, 0, deltaBytes), numValues).right.get.size
+}
@@ -0,0 +1,76 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{CompressedVec, NibblePack, NibbleSelect}
+
+/**
+ * Compares picking 1% of the values out of a long NibblePacked vector with NibbleSelect against unpacking the
+ * whole vector and indexing into it, both for values spread over the vector and for a window of it.
+ */
+@State(Scope.Thread)
+class NibbleSelectBenchmark {
+  val numValues = 100000
+  val inputs = Array.tabulate(numValues) { i => i * 1000L + util.Random.nextInt(100) }
+  val indices = (0 until numValues by 100).toArray
+  val packed = NibblePack.packDeltaToBytes(inputs)
+  val nonDeltaPacked = {
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = NibblePack.packNonIncreasing(inputs, buf, 0)
+    java.util.Arrays.copyOf(buf.byteArray, numBytes)
+  }
+  val windowStart = numValues / 2
+  val windowEnd = windowStart + numValues / 100
+  val vec = {
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = CompressedVec.encode(inputs, buf, 0)
+    CompressedVec(new UnsafeBuffer(buf, 0, numBytes)).right.get
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def fullUnpackDelta(): Long = {
+    val values = NibblePack.unpackDeltaFromBytes(packed, numValues).right.get
+    indices.map(values).sum
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def selectDelta(): Long =
+    NibbleSelect.unpackDeltaIndices(new UnsafeBuffer(packed), numValues, indices).right.get.sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def selectNonDelta(): Long =
+    NibbleSelect.unpackIndices(new UnsafeBuffer(nonDeltaPacked), numValues, indices).right.get.sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def fullUnpackDeltaWindow(): Long =
+    NibblePack.unpackDeltaFromBytes(packed, numValues).right.get.slice(windowStart, windowEnd).sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def rangeDelta(): Long =
+    NibbleSelect.unpackDeltaRange(new UnsafeBuffer(packed), numValues, windowStart, windowEnd).right.get.sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def rangeNonDelta(): Long =
+    NibbleSelect.unpackRange(new UnsafeBuffer(nonDeltaPacked), numValues, windowStart, windowEnd).right.get.sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def rangeWithSkipTable(): Long = vec.range(windowStart, windowEnd).get.sum
+}
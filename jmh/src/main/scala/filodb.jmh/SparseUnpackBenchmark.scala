@@ -0,0 +1,43 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibblePack, NibbleSinks}
+
+/**
+ * Measures unpacking mostly-zero data such as counters which rarely change, where each block has only one or
+ * two nonzero values, against dense data where every value is nonzero.
+ */
+@State(Scope.Thread)
+class SparseUnpackBenchmark {
+  val numValues = 100000
+  val sparse = Array.fill(numValues) { if (util.Random.nextInt(8) == 0) 1000L + util.Random.nextInt(100) else 0L }
+  val dense = Array.fill(numValues) { 1000L + util.Random.nextInt(100) }
+
+  val sparseBuf = new ExpandableArrayBuffer()
+  val sparseBytes = NibblePack.packNonIncreasing(sparse, sparseBuf, 0)
+  val denseBuf = new ExpandableArrayBuffer()
+  val denseBytes = NibblePack.packNonIncreasing(dense, denseBuf, 0)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackSparse(): Long = {
+    val sink = new NibbleSinks.SumSink(numValues)
+    NibblePack.unpackToSink(new UnsafeBuffer(sparseBuf, 0, sparseBytes), sink, numValues)
+    sink.sum
+  }
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MICROSECONDS)
+  def unpackDense(): Long = {
+    val sink = new NibbleSinks.SumSink(numValues)
+    NibblePack.unpackToSink(new UnsafeBuffer(denseBuf, 0, denseBytes), sink, numValues)
+    sink.sum
+  }
+}
@@ -0,0 +1,39 @@
+package filodb.jmh
+
+import java.util.concurrent.TimeUnit
+
+import scala.concurrent.Await
+import scala.concurrent.ExecutionContext.Implicits.global
+import scala.concurrent.duration._
+
+import org.agrona.concurrent.UnsafeBuffer
+import org.openjdk.jmh.annotations._
+
+import filodb.memory.format.{NibbleBatch, NibblePack}
+
+/**
+ * Compares unpacking thousands of independent delta-packed vectors one after the other with unpacking them in
+ * parallel through NibbleBatch.unpackDeltaMany, as in a bulk backfill.
+ */
+@State(Scope.Thread)
+class UnpackManyBenchmark {
+  val numVectors = 5000
+  val numValues = 720
+  val vectors = (0 until numVectors).map { n =>
+    NibblePack.packDeltaToBytes(Array.tabulate(numValues) { i => n * 100000L + i * 1000 + util.Random.nextInt(100) })
+  }
+  val buffers = vectors.map(new UnsafeBuffer(_))
+  val counts = Seq.fill(numVectors)(numValues)
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MILLISECONDS)
+  def unpackSerial(): Int =
+    vectors.map { bytes => NibblePack.unpackDeltaFromBytes(bytes, numValues).right.get.size }.sum
+
+  @Benchmark
+  @BenchmarkMode(Array(Mode.AverageTime))
+  @OutputTimeUnit(TimeUnit.MILLISECONDS)
+  def unpackParallel(): Int =
+    Await.result(NibbleBatch.unpackDeltaMany(buffers, counts), 60.seconds).right.get.map(_.size).sum
+}
@@ -1,8 +1,13 @@
 package filodb.memory
 
+import java.nio.ByteOrder
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
 import net.jpountz.xxhash.XXHashFactory
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
 
-import filodb.memory.format.UnsafeUtils
+import filodb.memory.format.{NibblePack, UnsafeUtils}
 
 /**
  * A BinaryRegion is just an area of memory (heap or offheap) with a length prefix.
@@ -37,6 +42,25 @@ object BinaryRegion {
     case UnsafeUtils.ZeroPointer => hasher32.hash(UnsafeUtils.asDirectBuffer(offset, len), Seed)
   }
 
+  // CRC32 of each byte value, for the reflected polynomial 0xEDB88320 used by zlib and java.util.zip.CRC32
+  private val crc32Table = Array.tabulate(256) { n =>
+    (0 until 8).foldLeft(n) { (c, _) => if ((c & 1) != 0) 0xEDB88320 ^ (c >>> 1) else c >>> 1 }
+  }
+
+  /**
+   * The standard CRC32 of len bytes of memory, the same value as java.util.zip.CRC32 or zlib give, so both sides
+   * of an interface can compute it with whatever they have.  Slower than hash32, but portable.
+   */
+  def crc32(base: Any, offset: Long, len: Int): Int = {
+    var crc = 0xffffffff
+    var i = 0
+    while (i < len) {
+      crc = crc32Table((crc ^ getByte(base, offset + i)) & 0x00ff) ^ (crc >>> 8)
+      i += 1
+    }
+    ~crc
+  }
+
   /**
    * Returns true if the source byte array is equal to the destination byte array, at the given
    * index and # of bytes into the source array.  Destination is compared whole.
@@ -47,6 +71,32 @@ object BinaryRegion {
   def copyArray(source: Array[Byte], dest: Array[Byte], destOffset: Int): Unit =
     System.arraycopy(source, 0, dest, destOffset, source.size)
 
+  /**
+   * A length-prefixed region which has been checked to fit exactly in its byte array, see parse().
+   * The payload does not include the length prefix.
+   */
+  sealed trait Region {
+    def payload: DirectBuffer
+  }
+  final case class MediumRegion(payload: DirectBuffer) extends Region
+  final case class LargeRegion(payload: DirectBuffer) extends Region
+
+  /**
+   * Parses a byte array holding exactly one BinaryRegionMedium or BinaryRegionLarge, working out which framing
+   * was used from which length prefix matches the size of the array.  The two can never both match.
+   * @return the region, or NibbleError if the array is too short or neither length prefix matches its size
+   */
+  def parse(bytes: Array[Byte]): Either[NibblePack.NibbleError, Region] =
+    if (bytes.size < BinaryRegionMedium.lenBytes) {
+      Left(NibblePack.InputTooShort(BinaryRegionMedium.lenBytes, bytes.size))
+    } else if (BinaryRegionMedium.safeNumBytes(bytes, 0) == Some(bytes.size - BinaryRegionMedium.lenBytes)) {
+      Right(MediumRegion(BinaryRegionMedium.safeSlice(bytes, 0).get))
+    } else if (BinaryRegionLarge.safeNumBytes(bytes, 0) == Some(bytes.size - BinaryRegionLarge.lenBytes)) {
+      Right(LargeRegion(BinaryRegionLarge.safeSlice(bytes, 0).get))
+    } else {
+      Left(NibblePack.InvalidHeader("length", BinaryRegionMedium.numBytes(bytes, arayOffset)))
+    }
+
   // 64-bit pointer to native/offheap memory.  NOTE: instead of using this, please use the Ptr*
   // value classes as they are much more type safe
   type NativePointer = Long
@@ -56,13 +106,55 @@ trait BinaryRegion {
   import format.UnsafeUtils
   import BinaryRegion._
 
-  // Returns the length from the initial bytes of the region
+  // Returns the length from the initial bytes of the region.  NOTE: no bounds checking is done on the read or
+  // the length, so a corrupt length prefix leads to reading past the region.  See safeNumBytes for a checked version.
   def numBytes(base: Any, offset: Long): Int
   final def numBytes(address: Long): Int = numBytes(UnsafeUtils.ZeroPointer, address)
 
   // The number of bytes used up by the length header
   def lenBytes: Int
 
+  // The largest payload the length header can describe
+  def maxNumBytes: Int
+
+  // Writes the length header for a payload of len bytes at index within buf
+  protected def putNumBytes(buf: MutableDirectBuffer, index: Int, len: Int): Unit
+
+  /**
+   * Writes a region holding a copy of payload at index within buf, length prefix first.  The inverse of
+   * safeSlice, for turning bytes from outside, such as another process, back into regions readers can use.
+   * @return the position within buf after the region
+   * @throws IllegalArgumentException if payload is longer than maxNumBytes
+   */
+  final def writeRegion(payload: DirectBuffer, buf: MutableDirectBuffer, index: Int): Int = {
+    require(payload.capacity <= maxNumBytes, s"Payload of ${payload.capacity} bytes is over the $maxNumBytes limit")
+    putNumBytes(buf, index, payload.capacity)
+    buf.putBytes(index + lenBytes, payload, 0, payload.capacity)
+    index + lenBytes + payload.capacity
+  }
+
+  final def writeRegion(payload: Array[Byte], buf: MutableDirectBuffer, index: Int): Int =
+    writeRegion(new UnsafeBuffer(payload), buf, index)
+
+  /**
+   * Bounds-checked version of numBytes for a region starting at index within a byte array.
+   * @return None if the length prefix, or the number of bytes it declares, would run past the end of the array
+   */
+  final def safeNumBytes(bytes: Array[Byte], index: Int): Option[Int] =
+    if (index < 0 || index.toLong + lenBytes > bytes.size) {
+      None
+    } else {
+      val len = numBytes(bytes, UnsafeUtils.arayOffset + index)
+      if (len < 0 || index.toLong + lenBytes + len > bytes.size) None else Some(len)
+    }
+
+  /**
+   * Returns a view of the bytes of the region starting at index within a byte array, not including the length
+   * prefix, or None if the region would run past the end of the array.  No copying is done.
+   */
+  final def safeSlice(bytes: Array[Byte], index: Int): Option[DirectBuffer] =
+    safeNumBytes(bytes, index).map { len => new UnsafeBuffer(bytes, index + lenBytes, len) }
+
   /**
    * Returns 1 if region1 (base1, offset1) > region2 (base2, offset2), 0 if equal, -1 if region1 is less
    * Compares byte by byte.  The minimum of the lengths of two regions are compared.
@@ -132,17 +224,26 @@ trait BinaryRegion {
 
 /**
  * A BinaryRegionMedium uses two bytes to store the length prefix, thus a region can be up to 64KB in size.
+ * The prefix is always little endian, as for UTF8StringMedium and BinaryHistogram.
  */
 object BinaryRegionMedium extends BinaryRegion {
   import format.UnsafeUtils
 
-  final def numBytes(base: Any, offset: Long): Int = UnsafeUtils.getShort(base, offset) & 0x0FFFF
+  final def numBytes(base: Any, offset: Long): Int = UnsafeUtils.getShortLE(base, offset) & 0x0FFFF
 
   val lenBytes = 2
+  val maxNumBytes = 0x0FFFF
+
+  protected def putNumBytes(buf: MutableDirectBuffer, index: Int, len: Int): Unit =
+    buf.putShort(index, len.toShort, LITTLE_ENDIAN)
 }
 
 /**
  * A BinaryRegionLarge uses four bytes to store the length prefix, thus a region can be up to 2GB in size.
+ * Unlike BinaryRegionMedium, the prefix is deliberately left in host order: it is the same 4 bytes which frame
+ * every BinaryVector and RecordContainer, and which their writers, readers and the Kryo serializers set and read
+ * with UnsafeUtils.setInt and getInt.  Making it little endian would mean converting all of those together, which
+ * is outside the scope of the little endian Medium prefix.  This is only a difference on big endian hosts.
  */
 object BinaryRegionLarge extends BinaryRegion {
   import format.UnsafeUtils
@@ -150,6 +251,10 @@ object BinaryRegionLarge extends BinaryRegion {
   final def numBytes(base: Any, offset: Long): Int = UnsafeUtils.getInt(base, offset)
 
   val lenBytes = 4
+  val maxNumBytes = Int.MaxValue
+
+  protected def putNumBytes(buf: MutableDirectBuffer, index: Int, len: Int): Unit =
+    buf.putInt(index, len, ByteOrder.nativeOrder)
 }
 
 /**
@@ -32,7 +32,7 @@ object UTF8StringMedium extends BinaryRegion {
   import UTF8String._
   import java.nio.charset.StandardCharsets
 
-  final def numBytes(base: Any, offset: Long): Int = UnsafeUtils.getShort(base, offset) & 0x0FFFF
+  final def numBytes(base: Any, offset: Long): Int = UnsafeUtils.getShortLE(base, offset) & 0x0FFFF
 
   val lenBytes = 2
 
@@ -51,12 +51,12 @@ object UTF8StringMedium extends BinaryRegion {
 
   def copyByteArrayTo(bytes: Array[Byte], dest: Any, destOffset: Long): Unit = {
     UnsafeUtils.unsafe.copyMemory(bytes, UnsafeUtils.arayOffset, dest, destOffset + lenBytes, bytes.size)
-    UnsafeUtils.setShort(dest, destOffset, bytes.size.toShort)
+    UnsafeUtils.setShortLE(dest, destOffset, bytes.size.toShort)
   }
 
   def copyByteArrayTo(bytes: Array[Byte], byteIndex: Int, len: Int, dest: Any, destOffset: Long): Unit = {
     UnsafeUtils.unsafe.copyMemory(bytes, UnsafeUtils.arayOffset + byteIndex, dest, destOffset + lenBytes, len)
-    UnsafeUtils.setShort(dest, destOffset, len.toShort)
+    UnsafeUtils.setShortLE(dest, destOffset, len.toShort)
   }
 
   /**
@@ -0,0 +1,158 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+import filodb.memory.format.NibblePack.{InputTooShort, NibbleError}
+
+/*
+ * Bit level readers and writers shared by the codecs, so that the logic for values which span bytes or words is
+ * written and tested once.  There are two bit orders:
+ * - BitWriter and BitReader put the most significant bit first, for bit streams such as DoubleXORPack
+ * - LsbBitWriter and LsbBitReader put the least significant bit first into little-endian 64-bit words, which is
+ *   how NibblePack stores the nibbles of each block, see pack8
+ * Values are Longs of up to 64 bits.  None of them are thread safe.
+ */
+
+/**
+ * Writes bits most significant first into a buffer, one byte at a time.
+ */
+private[format] final class BitWriter(buf: MutableDirectBuffer, startPos: Int) {
+  private var pos = startPos
+  private var current = 0
+  private var bitOffset = 0
+
+  // Writes the lowest numBits bits of value
+  final def write(value: Long, numBits: Int): Unit = {
+    var n = numBits
+    while (n > 0) {
+      val avail = 8 - bitOffset
+      val take = Math.min(avail, n)
+      val chunk = ((value >>> (n - take)) & ((1L << take) - 1)).toInt
+      current |= chunk << (avail - take)
+      bitOffset += take
+      n -= take
+      if (bitOffset == 8) {
+        buf.putByte(pos, current.toByte)
+        pos += 1
+        current = 0
+        bitOffset = 0
+      }
+    }
+  }
+
+  // Writes out any partial byte, returning the final position within the buffer
+  final def finish(): Int = {
+    if (bitOffset > 0) {
+      buf.putByte(pos, current.toByte)
+      pos += 1
+      current = 0
+      bitOffset = 0
+    }
+    pos
+  }
+}
+
+/**
+ * Reads bits most significant first from the start of a buffer.  Callers check has() before each read.
+ */
+private[format] final class BitReader(buf: DirectBuffer) {
+  private var bitPos = 0L
+
+  final def has(numBits: Int): Boolean = bitPos + numBits <= buf.capacity.toLong * 8
+  final def tooShort(numBits: Int): NibbleError = InputTooShort(((bitPos + numBits + 7) / 8).toInt, buf.capacity)
+  final def bytesRead: Int = ((bitPos + 7) / 8).toInt
+
+  final def read(numBits: Int): Long = {
+    var result = 0L
+    var n = numBits
+    while (n > 0) {
+      val bitOffset = (bitPos & 7).toInt
+      val avail = 8 - bitOffset
+      val take = Math.min(avail, n)
+      val byte = buf.getByte((bitPos >>> 3).toInt) & 0x0ff
+      result = (result << take) | ((byte >>> (avail - take)) & ((1 << take) - 1))
+      bitPos += take
+      n -= take
+    }
+    result
+  }
+}
+
+/**
+ * Writes values least significant bit first, back to back into little-endian 64-bit words, as NibblePack stores
+ * nibbles.  A value may span two words.  Whole words are written, so the buffer needs 8 bytes of room past the
+ * final position, but only the bytes holding bits count towards it.
+ */
+private[format] final class LsbBitWriter(startBuf: MutableDirectBuffer, startPos: Int) {
+  private var buf = startBuf
+  private var pos = startPos
+  private var word = 0L
+  private var bitCursor = 0
+
+  // Starts writing afresh at newPos in newBuf, so that one writer can be reused for many blocks
+  final def reset(newBuf: MutableDirectBuffer, newPos: Int): Unit = {
+    buf = newBuf
+    pos = newPos
+    word = 0L
+    bitCursor = 0
+  }
+
+  // Writes the lowest numBits bits of value, for numBits from 1 to 64
+  final def write(value: Long, numBits: Int): Unit = {
+    val bits = if (numBits >= 64) value else value & ((1L << numBits) - 1)
+    val remaining = 64 - bitCursor
+    word |= bits << bitCursor
+    if (remaining <= numBits) {
+      buf.putLong(pos, word, LITTLE_ENDIAN)
+      pos += 8
+      // The most significant part left over for the next word, if any
+      word = if (remaining < numBits) bits >>> remaining else 0L
+    }
+    bitCursor = (bitCursor + numBits) % 64
+  }
+
+  // Writes out any partial word, returning the final position within the buffer
+  final def finish(): Int = {
+    if (bitCursor > 0) {
+      buf.putLong(pos, word, LITTLE_ENDIAN)
+      pos += (bitCursor + 7) / 8
+      word = 0L
+      bitCursor = 0
+    }
+    pos
+  }
+}
+
+private[format] object LsbBitWriter {
+  private val tlWriter = new ThreadLocal[LsbBitWriter] {
+    override def initialValue(): LsbBitWriter = new LsbBitWriter(new UnsafeBuffer(new Array[Byte](0)), 0)
+  }
+
+  // One writer per thread, for callers which reset it before each use rather than allocating one
+  def threadLocal: LsbBitWriter = tlWriter.get
+}
+
+/**
+ * Reads values written by LsbBitWriter, starting at startPos.  Callers check has() before each read.
+ */
+private[format] final class LsbBitReader(buf: DirectBuffer, startPos: Int) {
+  private var bitPos = startPos.toLong * 8
+
+  final def has(numBits: Int): Boolean = bitPos + numBits <= buf.capacity.toLong * 8
+  final def tooShort(numBits: Int): NibbleError = InputTooShort(((bitPos + numBits + 7) / 8).toInt, buf.capacity)
+  final def bytesRead: Int = ((bitPos + 7) / 8).toInt - startPos
+
+  // Reads numBits bits, for numBits from 1 to 64
+  final def read(numBits: Int): Long = {
+    val index = (bitPos >>> 3).toInt
+    val shift = (bitPos & 7).toInt
+    var result = NibblePack.readLong(buf, index) >>> shift
+    // Up to 7 bits past the shift are in the ninth byte
+    if (shift + numBits > 64) result |= (buf.getByte(index + 8) & 0x0ffL) << (64 - shift)
+    bitPos += numBits
+    if (numBits >= 64) result else result & ((1L << numBits) - 1)
+  }
+}
@@ -0,0 +1,159 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * A run-length encoded vector of Booleans, for series such as "up" or presence flags which hold one value for
+ * long stretches.  Only the lengths of the runs of equal values are stored, NibblePacked, so a series which
+ * never changes takes a few bytes no matter how long it is.  get() and countOnes work directly on the runs,
+ * without unpacking the Booleans.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Bool_Runs
+ *   +1   numValues, Int
+ *   +5   numRuns, Int
+ *   +9   the value of the first run, 1 byte: 1 for true and 0 for false.  Runs alternate after that.
+ *   +10  run lengths as NibblePack.packNonIncreasing
+ * }}}
+ */
+final class BitVec private(buf: DirectBuffer) {
+  import BitVec._
+
+  val numValues = buf.getInt(1, LITTLE_ENDIAN)
+  val numRuns = buf.getInt(5, LITTLE_ENDIAN)
+  val firstValue = buf.getByte(9) != 0
+
+  /**
+   * Returns the value at index idx, or None if idx is out of range.  Only the run lengths up to idx are read.
+   */
+  final def get(idx: Int): Option[Boolean] = if (idx < 0 || idx >= numValues) None else {
+    val sink = walkRuns(buf, numRuns, idx, firstValue)
+    Some(runValue(firstValue, sink.runNo - 1))
+  }
+
+  /**
+   * The number of true values, added up from the run lengths.
+   */
+  final def countOnes: Int = walkRuns(buf, numRuns, Long.MaxValue, firstValue).ones.toInt
+}
+
+object BitVec {
+  import NibblePack.{unpack8, InputTooShort, InvalidHeader, NibbleError, Ok, Sink, UnpackResult}
+
+  val HeaderBytes = 10
+
+  /**
+   * Packs the Booleans as run lengths, writing NibbleFormat.Format_Bool_Runs first.
+   * @return the final position within the buffer after packing
+   */
+  final def packBools(values: Array[Boolean], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val packer = new NibblePack.Packer(buf, bufindex + HeaderBytes)
+    var runStart = 0
+    var i = 1
+    while (i <= values.size) {
+      if (i == values.size || values(i) != values(i - 1)) {
+        packer.add(i - runStart)
+        runStart = i
+      }
+      i += 1
+    }
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_Bool_Runs))
+    buf.putInt(bufindex + 1, values.size, LITTLE_ENDIAN)
+    buf.putInt(bufindex + 5, packer.numValues, LITTLE_ENDIAN)
+    buf.putByte(bufindex + 9, (if (values.nonEmpty && values(0)) 1 else 0).toByte)
+    packer.finish()
+  }
+
+  /**
+   * Unpacks a stream written by packBools.  The buffer is not mutated.
+   */
+  final def unpackBools(compressed: DirectBuffer): Either[NibbleError, Array[Boolean]] =
+    apply(compressed).right.map { vec =>
+      val out = new Array[Boolean](vec.numValues)
+      val sink = new RunSink(vec.numRuns, Long.MaxValue, vec.firstValue) {
+        override def onRun(start: Long, length: Long, value: Boolean): Unit =
+          if (value) java.util.Arrays.fill(out, start.toInt, (start + length).toInt, true)
+      }
+      unpackRuns(compressed, sink)
+      out
+    }
+
+  /**
+   * Wraps a buffer starting with a BitVec, after checking that its header makes sense and that its run lengths
+   * are all there and add up to numValues, so that get and countOnes can never fail.
+   */
+  final def apply(buf: DirectBuffer): Either[NibbleError, BitVec] =
+    if (buf.capacity < HeaderBytes) {
+      Left(InputTooShort(HeaderBytes, buf.capacity))
+    } else {
+      NibbleFormat.checkFormat(new UnsafeBuffer(buf, 0, buf.capacity), NibbleFormat.Format_Bool_Runs) match {
+        case Ok =>
+          val numValues = buf.getInt(1, LITTLE_ENDIAN)
+          val numRuns = buf.getInt(5, LITTLE_ENDIAN)
+          val sink = new RunSink(numRuns, Long.MaxValue, buf.getByte(9) != 0)
+          if (numValues < 0) {
+            Left(InvalidHeader("numValues", numValues))
+          } else if (numRuns < 0 || numRuns > numValues || (numRuns == 0) != (numValues == 0)) {
+            Left(InvalidHeader("numRuns", numRuns))
+          } else {
+            unpackRuns(buf, sink) match {
+              case e: NibbleError                 => Left(e)
+              case Ok if sink.invalid             => Left(InvalidHeader("runLength", sink.runNo - 1))
+              case Ok if sink.runNo < numRuns     => Left(InputTooShort(buf.capacity + 1, buf.capacity))
+              case Ok if sink.values != numValues => Left(InvalidHeader("numValues", numValues))
+              case Ok                             => Right(new BitVec(buf))
+            }
+          }
+        case e: NibbleError => Left(e)
+      }
+    }
+
+  @inline private def runValue(firstValue: Boolean, runNo: Int): Boolean = firstValue ^ (runNo % 2 == 1)
+
+  private def walkRuns(buf: DirectBuffer, numRuns: Int, target: Long, firstValue: Boolean): RunSink = {
+    val sink = new RunSink(numRuns, target, firstValue)
+    unpackRuns(buf, sink)
+    sink
+  }
+
+  // Unpacks run lengths until the sink is done, or the input runs out
+  private def unpackRuns(buf: DirectBuffer, sink: RunSink): UnpackResult = {
+    val view = new UnsafeBuffer(buf, HeaderBytes, buf.capacity - HeaderBytes)
+    var res: UnpackResult = Ok
+    while (!sink.done && res == Ok && view.capacity > 0) res = unpack8(view, sink)
+    res
+  }
+
+  /**
+   * Goes through the first numRuns run lengths, stopping after the run which holds the value at index target.
+   */
+  private class RunSink(numRuns: Int, target: Long, firstValue: Boolean) extends Sink {
+    var runNo = 0          // the number of runs seen
+    var values = 0L        // the number of values in the runs seen
+    var ones = 0L          // the number of true values in the runs seen
+    var invalid = false    // a run was empty or impossibly long
+    private var found = false
+    final def done: Boolean = found || runNo >= numRuns
+
+    final def process(data: Array[Long]): Unit = {
+      var n = 0
+      while (n < 8 && !done) {
+        val length = data(n)
+        if (length <= 0 || length > Int.MaxValue) invalid = true
+        val value = runValue(firstValue, runNo)
+        onRun(values, length, value)
+        if (value) ones += length
+        values += length
+        runNo += 1
+        if (values > target || invalid) found = true
+        n += 1
+      }
+    }
+
+    def onRun(start: Long, length: Long, value: Boolean): Unit = {}
+  }
+}
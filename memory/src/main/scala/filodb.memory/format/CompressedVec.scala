@@ -0,0 +1,186 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * A read-only view over NibblePacked Long values which allows fetching element N without unpacking every
+ * value before it.  At encode time the byte offset of every K-th block of 8 values is recorded in a skip table
+ * in the footer.  get() jumps to the nearest recorded block, skips over at most K-1 blocks by reading just their
+ * headers, then unpacks the one block containing the value.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Skip_Table
+ *   +1   numValues, Int
+ *   +5   blocksPerSkip (K), Short
+ *   +7   offset of footer from start of vector, Int
+ *   +11  NibblePacked blocks of 8 values, see NibblePack.pack8
+ *   footer: Int offset from start of vector of blocks 0, K, 2K, ...
+ * }}}
+ * The values are packed as is, not as deltas, so that every block can be unpacked on its own.
+ */
+final class CompressedVec private(buf: DirectBuffer) {
+  import CompressedVec._
+  import NibblePack.{blockSize, unpack8, Ok, Sink}
+
+  val numValues = buf.getInt(NumValuesOffset, LITTLE_ENDIAN)
+  val blocksPerSkip = buf.getShort(BlocksPerSkipOffset, LITTLE_ENDIAN).toInt
+  private val footerOffset = buf.getInt(FooterOffsetOffset, LITTLE_ENDIAN)
+
+  /**
+   * Returns the value at index idx, or None if idx is out of range or the block containing it is malformed.
+   */
+  final def get(idx: Int): Option[Long] = if (idx < 0 || idx >= numValues) None else {
+    blockPos(idx / 8).flatMap { pos =>
+      val sink = new ElementSink(idx % 8)
+      unpack8(new UnsafeBuffer(buf, pos, footerOffset - pos), sink) match {
+        case Ok => Some(sink.value)
+        case _  => None
+      }
+    }
+  }
+
+  /**
+   * Returns the values from start until end, eg for a window of a longer vector.  The skip table finds the block
+   * holding start, and unpacking stops after the block holding end - 1, see NibbleSelect.unpackRange.
+   * @return None if the range is not within 0 until numValues or a block holding it is malformed
+   */
+  final def range(start: Int, end: Int): Option[Array[Long]] =
+    if (start < 0 || start > end || end > numValues) None
+    else if (start == end) Some(Array.empty[Long])
+    else blockPos(start / 8).flatMap { pos =>
+      val firstIndex = start / 8 * 8
+      NibbleSelect.unpackRange(new UnsafeBuffer(buf, pos, footerOffset - pos), numValues - firstIndex,
+                               start - firstIndex, end - firstIndex).right.toOption
+    }
+
+  // The position of block blockNo, found by skipping block headers from the nearest block in the skip table
+  private def blockPos(blockNo: Int): Option[Int] = {
+    var pos = buf.getInt(footerOffset + 4 * (blockNo / blocksPerSkip), LITTLE_ENDIAN)
+    var toSkip = blockNo % blocksPerSkip
+    while (toSkip > 0 && pos >= HeaderBytes && pos < footerOffset) {
+      pos += blockSize(buf, pos)
+      toSkip -= 1
+    }
+    if (pos < HeaderBytes || pos >= footerOffset) None else Some(pos)
+  }
+
+  /**
+   * For a vector whose values are sorted in increasing order, such as timestamps, returns whether target is one
+   * of them.  Binary searches the blocks by their first value, found from the skip table and block headers, for
+   * the one block which could hold target, then looks for it among that block's values.  So about
+   * log2(numValues / 8) blocks are unpacked, each after skipping over at most blocksPerSkip - 1 block headers.
+   * The answer is meaningless if the values are not sorted.
+   * @return None if a block the search needed is malformed
+   */
+  final def containsSorted(target: Long): Option[Boolean] = if (numValues == 0) Some(false) else {
+    // The last block whose first value is at most target, as every later block starts above it
+    var lo = 0
+    var hi = (numValues - 1) / 8
+    var malformed = false
+    while (lo < hi && !malformed) {
+      val mid = (lo + hi + 1) >>> 1
+      block(mid) match {
+        case Some(values) if values(0) <= target => lo = mid
+        case Some(_)                             => hi = mid - 1
+        case None                                => malformed = true
+      }
+    }
+    if (malformed) None else block(lo).map { values =>
+      val numElems = Math.min(numValues - lo * 8, 8)
+      values.iterator.take(numElems).contains(target)
+    }
+  }
+
+  // The 8 values of block blockNo, or None if it is malformed
+  private def block(blockNo: Int): Option[Array[Long]] = blockPos(blockNo).flatMap { pos =>
+    val sink = new BlockSink
+    unpack8(new UnsafeBuffer(buf, pos, footerOffset - pos), sink) match {
+      case Ok => Some(sink.values)
+      case _  => None
+    }
+  }
+
+  private final class BlockSink extends Sink {
+    val values = new Array[Long](8)
+    final def process(data: Array[Long]): Unit = System.arraycopy(data, 0, values, 0, 8)
+  }
+
+  private final class ElementSink(n: Int) extends Sink {
+    var value = 0L
+    final def process(data: Array[Long]): Unit = { value = data(n) }
+  }
+}
+
+object CompressedVec {
+  import NibblePack.{pack8With, packRemainder, tempArray, InputTooShort, InvalidHeader, NibbleError, Ok}
+
+  val DefaultBlocksPerSkip = 16
+  // The positions of the header fields, see the layout above.  numValues is where peekCount expects it.
+  val NumValuesOffset = NibbleFormat.CountOffset
+  val BlocksPerSkipOffset = 5
+  val FooterOffsetOffset = 7
+  val HeaderBytes = 11
+
+  /**
+   * Packs the input into a CompressedVec, recording the offset of every blocksPerSkip-th block in the footer.
+   * Larger values of blocksPerSkip make the footer smaller, at the cost of more blocks to skip over in get().
+   * @return the final position within the buffer after packing
+   */
+  final def encode(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int,
+                   blocksPerSkip: Int = DefaultBlocksPerSkip): Int = {
+    require(blocksPerSkip > 0 && blocksPerSkip <= Short.MaxValue, s"Invalid blocksPerSkip $blocksPerSkip")
+    val numBlocks = (input.size + 7) / 8
+    val skipOffsets = new Array[Int]((numBlocks + blocksPerSkip - 1) / blocksPerSkip)
+    val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
+    var pos = bufindex + HeaderBytes
+    var i = 0
+    while (i < input.size) {
+      if (i % (8 * blocksPerSkip) == 0) skipOffsets(i / (8 * blocksPerSkip)) = pos - bufindex
+      inputArray(i % 8) = input(i)
+      i += 1
+      if (i % 8 == 0) pos = pack8With(inputArray, buf, pos, writer)
+    }
+    pos = packRemainder(inputArray, buf, pos, i, writer)
+
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_Skip_Table))
+    buf.putInt(bufindex + NumValuesOffset, input.size, LITTLE_ENDIAN)
+    buf.putShort(bufindex + BlocksPerSkipOffset, blocksPerSkip.toShort, LITTLE_ENDIAN)
+    buf.putInt(bufindex + FooterOffsetOffset, pos - bufindex, LITTLE_ENDIAN)
+    skipOffsets.foreach { offset =>
+      buf.putInt(pos, offset, LITTLE_ENDIAN)
+      pos += 4
+    }
+    pos
+  }
+
+  /**
+   * Wraps a buffer starting with a CompressedVec, after checking that the header and footer fit in it.
+   */
+  final def apply(buf: DirectBuffer): Either[NibbleError, CompressedVec] = {
+    if (buf.capacity < HeaderBytes) return Left(InputTooShort(HeaderBytes, buf.capacity))
+    NibbleFormat.checkFormat(new UnsafeBuffer(buf, 0, buf.capacity), NibbleFormat.Format_Skip_Table) match {
+      case Ok =>
+        val numValues = buf.getInt(NumValuesOffset, LITTLE_ENDIAN)
+        val blocksPerSkip = buf.getShort(BlocksPerSkipOffset, LITTLE_ENDIAN).toInt
+        val footerOffset = buf.getInt(FooterOffsetOffset, LITTLE_ENDIAN)
+        if (numValues < 0) {
+          Left(InvalidHeader("numValues", numValues))
+        } else if (blocksPerSkip <= 0) {
+          Left(InvalidHeader("blocksPerSkip", blocksPerSkip))
+        } else if (footerOffset < HeaderBytes) {
+          Left(InvalidHeader("footerOffset", footerOffset))
+        } else {
+          val numSkips = ((numValues.toLong + 7) / 8 + blocksPerSkip - 1) / blocksPerSkip
+          val totalBytes = footerOffset.toLong + 4L * numSkips
+          if (totalBytes > buf.capacity) Left(InputTooShort(Math.min(totalBytes, Int.MaxValue).toInt, buf.capacity))
+          else Right(new CompressedVec(buf))
+        }
+      case e: NibbleError => Left(e)
+    }
+  }
+}
@@ -0,0 +1,70 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import filodb.memory.BinaryRegion
+
+/**
+ * A least recently used cache of unpacked delta vectors, for query plans which unpack the same chunk again and
+ * again.  Entries are found by an XXHash32 of the packed bytes, the number of bytes and numValues.  As different
+ * bytes could hash the same, each entry keeps a copy of its packed bytes to compare against on a hit.
+ * Using a cache is up to the caller; nothing else in NibblePack goes through one.
+ * Thread safe: lookups and evictions are synchronized, while unpacking on a miss is done outside the lock, so
+ * two threads missing on the same bytes may both unpack them.
+ * @param maxEntries the most vectors kept; adding one more evicts the least recently used
+ */
+final class DecodeCache(maxEntries: Int) {
+  import DecodeCache._
+  import NibblePack.NibbleError
+
+  require(maxEntries > 0, s"maxEntries must be positive, not $maxEntries")
+
+  private val entries = new java.util.LinkedHashMap[Key, Entry](16, 0.75f, true) {
+    override def removeEldestEntry(eldest: java.util.Map.Entry[Key, Entry]): Boolean = size > maxEntries
+  }
+  private var numHits = 0L
+  private var numMisses = 0L
+
+  def size: Int = synchronized { entries.size }
+  def hits: Long = synchronized { numHits }
+  def misses: Long = synchronized { numMisses }
+
+  /**
+   * Unpacks numValues deltas like NibblePack.unpackDelta, or returns them from the cache if the same bytes were
+   * unpacked before.  Errors are not cached.
+   * @param compressed a DirectBuffer wrapping exactly the packed bytes.  Not mutated.
+   * @return a new array of the values, which the caller is free to change, or the NibbleError
+   */
+  final def unpackDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Array[Long]] = {
+    val key = Key(BinaryRegion.hash32(compressed.byteArray, compressed.addressOffset, compressed.capacity),
+                  compressed.capacity, numValues)
+    val cached = synchronized {
+      val entry = entries.get(key)
+      val hit = entry != null && sameBytes(compressed, entry.bytes)
+      if (hit) numHits += 1 else numMisses += 1
+      if (hit) Some(entry.values) else None
+    }
+    cached.map(v => Right(v.clone)).getOrElse {
+      NibblePack.unpackDelta(new UnsafeBuffer(compressed, 0, compressed.capacity), numValues).right.map { values =>
+        val bytes = new Array[Byte](compressed.capacity)
+        compressed.getBytes(0, bytes)
+        synchronized { entries.put(key, Entry(bytes, values.clone)) }
+        values
+      }
+    }
+  }
+
+  def clear(): Unit = synchronized { entries.clear() }
+}
+
+object DecodeCache {
+  private final case class Key(hash: Int, numBytes: Int, numValues: Int)
+  private final case class Entry(bytes: Array[Byte], values: Array[Long])
+
+  private def sameBytes(compressed: DirectBuffer, bytes: Array[Byte]): Boolean = {
+    var i = 0
+    while (i < bytes.size && compressed.getByte(i) == bytes(i)) i += 1
+    i == bytes.size
+  }
+}
@@ -0,0 +1,61 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+
+/**
+ * Owns all the state needed for unpacking -- the block scratch array and a growable output array -- so that
+ * callers such as query threads get explicit, testable ownership instead of relying on the thread locals used
+ * by the NibblePack methods.  This matters for code running on pooled threads or across async boundaries, where
+ * a thread local may be shared by unrelated tasks.
+ * A DecodeContext is not thread safe: give each concurrent query or task its own, and reuse it across calls
+ * to avoid allocating output arrays.
+ */
+final class DecodeContext(initialSize: Int = 64) {
+  import NibblePack.{unpackAllToSink, AccumulatorOverflow, NibbleError, Ok}
+
+  private val scratch = new Array[Long](8)
+  private var outArray = new Array[Long](initialSize)
+
+  /**
+   * The output of the last unpack.  Only the first numValues elements are valid, and they are overwritten by
+   * the next unpack with this context.
+   */
+  def values: Array[Long] = outArray
+
+  /**
+   * Unpacks numValues delta-encoded Longs such as those written by NibblePack.packDelta into values, growing it
+   * if needed.  Like NibblePack.unpackDelta, the running total is checked for overflow.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see NibblePack.unpackToSink.
+   * @return the number of values unpacked at the start of values, or the NibbleError, eg InputTooShort if the
+   *         input ends before numValues or ImplausibleCount for a numValues the input could not hold
+   */
+  final def unpackDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Int] =
+    NibbleFormat.checkCount(numValues, compressed.capacity) match {
+      case Some(e) => Left(e)
+      case None =>
+        if (outArray.size < numValues) outArray = new Array[Long](Math.max(numValues, outArray.size * 2))
+        val sink = new NibbleSinks.CheckedDeltaSink(outArray, numValues)
+        unpackAllToSink(compressed, sink, numValues, scratch) match {
+          case Ok if sink.overflowIndex >= 0 => Left(AccumulatorOverflow(sink.overflowIndex))
+          case Ok                            => Right(numValues)
+          case e: NibbleError                => Left(e)
+        }
+    }
+}
+
+object DecodeContext {
+  import NibblePack.NibbleError
+
+  /**
+   * Like NibblePack.unpackDelta, but with a fresh DecodeContext for the call instead of the thread local scratch
+   * array.  The NibblePack methods are not reentrant: a Sink which unpacks another vector on the same thread
+   * overwrites the block it was handed, see NibblePack.unpack8.  This variant is safe to call from inside a Sink,
+   * at the cost of allocating the scratch array every call.
+   * @return a new array of exactly numValues values, or the NibbleError
+   */
+  final def unpackDeltaReentrant(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Array[Long]] = {
+    // Starting from an empty array, unpackDelta grows it to exactly numValues
+    val ctx = new DecodeContext(0)
+    ctx.unpackDelta(compressed, numValues).right.map(_ => ctx.values)
+  }
+}
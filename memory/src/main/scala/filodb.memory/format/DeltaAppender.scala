@@ -0,0 +1,74 @@
+package filodb.memory.format
+
+import org.agrona.MutableDirectBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * Appends values one at a time to a stream written by NibblePack.packDelta, in place, for chunks which grow as
+ * samples arrive.  Only the last block is ever rewritten: each push repacks the partial final block with the new
+ * delta, and once a block has 8 values the next push starts a new one.  After every push the stream holds the same
+ * bytes as packDelta of all the values so far, provided they are increasing.  Like packDelta, a drop is packed
+ * as a zero delta.
+ * Not thread safe, and nothing else should write to the buffer past the start of the last block meanwhile.
+ */
+final class DeltaAppender private(buf: MutableDirectBuffer, private var blockPos: Int, block: Array[Long],
+                                  private var numInBlock: Int, private var last: Long, private var endPos: Int,
+                                  private var count: Int) {
+  import NibblePack.pack8
+
+  // The number of values in the stream, including the ones it held before the appender was created
+  def numValues: Int = count
+
+  // The last value pushed, or the sum of the deltas of the stream the appender was created on
+  def lastValue: Long = last
+
+  // The final position of the stream within the buffer
+  def position: Int = endPos
+
+  /**
+   * Appends a value, rewriting the final block.
+   * @return the final position of the stream within the buffer
+   */
+  final def push(value: Long): Int = {
+    block(numInBlock) = if (value >= last) value - last else 0L
+    last = value
+    numInBlock += 1
+    count += 1
+    // The rest of the block is always zeroes, so it packs like packDelta's padded final block
+    endPos = pack8(block, buf, blockPos)
+    if (numInBlock == 8) {
+      java.util.Arrays.fill(block, 0L)
+      numInBlock = 0
+      blockPos = endPos
+    }
+    endPos
+  }
+}
+
+object DeltaAppender {
+  import NibblePack.NibbleError
+
+  /**
+   * Creates an appender for a stream written by packDelta, reading only the last value and the partial final
+   * block from it.  With numBytes and numValues of 0, it starts a new stream at bufindex.
+   * @param buf the buffer holding the stream.  Highly recommended this be an ExpandableArrayBuffer or equiv.
+   *            so it can grow.
+   * @param numBytes the size of the stream, which starts at bufindex
+   * @param numValues the number of values in the stream
+   * @return the appender, or the NibbleError if the stream does not hold numValues values
+   */
+  final def apply(buf: MutableDirectBuffer, bufindex: Int, numBytes: Int,
+                  numValues: Int): Either[NibbleError, DeltaAppender] = {
+    def existing(start: Int) = new UnsafeBuffer(buf, bufindex + start, numBytes - start)
+    for {
+      _         <- NibbleFormat.checkCount(numValues, numBytes).toLeft(()).right
+      fullBytes <- NibbleSplice.blocksBytes(existing(0), numValues / 8).right
+      last      <- NibbleAggregations.sum(existing(0), numValues).right
+      tail      <- NibbleSplice.rawValues(existing(fullBytes), numValues % 8).right
+    } yield {
+      val block = new Array[Long](8)
+      System.arraycopy(tail, 0, block, 0, tail.size)
+      new DeltaAppender(buf, bufindex + fullBytes, block, tail.size, last, bufindex + numBytes, numValues)
+    }
+  }
+}
@@ -0,0 +1,100 @@
+package filodb.memory.format
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Gorilla-style XOR compression for Double time series such as floating point gauges, see
+ * http://www.vldb.org/pvldb/vol8/p1816-teller.pdf section 4.1.2.
+ * The first value is written verbatim.  Each following value is XORed with the previous one, and then:
+ * - a single 0 bit is written if the XOR is zero (the value repeats)
+ * - otherwise a 1 bit, then a 0 bit and the meaningful bits if the XOR fits in the previous leading/trailing
+ *   zero window, or else a 1 bit, 5 bits of leading zero count, 6 bits of meaningful bit count and the bits.
+ * Values are compared as raw bits, so NaN payloads and infinities round trip exactly.
+ *
+ * Unlike NibblePack.packDoubles, which NibblePacks XORs 8 at a time, this is a bit stream and writes
+ * NibbleFormat.Format_XOR_Double first so it can be decoded without outside context.
+ */
+object DoubleXORPack {
+  import NibblePack.{subslice, InvalidHeader, NibbleError, Ok, UnpackResult}
+
+  /**
+   * Packs the Doubles, writing NibbleFormat.Format_XOR_Double first.
+   * @return the final position within the buffer after packing
+   */
+  final def pack(inputs: Array[Double], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_XOR_Double))
+    val writer = new BitWriter(buf, bufindex + 1)
+    if (inputs.nonEmpty) {
+      var last = java.lang.Double.doubleToRawLongBits(inputs(0))
+      writer.write(last, 64)
+      var prevLeading = -1     // no window yet
+      var prevTrailing = 0
+      for { i <- 1 until inputs.size optimized } {
+        val bits = java.lang.Double.doubleToRawLongBits(inputs(i))
+        val xor = bits ^ last
+        if (xor == 0) {
+          writer.write(0, 1)
+        } else {
+          val leading = Math.min(java.lang.Long.numberOfLeadingZeros(xor), 31)
+          val trailing = java.lang.Long.numberOfTrailingZeros(xor)
+          if (prevLeading >= 0 && leading >= prevLeading && trailing >= prevTrailing) {
+            writer.write(0x2, 2)
+            writer.write(xor >>> prevTrailing, 64 - prevLeading - prevTrailing)
+          } else {
+            val meaningful = 64 - leading - trailing
+            writer.write(0x3, 2)
+            writer.write(leading, 5)
+            writer.write(meaningful & 0x3f, 6)    // 64 meaningful bits is written as 0
+            writer.write(xor >>> trailing, meaningful)
+            prevLeading = leading
+            prevTrailing = trailing
+          }
+        }
+        last = bits
+      }
+    }
+    writer.finish()
+  }
+
+  /**
+   * Unpacks a stream written by pack into outArray, which should be sized to the number of values packed.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values
+   */
+  final def unpack(compressed: DirectBuffer, outArray: Array[Double]): UnpackResult =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_XOR_Double) match {
+      case Ok if outArray.isEmpty => Ok
+      case Ok                     => unpackBits(compressed, outArray)
+      case e: NibbleError         => e
+    }
+
+  private def unpackBits(compressed: DirectBuffer, outArray: Array[Double]): UnpackResult = {
+    val reader = new BitReader(compressed)
+    if (!reader.has(64)) return reader.tooShort(64)
+    var last = reader.read(64)
+    outArray(0) = java.lang.Double.longBitsToDouble(last)
+    var leading = 0
+    var trailing = 0
+    var i = 1
+    while (i < outArray.size) {
+      if (!reader.has(1)) return reader.tooShort(1)
+      if (reader.read(1) != 0) {
+        if (!reader.has(1)) return reader.tooShort(1)
+        if (reader.read(1) != 0) {
+          if (!reader.has(11)) return reader.tooShort(11)
+          leading = reader.read(5).toInt
+          val meaningful = reader.read(6).toInt
+          trailing = 64 - leading - (if (meaningful == 0) 64 else meaningful)
+          if (trailing < 0) return InvalidHeader("meaningfulBits", meaningful)
+        }
+        val numBits = 64 - leading - trailing
+        if (!reader.has(numBits)) return reader.tooShort(numBits)
+        last ^= reader.read(numBits) << trailing
+      }
+      outArray(i) = java.lang.Double.longBitsToDouble(last)
+      i += 1
+    }
+    subslice(compressed, reader.bytesRead)
+    Ok
+  }
+}
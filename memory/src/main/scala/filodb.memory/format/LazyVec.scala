@@ -0,0 +1,77 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * A read-only view with vector semantics over NibblePacked Long values written by NibblePack.packNonIncreasing,
+ * which unpacks blocks only when they are accessed.  Unlike CompressedVec, the stream needs no skip table:
+ * the offset of every block is found by walking the block headers once when the view is created.
+ * The most recently unpacked block is cached, so iterating or reading nearby values unpacks each block once.
+ * The bytes are not copied, so the buffer must not change while the view is in use.  Not thread safe.
+ *
+ * Streams written by packDelta cannot be viewed this way, since each value depends on every delta before it.
+ */
+final class LazyVec private(buf: DirectBuffer, blockOffsets: Array[Int], val length: Int) {
+  import NibblePack.{unpack8, Sink}
+
+  private val block = new UnsafeBuffer(buf, 0, 0)
+  private val cached = new Array[Long](8)
+  private var cachedBlockNo = -1
+  private val sink = new Sink {
+    final def process(data: Array[Long]): Unit = System.arraycopy(data, 0, cached, 0, 8)
+  }
+
+  /**
+   * Returns the value at index idx, or None if idx is out of range.
+   */
+  final def get(idx: Int): Option[Long] = if (idx < 0 || idx >= length) None else Some(valueAt(idx))
+
+  /**
+   * Iterates over all the values in order, unpacking each block once.
+   */
+  final def iterator: Iterator[Long] = Iterator.range(0, length).map(valueAt)
+
+  private def valueAt(idx: Int): Long = {
+    val blockNo = idx / 8
+    if (blockNo != cachedBlockNo) {
+      // The headers were all checked in LazyVec.apply, so the block always unpacks
+      val pos = blockOffsets(blockNo)
+      block.wrap(buf, pos, blockOffsets(blockNo + 1) - pos)
+      unpack8(block, sink)
+      cachedBlockNo = blockNo
+    }
+    cached(idx % 8)
+  }
+}
+
+object LazyVec {
+  import NibblePack.NibbleError
+
+  /**
+   * Creates a view over numValues values, after checking that the header of every block they need parses and
+   * that the blocks fit in the buffer.
+   * @param buf a buffer starting with the first block, eg the output of NibblePack.packNonIncreasing
+   * @return the view, or the error from the first bad block, see NibbleBlocks.parse
+   */
+  final def apply(buf: DirectBuffer, numValues: Int): Either[NibbleError, LazyVec] =
+    NibbleFormat.checkCount(numValues, buf.capacity).toLeft(()).right.flatMap { _ =>
+      val numBlocks = (numValues + 7) / 8
+      // One more offset than blocks, for the end of the last block
+      val blockOffsets = new Array[Int](numBlocks + 1)
+      var error: Option[NibbleError] = None
+      var b = 0
+      while (b < numBlocks && error.isEmpty) {
+        if (blockOffsets(b) >= buf.capacity) {
+          error = Some(NibblePack.InputTooShort(1, 0))
+        } else {
+          NibbleBlocks.parse(buf, blockOffsets(b)) match {
+            case Right(info) => blockOffsets(b + 1) = info.byteOffset + info.numBytes
+                                b += 1
+            case Left(e)     => error = Some(e)
+          }
+        }
+      }
+      error.toLeft(new LazyVec(buf, blockOffsets, numValues))
+    }
+}
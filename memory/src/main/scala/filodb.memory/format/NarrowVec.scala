@@ -0,0 +1,68 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import scalaxy.loops._
+
+import filodb.memory.format.NibblePack.NibbleError
+
+/**
+ * Unpacked non-negative Longs kept in the narrowest unsigned width which holds all of them, for downstream
+ * columns of small values which would waste most of an Array[Long].  The JVM has no unsigned types, so each
+ * array element holds the low bits of its value, and apply gives the value back as a Long.
+ */
+sealed trait NarrowVec {
+  def size: Int
+  def apply(i: Int): Long
+}
+
+object NarrowVec {
+  final case class U8(values: Array[Byte]) extends NarrowVec {
+    def size: Int = values.size
+    def apply(i: Int): Long = values(i) & 0xffL
+  }
+
+  final case class U16(values: Array[Short]) extends NarrowVec {
+    def size: Int = values.size
+    def apply(i: Int): Long = values(i) & 0xffffL
+  }
+
+  final case class U32(values: Array[Int]) extends NarrowVec {
+    def size: Int = values.size
+    def apply(i: Int): Long = values(i) & 0xffffffffL
+  }
+
+  final case class U64(values: Array[Long]) extends NarrowVec {
+    def size: Int = values.size
+    def apply(i: Int): Long = values(i)
+  }
+
+  /**
+   * Unpacks numValues values from a packDelta stream like NibblePack.unpackDelta, then narrows them to the
+   * smallest of U8, U16, U32 and U64 which fits the largest value.  The Longs are unpacked first, so this saves
+   * memory in what is kept rather than while unpacking.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see NibblePack.unpackToSink.
+   * @return the values, or the NibbleError as from unpackDelta
+   */
+  final def unpackNarrowest(compressed: DirectBuffer, numValues: Int): Either[NibbleError, NarrowVec] =
+    NibblePack.unpackDelta(compressed, numValues).right.map(narrow)
+
+  /**
+   * Narrows non-negative values to the smallest width which fits the largest of them.  Negative values only fit
+   * in U64.
+   */
+  final def narrow(values: Array[Long]): NarrowVec = {
+    var max = 0L
+    for { i <- 0 until values.size optimized } {
+      max = if (values(i) < 0 || max < 0) -1L else Math.max(max, values(i))
+    }
+    if (max < 0 || max > 0xffffffffL) {
+      U64(values)
+    } else if (max > 0xffffL) {
+      U32(values.map(_.toInt))
+    } else if (max > 0xffL) {
+      U16(values.map(_.toShort))
+    } else {
+      U8(values.map(_.toByte))
+    }
+  }
+}
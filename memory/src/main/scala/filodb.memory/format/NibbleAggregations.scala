@@ -0,0 +1,224 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import scalaxy.loops._
+
+/**
+ * Common reductions computed directly over NibblePacked data, unpacking one block of 8 at a time and folding
+ * the values without materializing them in an output array.
+ * The plain versions work on values packed as is (packNonIncreasing), and the Delta versions on streams written
+ * by packDelta, folding over the reconstructed values rather than the deltas.
+ * The input must hold all numValues values, see NibblePack.unpackAllToSink.
+ * For zero values, sum returns 0, min Long.MaxValue and max Long.MinValue.
+ * sum wraps around like Long addition; sumSaturating and sumWide are for totals which may not fit in a Long.
+ * NOTE: the compressed buffer is mutated to wrap the bytes after the unpacked values, as in unpackToSink.
+ */
+object NibbleAggregations {
+  import NibblePack.{unpackAllToSink, InvalidParameter, NibbleError, Ok}
+  import NibbleSinks.BoundedSink
+
+  final def sum(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    fold(compressed, numValues, false, 0L, add)
+  final def min(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    fold(compressed, numValues, false, Long.MaxValue, minOf)
+  final def max(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    fold(compressed, numValues, false, Long.MinValue, maxOf)
+
+  final def sumDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    fold(compressed, numValues, true, 0L, add)
+  final def minDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    fold(compressed, numValues, true, Long.MaxValue, minOf)
+  final def maxDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    fold(compressed, numValues, true, Long.MinValue, maxOf)
+
+  /**
+   * Folds f over the values from left to right, starting from init, for reductions other than the ones here.  The
+   * sum, min and max above are folds too.  As for those, one block is unpacked at a time, and no array of the
+   * values is allocated.
+   * @return the final result of f, or the error from unpacking
+   */
+  final def foldLeft[B](compressed: DirectBuffer, numValues: Int, init: B)
+                       (f: (B, Long) => B): Either[NibbleError, B] =
+    run(compressed, numValues, new FoldSink(numValues, false, init, f)).right.map(_.result)
+  final def foldLeftDelta[B](compressed: DirectBuffer, numValues: Int, init: B)
+                            (f: (B, Long) => B): Either[NibbleError, B] =
+    run(compressed, numValues, new FoldSink(numValues, true, init, f)).right.map(_.result)
+
+  /**
+   * Like sum, but a total beyond the range of a Long is capped at Long.MaxValue (or Long.MinValue) instead of
+   * wrapping around, eg for counters summed over a long range.  Values after the cap still count, as for a series
+   * of saturating additions.
+   * @return the total, and true if it was capped at any point
+   */
+  final def sumSaturating(compressed: DirectBuffer, numValues: Int): Either[NibbleError, (Long, Boolean)] =
+    run(compressed, numValues, new SaturatingSink(numValues, false)).right.map(s => (s.result, s.saturated))
+  final def sumDeltaSaturating(compressed: DirectBuffer, numValues: Int): Either[NibbleError, (Long, Boolean)] =
+    run(compressed, numValues, new SaturatingSink(numValues, true)).right.map(s => (s.result, s.saturated))
+
+  /**
+   * Like sum, but adds up in 128 bits, so the total of any number of Longs is exact.  The JVM has no 128-bit
+   * integer, so the total is returned as its high and low 64 bits as in NibblePack128, in two's complement.
+   */
+  final def sumWide(compressed: DirectBuffer, numValues: Int): Either[NibbleError, (Long, Long)] =
+    run(compressed, numValues, new WideSink(numValues, false)).right.map(s => (s.hi, s.lo))
+  final def sumDeltaWide(compressed: DirectBuffer, numValues: Int): Either[NibbleError, (Long, Long)] =
+    run(compressed, numValues, new WideSink(numValues, true)).right.map(s => (s.hi, s.lo))
+
+  /**
+   * Counts the distinct values, eg for cardinality estimates, without keeping the unpacked vector.  The count is
+   * exact for up to ExactDistinctLimit distinct values, which are kept in a set.  Past that the set is dropped
+   * and the rest of the count is a HyperLogLog estimate over 2^DistinctPrecision registers, with a standard error
+   * of about 1.04 / sqrt(2^DistinctPrecision), or 1.6%.
+   */
+  final def countDistinct(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    run(compressed, numValues, new DistinctSink(numValues, false)).right.map(_.result)
+  final def countDistinctDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    run(compressed, numValues, new DistinctSink(numValues, true)).right.map(_.result)
+
+  /**
+   * Computes the per-second rate of a counter straight from its two chunks, the core of PromQL rate(): the values
+   * packed by NibblePackSigned.packDelta, which keeps drops so that resets can be found, and their timestamps in
+   * epoch millis, in either format NibbleTimestamps.unpackMillis reads.  A value lower than the one before it is
+   * taken as a reset to 0, so the value before the reset is added to last - first.  Unlike Prometheus, the rate is
+   * not extrapolated to the edges of the window.
+   * NOTE: both buffers are mutated, see NibblePack.unpackToSink
+   * @return the rate, NaN for fewer than 2 samples or no time between the first and the last, InvalidParameter if
+   *         the number of timestamps is not numValues, or an error unpacking either chunk
+   */
+  final def rate(valueBuf: DirectBuffer, tsBuf: DirectBuffer, numValues: Int): Either[NibbleError, Double] =
+    for {
+      values     <- counterValues(valueBuf, numValues).right
+      timestamps <- NibbleTimestamps.unpackMillis(tsBuf).right
+      _          <- (if (timestamps.size == numValues) None else Some(InvalidParameter("numValues", numValues)))
+                      .toLeft(()).right
+    } yield {
+      val last = numValues - 1
+      if (numValues < 2 || timestamps(last) == timestamps(0)) {
+        Double.NaN
+      } else {
+        var increase = (values(last) - values(0)).toDouble
+        for { i <- 1 until numValues optimized } {
+          if (values(i) < values(i - 1)) increase += values(i - 1)
+        }
+        increase / ((timestamps(last) - timestamps(0)) / 1000.0)
+      }
+    }
+
+  private def counterValues(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkCount(numValues, compressed.capacity).toLeft(new Array[Long](numValues)).right.flatMap { out =>
+      val res = NibbleFormat.checkFormat(compressed, NibbleFormat.Format_ZigZag_Delta) match {
+        case Ok             => unpackAllToSink(compressed, NibblePackSigned.ZigZagDeltaSink(out), numValues)
+        case e: NibbleError => e
+      }
+      res match {
+        case Ok             => Right(out)
+        case e: NibbleError => Left(e)
+      }
+    }
+
+  val ExactDistinctLimit = 4096
+  val DistinctPrecision = 12
+
+  private val add = (a: Long, b: Long) => a + b
+  private val minOf = (a: Long, b: Long) => Math.min(a, b)
+  private val maxOf = (a: Long, b: Long) => Math.max(a, b)
+
+  private def fold(compressed: DirectBuffer, numValues: Int, isDelta: Boolean,
+                   init: Long, func: (Long, Long) => Long): Either[NibbleError, Long] =
+    run(compressed, numValues, new FoldSink[Long](numValues, isDelta, init, func)).right.map(_.result)
+
+  private def run[S <: ValueSink](compressed: DirectBuffer, numValues: Int, sink: S): Either[NibbleError, S] =
+    unpackAllToSink(compressed, sink, numValues) match {
+      case Ok             => Right(sink)
+      case e: NibbleError => Left(e)
+    }
+
+  // Passes each value on to consume, reconstructing the values from the deltas if isDelta
+  private abstract class ValueSink(numValues: Int, isDelta: Boolean) extends BoundedSink(numValues) {
+    private var current = 0L
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        current = if (isDelta) current + data(n) else data(n)
+        consume(current)
+      }
+    protected def consume(value: Long): Unit
+  }
+
+  // Specialized so that the Long folds above do not box their running result
+  private class FoldSink[@specialized(Long) B](numValues: Int, isDelta: Boolean, init: B, func: (B, Long) => B)
+  extends ValueSink(numValues, isDelta) {
+    var result = init
+    protected def consume(value: Long): Unit = { result = func(result, value) }
+  }
+
+  private final class SaturatingSink(numValues: Int, isDelta: Boolean) extends ValueSink(numValues, isDelta) {
+    var result = 0L
+    var saturated = false
+    protected def consume(value: Long): Unit = {
+      val total = result + value
+      // Overflowed if both had the same sign and the total has the other one
+      if (((result ^ total) & (value ^ total)) < 0) {
+        result = if (value > 0) Long.MaxValue else Long.MinValue
+        saturated = true
+      } else {
+        result = total
+      }
+    }
+  }
+
+  private final class WideSink(numValues: Int, isDelta: Boolean) extends ValueSink(numValues, isDelta) {
+    var hi = 0L
+    var lo = 0L
+    protected def consume(value: Long): Unit = {
+      val newLo = lo + value
+      // The sign extension of value, plus the carry out of the low 64 bits added as unsigned
+      hi += (value >> 63) + (if (java.lang.Long.compareUnsigned(newLo, lo) < 0) 1 else 0)
+      lo = newLo
+    }
+  }
+
+  private final class DistinctSink(numValues: Int, isDelta: Boolean) extends ValueSink(numValues, isDelta) {
+    private val exact = debox.Set.empty[Long]
+    private var isExact = true
+    private val registers = new Array[Byte](1 << DistinctPrecision)
+    protected def consume(value: Long): Unit =
+      if (!isExact) {
+        addRegister(value)
+      } else {
+        exact.add(value)
+        if (exact.size > ExactDistinctLimit) {
+          exact.foreach(addRegister)
+          isExact = false
+        }
+      }
+
+    // The top bits of the hash pick a register, which keeps the most leading zeroes seen in the rest plus one
+    private def addRegister(value: Long): Unit = {
+      val hash = mix64(value)
+      val index = (hash >>> (64 - DistinctPrecision)).toInt
+      val rank = Math.min(java.lang.Long.numberOfLeadingZeros(hash << DistinctPrecision), 64 - DistinctPrecision) + 1
+      if (rank > registers(index)) registers(index) = rank.toByte
+    }
+
+    def result: Long = if (isExact) exact.size.toLong else {
+      val m = registers.size.toDouble
+      var sum = 0.0
+      var zeroes = 0
+      for { i <- 0 until registers.size optimized } {
+        sum += Math.pow(2.0, -registers(i))
+        if (registers(i) == 0) zeroes += 1
+      }
+      val estimate = 0.7213 / (1 + 1.079 / m) * m * m / sum
+      // Linear counting is more accurate while many registers are still empty
+      Math.round(if (estimate <= 2.5 * m && zeroes > 0) m * Math.log(m / zeroes) else estimate)
+    }
+  }
+
+  // The MurmurHash3 finalizer, so that nearby values land in unrelated registers
+  private def mix64(value: Long): Long = {
+    var h = value
+    h = (h ^ (h >>> 33)) * 0xff51afd7ed558ccdL
+    h = (h ^ (h >>> 33)) * 0xc4ceb9fe1a85ec53L
+    h ^ (h >>> 33)
+  }
+}
@@ -0,0 +1,143 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+import filodb.memory.format.vectors.{BinaryHistogram, Histogram, HistogramBuckets, LongHistogram}
+
+/**
+ * One decode entry point for any self-describing stream, for tooling which is handed buffers without being told
+ * what is in them.  The format code at the start of the stream picks the decoder, and so the kind of values:
+ * Longs, Doubles, Booleans or a histogram.  Histograms are written with packHistogram, which puts
+ * NibbleFormat.Format_Histogram in front of a BinaryHistogram so that they have a format code like every other
+ * stream.  Arithmetic sequences are returned as the lazy ArithmeticSeq rather than unpacked, as a header of a few
+ * bytes can declare hundreds of millions of values.
+ *
+ * Only streams which record their count can be decoded on their own.  Raw streams, eg from NibblePack.packDelta,
+ * and formats without a count or with other inputs, such as Format_ZigZag_Delta, Format_U32, Format_XOR_Double,
+ * Format_Batch or Format_Patch, are refused with UnexpectedFormat.
+ */
+object NibbleAny {
+  import NibbleFormat._
+  import NibblePack.{subslice, InputTooShort, InvalidHeader, NibbleError, Ok, UnexpectedFormat}
+  import BinaryHistogram.BinHistogramFormat
+
+  // The values of a stream, by their kind
+  sealed trait Decoded
+  object Decoded {
+    final case class Longs(values: Array[Long]) extends Decoded
+    final case class Arithmetic(seq: NibbleArithmetic.ArithmeticSeq) extends Decoded
+    final case class Doubles(values: Array[Double]) extends Decoded
+    final case class Bools(values: Array[Boolean]) extends Decoded
+    final case class Hist(histogram: Histogram) extends Decoded
+  }
+  import Decoded._
+
+  // A BinaryHistogram geometric bucket definition: the number of buckets, the first bucket and the multiplier
+  private val GeometricDefBytes = 18
+
+  /**
+   * Writes Format_Histogram, then the buckets and values as BinaryHistogram.writeDelta.
+   * @return the final position within the buffer after writing
+   */
+  final def packHistogram(buckets: HistogramBuckets, values: Array[Long],
+                          buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val pos = putFormat(buf, bufindex, Format_Histogram)
+    val hist = new ExpandableArrayBuffer()
+    val numBytes = BinaryHistogram.writeDelta(buckets, values, hist)
+    buf.putBytes(pos, hist, 0, numBytes)
+    pos + numBytes
+  }
+
+  /**
+   * Decodes any stream which records its count, with the decoder its format code names.  The buffer is not
+   * mutated.
+   * @return the values, or UnexpectedFormat for a stream which cannot be decoded on its own, see above, or the
+   *         error from its decoder
+   */
+  final def decode(compressed: DirectBuffer): Either[NibbleError, Decoded] = {
+    val view = new UnsafeBuffer(compressed, 0, compressed.capacity)
+    formatCode(view).right.flatMap {
+      case Format_Arithmetic    => NibbleArithmetic.read(view).right.map(Arithmetic)
+      case Format_Skip_Table    => skipTable(view).right.map(Longs)
+      case Format_Delta_Counted => NibblePack.unpackDeltaCounted(view).right.map(Longs)
+      case Format_ZigZag_DoD    => NibblePackSigned.unpackDeltaOfDelta(view).right.map(Longs)
+      case Format_Bool_Runs     => BitVec.unpackBools(view).right.map(Bools)
+      case Format_FOR           => NibbleFOR.unpackFOR(view).right.map(Longs)
+      case Format_Delta_Runs    => NibbleRuns.unpackDeltaRuns(view).right.map(Longs)
+      case Format_Delta_Chunks  => NibbleChunks.unpackDeltaChunks(view).right.map(Longs)
+      case Format_Delta_Blocks  => NibbleBlockSize.unpackDelta(view).right.map(Longs)
+      case Format_Sparse_Double => SparseDoublePack.unpack(view).right.map(Doubles)
+      case Format_Delta_Fixed   => NibbleFixedWidth.unpackDelta(view).right.map(Longs)
+      case Format_Delta_Hybrid  => NibbleHybridDelta.unpackDelta(view).right.map(Longs)
+      case Format_Scaled_Double => NibbleScaled.unpackScaled(view).right.map(Doubles)
+      case Format_Delta_Clamped => NibbleClamped.unpackOriginal(view).right.map(Longs)
+      case Format_Histogram     => histogram(view).right.map(Hist)
+      case other                => Left(UnexpectedFormat(other))
+    }
+  }
+
+  // The format code of the stream, from its second byte for an extended format
+  private[format] def formatCode(compressed: DirectBuffer): Either[NibbleError, Byte] =
+    if (compressed.capacity < 1) Left(InputTooShort(1, 0))
+    else if (formatOf(compressed) != Format_Extended) Right(formatOf(compressed))
+    else if (compressed.capacity < 2) Left(InputTooShort(2, compressed.capacity))
+    else Right(compressed.getByte(1))
+
+  // The values of a CompressedVec are packed as is between its header and footer
+  private def skipTable(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    CompressedVec(compressed).right.flatMap { vec =>
+      val footerOffset = compressed.getInt(CompressedVec.FooterOffsetOffset, LITTLE_ENDIAN)
+      val blocks = new UnsafeBuffer(compressed, CompressedVec.HeaderBytes, footerOffset - CompressedVec.HeaderBytes)
+      NibbleSplice.rawValues(blocks, vec.numValues)
+    }
+
+  // Copies the BinaryHistogram after the format code into an array, as bucket definitions are read from one
+  private def histogram(compressed: DirectBuffer): Either[NibbleError, Histogram] =
+    checkFormat(compressed, Format_Histogram) match {
+      case Ok =>
+        BinaryHistogram.readFormat(compressed).right.flatMap {
+          case BinHistogramFormat.Null => Right(Histogram.empty)
+          case format =>
+            if (compressed.capacity < 5) {
+              Left(InputTooShort(5, compressed.capacity))
+            } else {
+              val totalLength = (compressed.getShort(0, LITTLE_ENDIAN) & 0xffff) + 2
+              val defBytes = compressed.getShort(3, LITTLE_ENDIAN) & 0xffff
+              if (compressed.capacity < totalLength) {
+                Left(InputTooShort(totalLength, compressed.capacity))
+              } else if (5 + defBytes > totalLength) {
+                Left(InvalidHeader("totalLength", totalLength))
+              } else {
+                val bytes = new Array[Byte](totalLength)
+                compressed.getBytes(0, bytes)
+                subslice(compressed, totalLength)
+                histogramValues(BinaryHistogram.BinHistogram(new UnsafeBuffer(bytes)), format)
+              }
+            }
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  private def histogramValues(hist: BinaryHistogram.BinHistogram,
+                              format: BinHistogramFormat): Either[NibbleError, Histogram] = {
+    val bytes = hist.buf.byteArray
+    val buckets: Either[NibbleError, HistogramBuckets] = format match {
+      case BinHistogramFormat.GeometricDelta if hist.bucketDefNumBytes >= GeometricDefBytes =>
+        Right(HistogramBuckets.geometric(bytes, hist.bucketDefOffset, false))
+      case BinHistogramFormat.Geometric1Delta if hist.bucketDefNumBytes >= GeometricDefBytes =>
+        Right(HistogramBuckets.geometric(bytes, hist.bucketDefOffset, true))
+      case BinHistogramFormat.CustomDelta if hist.bucketDefNumBytes >= 2 =>
+        scala.util.Try(HistogramBuckets.custom(bytes, hist.bucketDefOffset - 2)).toOption
+          .toRight(InvalidHeader("bucketDefNumBytes", hist.bucketDefNumBytes))
+      case _ =>
+        Left(InvalidHeader("bucketDefNumBytes", hist.bucketDefNumBytes))
+    }
+    buckets.right.flatMap { b =>
+      val values = new UnsafeBuffer(bytes, hist.valuesIndex, hist.valuesNumBytes)
+      NibblePack.unpackDelta(values, b.numBuckets).right.map(LongHistogram(b, _))
+    }
+  }
+}
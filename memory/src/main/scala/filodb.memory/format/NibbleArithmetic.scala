@@ -0,0 +1,123 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Arithmetic sequences, where every delta is the same, such as timestamps at a fixed interval.  These are stored
+ * as just the first value, the step and the count: a few bytes however long the sequence is, less than even the
+ * regular case of delta-of-delta encoding as the values are not packed at all.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Arithmetic
+ *   +1   numValues, Int
+ *   +5   widths: the number of bytes of the base in the low nibble and of the step in the high nibble, 0 to 8
+ *   +6   the base (first value) then the step, each ZigZag encoded in just the little endian bytes it needs
+ * }}}
+ */
+object NibbleArithmetic {
+  import NibbleFormat.{checkAvailable, CountOffset, CountedHeaderBytes, Format_Arithmetic, MaxDecodeValues}
+  import NibblePack.{subslice, ImplausibleCount, InvalidHeader, NibbleError, Ok}
+  import NibblePackSigned.{unzigzag, zigzag}
+
+  val WidthsOffset = CountedHeaderBytes
+  val HeaderBytes = WidthsOffset + 1
+
+  /**
+   * The values base, base + step, base + 2 * step and so on, numValues of them.  Computed on demand, so that a
+   * sequence of any length takes no memory until toArray.  Like the deltas of any Longs, the values wrap around
+   * past Long.MaxValue or Long.MinValue, and so come out exactly as detected.
+   */
+  final case class ArithmeticSeq(base: Long, step: Long, numValues: Int) {
+    require(numValues >= 0)
+
+    def apply(index: Int): Long = base + index * step
+
+    def toArray: Array[Long] = {
+      val out = new Array[Long](numValues)
+      for { i <- 0 until numValues optimized } { out(i) = base + i * step }
+      out
+    }
+  }
+
+  /**
+   * Returns the sequence of the input if all its deltas are equal, including any input of fewer than 3 values.
+   */
+  final def detect(input: Array[Long]): Option[ArithmeticSeq] = {
+    val step = if (input.size > 1) input(1) - input(0) else 0L
+    var i = 2
+    while (i < input.size && input(i) - input(i - 1) == step) i += 1
+    if (i >= input.size) Some(ArithmeticSeq(if (input.size > 0) input(0) else 0L, step, input.size)) else None
+  }
+
+  /**
+   * Packs a sequence, writing NibbleFormat.Format_Arithmetic first.
+   * @return the final position within the buffer after packing, at most HeaderBytes + 16 past bufindex
+   */
+  final def pack(seq: ArithmeticSeq, buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val baseBytes = numBytes(zigzag(seq.base))
+    val stepBytes = numBytes(zigzag(seq.step))
+    buf.putByte(bufindex, NibbleFormat.versioned(Format_Arithmetic))
+    buf.putInt(bufindex + CountOffset, seq.numValues, LITTLE_ENDIAN)
+    buf.putByte(bufindex + WidthsOffset, (baseBytes | (stepBytes << 4)).toByte)
+    putBytes(zigzag(seq.base), baseBytes, buf, bufindex + HeaderBytes)
+    putBytes(zigzag(seq.step), stepBytes, buf, bufindex + HeaderBytes + baseBytes)
+    bufindex + HeaderBytes + baseBytes + stepBytes
+  }
+
+  /**
+   * Reads the sequence of a stream written by pack, without expanding it.
+   * @param compressed NOTE: mutated to wrap the bytes after the sequence, see NibblePack.unpackToSink
+   */
+  final def read(compressed: DirectBuffer): Either[NibbleError, ArithmeticSeq] =
+    NibbleFormat.checkFormat(compressed, Format_Arithmetic) match {
+      case Ok => checkAvailable(compressed, HeaderBytes - 1) match {
+        case Ok =>
+          val numValues = compressed.getInt(CountOffset - 1, LITTLE_ENDIAN)
+          val widths = compressed.getByte(WidthsOffset - 1) & 0x00ff
+          val baseBytes = widths & 0x0f
+          val stepBytes = widths >> 4
+          if (numValues < 0) {
+            Left(InvalidHeader("numValues", numValues))
+          } else if (baseBytes > 8 || stepBytes > 8) {
+            Left(InvalidHeader("widths", widths))
+          } else {
+            checkAvailable(compressed, HeaderBytes - 1 + baseBytes + stepBytes) match {
+              case Ok =>
+                val base = unzigzag(getBytes(compressed, HeaderBytes - 1, baseBytes))
+                val step = unzigzag(getBytes(compressed, HeaderBytes - 1 + baseBytes, stepBytes))
+                subslice(compressed, HeaderBytes - 1 + baseBytes + stepBytes)
+                Right(ArithmeticSeq(base, step, numValues))
+              case e: NibbleError => Left(e)
+            }
+          }
+        case e: NibbleError => Left(e)
+      }
+      case e: NibbleError => Left(e)
+    }
+
+  /**
+   * Expands a stream written by pack into all of its values.  As the stream is the same size for any count, the
+   * count is checked against NibbleFormat.MaxDecodeValues rather than against the size of the input.
+   * @param compressed NOTE: mutated to wrap the bytes after the sequence, see NibblePack.unpackToSink
+   */
+  final def unpack(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    read(compressed).right.flatMap { seq =>
+      if (seq.numValues > MaxDecodeValues) Left(ImplausibleCount(seq.numValues, MaxDecodeValues))
+      else Right(seq.toArray)
+    }
+
+  private def numBytes(value: Long): Int = (64 - java.lang.Long.numberOfLeadingZeros(value) + 7) / 8
+
+  private def putBytes(value: Long, numBytes: Int, buf: MutableDirectBuffer, index: Int): Unit =
+    for { b <- 0 until numBytes optimized } { buf.putByte(index + b, (value >>> (b * 8)).toByte) }
+
+  private def getBytes(buf: DirectBuffer, index: Int, numBytes: Int): Long = {
+    var value = 0L
+    for { b <- 0 until numBytes optimized } { value |= (buf.getByte(index + b) & 0x00ffL) << (b * 8) }
+    value
+  }
+}
@@ -0,0 +1,74 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Unpacks straight into the buffer layout of an Apache Arrow fixed width vector, such as a UInt8Vector or
+ * BigIntVector, for handing vectors to Arrow based query engines without an intermediate Long array.  Arrow
+ * itself is not a dependency: allocate the vector with at least valueBufferBytes and validityBufferBytes, wrap
+ * the memory addresses of its data and validity buffers in UnsafeBuffers, unpack, then set the value count.
+ * See https://arrow.apache.org/docs/format/Columnar.html#fixed-size-primitive-layout
+ */
+object NibbleArrow {
+  import NibblePack.{unpackAllToSink, AccumulatorOverflow, NibbleError, Ok, OutputTooSmall}
+
+  // Arrow recommends padding buffers to 64 bytes
+  val Alignment = 64
+
+  def valueBufferBytes(numValues: Int): Int = padded(numValues * 8)
+  def validityBufferBytes(numValues: Int): Int = padded((numValues + 7) / 8)
+
+  /**
+   * Unpacks numValues delta-encoded Longs such as those written by NibblePack.packDelta into values, as
+   * little-endian 64-bit values, and marks them all valid in validity since these vectors have no nulls.  Like
+   * NibblePack.unpackDelta, the running total is checked for overflow, and it is an error for the input to end
+   * before numValues.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see NibblePack.unpackToSink.
+   * @return the number of values written, OutputTooSmall with the number of bytes needed in either buffer, or
+   *         InputTooShort if the input ends early
+   */
+  final def unpackDeltaToArrow(compressed: DirectBuffer, numValues: Int,
+                               values: MutableDirectBuffer,
+                               validity: MutableDirectBuffer): Either[NibbleError, Int] =
+    if (values.capacity < numValues.toLong * 8) {
+      Left(OutputTooSmall(numValues.toLong * 8, values.capacity))
+    } else if (validity.capacity < (numValues + 7) / 8) {
+      Left(OutputTooSmall((numValues + 7) / 8, validity.capacity))
+    } else {
+      val sink = new ArrowDeltaSink(values, numValues)
+      unpackAllToSink(compressed, sink, numValues) match {
+        case Ok if sink.overflowIndex >= 0 => Left(AccumulatorOverflow(sink.overflowIndex))
+        case Ok =>
+          setAllValid(validity, numValues)
+          Right(numValues)
+        case e: NibbleError => Left(e)
+      }
+    }
+
+  private def padded(numBytes: Int): Int = (numBytes + Alignment - 1) / Alignment * Alignment
+
+  // Sets the first numValues bits, least significant bit first, and clears the rest of the last byte
+  private def setAllValid(validity: MutableDirectBuffer, numValues: Int): Unit = {
+    validity.setMemory(0, numValues / 8, 0xff.toByte)
+    if (numValues % 8 != 0) validity.putByte(numValues / 8, ((1 << (numValues % 8)) - 1).toByte)
+  }
+
+  // Adds up deltas like NibbleSinks.CheckedDeltaSink, writing the totals into an Arrow data buffer
+  private final class ArrowDeltaSink(values: MutableDirectBuffer, numValues: Int)
+  extends NibbleSinks.BoundedSink(numValues) {
+    var overflowIndex = -1
+    private var current = 0L
+    private var i = 0
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        val next = current + data(n)
+        if ((data(n) < 0 || next < 0) && overflowIndex < 0) overflowIndex = i
+        current = next
+        values.putLong(i * 8, current, LITTLE_ENDIAN)
+        i += 1
+      }
+  }
+}
@@ -0,0 +1,110 @@
+package filodb.memory.format
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * Picks the codec for a vector of Longs so callers do not have to: delta encoding (NibblePack.packDeltaCounted)
+ * or frame-of-reference (NibbleFOR), whichever packs a sample from the start of the input smaller.  Delta
+ * encoding is only a candidate for input which never decreases, as it cannot hold drops.  No raw codec is
+ * needed, since frame-of-reference of incompressible values is only 10 bytes per 8 values over raw.
+ * Input whose deltas are all equal is stored as just its base and step (NibbleArithmetic) if that is smaller.
+ * All the codecs write their format code and count, so unpackAuto needs nothing else to decode.
+ */
+object NibbleAuto {
+  import NibbleFormat.{formatOf, ChecksumBytes, ChecksumFlag, Format_Arithmetic, Format_Delta_Counted, Format_FOR}
+  import NibblePack.{InputTooShort, NibbleError, UnexpectedFormat}
+
+  // The number of values from the start of the input which are packed with each codec to compare them
+  val SampleValues = 1024
+
+  /**
+   * Packs the values with the codec chosen by chooseFormat.
+   * @return the final position within the buffer after packing
+   */
+  final def packAuto(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int =
+    chooseFormat(input) match {
+      case Format_Arithmetic    => NibbleArithmetic.pack(NibbleArithmetic.detect(input).get, buf, bufindex)
+      case Format_Delta_Counted => NibblePack.packDeltaCounted(input, buf, bufindex)
+      case _                    => NibbleFOR.packFOR(input, buf, bufindex)
+    }
+
+  /**
+   * Returns the format code of the codec which packs the first SampleValues values smallest, preferring delta
+   * encoding on a tie.  The whole input is checked for drops, but only the sample is packed.  An arithmetic
+   * sequence packs to the same size however long it is, so it is chosen if that is no more than the winner's
+   * sample size.
+   */
+  final def chooseFormat(input: Array[Long]): Byte = {
+    val sample = if (input.size <= SampleValues) input else java.util.Arrays.copyOf(input, SampleValues)
+    val scratch = new ExpandableArrayBuffer(sample.size * 10 + NibbleFOR.HeaderBytes)
+    val deltaBytes = if (isIncreasing(input)) NibblePack.packDeltaCounted(sample, scratch, 0) else Int.MaxValue
+    val forBytes = NibbleFOR.packFOR(sample, scratch, 0)
+    val arithmeticBytes = NibbleArithmetic.detect(input).map(NibbleArithmetic.pack(_, scratch, 0))
+    if (arithmeticBytes.exists(_ <= Math.min(deltaBytes, forBytes))) Format_Arithmetic
+    else if (forBytes < deltaBytes) Format_FOR
+    else Format_Delta_Counted
+  }
+
+  // True if packDelta can hold the input exactly: no value is negative or lower than the one before it
+  private def isIncreasing(input: Array[Long]): Boolean = {
+    var last = 0L
+    var i = 0
+    while (i < input.size && input(i) >= last) {
+      last = input(i)
+      i += 1
+    }
+    i == input.size
+  }
+
+  /**
+   * Unpacks a stream written by packAuto, with the codec named by its format code.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   * @return the values, or UnexpectedFormat for a stream packAuto could not have written
+   */
+  final def unpackAuto(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    if (compressed.capacity < 1) {
+      Left(InputTooShort(1, 0))
+    } else {
+      formatOf(compressed) match {
+        case Format_Arithmetic    => NibbleArithmetic.unpack(compressed)
+        case Format_Delta_Counted => NibblePack.unpackDeltaCounted(compressed)
+        case Format_FOR           => NibbleFOR.unpackFOR(compressed)
+        case other                => Left(UnexpectedFormat(other))
+      }
+    }
+
+  /**
+   * Tells whether two streams written by packAuto hold the same values, eg to dedupe identical chunks in
+   * content-addressed storage.  Streams with the same format code are compared byte for byte without unpacking,
+   * leaving out the version and the checksum of a stream which has one.  Streams with different formats are
+   * unpacked with unpackAuto and their values compared.  Neither buffer is mutated.
+   * NOTE: the byte comparison only guarantees that equal bytes mean equal values.  The same values can be packed
+   * into different bytes, eg by an older encoder which did not write constant blocks, and then compare unequal.
+   * Any trailers after the values are compared along with them, see NibbleTrailers.
+   * @return Right(true) if the streams are equal, or the error from unpacking either of them
+   */
+  final def encodedEquals(a: DirectBuffer, b: DirectBuffer): Either[NibbleError, Boolean] =
+    if (a.capacity < 1 || b.capacity < 1) {
+      Left(InputTooShort(1, 0))
+    } else if (formatOf(a) == formatOf(b)) {
+      Right(sameBytes(content(a), content(b)))
+    } else {
+      for {
+        aValues <- unpackAuto(new UnsafeBuffer(a, 0, a.capacity)).right
+        bValues <- unpackAuto(new UnsafeBuffer(b, 0, b.capacity)).right
+      } yield java.util.Arrays.equals(aValues, bValues)
+    }
+
+  // The bytes after the format code, leaving out any checksum
+  private def content(compressed: DirectBuffer): DirectBuffer = {
+    val checksumBytes = if ((compressed.getByte(0) & ChecksumFlag) != 0) ChecksumBytes else 0
+    new UnsafeBuffer(compressed, 1, Math.max(compressed.capacity - 1 - checksumBytes, 0))
+  }
+
+  private def sameBytes(a: DirectBuffer, b: DirectBuffer): Boolean = a.capacity == b.capacity && {
+    var i = 0
+    while (i < a.capacity && a.getByte(i) == b.getByte(i)) i += 1
+    i == a.capacity
+  }
+}
@@ -0,0 +1,248 @@
+package filodb.memory.format
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Walks the blocks of a NibblePacked stream, parsing only their headers, for debugging and tooling such as
+ * reporting compression ratios per block or spotting pathological encodings.
+ * The stream must start with the first block, ie positioned after any format code or header of a
+ * self-describing stream.  The buffer is not mutated.
+ * Also works out the size blocks will take before packing them, see packedSize, compares packed sizes with the
+ * entropy of the values, see compressionReport, and renders streams as text, see dump.
+ */
+object NibbleBlocks {
+  import NibbleFormat._
+  import NibblePack.{packDelta, InputTooShort, InvalidHeader, InvalidNibbleWidth, NibbleError, Ok, UnpackResult}
+
+  /**
+   * The header of one packed block of 8 values.
+   * @param byteOffset the position of the block in the stream
+   * @param nibbleWidth the number of nibbles stored per nonzero value, 0 for a block of all zeroes
+   * @param trailingNibbles the number of trailing zero nibbles left out of each nonzero value
+   * @param bitmask the bitmask of nonzero values
+   * @param numValues the number of nonzero values stored in the block
+   * @param numBytes the number of bytes taken up by the block, including its header
+   * @param constant true for a constant block, which stores one value for all 8, see NibblePack.pack8
+   */
+  final case class BlockInfo(byteOffset: Int, nibbleWidth: Int, trailingNibbles: Int, bitmask: Int,
+                             numValues: Int, numBytes: Int, constant: Boolean)
+
+  /**
+   * How well a vector compressed, for operators and tooling.
+   * @param avgNibbleWidth the mean nibbleWidth over all the blocks, see BlockInfo
+   */
+  final case class PackStats(inputBytes: Int, outputBytes: Int, numBlocks: Int, avgNibbleWidth: Double) {
+    def compressionRatio: Double = if (outputBytes == 0) 1.0 else inputBytes.toDouble / outputBytes
+
+    /**
+     * True if the blocks are on average close to the full 16 nibbles wide, ie the values gained almost nothing
+     * from packing, eg random data or a delta-encoded series which is not actually increasing.
+     * Ingestion can use this to alert on unexpected data, or to store such vectors some other way.
+     */
+    def incompressible: Boolean = numBlocks > 0 && avgNibbleWidth >= IncompressibleNibbleWidth
+  }
+
+  // The mean nibble width from which a vector counts as incompressible, see PackStats.incompressible
+  val IncompressibleNibbleWidth = 14.0
+
+  /**
+   * Packs the input with NibblePack.packDelta, then reads back the block headers it wrote for the PackStats.
+   * The packing itself is not slowed down, and only the headers are read afterwards.
+   * @return the final position within the buffer after packing, and the stats
+   */
+  final def packDeltaStats(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): (Int, PackStats) = {
+    val endPos = packDelta(input, buf, bufindex)
+    (endPos, stats(new UnsafeBuffer(buf, bufindex, endPos - bufindex), input.size))
+  }
+
+  /**
+   * Computes the PackStats for a stream of numValues values, eg from packDelta or packNonIncreasing, from its
+   * block headers.  Blocks which cannot be parsed are left out.
+   */
+  final def stats(compressed: DirectBuffer, numValues: Int): PackStats = {
+    val infos = blocks(compressed).collect { case Right(info) => info }.toBuffer
+    val avgWidth = if (infos.isEmpty) 0.0 else infos.map(_.nibbleWidth).sum.toDouble / infos.size
+    PackStats(numValues * 8, compressed.capacity, infos.size, avgWidth)
+  }
+
+  /**
+   * How close a codec gets to what the values' distribution allows, for research and tuning.
+   * @param rawBytes the size of the values unpacked, 8 bytes each
+   * @param actualBytes the size NibbleAuto.packAuto packs the values to
+   * @param entropyBytes the Shannon entropy of the distribution of the values, ie the least any codec which
+   *                     codes each value on its own could take.  Codecs which use the order of the values, eg
+   *                     deltas or constant blocks, can take less.
+   * @param bitWidths the number of values of each bit width, from 0 (for 0) to 64
+   */
+  final case class CompressionReport(rawBytes: Int, actualBytes: Int, entropyBytes: Double, bitWidths: Seq[Int]) {
+    // How many times larger the packed values are than the entropy bound
+    def overhead: Double = if (entropyBytes == 0.0) Double.PositiveInfinity else actualBytes / entropyBytes
+  }
+
+  /**
+   * Packs the values with NibbleAuto.packAuto and compares the size with their entropy, to judge whether a
+   * different codec could do better.  Counts every distinct value, so it is for diagnostics, not ingestion.
+   */
+  final def compressionReport(values: Array[Long]): CompressionReport = {
+    val actualBytes = NibbleAuto.packAuto(values, new ExpandableArrayBuffer(), 0)
+    val counts = collection.mutable.HashMap.empty[Long, Int].withDefaultValue(0)
+    val bitWidths = new Array[Int](65)
+    values.foreach { v =>
+      counts(v) += 1
+      bitWidths(64 - java.lang.Long.numberOfLeadingZeros(v)) += 1
+    }
+    val entropyBits = counts.values.map { count =>
+      val p = count.toDouble / values.size
+      -count * Math.log(p) / Math.log(2)
+    }.sum
+    CompressionReport(values.size * 8, actualBytes, entropyBits / 8, bitWidths.toSeq)
+  }
+
+  /**
+   * Returns exactly the number of bytes NibblePack.packNonIncreasing will write for the input, working out each
+   * block's nibble width in one pass without writing anything, so that output buffers can be sized up front.
+   * NOTE: a buffer of exactly this size is too small.  Nibbles are written a whole 64-bit word at a time, so
+   * packing needs 8 bytes of room past the end, though they are not part of the packed bytes.
+   */
+  final def packedSize(input: Array[Long]): Int = sizeOf(input, false)
+
+  /**
+   * Returns exactly the number of bytes NibblePack.packDelta will write for the input.  As for packedSize, the
+   * buffer needs 8 bytes more.
+   */
+  final def packedSizeDelta(input: Array[Long]): Int = sizeOf(input, true)
+
+  private def sizeOf(input: Array[Long], isDelta: Boolean): Int = {
+    val block = new Array[Long](8)
+    var size = 0
+    var last = 0L
+    var i = 0
+    while (i < input.size) {
+      block(i % 8) = if (!isDelta) input(i) else if (input(i) >= last) input(i) - last else 0L
+      last = input(i)
+      i += 1
+      if (i % 8 == 0 || i == input.size) {
+        for { j <- (i - 1) % 8 + 1 until 8 optimized } { block(j) = 0L }
+        size += block8Size(block)
+      }
+    }
+    size
+  }
+
+  // The number of bytes NibblePack.pack8 writes for the block of 8 values
+  private def block8Size(block: Array[Long]): Int = {
+    var bitmask = 0
+    var minLeadingZeros = 64
+    var minTrailingZeros = 64
+    for { j <- 0 until 8 optimized } {
+      if (block(j) != 0) bitmask |= 1 << j
+      minLeadingZeros = Math.min(minLeadingZeros, java.lang.Long.numberOfLeadingZeros(block(j)))
+      minTrailingZeros = Math.min(minTrailingZeros, java.lang.Long.numberOfTrailingZeros(block(j)))
+    }
+    if (bitmask == 0) {
+      ZeroBlockBytes
+    } else if (bitmask == AllNonzeroBitmask && block.forall(_ == block(0))) {
+      BlockHeaderBytes + (64 - minLeadingZeros + 7) / 8
+    } else {
+      val numNibbles = 16 - (minLeadingZeros / 4) - (minTrailingZeros / 4)
+      BlockHeaderBytes + (numNibbles * 4 * java.lang.Integer.bitCount(bitmask) + 7) / 8
+    }
+  }
+
+  /**
+   * Returns an iterator over the headers of every block until the end of the buffer.  A block which cannot be
+   * parsed, eg because it is truncated, is returned as an error and ends the iteration.
+   */
+  final def blocks(compressed: DirectBuffer): Iterator[Either[NibbleError, BlockInfo]] =
+    new Iterator[Either[NibbleError, BlockInfo]] {
+      private var pos = 0
+      final def hasNext: Boolean = pos < compressed.capacity
+      final def next(): Either[NibbleError, BlockInfo] = {
+        val info = parse(compressed, pos)
+        pos = info.fold(_ => compressed.capacity, b => b.byteOffset + b.numBytes)
+        info
+      }
+    }
+
+  /**
+   * Parses the header of the block starting at pos, checking it the same way as NibblePack.unpack8.
+   */
+  final def parse(compressed: DirectBuffer, pos: Int): Either[NibbleError, BlockInfo] = {
+    val available = compressed.capacity - pos
+    val bitmask = if (available < ZeroBlockBytes) 0 else compressed.getByte(pos + BitmaskOffset) & 0x0ff
+    if (available < ZeroBlockBytes) {
+      Left(InputTooShort(ZeroBlockBytes, Math.max(available, 0)))
+    } else if (bitmask == 0) {
+      Right(BlockInfo(pos, 0, 0, 0, 0, ZeroBlockBytes, false))
+    } else if (available < BlockHeaderBytes) {
+      Left(InputTooShort(BlockHeaderBytes, available))
+    } else {
+      val header = compressed.getByte(pos + NibbleHeaderOffset) & 0x0ff
+      if (isConstantBlock(bitmask, header)) {
+        val numBytes = BlockHeaderBytes + (header & ConstantBytesMask)
+        if (available < numBytes) Left(InputTooShort(numBytes, available))
+        else Right(BlockInfo(pos, (header & ConstantBytesMask) * 2, 0, bitmask, 8, numBytes, true))
+      } else {
+        val width = nibbleWidth(header)
+        val trailingNibbles = header & TrailingNibblesMask
+        val numValues = java.lang.Integer.bitCount(bitmask)
+        val numBytes = BlockHeaderBytes + (width * 4 * numValues + 7) / 8
+        if (width + trailingNibbles > 16) Left(InvalidNibbleWidth(width + trailingNibbles))
+        else if (available < numBytes) Left(InputTooShort(numBytes, available))
+        else Right(BlockInfo(pos, width, trailingNibbles, bitmask, numValues, numBytes, false))
+      }
+    }
+  }
+
+  /**
+   * Checks a stream written by NibblePack.packDeltaCounted without unpacking any values, eg to accept or refuse
+   * data at ingestion: its format code and version, that its count is expectedValues, and that each block header
+   * is valid and its block fits in the buffer.  Only the headers are read, so this is much cheaper than unpacking.
+   * Unlike the rest of NibbleBlocks, the stream starts with its format code.  The buffer is not mutated.
+   * @return Ok, or the first error found: InvalidHeader("numValues", count) if the count is not expectedValues,
+   *         otherwise the error unpacking would return, eg InputTooShort for a truncated stream
+   */
+  final def validate(compressed: DirectBuffer, expectedValues: Int): UnpackResult =
+    NibblePack.readCount(new UnsafeBuffer(compressed, 0, compressed.capacity)) match {
+      case Left(e)                                 => e
+      case Right(count) if count != expectedValues => InvalidHeader("numValues", count)
+      case Right(count) =>
+        var res: UnpackResult = Ok
+        var pos = CountedHeaderBytes
+        var block = 0
+        while (block * 8 < count && res == Ok) {
+          parse(compressed, pos) match {
+            case Right(info) => pos += info.numBytes
+            case Left(e)     => res = e
+          }
+          block += 1
+        }
+        res
+    }
+
+  /**
+   * Renders a stream of numValues values as text for diagnosing bad data, like a hexdump for NibblePack: one
+   * line per block with its header fields and the values stored in it, ie deltas for a stream written by
+   * packDelta.  Only the first numValues values are shown, as the last block is padded out to 8.
+   * A block which cannot be parsed ends the dump with its error.
+   */
+  final def dump(compressed: DirectBuffer, numValues: Int): String = {
+    val sb = new StringBuilder(s"$numValues values in ${compressed.capacity} bytes\n")
+    sb ++= "offset  bytes  kind    bitmask  width  trailing  values\n"
+    val sink = new NibbleSinks.BufferSink(numValues)
+    blocks(compressed).foreach {
+      case Right(info) =>
+        val start = sink.values.length
+        NibblePack.unpack8(new UnsafeBuffer(compressed, info.byteOffset, info.numBytes), sink)
+        val values = (start until sink.values.length).map(sink.values(_)).mkString(" ")
+        val kind = if (info.constant) "const" else if (info.bitmask == 0) "zeroes" else "packed"
+        sb ++= f"${info.byteOffset}%6d  ${info.numBytes}%5d  $kind%-6s  0x${info.bitmask}%02x     " +
+               f"${info.nibbleWidth}%5d  ${info.trailingNibbles}%8d  $values\n"
+      case Left(err) =>
+        sb ++= s"error: $err\n"
+    }
+    sb.toString
+  }
+}
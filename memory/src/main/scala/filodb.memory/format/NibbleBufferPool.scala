@@ -0,0 +1,63 @@
+package filodb.memory.format
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * A pool of buffers to pack into, so that ingestion encoding many vectors does not allocate a fresh buffer for
+ * each one.  Buffers are checked out by the pack methods, and come back when the PooledBuffer is closed, eg with
+ * try/finally once its bytes have been copied out or written.  Buffers grow as needed and keep their size.
+ * Thread safe: many tasks can check out and close buffers of the same pool.
+ * @param initialCapacity the size in bytes of each new buffer
+ * @param maxPooled the most buffers kept for reuse; buffers closed beyond that are left to the GC
+ */
+class NibbleBufferPool(initialCapacity: Int = 4096, maxPooled: Int = 64) {
+  private val buffers = new collection.mutable.Queue[ExpandableArrayBuffer]()
+
+  def poolSize: Int = synchronized { buffers.length }
+
+  /**
+   * Packs the values with NibblePack.packDelta into a buffer from the pool.
+   */
+  final def packDelta(values: Array[Long]): PooledBuffer = {
+    val buf = checkout()
+    new PooledBuffer(buf, NibblePack.packDelta(values, buf, 0), this)
+  }
+
+  /**
+   * Packs the values with NibblePack.packNonIncreasing into a buffer from the pool.
+   */
+  final def packNonIncreasing(values: Array[Long]): PooledBuffer = {
+    val buf = checkout()
+    new PooledBuffer(buf, NibblePack.packNonIncreasing(values, buf, 0), this)
+  }
+
+  private[format] def checkout(): ExpandableArrayBuffer = synchronized {
+    if (buffers.nonEmpty) buffers.dequeue else new ExpandableArrayBuffer(initialCapacity)
+  }
+
+  // Clears the bytes written so that a buffer is handed out again as new, including the 8 bytes past numBytes
+  // which packing writes whole 64-bit words into
+  private[format] def release(buf: ExpandableArrayBuffer, numBytes: Int): Unit = {
+    buf.setMemory(0, Math.min(numBytes + 8, buf.capacity), 0)
+    synchronized { if (buffers.length < maxPooled) buffers += buf }
+  }
+}
+
+/**
+ * Packed bytes in a buffer checked out of a NibbleBufferPool.  Closing returns the buffer to the pool, after
+ * which neither it nor slice may be used.  Closing more than once does nothing.
+ * @param numBytes the number of bytes packed, from the start of buffer
+ */
+final class PooledBuffer private[format] (val buffer: ExpandableArrayBuffer, val numBytes: Int,
+                                          pool: NibbleBufferPool) extends AutoCloseable {
+  private var closed = false
+
+  // The packed bytes, eg for unpacking or copying out
+  def slice: DirectBuffer = new UnsafeBuffer(buffer, 0, numBytes)
+
+  def close(): Unit = if (!closed) {
+    closed = true
+    pool.release(buffer, numBytes)
+  }
+}
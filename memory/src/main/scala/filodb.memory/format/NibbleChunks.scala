@@ -0,0 +1,151 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import scala.concurrent.{ExecutionContext, Future}
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * Delta encoding of very large vectors split into chunks which are packed, and can be unpacked, in parallel.
+ * Each chunk is packed as NibblePack.packDelta on its own, so its first delta is from 0 and holds the
+ * absolute value: no chunk depends on the one before it.  A directory up front says where each chunk starts
+ * and how many values it has, so the chunks can be unpacked one after the other or all at once.
+ * The only difference from packing the whole vector with packDelta is at chunk boundaries, where a value lower
+ * than the one before it is kept instead of being packed as a zero delta.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Delta_Chunks
+ *   +1   numValues, Int
+ *   +5   numChunks, Int
+ *   +9   directory: for each chunk, its offset from the start of the stream (Int) and its numValues (Int)
+ *        the chunks, in order, each packed as NibblePack.packDelta
+ * }}}
+ */
+object NibbleChunks {
+  import NibblePack.{packDelta, unpackAllToSink, AccumulatorOverflow, InputTooShort, InvalidHeader, NibbleError, Ok}
+
+  val HeaderBytes = 9
+  val DirectoryEntryBytes = 8
+  val DefaultChunkValues = 1 << 20
+
+  /**
+   * Packs the values in chunks of chunkSize, each on a thread of the ExecutionContext, then copies the chunks
+   * into buf after the directory once they are all done.  Each chunk is packed into its own buffer, and the
+   * only shared state, NibblePack.tempArray, is per thread.
+   * @return the final position within the buffer after packing
+   */
+  final def packDeltaParallel(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int,
+                              chunkSize: Int = DefaultChunkValues)(implicit ec: ExecutionContext): Future[Int] = {
+    require(chunkSize > 0, s"chunkSize must be positive, not $chunkSize")
+    val numChunks = ((input.size.toLong + chunkSize - 1) / chunkSize).toInt
+    val futures = (0 until numChunks).map { c =>
+      Future {
+        val from = c * chunkSize
+        val chunk = java.util.Arrays.copyOfRange(input, from, Math.min(from.toLong + chunkSize, input.size).toInt)
+        val chunkBuf = new ExpandableArrayBuffer()
+        (chunk.size, chunkBuf, packDelta(chunk, chunkBuf, 0))
+      }
+    }
+    Future.sequence(futures).map { chunks =>
+      buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_Delta_Chunks))
+      buf.putInt(bufindex + 1, input.size, LITTLE_ENDIAN)
+      buf.putInt(bufindex + 5, numChunks, LITTLE_ENDIAN)
+      var pos = bufindex + HeaderBytes + DirectoryEntryBytes * numChunks
+      chunks.zipWithIndex.foreach { case ((numValues, chunkBuf, numBytes), n) =>
+        val entry = bufindex + HeaderBytes + DirectoryEntryBytes * n
+        buf.putInt(entry, pos - bufindex, LITTLE_ENDIAN)
+        buf.putInt(entry + 4, numValues, LITTLE_ENDIAN)
+        buf.putBytes(pos, chunkBuf, 0, numBytes)
+        pos += numBytes
+      }
+      pos
+    }
+  }
+
+  /**
+   * Unpacks a stream written by packDeltaParallel one chunk after the other.  The buffer is not mutated.
+   */
+  final def unpackDeltaChunks(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    readDirectory(compressed).right.flatMap { case (numValues, chunks) =>
+      val outArray = new Array[Long](numValues)
+      chunks.iterator.map(unpackChunk(compressed, _, outArray)).collectFirst { case Left(e) => e }.toLeft(outArray)
+    }
+
+  /**
+   * Unpacks a stream written by packDeltaParallel with each chunk on a thread of the ExecutionContext.  The
+   * chunks are unpacked straight into their own parts of one output array.  The buffer is not mutated.
+   * @return the values, or the error of the first chunk which failed
+   */
+  final def unpackDeltaChunksParallel(compressed: DirectBuffer)
+                                     (implicit ec: ExecutionContext): Future[Either[NibbleError, Array[Long]]] =
+    readDirectory(compressed) match {
+      case Left(e) => Future.successful(Left(e))
+      case Right((numValues, chunks)) =>
+        val outArray = new Array[Long](numValues)
+        Future.sequence(chunks.map { chunk => Future(unpackChunk(compressed, chunk, outArray)) }).map { results =>
+          results.collectFirst { case Left(e) => e }.toLeft(outArray)
+        }
+    }
+
+  // A chunk of a stream: where its bytes are, and the index of its first value and its number of values
+  private final case class Chunk(offset: Int, numBytes: Int, firstValue: Int, numValues: Int)
+
+  /**
+   * Reads and checks the directory.  Chunks must be in order and each can only hold as many values as its
+   * bytes could, so the total, which is allocated for, is never more than the stream could hold.
+   * @return the total number of values and the chunks
+   */
+  private def readDirectory(compressed: DirectBuffer): Either[NibbleError, (Int, Seq[Chunk])] =
+    NibbleFormat.checkFormat(new UnsafeBuffer(compressed, 0, compressed.capacity),
+                             NibbleFormat.Format_Delta_Chunks) match {
+      case Ok if compressed.capacity < HeaderBytes => Left(InputTooShort(HeaderBytes, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(1, LITTLE_ENDIAN)
+        val numChunks = compressed.getInt(5, LITTLE_ENDIAN)
+        val directoryEnd = HeaderBytes + DirectoryEntryBytes.toLong * numChunks
+        if (numValues < 0) Left(InvalidHeader("numValues", numValues))
+        else if (numChunks < 0) Left(InvalidHeader("numChunks", numChunks))
+        else if (directoryEnd > compressed.capacity) Left(InputTooShort(directoryEnd.toInt, compressed.capacity))
+        else readChunks(compressed, numValues, numChunks).right.map(chunks => (numValues, chunks))
+      case e: NibbleError => Left(e)
+    }
+
+  private def readChunks(compressed: DirectBuffer, numValues: Int, numChunks: Int): Either[NibbleError, Seq[Chunk]] = {
+    val chunks = new collection.mutable.ArrayBuffer[Chunk](numChunks)
+    var start = HeaderBytes + DirectoryEntryBytes * numChunks
+    var firstValue = 0
+    var error: Option[NibbleError] = None
+    var n = 0
+    while (n < numChunks && error.isEmpty) {
+      val entry = HeaderBytes + DirectoryEntryBytes * n
+      val offset = compressed.getInt(entry, LITTLE_ENDIAN)
+      val count = compressed.getInt(entry + 4, LITTLE_ENDIAN)
+      val end = if (n + 1 < numChunks) compressed.getInt(entry + DirectoryEntryBytes, LITTLE_ENDIAN)
+                else compressed.capacity
+      if (offset < start || end < offset || end > compressed.capacity) {
+        error = Some(InvalidHeader("offset", offset))
+      } else if (count < 0 || count > numValues - firstValue || count.toLong > (end - offset).toLong * 8) {
+        // Every block of 8 values takes at least one byte
+        error = Some(InvalidHeader("numValues", count))
+      } else {
+        chunks += Chunk(offset, end - offset, firstValue, count)
+        start = end
+        firstValue += count
+      }
+      n += 1
+    }
+    error.orElse(if (firstValue != numValues) Some(InvalidHeader("numValues", numValues)) else None).toLeft(chunks)
+  }
+
+  private def unpackChunk(compressed: DirectBuffer, chunk: Chunk, outArray: Array[Long]): Either[NibbleError, Unit] = {
+    val sink = new NibbleSinks.CheckedDeltaSink(outArray, chunk.numValues, chunk.firstValue)
+    unpackAllToSink(new UnsafeBuffer(compressed, chunk.offset, chunk.numBytes), sink, chunk.numValues) match {
+      case Ok if sink.overflowIndex >= 0 => Left(AccumulatorOverflow(sink.overflowIndex))
+      case Ok                            => Right(())
+      case e: NibbleError                => Left(e)
+    }
+  }
+}
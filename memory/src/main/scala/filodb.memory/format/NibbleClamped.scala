@@ -0,0 +1,113 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Deltas for counters which are increasing but for the odd small glitch backwards, eg from clock skew or from
+ * rounding when cast from floating point.  Each value lower than the highest before it is clamped up to that
+ * highest value, so that all the deltas are non-negative and pack unsigned as in NibblePack.packDelta.  Unlike
+ * packDelta, which packs a drop as a 0 delta and so shifts every value after it, the clamped series stays within
+ * the glitch of the original, and the glitches are kept in a side list so that the original can be decoded too.
+ * Values should be non-negative, as for packDelta.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Extended, then NibbleFormat.Format_Delta_Clamped
+ *   +2   numValues, Int
+ *   +6   numClamped, Int
+ *   +10  the clamped series as NibblePack.packDelta
+ *   ...  the indices of the clamped values, increasing, as NibblePack.packDelta
+ *   ...  how far each was raised, as NibblePack.packNonIncreasing
+ * }}}
+ */
+object NibbleClamped {
+  import NibbleFormat.Format_Delta_Clamped
+  import NibblePack.{subslice, InputTooShort, InvalidHeader, NibbleError, Ok}
+
+  val HeaderBytes = 10
+
+  /**
+   * How a series was packed by packDelta.
+   * @param endPos the final position within the buffer after packing
+   * @param numClamped the number of values which were lower than one before them, and so were clamped
+   */
+  final case class ClampReport(endPos: Int, numClamped: Int)
+
+  /**
+   * Packs the values as deltas, clamping each value lower than the highest before it up to that value.
+   */
+  final def packDelta(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): ClampReport = {
+    val clamped = new Array[Long](input.size)
+    val indices = new collection.mutable.ArrayBuilder.ofLong
+    val amounts = new collection.mutable.ArrayBuilder.ofLong
+    var max = 0L
+    for { i <- 0 until input.size optimized } {
+      if (input(i) < max) {
+        indices += i
+        amounts += max - input(i)
+      }
+      max = Math.max(max, input(i))
+      clamped(i) = max
+    }
+    val clampedIndices = indices.result()
+    val countPos = NibbleFormat.putFormat(buf, bufindex, Format_Delta_Clamped)
+    buf.putInt(countPos, input.size, LITTLE_ENDIAN)
+    buf.putInt(countPos + 4, clampedIndices.size, LITTLE_ENDIAN)
+    val indicesPos = NibblePack.packDelta(clamped, buf, countPos + 8)
+    val amountsPos = NibblePack.packDelta(clampedIndices, buf, indicesPos)
+    ClampReport(NibblePack.packNonIncreasing(amounts.result(), buf, amountsPos), clampedIndices.size)
+  }
+
+  /**
+   * Unpacks the clamped series, which never decreases, from a stream written by packDelta.  The side list of
+   * clamped values is not read.
+   * @param compressed NOTE: mutated to wrap the bytes after the clamped series
+   */
+  final def unpackClamped(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    readHeader(compressed).right.flatMap { case (numValues, _) => NibblePack.unpackDelta(compressed, numValues) }
+
+  /**
+   * Unpacks the original values from a stream written by packDelta, lowering the clamped values back down.
+   * @param compressed NOTE: mutated to wrap the bytes after the stream
+   */
+  final def unpackOriginal(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    for {
+      header  <- readHeader(compressed).right
+      values  <- NibblePack.unpackDelta(compressed, header._1).right
+      indices <- NibblePack.unpackDelta(compressed, header._2).right
+      amounts <- NibbleSplice.rawValues(compressed, header._2).right
+      _       <- unclamp(values, indices, amounts).toLeft(()).right
+    } yield values
+
+  // Checks the format code and returns numValues and numClamped, leaving compressed at the clamped series
+  private def readHeader(compressed: DirectBuffer): Either[NibbleError, (Int, Int)] =
+    NibbleFormat.checkFormat(compressed, Format_Delta_Clamped) match {
+      case Ok if compressed.capacity < HeaderBytes - 2 =>
+        Left(InputTooShort(HeaderBytes - 2, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        val numClamped = compressed.getInt(4, LITTLE_ENDIAN)
+        subslice(compressed, HeaderBytes - 2)
+        if (numValues < 0) Left(InvalidHeader("numValues", numValues))
+        else if (numClamped < 0 || numClamped > numValues) Left(InvalidHeader("numClamped", numClamped))
+        else Right((numValues, numClamped))
+      case e: NibbleError => Left(e)
+    }
+
+  // Lowers each clamped value back down, checking that the indices increase and are within the values
+  private def unclamp(values: Array[Long], indices: Array[Long], amounts: Array[Long]): Option[NibbleError] = {
+    var err: Option[NibbleError] = None
+    for { n <- 0 until indices.size optimized } {
+      val index = indices(n)
+      if (index >= values.size || (n > 0 && index == indices(n - 1))) {
+        if (err.isEmpty) err = Some(InvalidHeader("index", index))
+      } else {
+        values(index.toInt) -= amounts(n)
+      }
+    }
+    err
+  }
+}
@@ -0,0 +1,104 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import scalaxy.loops._
+
+/**
+ * Unpacks long series straight down to about as many points as a chart can show, for dashboards querying long
+ * ranges.  The values are split into target / 2 equal buckets, and only the minimum and maximum of each bucket
+ * are kept, in the order they occur, so spikes survive where plain striding would skip them.  The unpacked
+ * values are never all stored: each block is folded into its bucket as it is unpacked.
+ */
+object NibbleDownsample {
+  import NibblePack.{unpackToSink, InvalidParameter, NibbleError, Ok}
+
+  /**
+   * Unpacks numValues values written by NibblePack.packNonIncreasing, downsampled to at most target points.
+   * If there are no more than target values, they are all returned.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see NibblePack.unpackToSink.
+   * @return the points, or InvalidParameter for a target below 2 or a negative numValues
+   */
+  final def unpackDownsampled(compressed: DirectBuffer, numValues: Int,
+                              target: Int): Either[NibbleError, Array[Long]] =
+    downsample(compressed, numValues, target, false)
+
+  /**
+   * Like unpackDownsampled, but for delta-encoded Longs such as those written by NibblePack.packDelta.
+   */
+  final def unpackDeltaDownsampled(compressed: DirectBuffer, numValues: Int,
+                                   target: Int): Either[NibbleError, Array[Long]] =
+    downsample(compressed, numValues, target, true)
+
+  private def downsample(compressed: DirectBuffer, numValues: Int, target: Int,
+                         isDelta: Boolean): Either[NibbleError, Array[Long]] =
+    if (target < 2) {
+      Left(InvalidParameter("target", target))
+    } else if (numValues < 0) {
+      Left(InvalidParameter("numValues", numValues))
+    } else {
+      val numBuckets = if (numValues <= target) numValues else target / 2
+      val sink = new MinMaxSink(numValues, Math.max(numBuckets, 1), numValues <= target, isDelta)
+      unpackToSink(compressed, sink, numValues) match {
+        case Ok             => Right(sink.result)
+        case e: NibbleError => Left(e)
+      }
+    }
+
+  // Folds each value into its bucket, emitting the bucket's min and max once the next bucket starts
+  private final class MinMaxSink(numValues: Int, numBuckets: Int, keepAll: Boolean, isDelta: Boolean)
+  extends NibbleSinks.BoundedSink(numValues) {
+    private val out = new Array[Long](if (keepAll) numValues else numBuckets * 2)
+    private var numOut = 0
+    private var i = 0
+    private var total = 0L
+    private var bucket = 0
+    private var min = 0L
+    private var max = 0L
+    private var minIndex = -1    // the index of min within the values, -1 if the bucket is empty
+    private var maxIndex = -1
+
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        total = if (isDelta) total + data(n) else data(n)
+        if (keepAll) {
+          out(i) = total
+        } else {
+          val valueBucket = (i.toLong * numBuckets / numValues).toInt
+          if (valueBucket != bucket) {
+            emitBucket()
+            bucket = valueBucket
+          }
+          if (minIndex < 0 || total < min) {
+            min = total
+            minIndex = i
+          }
+          if (maxIndex < 0 || total > max) {
+            max = total
+            maxIndex = i
+          }
+        }
+        i += 1
+      }
+
+    private def emitBucket(): Unit = if (minIndex >= 0) {
+      emit(if (minIndex <= maxIndex) min else max)
+      if (minIndex != maxIndex) emit(if (minIndex < maxIndex) max else min)
+      minIndex = -1
+      maxIndex = -1
+    }
+
+    private def emit(value: Long): Unit = {
+      out(numOut) = value
+      numOut += 1
+    }
+
+    // The points from the values unpacked so far
+    def result: Array[Long] =
+      if (keepAll) {
+        java.util.Arrays.copyOf(out, i)
+      } else {
+        emitBucket()
+        java.util.Arrays.copyOf(out, numOut)
+      }
+  }
+}
@@ -0,0 +1,105 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Frame-of-reference encoding: each block of 8 values is stored as its minimum, the base, followed by the
+ * NibblePacked offsets of the values from that base.  This beats delta encoding for values which cluster tightly
+ * within a block but jump between blocks, such as IDs or gauges sampled from several sources, since a jump costs
+ * one base instead of two large deltas.  Values may be in any order and any sign.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_FOR
+ *   +1   numValues, Int
+ *   +5   for each block: the base, Long, then the 8 offsets from it as NibblePack.pack8
+ * }}}
+ */
+object NibbleFOR {
+  import NibblePack.{pack8With, subslice, tempArray, unpack8, InputTooShort, InvalidHeader, NibbleError, Ok, Sink}
+
+  val HeaderBytes = 5
+  // The base plus at least the bitmask byte of pack8
+  val MinBlockBytes = 9
+
+  /**
+   * Packs the values with a per-block base, writing NibbleFormat.Format_FOR first.  The last block is padded
+   * with its base.
+   * @return the final position within the buffer after packing
+   */
+  final def packFOR(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_FOR))
+    buf.putInt(bufindex + 1, input.size, LITTLE_ENDIAN)
+    val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
+    var pos = bufindex + HeaderBytes
+    var blockStart = 0
+    while (blockStart < input.size) {
+      val blockEnd = Math.min(blockStart + 8, input.size)
+      var base = input(blockStart)
+      for { i <- blockStart + 1 until blockEnd optimized } { base = Math.min(base, input(i)) }
+      // The difference of two Longs always fits in 64 unsigned bits, and adding the base back undoes it
+      for { i <- 0 until 8 optimized } {
+        inputArray(i) = if (blockStart + i < blockEnd) input(blockStart + i) - base else 0L
+      }
+      buf.putLong(pos, base, LITTLE_ENDIAN)
+      pos = pack8With(inputArray, buf, pos + 8, writer)
+      blockStart = blockEnd
+    }
+    pos
+  }
+
+  /**
+   * Unpacks a stream written by packFOR, using the count in its header.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackFOR(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_FOR) match {
+      case Ok if compressed.capacity < HeaderBytes - 1 =>
+        Left(InputTooShort(HeaderBytes - 1, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        subslice(compressed, HeaderBytes - 1)
+        val numBlocks = (numValues.toLong + 7) / 8
+        if (numValues < 0 || numBlocks * MinBlockBytes > compressed.capacity) {
+          Left(InvalidHeader("numValues", numValues))
+        } else {
+          val sink = new BaseSink(new Array[Long](numValues))
+          unpackBlocks(compressed, sink, numBlocks.toInt).right.map(_ => sink.outArray)
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  private def unpackBlocks(compressed: DirectBuffer, sink: BaseSink, numBlocks: Int): Either[NibbleError, Unit] = {
+    var block = 0
+    var res: Either[NibbleError, Unit] = Right(())
+    while (block < numBlocks && res.isRight) {
+      if (compressed.capacity < MinBlockBytes) {
+        res = Left(InputTooShort(MinBlockBytes, compressed.capacity))
+      } else {
+        sink.base = compressed.getLong(0, LITTLE_ENDIAN)
+        subslice(compressed, 8)
+        unpack8(compressed, sink) match {
+          case Ok             =>
+          case e: NibbleError => res = Left(e)
+        }
+      }
+      block += 1
+    }
+    res
+  }
+
+  // Adds the base of the current block back to each offset
+  private final class BaseSink(val outArray: Array[Long]) extends Sink {
+    var base = 0L
+    private var i = 0
+    final def process(data: Array[Long]): Unit = {
+      val numElems = Math.min(outArray.size - i, 8)
+      for { n <- 0 until numElems optimized } { outArray(i + n) = data(n) + base }
+      i += 8
+    }
+  }
+}
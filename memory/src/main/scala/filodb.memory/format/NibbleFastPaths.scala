@@ -0,0 +1,31 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import scalaxy.loops._
+
+/**
+ * Specialized decoders for kinds of block which NibblePack.unpack8 hands off to once it has read and checked the
+ * block header.
+ */
+private[format] object NibbleFastPaths {
+  import NibbleFormat.{BlockHeaderBytes, ConstantBytesMask}
+  import NibblePack.{subslice, Ok, Sink, UnpackResult}
+
+  /**
+   * Unpacks a constant block, which stores one value of up to 8 bytes for all 8 values.
+   */
+  def unpackConstant(compressed: DirectBuffer, sink: Sink, header: Int, outArray: Array[Long]): UnpackResult = {
+    val numBytes = header & ConstantBytesMask
+    val totalBytes = BlockHeaderBytes + numBytes
+    val hasValues = NibbleFormat.checkAvailable(compressed, totalBytes)
+    if (hasValues != Ok) return hasValues
+    var value = 0L
+    for { i <- 0 until numBytes optimized } {
+      value |= (compressed.getByte(BlockHeaderBytes + i) & 0x0ffL) << (8 * i)
+    }
+    java.util.Arrays.fill(outArray, value)
+    sink.process(outArray)
+    subslice(compressed, totalBytes)
+    Ok
+  }
+}
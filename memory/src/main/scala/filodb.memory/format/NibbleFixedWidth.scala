@@ -0,0 +1,88 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Increasing Longs packed as deltas like NibblePack.packDelta, but all with one nibble width, the widest any delta
+ * needs.  There are no blocks, bitmasks or nibble headers, so the decoder is one tight loop with nothing to
+ * branch on per block, for latency critical reads.  The cost is size: every delta takes the width of the largest,
+ * and zero deltas are not left out.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Extended, then NibbleFormat.Format_Delta_Fixed
+ *   +2   numValues, Int
+ *   +6   numNibbles, byte: the width of every delta, 1 to 16
+ *   +7   the deltas back to back, numNibbles * 4 bits each, least significant bit first as NibblePack nibbles
+ * }}}
+ */
+object NibbleFixedWidth {
+  import NibbleFormat.Format_Delta_Fixed
+  import NibblePack.{subslice, InputTooShort, InvalidNibbleWidth, NibbleError, Ok}
+
+  val HeaderBytes = 7
+
+  /**
+   * Packs increasing values as deltas of one width, writing the format code, count and width first.  Like
+   * packDelta, a drop is packed as a zero delta.
+   * @return the final position within the buffer after packing
+   */
+  final def packDelta(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val deltas = new Array[Long](input.size)
+    var last = 0L
+    var allBits = 0L
+    for { i <- 0 until input.size optimized } {
+      deltas(i) = if (input(i) >= last) input(i) - last else 0L
+      last = input(i)
+      allBits |= deltas(i)
+    }
+    // At least one nibble, so that a count can always be checked against the size of the input
+    val numNibbles = Math.max((64 - java.lang.Long.numberOfLeadingZeros(allBits) + 3) / 4, 1)
+    val countPos = NibbleFormat.putFormat(buf, bufindex, Format_Delta_Fixed)
+    buf.putInt(countPos, input.size, LITTLE_ENDIAN)
+    buf.putByte(countPos + 4, numNibbles.toByte)
+    val writer = new LsbBitWriter(buf, countPos + 5)
+    for { i <- 0 until deltas.size optimized } { writer.write(deltas(i), numNibbles * 4) }
+    writer.finish()
+  }
+
+  /**
+   * Unpacks a stream written by packDelta, using the count and width in its header.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackDelta(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkFormat(compressed, Format_Delta_Fixed) match {
+      case Ok if compressed.capacity < HeaderBytes - 2 =>
+        Left(InputTooShort(HeaderBytes - 2, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        val numNibbles = compressed.getByte(4) & 0x00ff
+        subslice(compressed, HeaderBytes - 2)
+        val numBytes = (numValues.toLong * numNibbles * 4 + 7) / 8
+        if (numNibbles < 1 || numNibbles > 16) {
+          Left(InvalidNibbleWidth(numNibbles))
+        } else {
+          NibbleFormat.checkCount(numValues, compressed.capacity).toLeft(()).right.flatMap { _ =>
+            if (compressed.capacity < numBytes) Left(InputTooShort(numBytes.toInt, compressed.capacity))
+            else Right(unpackDeltas(compressed, numValues, numNibbles * 4, numBytes.toInt))
+          }
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  // The input is known to hold all the deltas, so the loop only reads and adds
+  private def unpackDeltas(compressed: DirectBuffer, numValues: Int, numBits: Int, numBytes: Int): Array[Long] = {
+    val out = new Array[Long](numValues)
+    val reader = new LsbBitReader(compressed, 0)
+    var total = 0L
+    for { i <- 0 until numValues optimized } {
+      total += reader.read(numBits)
+      out(i) = total
+    }
+    subslice(compressed, numBytes)
+    out
+  }
+}
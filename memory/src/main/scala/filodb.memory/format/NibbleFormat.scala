@@ -0,0 +1,250 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+
+import filodb.memory.BinaryRegion
+
+/**
+ * Format codes for self-describing NibblePack streams.
+ * The raw pack methods in [[NibblePack]] do not write any header as their containers (eg BinaryHistogram) already
+ * know what is inside.  Codecs which can be read back without outside context write a one-byte format code at the
+ * start of the stream, so that a decoder can tell for example a 32-bit stream from a 64-bit one.
+ *
+ * See the "Self-describing streams" section in [compression.md](doc/compression.md).
+ */
+object NibbleFormat {
+  import NibblePack._
+
+  val Format_Extended = 0x00.toByte       // the format code is in the next byte, see ExtendedCodes
+  val Format_Arithmetic = 0x01.toByte     // a base, step and count of equally spaced Longs, see NibbleArithmetic
+  val Format_U32 = 0x02.toByte            // NibblePacked 32-bit Ints, see NibblePack32
+  val Format_ZigZag_Delta = 0x03.toByte   // ZigZag encoded signed Long deltas, see NibblePackSigned
+  val Format_Skip_Table = 0x04.toByte     // NibblePacked Longs with a block skip table footer, see CompressedVec
+  val Format_XOR_Double = 0x05.toByte     // Gorilla-style XOR compressed Doubles, see DoubleXORPack
+  val Format_Delta_Counted = 0x06.toByte  // value count, then increasing Longs as NibblePack.packDelta
+  val Format_Batch = 0x07.toByte          // many vectors with a directory of their offsets, see NibbleBatch
+  val Format_ZigZag_DoD = 0x08.toByte     // ZigZag encoded delta-of-deltas for timestamps, see NibblePackSigned
+  val Format_Bool_Runs = 0x09.toByte      // run lengths of Booleans, see BitVec
+  val Format_FOR = 0x0A.toByte            // per-block base plus NibblePacked offsets, see NibbleFOR
+  val Format_Delta_Runs = 0x0B.toByte     // increasing Longs as deltas with runs of zero deltas, see NibbleRuns
+  val Format_Delta_Chunks = 0x0C.toByte   // independent packDelta chunks after a directory, see NibbleChunks
+  val Format_U128 = 0x0D.toByte           // NibblePacked unsigned 128-bit values, see NibblePack128
+  val Format_ZigZag_Base = 0x0E.toByte    // a base Long then ZigZag deltas from it, see NibblePackSigned
+  val Format_Delta_Blocks = 0x0F.toByte   // increasing Longs as deltas in blocks of a chosen size, see NibbleBlockSize
+
+  // With all 4 bits of the format code in use, further codecs write Format_Extended and then their code in the
+  // second byte.  Extended codes start at ExtendedCodes so that every code names exactly one format.
+  val ExtendedCodes = 0x10
+  val Format_Sparse_Double = 0x10.toByte  // a presence bitmap then XOR compressed Doubles, see SparseDoublePack
+  val Format_Delta_Fixed = 0x11.toByte    // increasing Longs as deltas all of one width, see NibbleFixedWidth
+  val Format_Delta_Hybrid = 0x12.toByte   // deltas ZigZag encoded only in blocks with a drop, see NibbleHybridDelta
+  val Format_Scaled_Double = 0x13.toByte  // fixed-point Doubles as Longs times a scale factor, see NibbleScaled
+  val Format_Patch = 0x14.toByte          // the values changed between two versions of a chunk, see NibblePatch
+  val Format_Delta_Clamped = 0x15.toByte  // deltas of a counter with glitches clamped, see NibbleClamped
+  val Format_Histogram = 0x16.toByte      // a BinaryHistogram, so histograms have a format code too, see NibbleAny
+
+  // Set in the format code of a stream which ends with a 4-byte checksum, see appendChecksum
+  val ChecksumFlag = 0x80
+  val ChecksumBytes = 4
+
+  // The version of the stream layouts, kept in bits 4-6 of the format code byte.  Streams from before versioning
+  // have zeroes there, so they read as version 0.  Bump this when a layout changes, so old decoders refuse
+  // the new streams instead of misreading them.
+  val FormatVersion = 0
+  val VersionMask = 0x70
+  val VersionShift = 4
+
+  // The byte to write at the start of a stream with the given format code, including the FormatVersion
+  @inline final def versioned(formatCode: Byte): Byte = (formatCode | (FormatVersion << VersionShift)).toByte
+
+  // The format code of the stream without the version and ChecksumFlag
+  @inline final def formatOf(compressed: DirectBuffer): Byte =
+    (compressed.getByte(0) & ~(ChecksumFlag | VersionMask)).toByte
+
+  @inline final def versionOf(compressed: DirectBuffer): Int = (compressed.getByte(0) & VersionMask) >> VersionShift
+
+  /**
+   * Writes the format code at the start of a stream: one byte, or for an extended code Format_Extended then the
+   * code.  Both include the FormatVersion.
+   * @return the position after the format code
+   */
+  final def putFormat(buf: MutableDirectBuffer, bufindex: Int, formatCode: Byte): Int =
+    if ((formatCode & 0x00ff) >= ExtendedCodes) {
+      buf.putByte(bufindex, versioned(Format_Extended))
+      buf.putByte(bufindex + 1, formatCode)
+      bufindex + 2
+    } else {
+      buf.putByte(bufindex, versioned(formatCode))
+      bufindex + 1
+    }
+
+  /**
+   * Checks that the stream starts with the given format code, and if so moves the buffer past it.  An extended
+   * code is checked against the second byte of a Format_Extended stream, see putFormat.
+   * Streams with a checksum are accepted without checking it, see verifyChecksum for that.
+   * @param compressed NOTE: mutated to wrap the bytes after the format code if the code matches
+   * @return Ok, UnexpectedFormat, or UnsupportedVersion for a stream written with a newer FormatVersion
+   */
+  final def checkFormat(compressed: DirectBuffer, formatCode: Byte): UnpackResult =
+    if (compressed.capacity < 1) {
+      InputTooShort(1, 0)
+    } else if (versionOf(compressed) > FormatVersion) {
+      UnsupportedVersion(versionOf(compressed))
+    } else if ((formatCode & 0x00ff) >= ExtendedCodes && formatOf(compressed) == Format_Extended) {
+      checkExtendedFormat(compressed, formatCode)
+    } else if (formatOf(compressed) != formatCode) {
+      UnexpectedFormat(formatOf(compressed))
+    } else {
+      subslice(compressed, 1)
+      Ok
+    }
+
+  private def checkExtendedFormat(compressed: DirectBuffer, formatCode: Byte): UnpackResult =
+    if (compressed.capacity < 2) {
+      InputTooShort(2, compressed.capacity)
+    } else if (compressed.getByte(1) != formatCode) {
+      UnexpectedFormat(compressed.getByte(1) & 0x00ff)
+    } else {
+      subslice(compressed, 2)
+      Ok
+    }
+
+  // Formats which record their count keep it as a little-endian Int right after the format code, see peekCount
+  val CountOffset = 1
+  val CountedHeaderBytes = CountOffset + 4
+
+  // The layout of a block of 8 values, see the storage scheme in compression.md.  Every block starts with a bitmask
+  // of its nonzero values; an all-zero block is only that byte.  Any other block then has a header byte with the
+  // number of nibbles per value - 1 in its high nibble and the number of trailing zero nibbles in its low nibble,
+  // and the packed nibbles start after it.  These are shared by NibblePack, NibblePack32 and NibbleBlocks.
+  val BitmaskOffset = 0
+  val NibbleHeaderOffset = 1
+  val ZeroBlockBytes = 1
+  val BlockHeaderBytes = 2
+  val AllNonzeroBitmask = 0xff
+  val NibbleWidthShift = 4
+  val TrailingNibblesMask = 0x0f
+
+  // A block of 8 equal nonzero values has the AllNonzeroBitmask and a header byte of ConstantBlockMarker | numBytes,
+  // which no regular block can have as 16 nibbles leave no room for trailing zeroes.  The value follows in
+  // numBytes little-endian bytes.
+  val ConstantBlockMarker = 0xf0
+  val ConstantBytesMask = 0x0f
+  val MaxConstantBytes = 8
+
+  // The number of nibbles per value of a block, from its unsigned header byte
+  @inline final def nibbleWidth(header: Int): Int = (header >>> NibbleWidthShift) + 1
+
+  // The header byte of a regular block
+  @inline final def nibbleHeader(numNibbles: Int, trailingNibbles: Int): Int =
+    ((numNibbles - 1) << NibbleWidthShift) | trailingNibbles
+
+  @inline final def isConstantBlock(bitmask: Int, header: Int): Boolean =
+    bitmask == AllNonzeroBitmask && header > ConstantBlockMarker && header <= (ConstantBlockMarker | MaxConstantBytes)
+
+  // The most values a decoder will allocate for when the count comes from the caller: 512MB of Longs
+  val MaxDecodeValues = 1 << 26
+
+  /**
+   * Checks a count of values passed in by the caller before allocating for it, so that a bogus count with
+   * untrusted input cannot exhaust memory.  Every packed block of 8 values takes at least one byte, so numBytes
+   * of input can never hold more than numBytes * 8 values.
+   * @return None if the count is plausible, or ImplausibleCount with the most values allowed
+   */
+  final def checkCount(numValues: Int, numBytes: Int): Option[NibbleError] = {
+    val maxValues = Math.min(numBytes.toLong * 8, MaxDecodeValues.toLong)
+    if (numValues < 0 || numValues > maxValues) Some(ImplausibleCount(numValues, maxValues)) else None
+  }
+
+  /**
+   * Checks that the next numBytes bytes of the input are there before a decoder reads them, so that a truncated
+   * stream or a miscomputed size returns InputTooShort instead of reading past the end of the buffer.
+   * @return Ok, or InputTooShort with the bytes needed and the bytes left
+   */
+  @inline final def checkAvailable(compressed: DirectBuffer, numBytes: Int): UnpackResult =
+    if (compressed.capacity >= numBytes) Ok else InputTooShort(numBytes, compressed.capacity)
+
+  /**
+   * Returns the number of values in a stream without unpacking it, so that callers can size their output first.
+   * Only formats which record their count (Format_Delta_Counted, Format_Skip_Table, Format_Delta_Blocks and
+   * Format_Arithmetic) have one to peek at; for the others the count has to come from outside the stream.
+   * The buffer is not mutated.
+   */
+  final def peekCount(compressed: DirectBuffer): Either[NibbleError, Int] =
+    if (compressed.capacity < 1) {
+      Left(InputTooShort(1, 0))
+    } else if (versionOf(compressed) > FormatVersion) {
+      Left(UnsupportedVersion(versionOf(compressed)))
+    } else {
+      formatOf(compressed) match {
+        case Format_Delta_Counted | Format_Skip_Table | Format_Delta_Blocks | Format_Arithmetic =>
+          if (compressed.capacity < CountedHeaderBytes) {
+            Left(InputTooShort(CountedHeaderBytes, compressed.capacity))
+          } else {
+            val count = compressed.getInt(CountOffset, LITTLE_ENDIAN)
+            if (count < 0) Left(InvalidHeader("numValues", count)) else Right(count)
+          }
+        case other => Left(UnexpectedFormat(other))
+      }
+    }
+
+  /**
+   * Makes a self-describing stream detectable if it is later truncated or corrupted, by setting the ChecksumFlag
+   * in its format code and appending an XXHash32 checksum of the whole stream.  Streams without the flag still
+   * decode as before, so checksums are opt-in.
+   * @param bufindex the position of the format code at the start of the stream
+   * @param endPos the final position after packing the stream, as returned by the pack method
+   * @return the final position after the checksum
+   */
+  final def appendChecksum(buf: MutableDirectBuffer, bufindex: Int, endPos: Int): Int = {
+    buf.putByte(bufindex, (buf.getByte(bufindex) | ChecksumFlag).toByte)
+    buf.putInt(endPos, checksum(buf, bufindex, endPos - bufindex), LITTLE_ENDIAN)
+    endPos + ChecksumBytes
+  }
+
+  /**
+   * For a stream with the ChecksumFlag set, recomputes the checksum and compares it with the one at the end,
+   * then shrinks the buffer to leave the checksum out.  Streams without the flag are left alone and return Ok.
+   * Call this before unpacking the stream as usual.
+   * @param compressed NOTE: mutated to leave out the checksum if it matches
+   * @return Ok, or ChecksumMismatch if the stream has been corrupted
+   */
+  final def verifyChecksum(compressed: DirectBuffer): UnpackResult =
+    if (compressed.capacity < 1 || (compressed.getByte(0) & ChecksumFlag) == 0) {
+      Ok
+    } else if (compressed.capacity < 1 + ChecksumBytes) {
+      InputTooShort(1 + ChecksumBytes, compressed.capacity)
+    } else {
+      val len = compressed.capacity - ChecksumBytes
+      val expected = compressed.getInt(len, LITTLE_ENDIAN)
+      val actual = checksum(compressed, 0, len)
+      if (expected != actual) {
+        ChecksumMismatch(expected, actual)
+      } else {
+        compressed.wrap(compressed, 0, len)
+        Ok
+      }
+    }
+
+  /**
+   * The CRC32 of len bytes of a buffer starting at index, for checking a whole buffer, eg one handed to native
+   * code, has not been corrupted on the way.  Unlike appendChecksum nothing is written to the buffer, and the
+   * value is the same as java.util.zip.CRC32 gives for the bytes.
+   */
+  final def bufferCrc32(buf: DirectBuffer, index: Int, len: Int): Int =
+    BinaryRegion.crc32(buf.byteArray, buf.addressOffset + index, len)
+
+  /**
+   * Checks len bytes of a buffer starting at index against a CRC32 from bufferCrc32.
+   * @return Ok, or ChecksumMismatch if the bytes have changed
+   */
+  final def verifyBufferCrc32(buf: DirectBuffer, index: Int, len: Int, expected: Int): UnpackResult = {
+    val actual = bufferCrc32(buf, index, len)
+    if (actual == expected) Ok else ChecksumMismatch(expected, actual)
+  }
+
+  private def checksum(buf: DirectBuffer, index: Int, len: Int): Int =
+    BinaryRegion.hash32(buf.byteArray, buf.addressOffset + index, len)
+}
\ No newline at end of file
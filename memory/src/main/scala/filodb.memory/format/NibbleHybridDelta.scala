@@ -0,0 +1,146 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Deltas for counters which mostly increase but sometimes dip.  NibblePack.packDelta loses drops, packing them as
+ * zero deltas, while NibblePackSigned.packDelta keeps them at the cost of a ZigZag bit on every delta.  Here each
+ * block of 8 deltas is packed unsigned as in packDelta if none of its values drop, and ZigZag encoded only if one
+ * does, so a dip costs just the block it is in.  A flag bit for each block says which it is.
+ * Deltas run on across blocks, from 0 for the first value, and any Longs round trip exactly.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Extended, then NibbleFormat.Format_Delta_Hybrid
+ *   +2   numValues, Int
+ *   +6   a flag bit for each block, least significant bit of the first byte first: 1 if the block is ZigZag
+ *   ...  the blocks as NibblePack.pack8
+ * }}}
+ */
+object NibbleHybridDelta {
+  import NibbleFormat.Format_Delta_Hybrid
+  import NibblePack.{pack8With, subslice, unpack8, InputTooShort, InvalidHeader, NibbleError, Ok, Sink}
+  import NibblePackSigned.{unzigzag, zigzag}
+
+  val HeaderBytes = 6
+
+  /**
+   * Packs the values as deltas, ZigZag encoding only the blocks with a drop, see above.
+   * @return the final position within the buffer after packing
+   */
+  final def packDelta(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val numBlocks = (input.size + 7) / 8
+    val countPos = NibbleFormat.putFormat(buf, bufindex, Format_Delta_Hybrid)
+    buf.putInt(countPos, input.size, LITTLE_ENDIAN)
+    val flagsPos = countPos + 4
+    buf.setMemory(flagsPos, flagBytes(numBlocks), 0)
+    val block = new Array[Long](8)
+    val writer = LsbBitWriter.threadLocal
+    var pos = flagsPos + flagBytes(numBlocks)
+    var last = 0L
+    for { b <- 0 until numBlocks optimized } {
+      val start = b * 8
+      val end = Math.min(start + 8, input.size)
+      var prev = last
+      var drops = false
+      for { i <- start until end optimized } {
+        drops = drops || input(i) < prev
+        prev = input(i)
+      }
+      java.util.Arrays.fill(block, 0L)
+      for { i <- start until end optimized } {
+        block(i - start) = if (drops) zigzag(input(i) - last) else input(i) - last
+        last = input(i)
+      }
+      if (drops) buf.putByte(flagsPos + b / 8, (buf.getByte(flagsPos + b / 8) | (1 << (b % 8))).toByte)
+      pos = pack8With(block, buf, pos, writer)
+    }
+    pos
+  }
+
+  /**
+   * Unpacks a stream written by packDelta, using the count in its header.  The stream must hold all the values.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackDelta(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkFormat(compressed, Format_Delta_Hybrid) match {
+      case Ok if compressed.capacity < HeaderBytes - 2 =>
+        Left(InputTooShort(HeaderBytes - 2, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        subslice(compressed, HeaderBytes - 2)
+        NibbleFormat.checkCount(numValues, compressed.capacity).toLeft(flagBytes((numValues + 7) / 8)).right
+          .flatMap { numFlagBytes =>
+            if (compressed.capacity < numFlagBytes) Left(InputTooShort(numFlagBytes, compressed.capacity))
+            else unpackBlocks(compressed, numValues, numFlagBytes)
+          }
+      case e: NibbleError => Left(e)
+    }
+
+  /**
+   * Which blocks of a stream written by packDelta are ZigZag encoded, from the flags in its header.  The buffer
+   * is not mutated.
+   * @return the numbers of the ZigZag blocks, in order, or an error for a malformed header
+   */
+  final def zigzagBlocks(compressed: DirectBuffer): Either[NibbleError, Seq[Int]] = {
+    val view = new UnsafeBuffer(compressed, 0, compressed.capacity)
+    NibbleFormat.checkFormat(view, Format_Delta_Hybrid) match {
+      case Ok if view.capacity < HeaderBytes - 2 =>
+        Left(InputTooShort(HeaderBytes - 2, view.capacity))
+      case Ok =>
+        val numValues = view.getInt(0, LITTLE_ENDIAN)
+        val numBlocks = (numValues.toLong + 7) / 8
+        if (numValues < 0) {
+          Left(InvalidHeader("numValues", numValues))
+        } else if (view.capacity - 4 < flagBytes(numBlocks.toInt)) {
+          Left(InputTooShort(4 + flagBytes(numBlocks.toInt), view.capacity))
+        } else {
+          Right((0 until numBlocks.toInt).filter(b => (view.getByte(4 + b / 8) & (1 << (b % 8))) != 0))
+        }
+      case e: NibbleError => Left(e)
+    }
+  }
+
+  private def flagBytes(numBlocks: Int): Int = (numBlocks + 7) / 8
+
+  private def unpackBlocks(compressed: DirectBuffer, numValues: Int,
+                           numFlagBytes: Int): Either[NibbleError, Array[Long]] = {
+    val flags = new Array[Byte](numFlagBytes)
+    compressed.getBytes(0, flags)
+    subslice(compressed, numFlagBytes)
+    val sink = new HybridSink(new Array[Long](numValues), flags)
+    var res: Either[NibbleError, Array[Long]] = Right(sink.outArray)
+    var block = 0
+    while (block * 8 < numValues && res.isRight) {
+      if (compressed.capacity < 1) {
+        res = Left(InputTooShort(1, 0))
+      } else {
+        unpack8(compressed, sink) match {
+          case Ok             =>
+          case e: NibbleError => res = Left(e)
+        }
+      }
+      block += 1
+    }
+    res
+  }
+
+  // Adds up each block of deltas, decoding the ZigZag ones first as their flag says
+  private final class HybridSink(val outArray: Array[Long], flags: Array[Byte]) extends Sink {
+    private var current = 0L
+    private var i = 0
+    final def process(data: Array[Long]): Unit = {
+      val zigzagged = (flags(i / 64) & (1 << (i / 8 % 8))) != 0
+      val numElems = Math.min(outArray.size - i, 8)
+      for { n <- 0 until numElems optimized } {
+        current += (if (zigzagged) unzigzag(data(n)) else data(n))
+        outArray(i + n) = current
+      }
+      i += 8
+    }
+  }
+}
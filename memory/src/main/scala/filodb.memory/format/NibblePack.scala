@@ -2,7 +2,8 @@ package filodb.memory.format
 
 import java.nio.ByteOrder.LITTLE_ENDIAN
 
-import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
 import scalaxy.loops._
 
 /**
@@ -10,23 +11,30 @@ import scalaxy.loops._
  * Works with a predictor that maximizes zero bits/words of floating point or integer data.
  */
 object NibblePack {
+  import NibbleFormat.{checkAvailable, isConstantBlock, nibbleHeader, nibbleWidth, versioned, AllNonzeroBitmask,
+                       BitmaskOffset, BlockHeaderBytes, ConstantBlockMarker, ConstantBytesMask, CountOffset,
+                       CountedHeaderBytes, Format_Delta_Counted, NibbleHeaderOffset, TrailingNibblesMask,
+                       ZeroBlockBytes}
+
   /**
    * Packs Long values directly using NibblePack.  Internally uses pack8.  Inputs are not transformed.
+   * @param scratch an array of at least 8 Longs to gather each block in, overwritten.  Defaults to tempArray.
    */
-  final def packNonIncreasing(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
-    val inputArray = tempArray
+  final def packNonIncreasing(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int,
+                              scratch: Array[Long] = tempArray): Int = {
+    val writer = LsbBitWriter.threadLocal
     var i = 0
     var pos = bufindex
     while (i < input.size) {
-      inputArray(i % 8) = input(i)
+      scratch(i % 8) = input(i)
       i += 1
       if (i % 8 == 0) {
-        pos = pack8(inputArray, buf, pos)
+        pos = pack8With(scratch, buf, pos, writer)
       }
     }
 
     // Flush remainder - if any left
-    packRemainder(inputArray, buf, pos, i)
+    packRemainder(scratch, buf, pos, i, writer)
   }
 
   /**
@@ -36,6 +44,7 @@ object NibblePack {
    */
   final def packDelta(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
     val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
     var last = 0L
     var i = 0
     var pos = bufindex
@@ -45,34 +54,87 @@ object NibblePack {
       inputArray(i % 8) = delta
       i += 1
       if (i % 8 == 0) {
-        pos = pack8(inputArray, buf, pos)
+        pos = pack8With(inputArray, buf, pos, writer)
       }
     }
 
     // Flush remainder - if any left
-    packRemainder(inputArray, buf, pos, i)
+    packRemainder(inputArray, buf, pos, i, writer)
+  }
+
+  /**
+   * Like packDelta, but refuses input which decreases anywhere rather than silently packing a 0 delta.
+   * Nothing is written if the input is rejected.
+   * @return Right(final position within the buffer after packing), or Left(NonIncreasing(index)) where index
+   *         is the first value lower than the previous one.  Negative first values are rejected at index 0.
+   */
+  final def packDeltaChecked(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] = {
+    var last = 0L
+    var i = 0
+    while (i < input.size && input(i) >= last) {
+      last = input(i)
+      i += 1
+    }
+    if (i < input.size) Left(NonIncreasing(i)) else Right(packDelta(input, buf, bufindex))
+  }
+
+  /**
+   * Packs values as they arrive one at a time, in the same format as packNonIncreasing, so there is no need to
+   * collect them all into an array first.  Each block of 8 is packed as soon as it fills up.
+   * Call finish() after the last value to pack any partial final block.
+   * @param buf the buffer to pack into.  Highly recommended this be an ExpandableArrayBuffer or equiv. so it can grow.
+   * @param bufindex the position within buf to start packing at
+   */
+  final class Packer(buf: MutableDirectBuffer, bufindex: Int) {
+    private val block = new Array[Long](8)
+    private val writer = new LsbBitWriter(buf, bufindex)
+    private var numInBlock = 0
+    private var pos = bufindex
+    var numValues = 0
+
+    final def add(value: Long): Unit = {
+      block(numInBlock) = value
+      numInBlock += 1
+      numValues += 1
+      if (numInBlock == 8) {
+        pos = pack8With(block, buf, pos, writer)
+        numInBlock = 0
+      }
+    }
+
+    /**
+     * Packs any remaining values, padded with zeroes.  The Packer should not be used afterwards.
+     * @return the final position within the buffer after packing
+     */
+    final def finish(): Int = {
+      pos = packRemainder(block, buf, pos, numInBlock, writer)
+      numInBlock = 0
+      pos
+    }
   }
 
   @inline
-  private def packRemainder(input: Array[Long], buf: MutableDirectBuffer, pos: Int, i: Int): Int =
+  private[format] def packRemainder(input: Array[Long], buf: MutableDirectBuffer, pos: Int, i: Int,
+                                    writer: LsbBitWriter): Int =
     if (i % 8 != 0) {
       for { j <- (i % 8) until 8 optimized } { input(j) = 0 }
-      pack8(input, buf, pos)
+      pack8With(input, buf, pos, writer)
     } else {
       pos
     }
 
   /**
    * Packs Double values using XOR encoding to find minimal # of bits difference between successive values.
-   * Initial Double value is written first.
+   * Initial Double value is written first.  Nothing at all is written for no values.
    * @return the final position within the buffer after packing
    */
   final def packDoubles(inputs: Array[Double], buf: MutableDirectBuffer, bufindex: Int): Int = {
-    require(inputs.size > 0)
+    if (inputs.isEmpty) return bufindex
     buf.putDouble(bufindex, inputs(0), LITTLE_ENDIAN)
     var pos = bufindex + 8
 
     val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
     var last = java.lang.Double.doubleToLongBits(inputs(0))
     var i = 0
     while (i < (inputs.size - 1)) {
@@ -81,28 +143,38 @@ object NibblePack {
       last = bits
       i += 1
       if (i % 8 == 0) {
-        pos = pack8(inputArray, buf, pos)
+        pos = pack8With(inputArray, buf, pos, writer)
       }
     }
 
     // Flush remainder - if any left
-    if (i % 8 != 0) {
-      for { j <- (i % 8) until 8 optimized } { inputArray(j) = 0 }
-      pos = pack8(inputArray, buf, pos)
-    }
-
-    pos
+    packRemainder(inputArray, buf, pos, i, writer)
   }
 
+  @inline private def allEqual(input: Array[Long]): Boolean =
+    input(1) == input(0) && input(2) == input(0) && input(3) == input(0) && input(4) == input(0) &&
+    input(5) == input(0) && input(6) == input(0) && input(7) == input(0)
+
+  // For each bitmask of nonzero values, the positions of its set bits in increasing order, see unpack8
+  private[format] val SetBitPositions: Array[Array[Int]] =
+    Array.tabulate(256) { mask => (0 until 8).filter(bit => (mask & (1 << bit)) != 0).toArray }
+
   /**
    * Packs 8 input values into a buffer using NibblePacking. Returns ending buffer position.
    * This is an internal method, usually one wants to use one of the other pack* methods.
+   * A block of 8 equal nonzero values, such as the deltas of a counter increasing at a steady rate, is packed as
+   * a constant block, see NibbleFormat.ConstantBlockMarker, unless constantBlocks is false, see NibblePackCompat.
    * @param buf the MutableDirectBuffer into which to write.  Recommended is to use ExpandableArrayBuffer or
-   *            ExpandableDirectByteBuffer so that it can grow as needed.
+   *            ExpandableDirectByteBuffer so that it can grow as needed.  The layout is described in NibbleFormat.
    * @param bufindex the starting index of the output buffer into which to write
    * @return the ending MutableDirectBuffer position
    */
-  final def pack8(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+  final def pack8(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int, constantBlocks: Boolean = true): Int =
+    pack8With(input, buf, bufindex, LsbBitWriter.threadLocal, constantBlocks)
+
+  // Like pack8, using writer for the nonzero values, so that callers packing many blocks look it up only once
+  private[format] def pack8With(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int, writer: LsbBitWriter,
+                                constantBlocks: Boolean = true): Int = {
     var bufpos = bufindex
     require(input.size >= 8)
 
@@ -114,7 +186,14 @@ object NibblePack {
     buf.putByte(bufpos, bitmask.toByte)
     bufpos += 1
 
-    if (bitmask != 0) {
+    if (constantBlocks && bitmask == AllNonzeroBitmask && allEqual(input)) {
+      val numBytes = (64 - java.lang.Long.numberOfLeadingZeros(input(0)) + 7) / 8
+      buf.putByte(bufpos, (ConstantBlockMarker | numBytes).toByte)
+      for { i <- 0 until numBytes optimized } {
+        buf.putByte(bufpos + 1 + i, (input(0) >>> (8 * i)).toByte)
+      }
+      bufpos += 1 + numBytes
+    } else if (bitmask != 0) {
       // figure out min # of nibbles to represent nonzero words
       var minLeadingZeros = 64
       var minTrailingZeros = 64
@@ -125,55 +204,15 @@ object NibblePack {
 
       val trailingNibbles = minTrailingZeros / 4
       val numNibbles = 16 - (minLeadingZeros / 4) - trailingNibbles
-      val nibbleWord = (((numNibbles - 1) << 4) | trailingNibbles)
-      buf.putByte(bufpos, nibbleWord.toByte)
+      buf.putByte(bufpos, nibbleHeader(numNibbles, trailingNibbles).toByte)
       bufpos += 1
 
-      // Decide which packer to use
-      bufpos = packUniversal(input, buf, bufpos, numNibbles, trailingNibbles)
-    }
-
-    bufpos
-  }
-
-  // Returns the final bufpos
-  private def packUniversal(inputs: Array[Long], buf: MutableDirectBuffer, bufindex: Int,
-                    numNibbles: Int, trailingZeroNibbles: Int): Int = {
-    var bufpos = bufindex
-    val trailingShift = trailingZeroNibbles * 4
-    val numBits = numNibbles * 4
-    var outWord = 0L
-    var bitCursor = 0
-
-    for { i <- 0 until 8 optimized } {
-      val input = inputs(i)
-      if (input != 0) {
-        val remaining = 64 - bitCursor
-        val shiftedInput = input >>> trailingShift
-
-        // This is least significant portion of input
-        outWord |= shiftedInput << bitCursor
-
-        // Write out current word if we've used up all 64 bits
-        if (remaining <= numBits) {
-          buf.putLong(bufpos, outWord, LITTLE_ENDIAN)
-          bufpos += 8
-          if (remaining < numBits) {
-              // Most significant portion left over from previous word
-              outWord = shiftedInput >>> remaining
-          } else {
-              outWord = 0    // reset for 64-bit input case
-          }
-        }
-
-        bitCursor = (bitCursor + numBits) % 64
+      // Write the nonzero inputs without their trailing zero nibbles, numNibbles each
+      writer.reset(buf, bufpos)
+      for { i <- 0 until 8 optimized } {
+        if (input(i) != 0) writer.write(input(i) >>> (trailingNibbles * 4), numNibbles * 4)
       }
-    }
-
-    // Write remainder word if there are any bits remaining, and only advance buffer right # of bytes
-    if (bitCursor > 0) {
-        buf.putLong(bufpos, outWord, LITTLE_ENDIAN)
-        bufpos += ((bitCursor + 7) / 8)
+      bufpos = writer.finish()
     }
 
     bufpos
@@ -187,12 +226,89 @@ object NibblePack {
     case a: Array[Long]          => a
   }
 
-  sealed trait UnpackResult
-  case object Ok extends UnpackResult
-  case object InputTooShort extends UnpackResult
+  /**
+   * Every result has a stable errorCode: 0 for Ok and a distinct negative number for each kind of error, so
+   * that results can be passed through interfaces which only carry an Int, such as JNI or a wire protocol.
+   * Codes are never reused; see the table in compression.md.
+   */
+  sealed trait UnpackResult {
+    def errorCode: Int
+  }
+  case object Ok extends UnpackResult {
+    val errorCode = 0
+  }
 
-  val empty = Array.empty[Byte]
+  /**
+   * The ways in which packing or unpacking can fail.  Each error carries enough detail for callers to surface a
+   * useful message.
+   */
+  sealed trait NibbleError extends UnpackResult
+  // The input ended before a complete block could be read: needed bytes vs the bytes available
+  final case class InputTooShort(needed: Int, got: Int) extends NibbleError {
+    def errorCode: Int = -1
+  }
+  // The block header declares more nibbles (data + trailing zeroes) than fit in a 64-bit word
+  final case class InvalidNibbleWidth(width: Int) extends NibbleError {
+    def errorCode: Int = -2
+  }
+  // The format code at the start of a self-describing stream is not the one expected, see NibbleFormat
+  final case class UnexpectedFormat(code: Int) extends NibbleError {
+    def errorCode: Int = -3
+  }
+  // A field in the header of a self-describing stream has a value which cannot be right, eg a negative count
+  final case class InvalidHeader(field: String, value: Long) extends NibbleError {
+    def errorCode: Int = -4
+  }
+  // Packing input which must be increasing, but the value at index is lower than the one before it
+  final case class NonIncreasing(index: Int) extends NibbleError {
+    def errorCode: Int = -5
+  }
+  // Two inputs which must share a scheme, such as the buckets of two histograms, do not
+  case object SchemaMismatch extends NibbleError {
+    val errorCode = -6
+  }
+  // The checksum at the end of a stream does not match its contents, see NibbleFormat.appendChecksum
+  final case class ChecksumMismatch(expected: Int, actual: Int) extends NibbleError {
+    def errorCode: Int = -7
+  }
+  // The output given for unpacking has room for fewer values than the stream holds, needed being a Long for bytes
+  final case class OutputTooSmall(needed: Long, got: Int) extends NibbleError {
+    def errorCode: Int = -8
+  }
+  // The running total of deltas went past Long.MaxValue at index, which no input to packDelta can produce
+  final case class AccumulatorOverflow(index: Int) extends NibbleError {
+    def errorCode: Int = -9
+  }
+  // The stream was written with a newer NibbleFormat.FormatVersion than this code can read
+  final case class UnsupportedVersion(version: Int) extends NibbleError {
+    def errorCode: Int = -10
+  }
+  // The value at index cannot be held exactly in the format being converted to, see NibbleTranscode
+  final case class LossyConversion(index: Int) extends NibbleError {
+    def errorCode: Int = -11
+  }
+  // A parameter for encoding, such as a histogram bucket scheme, has a value which cannot produce valid output
+  final case class InvalidParameter(name: String, value: Double) extends NibbleError {
+    def errorCode: Int = -12
+  }
+  // A count passed in by the caller is more than the input could hold or than is safe to allocate for
+  final case class ImplausibleCount(numValues: Long, maxValues: Long) extends NibbleError {
+    def errorCode: Int = -13
+  }
+  // A counter which must never go down dropped from prev to curr at index, as when its process restarts
+  final case class CounterReset(index: Int, prev: Long, curr: Long) extends NibbleError {
+    def errorCode: Int = -14
+  }
+  // A geometric bucket scheme read from a histogram header is non-finite or out of range, eg a multiplier below 1
+  final case class InvalidGeometry(name: String, value: Double) extends NibbleError {
+    def errorCode: Int = -15
+  }
+  // The indices from start until end asked for by a caller are not all within 0 until size, or end is before start
+  final case class IndexOutOfRange(start: Long, end: Long, size: Long) extends NibbleError {
+    def errorCode: Int = -16
+  }
 
+  val empty = Array.empty[Byte]
   val zeroOutput = new Array[Long](8)
 
   trait Sink {
@@ -202,6 +318,11 @@ object NibblePack {
     def process(data: Array[Long]): Unit
   }
 
+  /**
+   * A Sink which sums up deltas into outArray.  Sinks can be reused across unpacks by calling reset() in between,
+   * so one sink sized for the largest expected unpack avoids allocating an output array for every vector.
+   * After unpacking n values, they are in the first n elements of outArray.
+   */
   final case class DeltaSink(outArray: Array[Long]) extends Sink {
     private var current: Long = 0L
     private var i: Int = 0
@@ -216,12 +337,21 @@ object NibblePack {
       }
       i += 8
     }
+
+    /**
+     * Clears the running sum and starts writing again from the start of outArray, which is kept.
+     */
     def reset(): Unit = {
       i = 0
       current = 0L
     }
   }
 
+  object DeltaSink {
+    // Creates a DeltaSink which can unpack up to capacity values
+    def withCapacity(capacity: Int): DeltaSink = DeltaSink(new Array[Long](capacity))
+  }
+
   final case class DoubleXORSink(outArray: Array[Double], initial: Long) extends Sink {
     private var lastBits = initial
     private var pos: Int = 1
@@ -262,6 +392,7 @@ object NibblePack {
     var valueDropped: Boolean = false
     private var i: Int = 0
     private val packArray = new Array[Long](8)
+    private val writer = new LsbBitWriter(outBuf, pos)
     var writePos: Int = pos
 
     def reset(): Unit = {
@@ -280,7 +411,7 @@ object NibblePack {
       System.arraycopy(data, 0, lastHistDeltas, i, numElems)
       // if numElems < 8, zero out remainder of packArray
       if (numElems < 8) java.util.Arrays.fill(packArray, numElems, 8, 0L)
-      writePos = pack8(packArray, outBuf, writePos)
+      writePos = pack8With(packArray, outBuf, writePos, writer)
       i += 8
     }
   }
@@ -301,6 +432,7 @@ object NibblePack {
     var valueDropped: Boolean = false
     private var i: Int = 0
     private val packArray = new Array[Long](8)
+    private val writer = new LsbBitWriter(outBuf, pos)
     var writePos: Int = pos
 
     def reset(): Unit = {
@@ -317,7 +449,7 @@ object NibblePack {
       System.arraycopy(data, 0, lastHistDeltas, i, numElems)
       // if numElems < 8, zero out remainder of packArray
       if (numElems < 8) java.util.Arrays.fill(packArray, numElems, 8, 0L)
-      writePos = pack8(packArray, outBuf, writePos)
+      writePos = pack8With(packArray, outBuf, writePos, writer)
       i += 8
     }
 
@@ -334,20 +466,221 @@ object NibblePack {
    * @param compressed a DirectBuffer wrapping the compressed bytes. Position 0 must be the beginning of the buffer
    *                   to unpack.  NOTE: the passed in DirectBuffer will be mutated to wrap the NEXT bytes that
    *                   can be unpacked.
+   * @param scratch the array passed to the sink for each block, see unpack8
    */
-  final def unpackToSink(compressed: DirectBuffer, sink: Sink, numValues: Int): UnpackResult = {
+  final def unpackToSink(compressed: DirectBuffer, sink: Sink, numValues: Int,
+                         scratch: Array[Long] = tempArray): UnpackResult = {
     var res: UnpackResult = Ok
     var valuesLeft = numValues
     while (valuesLeft > 0 && res == Ok && compressed.capacity > 0) {
-      res = unpack8(compressed, sink)
+      res = unpack8(compressed, sink, scratch)
       valuesLeft -= 8
     }
     res
   }
 
+  /**
+   * Like unpackToSink, but it is an error (InputTooShort) for the input to end before numValues are unpacked.
+   * Use this when numValues comes from the data itself, so a truncated input is never mistaken for a short one.
+   */
+  final def unpackAllToSink(compressed: DirectBuffer, sink: Sink, numValues: Int,
+                            scratch: Array[Long] = tempArray): UnpackResult = {
+    var res: UnpackResult = Ok
+    var valuesLeft = numValues
+    while (valuesLeft > 0 && res == Ok) {
+      res = if (compressed.capacity > 0) unpack8(compressed, sink, scratch) else InputTooShort(1, 0)
+      valuesLeft -= 8
+    }
+    res
+  }
+
+  /**
+   * Unpacks delta-encoded Longs such as those written by packDelta into a newly allocated array of exactly
+   * numValues elements.  Convenient for callers that do not want to manage their own DeltaSink.
+   * Unlike DeltaSink, the running total is checked, so that corrupt deltas are not silently wrapped around.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see unpackToSink.
+   * @return Right(array) with the original values, or Left(error) if the input could not be unpacked, including
+   *         InputTooShort if it ends before numValues, and ImplausibleCount for a numValues the input could not
+   *         hold, see NibbleFormat.checkCount
+   */
+  final def unpackDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkCount(numValues, compressed.capacity).toLeft(new Array[Long](numValues)).right.flatMap { out =>
+      unpackDeltaChecked(compressed, out, numValues).right.map(_ => out)
+    }
+
+  /**
+   * Unpacks several packDelta streams, eg the chunks a query span crosses, one after the other into one array
+   * allocated up front for all of their values.  Each stream is a (buffer, numValues) pair, must hold all its
+   * values and is checked as in unpackDelta.  The index of an AccumulatorOverflow is into the whole array.
+   * @param buffers NOTE: each buffer will be mutated, see unpackToSink
+   */
+  final def unpackConcat(buffers: Seq[(DirectBuffer, Int)]): Either[NibbleError, Array[Long]] = {
+    val countErrors = buffers.iterator.flatMap { case (buf, n) => NibbleFormat.checkCount(n, buf.capacity) }
+    val total = buffers.map(_._2.toLong).sum
+    if (countErrors.hasNext) {
+      Left(countErrors.next())
+    } else if (total > NibbleFormat.MaxDecodeValues) {
+      Left(ImplausibleCount(total, NibbleFormat.MaxDecodeValues))
+    } else {
+      val out = new Array[Long](total.toInt)
+      buffers.foldLeft[Either[NibbleError, Int]](Right(0)) { case (res, (buf, n)) =>
+        res.right.flatMap { start => unpackDeltaChecked(buf, out, n, start).right.map(_ => start + n) }
+      }.right.map(_ => out)
+    }
+  }
+
+  // Unpacks numValues deltas into outArray from start through a CheckedDeltaSink, all of which must be there
+  private def unpackDeltaChecked(compressed: DirectBuffer, outArray: Array[Long], numValues: Int,
+                                 start: Int = 0): Either[NibbleError, Int] = {
+    val sink = new NibbleSinks.CheckedDeltaSink(outArray, numValues, start)
+    unpackAllToSink(compressed, sink, numValues) match {
+      case Ok if sink.overflowIndex >= 0 => Left(AccumulatorOverflow(sink.overflowIndex))
+      case Ok                            => Right(numValues)
+      case e: NibbleError                => Left(e)
+    }
+  }
+
+  /**
+   * Packs increasing Long values like packDelta into a byte array of exactly the packed size, for callers which
+   * hand the bytes to code outside the JVM and do not want to deal with Agrona buffers.
+   */
+  final def packDeltaToBytes(input: Array[Long]): Array[Byte] = {
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = packDelta(input, buf, 0)
+    java.util.Arrays.copyOf(buf.byteArray, numBytes)
+  }
+
+  /**
+   * Unpacks the bytes from packDeltaToBytes, or any other packDelta output in a byte array, which is not mutated
+   * and must hold all numValues values.  Bindings which own their output array should use unpackDeltaCountedFromBytes.
+   */
+  final def unpackDeltaFromBytes(bytes: Array[Byte], numValues: Int): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkCount(numValues, bytes.size).toLeft(new Array[Long](numValues)).right.flatMap { out =>
+      unpackDeltaChecked(new UnsafeBuffer(bytes), out, numValues).right.map(_ => out)
+    }
+
+  /**
+   * Packs increasing Long values like packDelta, but first writes NibbleFormat.Format_Delta_Counted and the number
+   * of values, so that unpackDeltaCounted can read them back without being told how many there are.
+   * @return the final position within the buffer after packing
+   */
+  final def packDeltaCounted(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putByte(bufindex, versioned(Format_Delta_Counted))
+    buf.putInt(bufindex + CountOffset, input.size, LITTLE_ENDIAN)
+    packDelta(input, buf, bufindex + CountedHeaderBytes)
+  }
+
+  /**
+   * Unpacks a stream written by packDeltaCounted using the count from its header, so a stale count from the
+   * caller can never truncate or over-read the values.  The stream must contain all the values, see
+   * unpackAllToSink.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see unpackToSink.
+   */
+  final def unpackDeltaCounted(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    readCount(compressed).right.flatMap { numValues =>
+      val outArray = new Array[Long](numValues)
+      unpackCountedValues(compressed, outArray, numValues).right.map(_ => outArray)
+    }
+
+  /**
+   * Unpacks a stream written by packDeltaCounted into an array provided by the caller, so that it can be reused
+   * across calls and threads can each own their output instead of sharing a thread local one.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see unpackToSink.
+   * @return the number of values written at the start of outArray, or OutputTooSmall if they do not all fit
+   */
+  final def unpackDeltaCountedInto(compressed: DirectBuffer, outArray: Array[Long]): Either[NibbleError, Int] =
+    readCount(compressed).right.flatMap { numValues => unpackCountedValues(compressed, outArray, numValues) }
+
+  /**
+   * Unpacks a packDeltaCounted stream in the first numBytes of bytes into an outArray owned by the caller, for
+   * bindings such as JNI which can only be handed back an Int.  Nothing thread local is returned or kept.
+   * @return the number of values written, or the negative errorCode of the error, eg OutputTooSmall
+   */
+  final def unpackDeltaCountedFromBytes(bytes: Array[Byte], numBytes: Int, outArray: Array[Long]): Int =
+    if (numBytes < 0 || numBytes > bytes.size) InputTooShort(numBytes, bytes.size).errorCode
+    else unpackDeltaCountedInto(new UnsafeBuffer(bytes, 0, numBytes), outArray).fold(_.errorCode, n => n)
+
+  // The number of values written and of input bytes used up by unpackDeltaCountedConsumed
+  final case class Consumed(valuesWritten: Int, bytesConsumed: Int)
+
+  /**
+   * Like unpackDeltaCountedInto, but also returns the number of bytes the stream took up, so that streams packed
+   * back to back in one buffer without a directory can be unpacked one after the other.
+   * @param compressed NOTE: mutated to wrap the bytes after the stream, ie the start of the next one
+   */
+  final def unpackDeltaCountedConsumed(compressed: DirectBuffer,
+                                       outArray: Array[Long]): Either[NibbleError, Consumed] = {
+    val startBytes = compressed.capacity
+    unpackDeltaCountedInto(compressed, outArray).right.map { n => Consumed(n, startBytes - compressed.capacity) }
+  }
+
+  private def unpackCountedValues(compressed: DirectBuffer, outArray: Array[Long],
+                                  numValues: Int): Either[NibbleError, Int] =
+    if (outArray.size < numValues) Left(OutputTooSmall(numValues, outArray.size))
+    else unpackDeltaChecked(compressed, outArray, numValues)
+
+  // Checks the format code and reads the count of a packDeltaCounted stream, leaving compressed at the values
+  private[format] def readCount(compressed: DirectBuffer): Either[NibbleError, Int] =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_Delta_Counted) match {
+      case Ok if compressed.capacity < 4 => Left(InputTooShort(4, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        subslice(compressed, 4)
+        // Every block of 8 values takes at least one byte
+        if (numValues < 0 || numValues.toLong > compressed.capacity.toLong * 8) {
+          Left(InvalidHeader("numValues", numValues))
+        } else {
+          Right(numValues)
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  /**
+   * An Iterator over delta-encoded Longs as written by packDelta, which unpacks 8 values at a time only as they
+   * are needed.  This allows filter, take etc. directly over compressed data without allocating an output array.
+   * If the input turns out to be truncated or malformed, iteration stops early and unpackResult has the error.
+   * @param compressed NOTE: mutated as blocks are unpacked, see unpackToSink
+   * @param numValues the number of values which were packed
+   */
+  final class UnpackIterator(compressed: DirectBuffer, numValues: Int) extends Iterator[Long] with Sink {
+    private val block = new Array[Long](8)
+    private var current = 0L
+    private var blockPos = 8
+    private var valuesLeft = numValues
+    var unpackResult: UnpackResult = Ok
+
+    final def process(data: Array[Long]): Unit = {
+      for { n <- 0 until 8 optimized } {
+        current += data(n)
+        block(n) = current
+      }
+      blockPos = 0
+    }
+
+    final def hasNext: Boolean = valuesLeft > 0 && (blockPos < 8 || (unpackResult == Ok && refill()))
+
+    final def next(): Long = {
+      if (!hasNext) throw new NoSuchElementException(s"No more values, unpackResult=$unpackResult")
+      valuesLeft -= 1
+      blockPos += 1
+      block(blockPos - 1)
+    }
+
+    private def refill(): Boolean = {
+      unpackResult = if (compressed.capacity > 0) unpack8(compressed, this) else InputTooShort(1, 0)
+      unpackResult == Ok
+    }
+
+    // For values stored scaled or offset, eg fixed-point.  Applied as each value is emitted, without a second pass
+    final def mapScale(factor: Double): Iterator[Double] = map(_ * factor)
+    final def mapOffset(delta: Long): Iterator[Long] = map(_ + delta)
+  }
+
   final def unpackDoubleXOR(compressed: DirectBuffer, outArray: Array[Double]): UnpackResult = {
-    if (compressed.capacity < 8) {
-      InputTooShort
+    if (outArray.isEmpty) {
+      Ok
+    } else if (compressed.capacity < 8) {
+      InputTooShort(8, compressed.capacity)
     } else {
       val initVal = readLong(compressed, 0)
       val sink = DoubleXORSink(outArray, initVal)
@@ -363,54 +696,60 @@ object NibblePack {
    *                   to unpack.  NOTE: the passed in DirectBuffer will be mutated to wrap the NEXT bytes that
    *                   can be unpacked.
    * @param sink a Sink for processing output values.  8 Longs will be passed to it.
+   * @param scratch the array of 8 Longs passed to the sink, by default a thread local one, see DecodeContext
    * @return an UnpackResult
    */
   //scalastyle:off method.length
-  final def unpack8(compressed: DirectBuffer, sink: Sink): UnpackResult = {
-    val nonzeroMask = compressed.getByte(0)
+  final def unpack8(compressed: DirectBuffer, sink: Sink, scratch: Array[Long] = tempArray): UnpackResult = {
+    val hasBlock = checkAvailable(compressed, ZeroBlockBytes)
+    if (hasBlock != Ok) return hasBlock
+    val nonzeroMask = compressed.getByte(BitmaskOffset)
     if (nonzeroMask == 0) {
       sink.process(zeroOutput)
-      subslice(compressed, 1)
+      subslice(compressed, ZeroBlockBytes)
       Ok
     } else {
-      val numNibblesU8 = compressed.getByte(1) & 0x00ff     // Make sure this is unsigned 8 bits!
-      val numBits = ((numNibblesU8 >>> 4) + 1) * 4
-      val trailingZeroes = (numNibblesU8 & 0x0f) * 4
-      val totalBytes = 2 + (numBits * java.lang.Integer.bitCount(nonzeroMask & 0x0ff) + 7) / 8
+      val hasHeader = checkAvailable(compressed, BlockHeaderBytes)
+      if (hasHeader != Ok) return hasHeader
+      val numNibblesU8 = compressed.getByte(NibbleHeaderOffset) & 0x00ff     // Make sure this is unsigned 8 bits!
+      if (isConstantBlock(nonzeroMask & 0x0ff, numNibblesU8)) {
+        return NibbleFastPaths.unpackConstant(compressed, sink, numNibblesU8, scratch)
+      }
+      val numBits = nibbleWidth(numNibblesU8) * 4
+      val trailingZeroes = (numNibblesU8 & TrailingNibblesMask) * 4
+      if (numBits + trailingZeroes > 64) return InvalidNibbleWidth((numBits + trailingZeroes) / 4)
+      val totalBytes = BlockHeaderBytes + (numBits * java.lang.Integer.bitCount(nonzeroMask & 0x0ff) + 7) / 8
+      val hasValues = checkAvailable(compressed, totalBytes)
+      if (hasValues != Ok) return hasValues
       val mask = if (numBits >= 64) -1L else (1L << numBits) - 1
-      var bufIndex = 2
+      var bufIndex = BlockHeaderBytes
       var bitCursor = 0
-      val outArray = tempArray
+      val outArray = scratch
 
       var inWord = readLong(compressed, bufIndex)
       bufIndex += 8
 
-      for { bit <- 0 until 8 optimized } {
-        if ((nonzeroMask & (1 << bit)) != 0) {
-          val remaining = 64 - bitCursor
-
-          // Shift and read in LSB
-          val shiftedIn = inWord >>> bitCursor
-          var outWord = shiftedIn & mask
-
-          // If remaining bits are in next word, read next word, unless no more space
-          if (remaining <= numBits && bufIndex < totalBytes) {
-            if (bufIndex < compressed.capacity) {
-              inWord = readLong(compressed, bufIndex)
-              bufIndex += 8
-              if (remaining < numBits) {
-                outWord |= (inWord << remaining) & mask
-              }
-            } else {
-              return InputTooShort
-            }
-          }
+      // Only visit the nonzero slots, so that sparse bitmasks do not cost a branch per slot
+      java.util.Arrays.fill(outArray, 0L)
+      val positions = SetBitPositions(nonzeroMask & 0x0ff)
+      for { p <- 0 until positions.size optimized } {
+        val remaining = 64 - bitCursor
 
-          outArray(bit) = outWord << trailingZeroes
-          bitCursor = (bitCursor + numBits) % 64
-        } else {
-          outArray(bit) = 0
+        // Shift and read in LSB
+        val shiftedIn = inWord >>> bitCursor
+        var outWord = shiftedIn & mask
+
+        // If remaining bits are in next word, read next word.  Capacity was checked against totalBytes above.
+        if (remaining <= numBits && bufIndex < totalBytes) {
+          inWord = readLong(compressed, bufIndex)
+          bufIndex += 8
+          if (remaining < numBits) {
+            outWord |= (inWord << remaining) & mask
+          }
         }
+
+        outArray(positions(p)) = outWord << trailingZeroes
+        bitCursor = (bitCursor + numBits) % 64
       }
 
       sink.process(outArray)
@@ -421,7 +760,23 @@ object NibblePack {
   }
   //scalastyle:on method.length
 
-  private def subslice(buffer: DirectBuffer, start: Int): Unit =
+  /**
+   * Returns the number of bytes taken up by the packed block of 8 values starting at pos, without unpacking it.
+   * Only the bitmask and nibble header bytes are read, and they are assumed to be present.
+   */
+  final def blockSize(compressed: DirectBuffer, pos: Int): Int = {
+    val nonzeroMask = compressed.getByte(pos + BitmaskOffset) & 0x0ff
+    val header = compressed.getByte(pos + NibbleHeaderOffset) & 0x00ff
+    if (nonzeroMask == 0) {
+      ZeroBlockBytes
+    } else if (isConstantBlock(nonzeroMask, header)) {
+      BlockHeaderBytes + (header & ConstantBytesMask)
+    } else {
+      BlockHeaderBytes + (nibbleWidth(header) * 4 * java.lang.Integer.bitCount(nonzeroMask) + 7) / 8
+    }
+  }
+
+  private[format] def subslice(buffer: DirectBuffer, start: Int): Unit =
     if (buffer.capacity > start) {
       buffer.wrap(buffer, start, buffer.capacity - start)
     } else {
@@ -429,7 +784,7 @@ object NibblePack {
     }
 
   // Method to read a Long but ensure we don't step out of bounds at the end if we don't have 8 bytes left
-  private def readLong(inbuf: DirectBuffer, index: Int): Long = {
+  private[format] def readLong(inbuf: DirectBuffer, index: Int): Long = {
     if ((index + 8) <= inbuf.capacity) {
       inbuf.getLong(index, LITTLE_ENDIAN)
     } else {
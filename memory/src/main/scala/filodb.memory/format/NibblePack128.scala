@@ -0,0 +1,226 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * NibblePack for unsigned 128-bit values, for counters which can outgrow 64 bits such as byte counts summed over
+ * a very large system, or for 128-bit IDs.  The JVM has no 128-bit integer, so each value is passed as two
+ * Longs: the high and low 64 bits, each from its own array.
+ *
+ * The block layout is the same as for 64-bit NibblePack, except that a value has up to 32 nibbles, so the nibble
+ * width (minus one) and trailing zero nibble fields each take a whole byte: a block is the bitmask, the width
+ * byte, the trailing zeroes byte, then the nibbles of the nonzero values.  There are no constant blocks.
+ *
+ * The stream starts with NibbleFormat.Format_U128 so it cannot be confused with a 64-bit stream.
+ */
+object NibblePack128 {
+  import NibbleFormat.checkAvailable
+  import NibblePack.{subslice, InvalidNibbleWidth, Ok, UnpackResult}
+
+  /**
+   * Packs 128-bit values, with the high 64 bits of value i in hi(i) and the low 64 bits in lo(i), writing the
+   * format code first.
+   * @return the final position within the buffer after packing
+   */
+  final def pack(hi: Array[Long], lo: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    require(hi.size == lo.size, s"High and low arrays have different sizes ${hi.size} and ${lo.size}")
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_U128))
+    val blockHi = new Array[Long](8)
+    val blockLo = new Array[Long](8)
+    var pos = bufindex + 1
+    var i = 0
+    while (i < hi.size) {
+      val numElems = Math.min(hi.size - i, 8)
+      java.util.Arrays.fill(blockHi, 0L)
+      java.util.Arrays.fill(blockLo, 0L)
+      System.arraycopy(hi, i, blockHi, 0, numElems)
+      System.arraycopy(lo, i, blockLo, 0, numElems)
+      pos = pack8(blockHi, blockLo, buf, pos)
+      i += numElems
+    }
+    pos
+  }
+
+  /**
+   * Packs 8 128-bit values into a buffer.  Returns ending buffer position.
+   */
+  //scalastyle:off method.length
+  final def pack8(hi: Array[Long], lo: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    var bufpos = bufindex
+    require(hi.size >= 8 && lo.size >= 8)
+
+    var bitmask = 0
+    for { i <- 0 until 8 optimized } {
+      if (hi(i) != 0 || lo(i) != 0) bitmask |= 1 << i
+    }
+    buf.putByte(bufpos, bitmask.toByte)
+    bufpos += 1
+
+    if (bitmask != 0) {
+      var minLeadingZeros = 128
+      var minTrailingZeros = 128
+      for { i <- 0 until 8 optimized } {
+        minLeadingZeros = Math.min(minLeadingZeros, leadingZeros(hi(i), lo(i)))
+        minTrailingZeros = Math.min(minTrailingZeros, trailingZeros(hi(i), lo(i)))
+      }
+
+      val trailingNibbles = minTrailingZeros / 4
+      val numNibbles = 32 - (minLeadingZeros / 4) - trailingNibbles
+      buf.putByte(bufpos, (numNibbles - 1).toByte)
+      buf.putByte(bufpos + 1, trailingNibbles.toByte)
+      bufpos += 2
+
+      // Values are written 32 bits at a time into a Long accumulator, as in NibblePack32
+      val trailingShift = trailingNibbles * 4
+      val numBits = numNibbles * 4
+      var outWord = 0L
+      var bitCursor = 0
+      for { i <- 0 until 8 optimized } {
+        if ((bitmask & (1 << i)) != 0) {
+          val shiftedLo = shiftRightLo(hi(i), lo(i), trailingShift)
+          val shiftedHi = shiftRightHi(hi(i), trailingShift)
+          var bitsLeft = numBits
+          var piece = 0
+          while (bitsLeft > 0) {
+            val pieceBits = Math.min(bitsLeft, 32)
+            val word = if (piece < 2) shiftedLo >>> (piece * 32) else shiftedHi >>> ((piece - 2) * 32)
+            outWord |= (word & ((1L << pieceBits) - 1)) << bitCursor
+            bitCursor += pieceBits
+            if (bitCursor >= 32) {
+              buf.putInt(bufpos, outWord.toInt, LITTLE_ENDIAN)
+              bufpos += 4
+              outWord = outWord >>> 32
+              bitCursor -= 32
+            }
+            bitsLeft -= pieceBits
+            piece += 1
+          }
+        }
+      }
+
+      // Write remainder word if there are any bits remaining, and only advance buffer right # of bytes
+      if (bitCursor > 0) {
+        buf.putInt(bufpos, outWord.toInt, LITTLE_ENDIAN)
+        bufpos += (bitCursor + 7) / 8
+      }
+    }
+
+    bufpos
+  }
+  //scalastyle:on method.length
+
+  /**
+   * Unpacks a stream written by pack into outHi and outLo, which must both be sized to the number of values
+   * originally packed.
+   * @param compressed a DirectBuffer wrapping the compressed bytes, starting with the format code.
+   *                   NOTE: it will be mutated to wrap the bytes after the unpacked values.
+   */
+  final def unpack(compressed: DirectBuffer, outHi: Array[Long], outLo: Array[Long]): UnpackResult = {
+    require(outHi.size == outLo.size, s"High and low arrays have different sizes ${outHi.size} and ${outLo.size}")
+    var res = NibbleFormat.checkFormat(compressed, NibbleFormat.Format_U128)
+    var pos = 0
+    while (pos < outHi.size && res == Ok) {
+      res = unpack8(compressed, outHi, outLo, pos)
+      pos += 8
+    }
+    res
+  }
+
+  /**
+   * Unpacks 8 128-bit values, writing the ones which fit into outHi and outLo starting at outPos.
+   * @param compressed NOTE: mutated to wrap the next bytes that can be unpacked, just like NibblePack.unpack8
+   */
+  //scalastyle:off method.length
+  final def unpack8(compressed: DirectBuffer, outHi: Array[Long], outLo: Array[Long], outPos: Int): UnpackResult = {
+    val numElems = Math.max(Math.min(outHi.size - outPos, 8), 0)
+    val hasBlock = checkAvailable(compressed, 1)
+    if (hasBlock != Ok) return hasBlock
+    val nonzeroMask = compressed.getByte(0) & 0x00ff
+    if (nonzeroMask == 0) {
+      java.util.Arrays.fill(outHi, outPos, outPos + numElems, 0L)
+      java.util.Arrays.fill(outLo, outPos, outPos + numElems, 0L)
+      subslice(compressed, 1)
+      Ok
+    } else {
+      val hasHeader = checkAvailable(compressed, 3)
+      if (hasHeader != Ok) return hasHeader
+      val numNibbles = (compressed.getByte(1) & 0x00ff) + 1
+      val trailingNibbles = compressed.getByte(2) & 0x00ff
+      val numBits = numNibbles * 4
+      val totalBytes = 3 + (numBits * java.lang.Integer.bitCount(nonzeroMask) + 7) / 8
+      if (numNibbles + trailingNibbles > 32) return InvalidNibbleWidth(numNibbles + trailingNibbles)
+      val hasValues = checkAvailable(compressed, totalBytes)
+      if (hasValues != Ok) return hasValues
+      var bufIndex = 3
+      var inWord = 0L
+      var bitsInWord = 0
+      for { bit <- 0 until 8 optimized } {
+        var valueHi = 0L
+        var valueLo = 0L
+        if ((nonzeroMask & (1 << bit)) != 0) {
+          var bitsLeft = numBits
+          var piece = 0
+          while (bitsLeft > 0) {
+            val pieceBits = Math.min(bitsLeft, 32)
+            // Top up the bit reservoir from the next 32-bit word when it runs low
+            if (bitsInWord < pieceBits) {
+              inWord |= (readInt(compressed, bufIndex) & 0xffffffffL) << bitsInWord
+              bufIndex += 4
+              bitsInWord += 32
+            }
+            val word = inWord & ((1L << pieceBits) - 1)
+            if (piece < 2) valueLo |= word << (piece * 32) else valueHi |= word << ((piece - 2) * 32)
+            inWord = inWord >>> pieceBits
+            bitsInWord -= pieceBits
+            bitsLeft -= pieceBits
+            piece += 1
+          }
+        }
+        if (bit < numElems) {
+          val trailingShift = trailingNibbles * 4
+          outHi(outPos + bit) = shiftLeftHi(valueHi, valueLo, trailingShift)
+          outLo(outPos + bit) = shiftLeftLo(valueLo, trailingShift)
+        }
+      }
+      subslice(compressed, totalBytes)
+      Ok
+    }
+  }
+  //scalastyle:on method.length
+
+  private def leadingZeros(hi: Long, lo: Long): Int =
+    if (hi != 0) java.lang.Long.numberOfLeadingZeros(hi) else 64 + java.lang.Long.numberOfLeadingZeros(lo)
+
+  private def trailingZeros(hi: Long, lo: Long): Int =
+    if (lo != 0) java.lang.Long.numberOfTrailingZeros(lo) else 64 + java.lang.Long.numberOfTrailingZeros(hi)
+
+  // The halves of a 128-bit value shifted by 0 to 127 bits.  JVM shifts only use the low 6 bits of the
+  // distance, so shifts of 0 and of 64 or more need their own cases.
+  private def shiftRightLo(hi: Long, lo: Long, shift: Int): Long =
+    if (shift == 0) lo else if (shift < 64) (lo >>> shift) | (hi << (64 - shift)) else hi >>> (shift - 64)
+
+  private def shiftRightHi(hi: Long, shift: Int): Long = if (shift < 64) hi >>> shift else 0L
+
+  private def shiftLeftHi(hi: Long, lo: Long, shift: Int): Long =
+    if (shift == 0) hi else if (shift < 64) (hi << shift) | (lo >>> (64 - shift)) else lo << (shift - 64)
+
+  private def shiftLeftLo(lo: Long, shift: Int): Long = if (shift < 64) lo << shift else 0L
+
+  // Reads an Int but does not step out of bounds if there are fewer than 4 bytes left
+  private def readInt(inbuf: DirectBuffer, index: Int): Int = {
+    if ((index + 4) <= inbuf.capacity) {
+      inbuf.getInt(index, LITTLE_ENDIAN)
+    } else {
+      var i = 0
+      var outWord = 0
+      while (index + i < inbuf.capacity) {
+        outWord |= (inbuf.getByte(index + i) & 0x00ff) << (8 * i)
+        i += 1
+      }
+      outWord
+    }
+  }
+}
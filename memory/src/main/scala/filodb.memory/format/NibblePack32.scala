@@ -0,0 +1,189 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * NibblePack for 32-bit Int values, for data which genuinely fits in 32 bits such as counters which never exceed
+ * 2^32.  The block layout is the same as for 64-bit NibblePack, except that a value has at most 8 nibbles, so the
+ * nibble width (minus one) and trailing zero nibble fields each only go up to 7.  Packing 32-bit values directly
+ * avoids widening to Longs and keeps the nibble accounting tighter.
+ *
+ * The stream starts with NibbleFormat.Format_U32 so it cannot be confused with a 64-bit stream.
+ */
+object NibblePack32 {
+  import NibbleFormat.{nibbleHeader, nibbleWidth, BitmaskOffset, BlockHeaderBytes, NibbleHeaderOffset,
+                       TrailingNibblesMask, ZeroBlockBytes}
+  import NibblePack.{subslice, InputTooShort, InvalidNibbleWidth, Ok, UnpackResult}
+
+  /**
+   * Packs Int values, treated as unsigned 32-bit values, writing the format code first.
+   * @return the final position within the buffer after packing
+   */
+  final def pack(input: Array[Int], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_U32))
+    val inputArray = tempArray
+    var i = 0
+    var pos = bufindex + 1
+    while (i < input.size) {
+      inputArray(i % 8) = input(i)
+      i += 1
+      if (i % 8 == 0) {
+        pos = pack8(inputArray, buf, pos)
+      }
+    }
+
+    // Flush remainder - if any left
+    if (i % 8 != 0) {
+      java.util.Arrays.fill(inputArray, i % 8, 8, 0)
+      pos = pack8(inputArray, buf, pos)
+    }
+    pos
+  }
+
+  /**
+   * Packs 8 Int values into a buffer.  Returns ending buffer position.
+   */
+  final def pack8(input: Array[Int], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    var bufpos = bufindex
+    require(input.size >= 8)
+
+    var bitmask = 0
+    for { i <- 0 until 8 optimized } {
+      if (input(i) != 0) bitmask |= 1 << i
+    }
+    buf.putByte(bufpos, bitmask.toByte)
+    bufpos += 1
+
+    if (bitmask != 0) {
+      var minLeadingZeros = 32
+      var minTrailingZeros = 32
+      for { i <- 0 until 8 optimized } {
+        minLeadingZeros = Math.min(minLeadingZeros, java.lang.Integer.numberOfLeadingZeros(input(i)))
+        minTrailingZeros = Math.min(minTrailingZeros, java.lang.Integer.numberOfTrailingZeros(input(i)))
+      }
+
+      val trailingNibbles = minTrailingZeros / 4
+      val numNibbles = 8 - (minLeadingZeros / 4) - trailingNibbles
+      buf.putByte(bufpos, nibbleHeader(numNibbles, trailingNibbles).toByte)
+      bufpos += 1
+
+      // Accumulate into a Long so values spanning two 32-bit words are easy to handle
+      val trailingShift = trailingNibbles * 4
+      val numBits = numNibbles * 4
+      var outWord = 0L
+      var bitCursor = 0
+      for { i <- 0 until 8 optimized } {
+        if (input(i) != 0) {
+          outWord |= ((input(i) >>> trailingShift) & 0xffffffffL) << bitCursor
+          bitCursor += numBits
+          if (bitCursor >= 32) {
+            buf.putInt(bufpos, outWord.toInt, LITTLE_ENDIAN)
+            bufpos += 4
+            outWord = outWord >>> 32
+            bitCursor -= 32
+          }
+        }
+      }
+
+      // Write remainder word if there are any bits remaining, and only advance buffer right # of bytes
+      if (bitCursor > 0) {
+        buf.putInt(bufpos, outWord.toInt, LITTLE_ENDIAN)
+        bufpos += (bitCursor + 7) / 8
+      }
+    }
+
+    bufpos
+  }
+
+  /**
+   * Unpacks a stream written by pack into outArray, which must be sized to the number of values originally packed.
+   * @param compressed a DirectBuffer wrapping the compressed bytes, starting with the format code.
+   *                   NOTE: it will be mutated to wrap the bytes after the unpacked values.
+   */
+  final def unpack(compressed: DirectBuffer, outArray: Array[Int]): UnpackResult = {
+    var res = NibbleFormat.checkFormat(compressed, NibbleFormat.Format_U32)
+    var pos = 0
+    while (pos < outArray.size && res == Ok) {
+      res = if (compressed.capacity > 0) unpack8(compressed, outArray, pos) else InputTooShort(1, 0)
+      pos += 8
+    }
+    res
+  }
+
+  /**
+   * Unpacks 8 Ints, writing the ones which fit into outArray starting at outPos.
+   * @param compressed NOTE: mutated to wrap the next bytes that can be unpacked, just like NibblePack.unpack8
+   */
+  //scalastyle:off method.length
+  final def unpack8(compressed: DirectBuffer, outArray: Array[Int], outPos: Int): UnpackResult = {
+    val numElems = Math.max(Math.min(outArray.size - outPos, 8), 0)
+    if (compressed.capacity < ZeroBlockBytes) return InputTooShort(ZeroBlockBytes, 0)
+    val nonzeroMask = compressed.getByte(BitmaskOffset) & 0x00ff
+    if (nonzeroMask == 0) {
+      java.util.Arrays.fill(outArray, outPos, outPos + numElems, 0)
+      subslice(compressed, ZeroBlockBytes)
+      Ok
+    } else if (compressed.capacity < BlockHeaderBytes) {
+      InputTooShort(BlockHeaderBytes, compressed.capacity)
+    } else {
+      val numNibblesU8 = compressed.getByte(NibbleHeaderOffset) & 0x00ff
+      val numBits = nibbleWidth(numNibblesU8) * 4
+      val trailingZeroes = (numNibblesU8 & TrailingNibblesMask) * 4
+      val totalBytes = BlockHeaderBytes + (numBits * java.lang.Integer.bitCount(nonzeroMask) + 7) / 8
+      if (numBits + trailingZeroes > 32) {
+        InvalidNibbleWidth((numBits + trailingZeroes) / 4)
+      } else if (compressed.capacity < totalBytes) {
+        InputTooShort(totalBytes, compressed.capacity)
+      } else {
+        val mask = (1L << numBits) - 1     // numBits <= 32 so this cannot overflow
+        var bufIndex = BlockHeaderBytes
+        var inWord = 0L
+        var bitsInWord = 0
+        for { bit <- 0 until 8 optimized } {
+          var value = 0
+          if ((nonzeroMask & (1 << bit)) != 0) {
+            // Top up the bit reservoir from the next 32-bit word when it runs low
+            if (bitsInWord < numBits) {
+              inWord |= (readInt(compressed, bufIndex) & 0xffffffffL) << bitsInWord
+              bufIndex += 4
+              bitsInWord += 32
+            }
+            value = ((inWord & mask) << trailingZeroes).toInt
+            inWord = inWord >>> numBits
+            bitsInWord -= numBits
+          }
+          if (bit < numElems) outArray(outPos + bit) = value
+        }
+        subslice(compressed, totalBytes)
+        Ok
+      }
+    }
+  }
+  //scalastyle:on method.length
+
+  private val tlTempArray = new ThreadLocal[Array[Int]]()
+  private def tempArray: Array[Int] = tlTempArray.get match {
+    case UnsafeUtils.ZeroPointer => val newArray = new Array[Int](8)
+                                    tlTempArray.set(newArray)
+                                    newArray
+    case a: Array[Int]           => a
+  }
+
+  // Reads an Int but does not step out of bounds if there are fewer than 4 bytes left
+  private def readInt(inbuf: DirectBuffer, index: Int): Int = {
+    if ((index + 4) <= inbuf.capacity) {
+      inbuf.getInt(index, LITTLE_ENDIAN)
+    } else {
+      var i = 0
+      var outWord = 0
+      while (index + i < inbuf.capacity) {
+        outWord |= (inbuf.getByte(index + i) & 0x00ff) << (8 * i)
+        i += 1
+      }
+      outWord
+    }
+  }
+}
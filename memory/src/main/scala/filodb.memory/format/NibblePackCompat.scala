@@ -0,0 +1,73 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.MutableDirectBuffer
+
+/**
+ * Packs raw NibblePack streams like NibblePack, but never as constant blocks, so that readers from before
+ * constant blocks can still read them.  Raw streams have no format code to carry a version, so the streams which
+ * are persisted and read back by other FiloDB versions, the bucket values and custom bucket definitions of
+ * BinaryHistogram and HistogramVector, are written with these.  See "Constant blocks" in compression.md.
+ */
+object NibblePackCompat {
+  import NibblePack.{pack8With, packRemainder, tempArray}
+
+  /**
+   * Like NibblePack.packNonIncreasing, without constant blocks.
+   * @return the final position within the buffer after packing
+   */
+  final def packNonIncreasing(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int,
+                              scratch: Array[Long] = tempArray): Int = {
+    val writer = LsbBitWriter.threadLocal
+    var pos = bufindex
+    var i = 0
+    while (i < input.size) {
+      scratch(i % 8) = input(i)
+      i += 1
+      if (i % 8 == 0) pos = pack8With(scratch, buf, pos, writer, constantBlocks = false)
+    }
+    packRemainder(scratch, buf, pos, i, writer)
+  }
+
+  /**
+   * Like NibblePack.packDelta, without constant blocks.
+   * @return the final position within the buffer after packing
+   */
+  final def packDelta(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
+    var last = 0L
+    var pos = bufindex
+    var i = 0
+    while (i < input.size) {
+      inputArray(i % 8) = if (input(i) >= last) input(i) - last else 0L
+      last = input(i)
+      i += 1
+      if (i % 8 == 0) pos = pack8With(inputArray, buf, pos, writer, constantBlocks = false)
+    }
+    packRemainder(inputArray, buf, pos, i, writer)
+  }
+
+  /**
+   * Like NibblePack.packDoubles, without constant blocks.
+   * @return the final position within the buffer after packing
+   */
+  final def packDoubles(inputs: Array[Double], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    if (inputs.isEmpty) return bufindex
+    buf.putDouble(bufindex, inputs(0), LITTLE_ENDIAN)
+    val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
+    var last = java.lang.Double.doubleToLongBits(inputs(0))
+    var pos = bufindex + 8
+    var i = 0
+    while (i < inputs.size - 1) {
+      val bits = java.lang.Double.doubleToLongBits(inputs(i + 1))
+      inputArray(i % 8) = bits ^ last
+      last = bits
+      i += 1
+      if (i % 8 == 0) pos = pack8With(inputArray, buf, pos, writer, constantBlocks = false)
+    }
+    packRemainder(inputArray, buf, pos, i, writer)
+  }
+}
@@ -0,0 +1,251 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * NibblePack codecs for signed Long series, such as gauges (temperature, pressure) which go up and down.
+ * NibblePack.packDelta only allows increasing values -- a drop is packed as a zero delta -- and a negative delta
+ * packed directly would have no leading zero nibbles at all.  Instead, deltas here are ZigZag encoded
+ * (see https://developers.google.com/protocol-buffers/docs/encoding#signed-integers), which maps small negative
+ * and positive deltas alike to small unsigned values before they are NibblePacked.
+ * For timestamps, which are spaced almost evenly, packDeltaOfDelta goes one step further and packs the changes
+ * in the interval between values.
+ */
+object NibblePackSigned {
+  import NibblePack.{pack8With, packRemainder, subslice, tempArray, unpackAllToSink, unpackToSink, CounterReset,
+                     InputTooShort, InvalidHeader, NibbleError, Ok, OutputTooSmall, Sink, UnpackResult}
+
+  @inline final def zigzag(n: Long): Long = (n << 1) ^ (n >> 63)
+  @inline final def unzigzag(n: Long): Long = (n >>> 1) ^ -(n & 1)
+
+  /**
+   * Packs signed Long values as ZigZag encoded deltas, writing NibbleFormat.Format_ZigZag_Delta first.
+   * The first delta is from 0, so the first value may be negative too.
+   * @return the final position within the buffer after packing
+   */
+  final def packDelta(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_ZigZag_Delta))
+    packZigZagDeltas(input, 0L, buf, bufindex + 1)
+  }
+
+  val BaseHeaderBytes = 9
+
+  /**
+   * Like packDelta, but the first delta is from base rather than 0, and base is stored in the header.  For series
+   * far from zero, eg large negative offsets, this keeps the first delta small, so that the first block is not
+   * packed at the full width of the first value.
+   * Layout: +0 NibbleFormat.Format_ZigZag_Base, +1 base (Long, little endian), +9 the ZigZag deltas.
+   * @param base the value to take the first delta from, usually the first value
+   * @return the final position within the buffer after packing
+   */
+  final def packDeltaFromBase(input: Array[Long], base: Long, buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_ZigZag_Base))
+    buf.putLong(bufindex + 1, base, LITTLE_ENDIAN)
+    packZigZagDeltas(input, base, buf, bufindex + BaseHeaderBytes)
+  }
+
+  // Packs the ZigZag encoded deltas of input, starting from the delta between start and the first value
+  private def packZigZagDeltas(input: Array[Long], start: Long, buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
+    var last = start
+    var i = 0
+    var pos = bufindex
+    while (i < input.size) {
+      inputArray(i % 8) = zigzag(input(i) - last)
+      last = input(i)
+      i += 1
+      if (i % 8 == 0) {
+        pos = pack8With(inputArray, buf, pos, writer)
+      }
+    }
+
+    // Flush remainder - if any left
+    packRemainder(inputArray, buf, pos, i, writer)
+  }
+
+  /**
+   * Unpacks a stream written by packDelta into outArray, which should be sized to the number of values packed.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackDelta(compressed: DirectBuffer, outArray: Array[Long]): UnpackResult =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_ZigZag_Delta) match {
+      case Ok             => unpackToSink(compressed, ZigZagDeltaSink(outArray), outArray.size)
+      case e: NibbleError => e
+    }
+
+  /**
+   * Unpacks a stream written by packDeltaFromBase into outArray, which should be sized to the number of values
+   * packed.  Unlike unpackDelta, it is an error (InputTooShort) for the stream to hold fewer values.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackDeltaFromBase(compressed: DirectBuffer, outArray: Array[Long]): UnpackResult =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_ZigZag_Base) match {
+      case Ok if compressed.capacity < BaseHeaderBytes - 1 =>
+        InputTooShort(BaseHeaderBytes, compressed.capacity + 1)
+      case Ok =>
+        val base = compressed.getLong(0, LITTLE_ENDIAN)
+        subslice(compressed, BaseHeaderBytes - 1)
+        unpackAllToSink(compressed, ZigZagDeltaSink(outArray, base), outArray.size)
+      case e: NibbleError => e
+    }
+
+  /**
+   * Like unpackDelta, but for counters, which should never go down.  Since packDelta keeps drops, unlike
+   * NibblePack.packDelta which packs them as zero deltas, a counter reset can be found and reported, for
+   * example so that rate() can account for it.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   * @return the number of values unpacked, or CounterReset for the first value lower than the one before it
+   */
+  final def unpackDeltaChecked(compressed: DirectBuffer, outArray: Array[Long]): Either[NibbleError, Int] =
+    unpackDelta(compressed, outArray) match {
+      case Ok =>
+        var i = 1
+        while (i < outArray.size && outArray(i) >= outArray(i - 1)) i += 1
+        if (i < outArray.size) Left(CounterReset(i, outArray(i - 1), outArray(i))) else Right(outArray.size)
+      case e: NibbleError => Left(e)
+    }
+
+  val DoDHeaderBytes = 22
+  // In the flags byte of a delta-of-delta stream: every interval is the same, so no blocks follow
+  val DoDRegular = 0x01
+
+  /**
+   * Packs signed Long values, usually timestamps, as ZigZag encoded delta-of-deltas: the difference between each
+   * interval and the one before it.  For near regular timestamps these are mostly zero or tiny, and when every
+   * interval is the same nothing at all is packed after the header.  Out-of-order values are fine too.
+   * Layout (Longs and Ints little endian):
+   * {{{
+   *   +0   NibbleFormat.Format_ZigZag_DoD
+   *   +1   numValues, Int
+   *   +5   first value, Long (0 if there are none)
+   *   +13  first delta, ie second value - first value, Long (0 if there are fewer than 2 values)
+   *   +21  flags: DoDRegular if every interval is the same
+   *   +22  numValues - 2 ZigZag delta-of-deltas as NibblePack.pack8 blocks, unless DoDRegular
+   * }}}
+   * @return the final position within the buffer after packing
+   */
+  final def packDeltaOfDelta(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val firstDelta = if (input.size >= 2) input(1) - input(0) else 0L
+    var regular = true
+    for { i <- 2 until input.size optimized } {
+      if (input(i) - input(i - 1) != firstDelta) regular = false
+    }
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_ZigZag_DoD))
+    buf.putInt(bufindex + 1, input.size, LITTLE_ENDIAN)
+    buf.putLong(bufindex + 5, if (input.size >= 1) input(0) else 0L, LITTLE_ENDIAN)
+    buf.putLong(bufindex + 13, firstDelta, LITTLE_ENDIAN)
+    buf.putByte(bufindex + 21, (if (regular) DoDRegular else 0).toByte)
+    if (regular) {
+      bufindex + DoDHeaderBytes
+    } else {
+      val inputArray = tempArray
+      val writer = LsbBitWriter.threadLocal
+      var pos = bufindex + DoDHeaderBytes
+      for { i <- 2 until input.size optimized } {
+        inputArray((i - 2) % 8) = zigzag((input(i) - input(i - 1)) - (input(i - 1) - input(i - 2)))
+        if ((i - 1) % 8 == 0) pos = pack8With(inputArray, buf, pos, writer)
+      }
+      packRemainder(inputArray, buf, pos, Math.max(input.size - 2, 0), writer)
+    }
+  }
+
+  /**
+   * Unpacks a stream written by packDeltaOfDelta, using the count in its header.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackDeltaOfDelta(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    readDoDHeader(compressed).right.flatMap { header =>
+      val outArray = new Array[Long](header.numValues)
+      unpackDoDValues(compressed, header, outArray).right.map(_ => outArray)
+    }
+
+  /**
+   * Unpacks a stream written by packDeltaOfDelta into an array owned by the caller.  Nothing is kept after the
+   * call returns, so unlike DecodeContext.values the output is never overwritten by a later unpack.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   * @return the number of values written at the start of outArray, or OutputTooSmall if they do not all fit
+   */
+  final def unpackDeltaOfDeltaInto(compressed: DirectBuffer, outArray: Array[Long]): Either[NibbleError, Int] =
+    readDoDHeader(compressed).right.flatMap { header =>
+      if (outArray.size < header.numValues) {
+        Left(OutputTooSmall(header.numValues, outArray.size))
+      } else {
+        unpackDoDValues(compressed, header, outArray).right.map(_ => header.numValues)
+      }
+    }
+
+  private[format] final case class DoDHeader(numValues: Int, first: Long, firstDelta: Long, regular: Boolean)
+
+  // Checks the format code and reads the header of a packDeltaOfDelta stream, leaving compressed at the values
+  private[format] def readDoDHeader(compressed: DirectBuffer): Either[NibbleError, DoDHeader] =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_ZigZag_DoD) match {
+      case Ok if compressed.capacity < DoDHeaderBytes - 1 =>
+        Left(InputTooShort(DoDHeaderBytes - 1, compressed.capacity))
+      case Ok =>
+        val header = DoDHeader(compressed.getInt(0, LITTLE_ENDIAN), compressed.getLong(4, LITTLE_ENDIAN),
+                               compressed.getLong(12, LITTLE_ENDIAN), (compressed.getByte(20) & DoDRegular) != 0)
+        subslice(compressed, DoDHeaderBytes - 1)
+        // Every block of 8 values takes at least one byte
+        if (header.numValues < 0 ||
+            (!header.regular && header.numValues.toLong - 2 > compressed.capacity.toLong * 8)) {
+          Left(InvalidHeader("numValues", header.numValues))
+        } else {
+          Right(header)
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  private def unpackDoDValues(compressed: DirectBuffer, header: DoDHeader,
+                              outArray: Array[Long]): Either[NibbleError, Unit] = {
+    val numValues = header.numValues
+    if (numValues >= 1) outArray(0) = header.first
+    if (numValues >= 2) outArray(1) = header.first + header.firstDelta
+    if (header.regular) {
+      for { i <- 2 until numValues optimized } { outArray(i) = outArray(i - 1) + header.firstDelta }
+      Right(())
+    } else {
+      val numDoDs = Math.max(numValues - 2, 0)
+      unpackAllToSink(compressed, new DoDSink(outArray, numDoDs, header.firstDelta), numDoDs) match {
+        case Ok             => Right(())
+        case e: NibbleError => Left(e)
+      }
+    }
+  }
+
+  // Adds up ZigZag delta-of-deltas into outArray from index 2, after the first two values
+  private final class DoDSink(outArray: Array[Long], numDoDs: Int, firstDelta: Long)
+  extends NibbleSinks.BoundedSink(numDoDs) {
+    private var delta = firstDelta
+    private var i = 2
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        delta += unzigzag(data(n))
+        outArray(i) = outArray(i - 1) + delta
+        i += 1
+      }
+  }
+
+  /**
+   * A Sink which undoes the ZigZag encoding and sums up the signed deltas.
+   */
+  final case class ZigZagDeltaSink(outArray: Array[Long], initial: Long = 0L) extends Sink {
+    private var current: Long = initial
+    private var i: Int = 0
+    final def process(data: Array[Long]): Unit = {
+      val numElems = Math.min(outArray.size - i, 8)
+      for { n <- 0 until numElems optimized } {
+        current += unzigzag(data(n))
+        outArray(i + n) = current
+      }
+      i += 8
+    }
+    def reset(): Unit = {
+      i = 0
+      current = initial
+    }
+  }
+}
@@ -0,0 +1,136 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Patches between two versions of a chunk packed by NibbleAuto.packAuto, so that a chunk whose values change a
+ * little can be stored as the old version plus a small patch instead of a second full copy.  A patch lists the
+ * indices whose values changed, with their new values, and the new number of values, so it also covers values
+ * appended or cut off at the end.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Extended, then NibbleFormat.Format_Patch
+ *   +2   numValues of the new version, Int
+ *   +6   numValues of the old version, Int, checked when the patch is applied
+ *   +10  numChanges, Int
+ *   +14  the changed indices, increasing, as NibblePack.packDelta
+ *   +..  the new values at those indices as a NibblePackSigned.packDelta stream, with its own format code
+ * }}}
+ * Every index from the old numValues up to the new one is a change, as those values are new.
+ */
+object NibblePatch {
+  import NibbleFormat.{Format_Patch, Format_ZigZag_Delta}
+  import NibblePack.{subslice, unpackAllToSink, InputTooShort, InvalidHeader, InvalidParameter, NibbleError, Ok}
+
+  val HeaderBytes = 14
+
+  /**
+   * Writes a patch which turns the values of old into those of updated, both streams written by packAuto.
+   * Neither buffer is mutated.
+   * @return the final position within buf after writing, or the error from unpacking either stream
+   */
+  final def diff(old: DirectBuffer, updated: DirectBuffer,
+                 buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    for {
+      oldValues <- NibbleAuto.unpackAuto(view(old)).right
+      newValues <- NibbleAuto.unpackAuto(view(updated)).right
+    } yield diffValues(oldValues, newValues, buf, bufindex)
+
+  /**
+   * Writes a patch which turns the old values into the updated ones, see diff.
+   * @return the final position within buf after writing
+   */
+  final def diffValues(old: Array[Long], updated: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val indices = (0 until updated.size).filter(i => i >= old.size || old(i) != updated(i)).map(_.toLong).toArray
+    val countPos = NibbleFormat.putFormat(buf, bufindex, Format_Patch)
+    buf.putInt(countPos, updated.size, LITTLE_ENDIAN)
+    buf.putInt(countPos + 4, old.size, LITTLE_ENDIAN)
+    buf.putInt(countPos + 8, indices.size, LITTLE_ENDIAN)
+    val valuesPos = NibblePack.packDelta(indices, buf, countPos + 12)
+    NibblePackSigned.packDelta(indices.map(i => updated(i.toInt)), buf, valuesPos)
+  }
+
+  /**
+   * Applies a patch written by diff to old, a stream written by packAuto, packing the new values with packAuto.
+   * Since packAuto picks its codec from the values, the bytes are the same as those diff was given if they were
+   * packed by packAuto too.  Neither buffer is mutated.
+   * @return the final position within buf after writing, InvalidParameter if old does not have the number of
+   *         values the patch was made from, or InvalidHeader for a patch with a change outside the new values
+   */
+  final def applyPatch(old: DirectBuffer, patch: DirectBuffer,
+                       buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    for {
+      oldValues <- NibbleAuto.unpackAuto(view(old)).right
+      newValues <- patchValues(oldValues, view(patch)).right
+    } yield NibbleAuto.packAuto(newValues, buf, bufindex)
+
+  /**
+   * Applies a patch written by diff to the old values, see applyPatch.
+   * @param patch NOTE: mutated to wrap the bytes after the patch
+   * @return the new values, or an error as from applyPatch
+   */
+  final def patchValues(old: Array[Long], patch: DirectBuffer): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkFormat(patch, Format_Patch) match {
+      case Ok if patch.capacity < HeaderBytes - 2 =>
+        Left(InputTooShort(HeaderBytes - 2, patch.capacity))
+      case Ok =>
+        val numValues = patch.getInt(0, LITTLE_ENDIAN)
+        val oldValues = patch.getInt(4, LITTLE_ENDIAN)
+        val numChanges = patch.getInt(8, LITTLE_ENDIAN)
+        subslice(patch, HeaderBytes - 2)
+        if (numChanges < 0 || numChanges > numValues) {
+          Left(InvalidHeader("numChanges", numChanges))
+        } else if (numValues < 0 || numValues.toLong > old.size.toLong + numChanges) {
+          // Appended values must all be changes, so there cannot be more new values than that
+          Left(InvalidHeader("numValues", numValues))
+        } else if (oldValues != old.size) {
+          Left(InvalidParameter("old", old.size))
+        } else {
+          for {
+            indices <- NibblePack.unpackDelta(patch, numChanges).right
+            changes <- changedValues(patch, numChanges).right
+            values  <- applyChanges(old, numValues, indices, changes).right
+          } yield values
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  private def changedValues(patch: DirectBuffer, numChanges: Int): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkCount(numChanges, patch.capacity).toLeft(new Array[Long](numChanges)).right.flatMap { out =>
+      val res = NibbleFormat.checkFormat(patch, Format_ZigZag_Delta) match {
+        case Ok             => unpackAllToSink(patch, NibblePackSigned.ZigZagDeltaSink(out), numChanges)
+        case e: NibbleError => e
+      }
+      res match {
+        case Ok             => Right(out)
+        case e: NibbleError => Left(e)
+      }
+    }
+
+  // Checks that the indices are increasing, within the new values and cover every appended one
+  private def applyChanges(old: Array[Long], numValues: Int, indices: Array[Long],
+                           changes: Array[Long]): Either[NibbleError, Array[Long]] = {
+    val values = java.util.Arrays.copyOf(old, numValues)
+    var bad = -1L
+    var numAppended = 0
+    for { n <- 0 until indices.size optimized } {
+      val index = indices(n)
+      if (index >= numValues || (n > 0 && index == indices(n - 1))) {
+        if (bad < 0) bad = index
+      } else {
+        values(index.toInt) = changes(n)
+        if (index >= old.size) numAppended += 1
+      }
+    }
+    if (bad >= 0) Left(InvalidHeader("index", bad))
+    else if (numAppended < numValues - old.size) Left(InvalidHeader("numChanges", indices.size))
+    else Right(values)
+  }
+
+  private def view(compressed: DirectBuffer): DirectBuffer = new UnsafeBuffer(compressed, 0, compressed.capacity)
+}
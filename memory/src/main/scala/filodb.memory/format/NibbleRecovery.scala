@@ -0,0 +1,151 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * Best effort decoding of damaged streams, eg to salvage what is left of a corrupted chunk.  Where the other
+ * decoders return only the error, these also return the values decoded before it.  Streams whose metadata is
+ * damaged but whose blocks are intact can be rewritten whole, see repair.
+ */
+object NibbleRecovery {
+  import NibbleFormat._
+  import NibblePack.{unpack8, AccumulatorOverflow, InputTooShort, NibbleError, Ok, UnexpectedFormat, UnpackResult,
+                     UnsupportedVersion}
+
+  /**
+   * Unpacks numValues deltas like NibblePack.unpackDelta, but on an error keeps the values of every block
+   * decoded before it.  A block is only ever decoded whole, so a stream truncated or corrupted within a block
+   * loses that entire block, and a total overflowing Long.MaxValue ends the values just before it.
+   * @param compressed a DirectBuffer wrapping the compressed bytes.  Will be mutated, see NibblePack.unpackToSink.
+   * @return the values decoded, all numValues if there is no error, and the error which stopped decoding if any,
+   *         eg InputTooShort for a truncated stream.  An implausible numValues returns no values at all.
+   */
+  final def unpackLenient(compressed: DirectBuffer, numValues: Int): (Array[Long], Option[NibbleError]) =
+    NibbleFormat.checkCount(numValues, compressed.capacity) match {
+      case Some(e) => (Array.empty[Long], Some(e))
+      case None =>
+        val out = new Array[Long](numValues)
+        val sink = new NibbleSinks.CheckedDeltaSink(out, numValues)
+        var decoded = 0
+        var res: UnpackResult = Ok
+        while (decoded < numValues && res == Ok) {
+          res = if (compressed.capacity > 0) unpack8(compressed, sink) else InputTooShort(1, 0)
+          if (res == Ok) decoded = Math.min(decoded + 8, numValues)
+        }
+        (sink.overflowIndex, res) match {
+          case (-1, Ok)             => (out, None)
+          case (-1, e: NibbleError) => (java.util.Arrays.copyOf(out, decoded), Some(e))
+          case (index, _)           => (java.util.Arrays.copyOf(out, index), Some(AccumulatorOverflow(index)))
+        }
+    }
+
+  /**
+   * Rewrites a packDeltaCounted or CompressedVec stream whose count or skip table is damaged but whose blocks are
+   * intact, for operators salvaging a chunk.  The blocks are walked from their headers and copied as they are,
+   * and the count, and for a CompressedVec the footer offset and skip table, are worked out again from them.
+   * A stream with a checksum gets a new one over the repaired stream.
+   * A count is only kept if it fits the number of blocks.  Otherwise the values are taken to end at the last
+   * nonzero value of the last block, as the zero padding of a final block cannot be told from zero values, so a
+   * stream which ended on zeroes, or on repeated values for packDeltaCounted, is repaired with fewer values.
+   * The blocks of a packDeltaCounted stream are taken to run to the end of the stream, so streams with trailers
+   * cannot be repaired.  Those of a CompressedVec end where the rest of the stream is the size of the skip table
+   * for the blocks before it; blocksPerSkip is taken from the header, or is DefaultBlocksPerSkip if that is not
+   * positive.  Damaged blocks cannot be repaired.
+   * @param compressed the damaged stream.  The buffer is not mutated.
+   * @return the final position within buf after writing the repaired stream, or the error parsing a block,
+   *         or UnexpectedFormat for any other format
+   */
+  final def repair(compressed: DirectBuffer, buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] = {
+    val hasChecksum = compressed.capacity > 0 && (compressed.getByte(0) & ChecksumFlag) != 0
+    val streamBytes = compressed.capacity - (if (hasChecksum) ChecksumBytes else 0)
+    val repaired: Either[NibbleError, Int] = if (streamBytes < 1) {
+      Left(InputTooShort(compressed.capacity - streamBytes + 1, compressed.capacity))
+    } else {
+      val stream = new UnsafeBuffer(compressed, 0, streamBytes)
+      if (versionOf(stream) > FormatVersion) Left(UnsupportedVersion(versionOf(stream)))
+      else formatOf(stream) match {
+        case Format_Delta_Counted => repairCounted(stream, buf, bufindex)
+        case Format_Skip_Table    => repairSkipTable(stream, buf, bufindex)
+        case other                => Left(UnexpectedFormat(other))
+      }
+    }
+    repaired.right.map(endPos => if (hasChecksum) appendChecksum(buf, bufindex, endPos) else endPos)
+  }
+
+  private def repairCounted(stream: DirectBuffer, buf: MutableDirectBuffer,
+                            bufindex: Int): Either[NibbleError, Int] =
+    if (stream.capacity < CountedHeaderBytes) {
+      Left(InputTooShort(CountedHeaderBytes, stream.capacity))
+    } else {
+      walkBlocks(stream, CountedHeaderBytes, (pos, _) => pos >= stream.capacity).right.map { walk =>
+        buf.putByte(bufindex, versioned(Format_Delta_Counted))
+        buf.putInt(bufindex + CountOffset, walk.count(stream.getInt(CountOffset, LITTLE_ENDIAN)), LITTLE_ENDIAN)
+        buf.putBytes(bufindex + CountedHeaderBytes, stream, CountedHeaderBytes, walk.end - CountedHeaderBytes)
+        bufindex + walk.end
+      }
+    }
+
+  private def repairSkipTable(stream: DirectBuffer, buf: MutableDirectBuffer,
+                              bufindex: Int): Either[NibbleError, Int] = {
+    import CompressedVec._
+    if (stream.capacity < HeaderBytes) {
+      Left(InputTooShort(HeaderBytes, stream.capacity))
+    } else {
+      val headerSkip = stream.getShort(BlocksPerSkipOffset, LITTLE_ENDIAN).toInt
+      val blocksPerSkip = if (headerSkip > 0) headerSkip else DefaultBlocksPerSkip
+      def skipTableBytes(numBlocks: Int): Int = 4 * ((numBlocks + blocksPerSkip - 1) / blocksPerSkip)
+      walkBlocks(stream, HeaderBytes, (pos, n) => stream.capacity - pos == skipTableBytes(n)).right.map { walk =>
+        buf.putByte(bufindex, versioned(Format_Skip_Table))
+        buf.putInt(bufindex + NumValuesOffset, walk.count(stream.getInt(NumValuesOffset, LITTLE_ENDIAN)),
+                   LITTLE_ENDIAN)
+        buf.putShort(bufindex + BlocksPerSkipOffset, blocksPerSkip.toShort, LITTLE_ENDIAN)
+        buf.putInt(bufindex + FooterOffsetOffset, walk.end, LITTLE_ENDIAN)
+        buf.putBytes(bufindex + HeaderBytes, stream, HeaderBytes, walk.end - HeaderBytes)
+        var pos = bufindex + walk.end
+        for { b <- 0 until walk.offsets.size by blocksPerSkip } {
+          buf.putInt(pos, walk.offsets(b), LITTLE_ENDIAN)
+          pos += 4
+        }
+        pos
+      }
+    }
+  }
+
+  /**
+   * The blocks of a stream, from walkBlocks.
+   * @param offsets the position of each block
+   * @param end the position after the last block
+   * @param lastValues the number of values the last block holds at least: up to its last nonzero value
+   */
+  private final case class BlockWalk(offsets: Array[Int], end: Int, lastValues: Int) {
+    // The count from the header if it fits the number of blocks, otherwise the count the blocks hold at least
+    def count(headerCount: Int): Int =
+      if (headerCount >= 0 && (headerCount.toLong + 7) / 8 == offsets.size) headerCount
+      else if (offsets.isEmpty) 0
+      else (offsets.size - 1) * 8 + lastValues
+  }
+
+  // Parses the headers of the blocks from start until atEnd(position, number of blocks so far)
+  private def walkBlocks(stream: DirectBuffer, start: Int,
+                         atEnd: (Int, Int) => Boolean): Either[NibbleError, BlockWalk] = {
+    val offsets = new collection.mutable.ArrayBuilder.ofInt
+    var numBlocks = 0
+    var pos = start
+    var lastValues = 0
+    var error: Option[NibbleError] = None
+    while (error.isEmpty && !atEnd(pos, numBlocks)) {
+      NibbleBlocks.parse(stream, pos) match {
+        case Right(info) =>
+          offsets += pos
+          numBlocks += 1
+          pos += info.numBytes
+          lastValues = Math.max(32 - java.lang.Integer.numberOfLeadingZeros(info.bitmask), 1)
+        case Left(e) => error = Some(e)
+      }
+    }
+    error.toLeft(BlockWalk(offsets.result(), pos, lastValues))
+  }
+}
@@ -0,0 +1,168 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Delta encoding for counters which stay flat for long stretches, such as error counts.  Each run of at least
+ * MinRunValues zero deltas is stored as just its length, wherever it starts and ends, instead of as one zero
+ * block per 8 values.  Unlike constant blocks, runs are not tied to block boundaries, so a counter flat for a
+ * million samples takes a few bytes.  The deltas between runs are NibblePacked as by NibblePack.packDelta,
+ * including a 0 for any value lower than the one before.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Delta_Runs
+ *   +1   numValues, Int
+ *   +5   segments, until there are numValues values: the number of literal deltas, Int, the literal deltas as
+ *        NibblePack.packNonIncreasing, then the number of zero deltas after them, Int
+ * }}}
+ */
+object NibbleRuns {
+  import NibblePack.{blockSize, pack8With, packRemainder, subslice, tempArray, unpackAllToSink, InputTooShort,
+                     InvalidHeader, NibbleError, Ok, Sink, UnpackResult}
+
+  val HeaderBytes = 5
+  // A shorter run costs no more as zero blocks, one byte per 8, than as a segment of 8 bytes
+  val MinRunValues = 64
+
+  /**
+   * Packs increasing Long values as deltas with their runs of zero deltas shortened, writing
+   * NibbleFormat.Format_Delta_Runs first.
+   * @return the final position within the buffer after packing
+   */
+  final def packDeltaRuns(input: Array[Long], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putByte(bufindex, NibbleFormat.versioned(NibbleFormat.Format_Delta_Runs))
+    buf.putInt(bufindex + 1, input.size, LITTLE_ENDIAN)
+    var pos = bufindex + HeaderBytes
+    var segmentStart = 0
+    var i = 0
+    while (i < input.size) {
+      if (delta(input, i) == 0) {
+        var runEnd = i + 1
+        while (runEnd < input.size && delta(input, runEnd) == 0) runEnd += 1
+        if (runEnd - i >= MinRunValues) {
+          pos = packSegment(input, segmentStart, i, runEnd - i, buf, pos)
+          segmentStart = runEnd
+        }
+        i = runEnd
+      } else {
+        i += 1
+      }
+    }
+    if (segmentStart < input.size) pos = packSegment(input, segmentStart, input.size, 0, buf, pos)
+    pos
+  }
+
+  @inline private def delta(input: Array[Long], i: Int): Long = {
+    val last = if (i == 0) 0L else input(i - 1)
+    if (input(i) >= last) input(i) - last else 0L
+  }
+
+  // Packs the deltas of input(start until end) followed by a run of runLength zero deltas
+  private def packSegment(input: Array[Long], start: Int, end: Int, runLength: Int,
+                          buf: MutableDirectBuffer, bufindex: Int): Int = {
+    buf.putInt(bufindex, end - start, LITTLE_ENDIAN)
+    val inputArray = tempArray
+    val writer = LsbBitWriter.threadLocal
+    var pos = bufindex + 4
+    var i = start
+    while (i < end) {
+      inputArray((i - start) % 8) = delta(input, i)
+      i += 1
+      if ((i - start) % 8 == 0) {
+        pos = pack8With(inputArray, buf, pos, writer)
+      }
+    }
+    pos = packRemainder(inputArray, buf, pos, end - start, writer)
+    buf.putInt(pos, runLength, LITTLE_ENDIAN)
+    pos + 4
+  }
+
+  /**
+   * Unpacks a stream written by packDeltaRuns, using the count in its header.  The segment headers are all
+   * checked before the output is allocated, since a few bytes of runs can claim any number of values.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackDeltaRuns(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_Delta_Runs) match {
+      case Ok if compressed.capacity < HeaderBytes - 1 =>
+        Left(InputTooShort(HeaderBytes - 1, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        subslice(compressed, HeaderBytes - 1)
+        if (numValues < 0 || numValues > NibbleFormat.MaxDecodeValues) {
+          Left(InvalidHeader("numValues", numValues))
+        } else {
+          checkSegments(compressed, numValues).toLeft(new RunSink(new Array[Long](numValues))).right.flatMap { sink =>
+            unpackSegments(compressed, sink).right.map(_ => sink.outArray)
+          }
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  // Walks the segment headers and block sizes, without unpacking, checking that they add up to numValues
+  private def checkSegments(compressed: DirectBuffer, numValues: Int): Option[NibbleError] = {
+    var pos = 0
+    var valuesLeft = numValues
+    while (valuesLeft > 0) {
+      if (compressed.capacity < pos + 4) return Some(InputTooShort(pos + 4, compressed.capacity))
+      val numLiterals = compressed.getInt(pos, LITTLE_ENDIAN)
+      if (numLiterals < 0 || numLiterals > valuesLeft) return Some(InvalidHeader("numLiterals", numLiterals))
+      pos += 4
+      var blocksLeft = (numLiterals + 7) / 8
+      while (blocksLeft > 0) {
+        if (compressed.capacity < pos + 2) return Some(InputTooShort(pos + 2, compressed.capacity))
+        pos += blockSize(compressed, pos)
+        blocksLeft -= 1
+      }
+      if (compressed.capacity < pos + 4) return Some(InputTooShort(pos + 4, compressed.capacity))
+      val runLength = compressed.getInt(pos, LITTLE_ENDIAN)
+      if (runLength < 0 || runLength > valuesLeft - numLiterals) return Some(InvalidHeader("runLength", runLength))
+      pos += 4
+      valuesLeft -= numLiterals + runLength
+    }
+    None
+  }
+
+  // Unpacks segments already checked by checkSegments
+  private def unpackSegments(compressed: DirectBuffer, sink: RunSink): Either[NibbleError, Unit] = {
+    var res: UnpackResult = Ok
+    while (sink.i < sink.outArray.size && res == Ok) {
+      sink.literalsLeft = compressed.getInt(0, LITTLE_ENDIAN)
+      subslice(compressed, 4)
+      res = unpackAllToSink(compressed, sink, sink.literalsLeft)
+      if (res == Ok) {
+        sink.fillRun(compressed.getInt(0, LITTLE_ENDIAN))
+        subslice(compressed, 4)
+      }
+    }
+    res match {
+      case Ok             => Right(())
+      case e: NibbleError => Left(e)
+    }
+  }
+
+  // Adds up the literal deltas of a segment, then repeats the total for its run
+  private final class RunSink(val outArray: Array[Long]) extends Sink {
+    var i = 0
+    var literalsLeft = 0
+    private var total = 0L
+    final def process(data: Array[Long]): Unit = {
+      val numElems = Math.min(literalsLeft, 8)
+      for { n <- 0 until numElems optimized } {
+        total += data(n)
+        outArray(i + n) = total
+      }
+      i += numElems
+      literalsLeft -= numElems
+    }
+
+    def fillRun(runLength: Int): Unit = {
+      java.util.Arrays.fill(outArray, i, i + runLength, total)
+      i += runLength
+    }
+  }
+}
@@ -0,0 +1,98 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+/**
+ * Fixed-point Doubles, such as prices or readings with a known number of decimal places, stored as Longs times a
+ * scale factor kept in the header.  Each value is multiplied by the scale and rounded to a Long, so these pack
+ * as small ZigZag deltas where DoubleXORPack would see noisy mantissas.
+ * This is lossy: values unpack as the rounded Long divided by the scale, so any digits finer than 1 / scale are
+ * lost, eg 3.14159 at a scale of 1000 comes back as 3.142.  Values which already have no finer digits come back
+ * as the nearest Double to that decimal, which need not be the exact Double packed.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Extended, then NibbleFormat.Format_Scaled_Double
+ *   +2   numValues, Int
+ *   +6   the scale, a positive Int
+ *   +10  the scaled values as a NibblePackSigned.packDelta stream, with its own format code
+ * }}}
+ */
+object NibbleScaled {
+  import NibbleFormat.{Format_Scaled_Double, Format_ZigZag_Delta}
+  import NibblePack.{subslice, unpackAllToSink, InputTooShort, InvalidHeader, InvalidParameter, NibbleError, Ok}
+
+  val HeaderBytes = 10
+
+  /**
+   * Packs the values multiplied by scale and rounded, see above.
+   * @param rounding how values between two multiples of 1 / scale are rounded: half up by default, or down or up
+   *                 with Floor or Ceil, see Rounding.toLong
+   * @return the final position within the buffer after packing, or InvalidParameter for a scale which is not
+   *         positive or a value which is not finite or does not fit in a Long once scaled.  Nothing is written
+   *         on an error.
+   */
+  final def packScaled(values: Array[Double], scale: Int, buf: MutableDirectBuffer, bufindex: Int,
+                       rounding: Rounding = Rounding.Nearest): Either[NibbleError, Int] =
+    if (scale <= 0) {
+      Left(InvalidParameter("scale", scale))
+    } else {
+      val scaled = new Array[Long](values.size)
+      var err: Option[NibbleError] = None
+      for { i <- 0 until values.size optimized } {
+        val v = values(i) * scale
+        // Long.MaxValue rounds up to 2^63 as a Double, so the bounds are exclusive
+        if (err.isEmpty && !(v > -9.223372036854775808e18 && v < 9.223372036854775808e18)) {
+          err = Some(InvalidParameter("value", values(i)))
+        }
+        scaled(i) = Rounding.toLong(v, rounding)
+      }
+      err.toLeft(()).right.map { _ =>
+        val countPos = NibbleFormat.putFormat(buf, bufindex, Format_Scaled_Double)
+        buf.putInt(countPos, values.size, LITTLE_ENDIAN)
+        buf.putInt(countPos + 4, scale, LITTLE_ENDIAN)
+        NibblePackSigned.packDelta(scaled, buf, countPos + 8)
+      }
+    }
+
+  /**
+   * Unpacks a stream written by packScaled, using the count in its header, dividing each value by the scale.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values
+   */
+  final def unpackScaled(compressed: DirectBuffer): Either[NibbleError, Array[Double]] =
+    NibbleFormat.checkFormat(compressed, Format_Scaled_Double) match {
+      case Ok if compressed.capacity < HeaderBytes - 2 =>
+        Left(InputTooShort(HeaderBytes - 2, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        val scale = compressed.getInt(4, LITTLE_ENDIAN)
+        subslice(compressed, HeaderBytes - 2)
+        if (scale <= 0) {
+          Left(InvalidHeader("scale", scale))
+        } else {
+          NibbleFormat.checkCount(numValues, compressed.capacity).toLeft(new Array[Long](numValues)).right
+            .flatMap { scaled => unpackValues(compressed, scaled, scale) }
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  private def unpackValues(compressed: DirectBuffer, scaled: Array[Long],
+                           scale: Int): Either[NibbleError, Array[Double]] = {
+    val res = NibbleFormat.checkFormat(compressed, Format_ZigZag_Delta) match {
+      case Ok             => unpackAllToSink(compressed, NibblePackSigned.ZigZagDeltaSink(scaled), scaled.size)
+      case e: NibbleError => e
+    }
+    res match {
+      case Ok             =>
+        val values = new Array[Double](scaled.size)
+        for { i <- 0 until scaled.size optimized } {
+          values(i) = scaled(i) / scale.toDouble
+        }
+        Right(values)
+      case e: NibbleError => Left(e)
+    }
+  }
+}
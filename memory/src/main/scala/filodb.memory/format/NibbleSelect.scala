@@ -0,0 +1,280 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Unpacks only selected positions of a NibblePacked vector, for queries which need a handful of values out of
+ * a long vector.  Blocks which hold none of the wanted values are skipped by reading just their headers.
+ * For delta-encoded vectors every value depends on all the deltas before it, so every block up to the last
+ * wanted value is still unpacked, but only into a running total rather than an output array.
+ */
+object NibbleSelect {
+  import NibblePack.{unpack8, unpackAllToSink, IndexOutOfRange, InputTooShort, InvalidHeader, NibbleError, Ok,
+                     Sink}
+  import NibbleSinks.BoundedSink
+
+  /**
+   * Unpacks the values at the given indices from a stream written by NibblePack.packNonIncreasing.
+   * @param compressed the packed blocks.  The buffer is not mutated.
+   * @param numValues the number of values which were packed
+   * @param indices the wanted positions, sorted in increasing order, each from 0 until numValues.  May repeat.
+   * @return the values at the indices, in the same order, or the NibbleError if a needed block is malformed.
+   *         An index out of range is IndexOutOfRange(index, index + 1, numValues), and one lower than the index
+   *         before it is IndexOutOfRange(previous index, index, numValues).
+   */
+  final def unpackIndices(compressed: DirectBuffer, numValues: Int,
+                          indices: Array[Int]): Either[NibbleError, Array[Long]] =
+    select(compressed, numValues, indices, false)
+
+  /**
+   * Like unpackIndices but for a stream written by NibblePack.packDelta, returning the original values.
+   */
+  final def unpackDeltaIndices(compressed: DirectBuffer, numValues: Int,
+                               indices: Array[Int]): Either[NibbleError, Array[Long]] =
+    select(compressed, numValues, indices, true)
+
+  /**
+   * Returns the last of numValues values in a stream written by NibblePack.packDelta, eg for appending to it or
+   * for rate calculations.  Every delta still has to be added up, but no output array is allocated.
+   */
+  final def lastValueDelta(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Long] =
+    if (numValues <= 0) Left(InvalidHeader("numValues", numValues))
+    else unpackDeltaIndices(compressed, numValues, Array(numValues - 1)).right.map(_(0))
+
+  /**
+   * Returns the first value of a stream written by NibblePack.packDelta or packNonIncreasing -- they are the
+   * same, as the first delta is from 0 -- unpacking only the first block.
+   */
+  final def firstValue(compressed: DirectBuffer): Either[NibbleError, Long] =
+    unpackIndices(compressed, 1, Array(0)).right.map(_(0))
+
+  /**
+   * Unpacks the values from start until end of a stream written by NibblePack.packNonIncreasing, eg for a window
+   * of a longer vector.  Blocks before the one holding start are skipped by reading just their headers, and
+   * unpacking stops after the block holding end - 1.
+   * @param compressed the packed blocks.  The buffer is not mutated.
+   * @param numValues the number of values which were packed
+   * @return the end - start values, or the NibbleError if a needed block is malformed, or
+   *         IndexOutOfRange(start, end, numValues) unless 0 <= start <= end <= numValues
+   */
+  final def unpackRange(compressed: DirectBuffer, numValues: Int,
+                        start: Int, end: Int): Either[NibbleError, Array[Long]] =
+    selectRange(compressed, numValues, start, end, false)
+
+  /**
+   * Like unpackRange but for a stream written by NibblePack.packDelta, returning the original values.  The
+   * blocks before start are only added up to get the value just before it, see lastValueDelta.
+   */
+  final def unpackDeltaRange(compressed: DirectBuffer, numValues: Int,
+                             start: Int, end: Int): Either[NibbleError, Array[Long]] =
+    selectRange(compressed, numValues, start, end, true)
+
+  /**
+   * Returns the nonzero values of a stream written by NibblePack.packNonIncreasing with their indices, in index
+   * order, for sparse vectors which are mostly zeroes.  Blocks of all zeroes are skipped by reading just their
+   * bitmask, and of the other blocks only the positions set in the bitmask are returned.
+   * A malformed block is returned as an error and ends the iteration.
+   * @param compressed the packed blocks.  The buffer is not mutated.
+   * @param numValues the number of values which were packed
+   */
+  final def nonzero(compressed: DirectBuffer, numValues: Int): Iterator[Either[NibbleError, (Int, Long)]] =
+    new NonzeroIterator(compressed, numValues)
+
+  /**
+   * Returns a bitmap of the positions of a stream written by NibblePack.packNonIncreasing whose values are
+   * greater than threshold, for pushing filters down to packed data.  Blocks are unpacked one at a time and
+   * compared without allocating for the values.  The other select methods are the same but for other comparisons,
+   * and the Delta versions are for streams written by NibblePack.packDelta, comparing the original values.
+   * Values are compared as signed Longs.
+   * @param compressed the packed blocks.  The buffer is not mutated.
+   * @return the bitmap, (numValues + 63) / 64 Longs with bit i % 64 of word i / 64 set for a matching value i,
+   *         or the NibbleError if the stream is malformed or holds fewer than numValues values, or
+   *         ImplausibleCount for a numValues the stream could not hold
+   */
+  final def selectGt(compressed: DirectBuffer, numValues: Int, threshold: Long): Either[NibbleError, Array[Long]] =
+    selectWhere(compressed, numValues, false, _ > threshold)
+  final def selectLt(compressed: DirectBuffer, numValues: Int, threshold: Long): Either[NibbleError, Array[Long]] =
+    selectWhere(compressed, numValues, false, _ < threshold)
+  final def selectEq(compressed: DirectBuffer, numValues: Int, value: Long): Either[NibbleError, Array[Long]] =
+    selectWhere(compressed, numValues, false, _ == value)
+
+  final def selectGtDelta(compressed: DirectBuffer, numValues: Int,
+                          threshold: Long): Either[NibbleError, Array[Long]] =
+    selectWhere(compressed, numValues, true, _ > threshold)
+  final def selectLtDelta(compressed: DirectBuffer, numValues: Int,
+                          threshold: Long): Either[NibbleError, Array[Long]] =
+    selectWhere(compressed, numValues, true, _ < threshold)
+  final def selectEqDelta(compressed: DirectBuffer, numValues: Int, value: Long): Either[NibbleError, Array[Long]] =
+    selectWhere(compressed, numValues, true, _ == value)
+
+  private def selectWhere(compressed: DirectBuffer, numValues: Int, isDelta: Boolean,
+                          matches: Long => Boolean): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkCount(numValues, compressed.capacity).toLeft(()).right.flatMap { _ =>
+      val sink = new BitmapSink(numValues, isDelta, matches)
+      unpackAllToSink(new UnsafeBuffer(compressed, 0, compressed.capacity), sink, numValues) match {
+        case Ok             => Right(sink.bitmap)
+        case e: NibbleError => Left(e)
+      }
+    }
+
+  private def select(compressed: DirectBuffer, numValues: Int, indices: Array[Int],
+                     isDelta: Boolean): Either[NibbleError, Array[Long]] = {
+    for { k <- 0 until indices.size optimized } {
+      val index = indices(k)
+      if (index < 0 || index >= numValues) return Left(IndexOutOfRange(index, index + 1L, numValues))
+      if (k > 0 && index < indices(k - 1)) return Left(IndexOutOfRange(indices(k - 1), index, numValues))
+    }
+    val sink = new SelectSink(indices, isDelta)
+    run(compressed, sink, isDelta).right.map(_ => sink.out)
+  }
+
+  private def selectRange(compressed: DirectBuffer, numValues: Int, start: Int, end: Int,
+                          isDelta: Boolean): Either[NibbleError, Array[Long]] = {
+    if (start < 0 || start > end || end > numValues) {
+      Left(IndexOutOfRange(start, end, numValues))
+    } else {
+      val sink = new RangeSink(start, end, isDelta)
+      run(compressed, sink, isDelta).right.map(_ => sink.out)
+    }
+  }
+
+  // Unpacks blocks into the sink until it has all the values it wants, skipping the blocks it needs none of
+  private def run(compressed: DirectBuffer, sink: PickSink, isDelta: Boolean): Either[NibbleError, Unit] = {
+    val view = new UnsafeBuffer(compressed, 0, compressed.capacity)
+    var pos = 0
+    while (!sink.done) {
+      if (pos >= compressed.capacity) return Left(InputTooShort(1, 0))
+      if (!isDelta && sink.nextWanted / 8 > sink.blockStart / 8) {
+        NibbleBlocks.parse(compressed, pos) match {
+          case Right(info) => pos += info.numBytes
+                              sink.blockStart += 8
+          case Left(e)     => return Left(e)
+        }
+      } else {
+        view.wrap(compressed, pos, compressed.capacity - pos)
+        unpack8(view, sink) match {
+          case Ok             => pos = compressed.capacity - view.capacity
+          case e: NibbleError => return Left(e)
+        }
+      }
+    }
+    Right(())
+  }
+
+  // A sink which keeps some of the values of the blocks, keeping a running total of deltas if isDelta
+  private abstract class PickSink(isDelta: Boolean) extends Sink {
+    var blockStart = 0     // the index of the first value in the next block
+    protected var total = 0L
+    def done: Boolean
+    def nextWanted: Int
+    // Called with each value of a block in turn, after adding it to the total if isDelta
+    protected def pick(index: Int, value: Long): Unit
+    final def process(data: Array[Long]): Unit = {
+      for { n <- 0 until 8 optimized } {
+        if (isDelta) total += data(n)
+        pick(blockStart + n, if (isDelta) total else data(n))
+      }
+      blockStart += 8
+    }
+  }
+
+  // Picks out the values at the indices
+  private final class SelectSink(indices: Array[Int], isDelta: Boolean) extends PickSink(isDelta) {
+    val out = new Array[Long](indices.size)
+    private var k = 0              // the next of the indices to fill
+    final def done: Boolean = k >= indices.size
+    final def nextWanted: Int = indices(k)
+    protected def pick(index: Int, value: Long): Unit =
+      while (k < indices.size && indices(k) == index) {
+        out(k) = value
+        k += 1
+      }
+  }
+
+  // Picks out the values from start until end
+  private final class RangeSink(start: Int, end: Int, isDelta: Boolean) extends PickSink(isDelta) {
+    val out = new Array[Long](end - start)
+    final def done: Boolean = start == end || blockStart >= end
+    final def nextWanted: Int = start
+    protected def pick(index: Int, value: Long): Unit = if (index >= start && index < end) out(index - start) = value
+  }
+
+  // Unpacks one block with a nonzero value at a time, keeping the positions set in its bitmask
+  private final class NonzeroIterator(compressed: DirectBuffer, numValues: Int)
+  extends Iterator[Either[NibbleError, (Int, Long)]] with Sink {
+    private val view = new UnsafeBuffer(compressed, 0, compressed.capacity)
+    private val values = new Array[Long](8)
+    private val positions = new Array[Int](8)
+    private var numPositions = 0
+    private var k = 0              // the next of the positions to return
+    private var pos = 0
+    private var blockStart = -8
+    private var error: Option[NibbleError] = None
+    private var failed = false
+
+    final def process(data: Array[Long]): Unit = System.arraycopy(data, 0, values, 0, 8)
+
+    final def hasNext: Boolean = {
+      advance()
+      !failed && (error.isDefined || k < numPositions)
+    }
+
+    final def next(): Either[NibbleError, (Int, Long)] =
+      if (!hasNext) {
+        throw new NoSuchElementException("no more nonzero values")
+      } else if (error.isDefined) {
+        failed = true
+        Left(error.get)
+      } else {
+        k += 1
+        Right((blockStart + positions(k - 1), values(positions(k - 1))))
+      }
+
+    // Moves on to the next block with a nonzero value before numValues, once the current one has none left
+    private def advance(): Unit =
+      while (error.isEmpty && k >= numPositions && blockStart + 8 < numValues) {
+        blockStart += 8
+        k = 0
+        numPositions = 0
+        if (pos >= compressed.capacity) {
+          error = Some(InputTooShort(1, 0))
+        } else {
+          NibbleBlocks.parse(compressed, pos) match {
+            case Right(info) if info.bitmask == 0 => pos += info.numBytes
+            case Right(info) =>
+              view.wrap(compressed, pos, compressed.capacity - pos)
+              unpack8(view, this) match {
+                case Ok             => pos += info.numBytes
+                                       keepPositions(info.bitmask)
+                case e: NibbleError => error = Some(e)
+              }
+            case Left(e) => error = Some(e)
+          }
+        }
+      }
+
+    private def keepPositions(bitmask: Int): Unit =
+      for { n <- 0 until 8 optimized } {
+        if ((bitmask & (1 << n)) != 0 && blockStart + n < numValues) {
+          positions(numPositions) = n
+          numPositions += 1
+        }
+      }
+  }
+
+  // Sets the bit of each value which matches, adding up deltas if isDelta
+  private final class BitmapSink(numValues: Int, isDelta: Boolean, matches: Long => Boolean)
+  extends BoundedSink(numValues) {
+    val bitmap = new Array[Long]((numValues + 63) / 64)
+    private var index = 0
+    private var current = 0L
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        current = if (isDelta) current + data(n) else data(n)
+        if (matches(current)) bitmap(index >> 6) |= 1L << (index & 63)
+        index += 1
+      }
+  }
+}
@@ -0,0 +1,125 @@
+package filodb.memory.format
+
+import debox.Buffer
+import scalaxy.loops._
+
+/**
+ * General purpose Sinks for NibblePack.unpackToSink and unpack8, which consume unpacked values directly --
+ * for example to sum up or count a compressed vector in one pass without materializing it.
+ * Apart from CheckedDeltaSink, these see the raw values exactly as they were packed, ie deltas for a stream
+ * written by packDelta.
+ * Sinks can be reused for another unpack by calling reset() in between.
+ */
+object NibbleSinks {
+  import NibblePack.Sink
+
+  /**
+   * A Sink which only passes on the first numValues values, since unpack8 always pads the last block out to 8.
+   */
+  abstract class BoundedSink(numValues: Int) extends Sink {
+    private var i = 0
+    final def process(data: Array[Long]): Unit = {
+      val numElems = Math.min(numValues - i, 8)
+      if (numElems > 0) processValues(data, numElems)
+      i += 8
+    }
+
+    // Processes the first numElems values of data
+    def processValues(data: Array[Long], numElems: Int): Unit
+
+    def reset(): Unit = { i = 0 }
+  }
+
+  /**
+   * Appends every value to an unboxed, growable Buffer.  Useful when the number of values is not known ahead
+   * of time; otherwise an array sized to the number of values is cheaper.
+   */
+  final class BufferSink(numValues: Int) extends BoundedSink(numValues) {
+    val values = Buffer.empty[Long]
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } { values += data(n) }
+    override def reset(): Unit = {
+      super.reset()
+      values.clear()
+    }
+  }
+
+  /**
+   * Keeps only a running total of the values.  Overflow wraps around, as with any Long addition.
+   */
+  final class SumSink(numValues: Int) extends BoundedSink(numValues) {
+    var sum = 0L
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } { sum += data(n) }
+    override def reset(): Unit = {
+      super.reset()
+      sum = 0L
+    }
+  }
+
+  /**
+   * Counts the values which are not zero, for example the number of changes in a delta-packed vector.
+   */
+  final class CountNonZeroSink(numValues: Int) extends BoundedSink(numValues) {
+    var count = 0
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } { if (data(n) != 0) count += 1 }
+    override def reset(): Unit = {
+      super.reset()
+      count = 0
+    }
+  }
+
+  /**
+   * Adds up deltas such as those written by packDelta into numValues elements of outArray from start, leaving the
+   * rest of a larger array alone.  Unlike NibblePack.DeltaSink it checks the running total: since packDelta only
+   * packs non-negative increasing values, a total past Long.MaxValue means corrupt input, and the index of the
+   * first such value is kept in overflowIndex (-1 if none) instead of silently wrapping around.
+   * @param start the index in outArray to write the first value to, so that pieces of a vector can be unpacked
+   *              into one array.  overflowIndex is an index into outArray too.
+   * @param base the total to add the first delta to, eg the last value of the chunk before
+   */
+  final class CheckedDeltaSink(outArray: Array[Long], numValues: Int, start: Int = 0, base: Long = 0L)
+  extends BoundedSink(numValues) {
+    require(start >= 0 && outArray.size >= start.toLong + numValues)
+    var overflowIndex = -1
+    private var current = base
+    private var i = start
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        val next = current + data(n)
+        if ((data(n) < 0 || next < 0) && overflowIndex < 0) overflowIndex = i
+        current = next
+        outArray(i) = current
+        i += 1
+      }
+    override def reset(): Unit = {
+      super.reset()
+      overflowIndex = -1
+      current = base
+      i = start
+    }
+  }
+
+  /**
+   * Adds up deltas such as those written by packDelta like NibblePack.DeltaSink, but writes each total into
+   * outArray as a Double, for feeding integer vectors straight into floating point aggregations.  Totals up to
+   * 2^53 convert exactly; larger ones are rounded to the nearest Double.
+   */
+  final class DoubleDeltaSink(outArray: Array[Double], numValues: Int) extends BoundedSink(numValues) {
+    require(outArray.size >= numValues)
+    private var current = 0L
+    private var i = 0
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        current += data(n)
+        outArray(i) = current.toDouble
+        i += 1
+      }
+    override def reset(): Unit = {
+      super.reset()
+      current = 0L
+      i = 0
+    }
+  }
+}
@@ -0,0 +1,279 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Operations which join or cut NibblePacked streams without unpacking and repacking all of them, eg for appending
+ * chunks over time or keeping a window of values.  Complete blocks are copied verbatim, and only the blocks at the
+ * boundary are recomputed.
+ * The streams are raw pack output without any header, except for rebaseDelta, so the number of values in each
+ * has to be passed in.
+ * The input buffers are not mutated.
+ */
+object NibbleSplice {
+  import NibblePack.{pack8, unpackAllToSink, AccumulatorOverflow, InputTooShort, InvalidParameter, NibbleError, Ok,
+                     Packer}
+  import NibblePackSigned.BaseHeaderBytes
+
+  /**
+   * Concatenates two streams written by packNonIncreasing, writing the same bytes as packNonIncreasing of all
+   * the values of a followed by all the values of b.
+   * If numA is a multiple of 8 the blocks of b are copied verbatim, otherwise b has to be repacked to line up
+   * with the last, partial block of a.
+   * @return the final position within buf after writing
+   */
+  final def concat(a: DirectBuffer, numA: Int, b: DirectBuffer, numB: Int,
+                   buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    splice(a, numA, b, numB, buf, bufindex, false)
+
+  /**
+   * Concatenates two streams written by packDelta, writing the same bytes as packDelta of all the values of a
+   * followed by all the values of b, as long as the values of a never decrease.  The first delta of b is rebased
+   * against the last value of a, which means reading the deltas of a (though not unpacking them into an array).
+   * A drop in a was packed as a 0 delta, so the deltas of a then add up to more than its last value, and the first
+   * delta of b comes out smaller than packDelta of the joined values would write.
+   * @return the final position within buf after writing
+   */
+  final def concatDelta(a: DirectBuffer, numA: Int, b: DirectBuffer, numB: Int,
+                        buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    splice(a, numA, b, numB, buf, bufindex, true)
+
+  /**
+   * Keeps just the first keep values of a stream, writing the same bytes as packing only those values.  Works for
+   * streams from both packNonIncreasing and packDelta, as the first deltas only depend on the first values.
+   * The first keep / 8 blocks are copied verbatim, and only the final partial block is unpacked and repacked.
+   * The stream does not record its count, so a keep past the last value but within its final block is not
+   * caught: the zeros padding that block come back as values.
+   * @return the final position within buf after writing, or InputTooShort if the stream ends before the block
+   *         holding value keep - 1
+   */
+  final def truncate(compressed: DirectBuffer, keep: Int,
+                     buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    for {
+      fullBytes <- blocksBytes(compressed, keep / 8).right
+      tail      <- rawValues(view(compressed, fullBytes), keep % 8).right
+    } yield {
+      buf.putBytes(bufindex, compressed, 0, fullBytes)
+      val packer = new Packer(buf, bufindex + fullBytes)
+      tail.foreach(packer.add)
+      packer.finish()
+    }
+
+  /**
+   * Splits a stream written by packNonIncreasing at index, eg for rebalancing chunks, writing the same bytes as
+   * packNonIncreasing of the values before index into left and of the values from index on into right.
+   * The left half is written as by truncate.  If index is a multiple of 8 the blocks after the one holding index
+   * are copied verbatim into right, otherwise they no longer line up and the right half is repacked.
+   * @return the final positions within left and right after writing, InvalidParameter for an index outside
+   *         0 to numValues, or InputTooShort if the stream has fewer values
+   */
+  final def splitAt(compressed: DirectBuffer, numValues: Int, index: Int,
+                    left: MutableDirectBuffer, leftIndex: Int,
+                    right: MutableDirectBuffer, rightIndex: Int): Either[NibbleError, (Int, Int)] =
+    split(compressed, numValues, index, left, leftIndex, right, rightIndex, false)
+
+  /**
+   * Like splitAt but for a stream written by packDelta.  The first delta of the right half is rebased to be
+   * from 0, which means reading the deltas before index (though not unpacking them into an array).  As for
+   * concatDelta, the right half is the same bytes as packDelta of its values only if the values before index
+   * never decrease, as after a drop the deltas add up to more than the values.
+   */
+  final def splitAtDelta(compressed: DirectBuffer, numValues: Int, index: Int,
+                         left: MutableDirectBuffer, leftIndex: Int,
+                         right: MutableDirectBuffer, rightIndex: Int): Either[NibbleError, (Int, Int)] =
+    split(compressed, numValues, index, left, leftIndex, right, rightIndex, true)
+
+  /**
+   * Packs increasing values like packDelta, but as a continuation of a chunk whose last value was last: the first
+   * delta is from last instead of from 0, so the chunk does not repeat an absolute value up front.  The bytes are
+   * the same as after the blocks of last's chunk in packDelta of both chunks, when that chunk is a multiple of 8.
+   * A first value lower than last packs a 0 delta, as packDelta does.
+   * @return the final position within buf after packing
+   */
+  final def packDeltaContinued(input: Array[Long], last: Long, buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val packer = new Packer(buf, bufindex)
+    var prev = last
+    input.foreach { value =>
+      packer.add(if (value >= prev) value - prev else 0L)
+      prev = value
+    }
+    packer.finish()
+  }
+
+  /**
+   * Unpacks numValues values written by packDeltaContinued, adding the first delta to base, the last value of the
+   * chunk before, instead of to 0.  The total is checked as in NibblePack.unpackDelta.
+   * @return the values, or InvalidParameter for a negative base, InputTooShort if the stream has fewer values,
+   *         or another error as from unpackDelta
+   */
+  final def unpackDeltaContinued(compressed: DirectBuffer, numValues: Int,
+                                 base: Long): Either[NibbleError, Array[Long]] =
+    NibbleFormat.checkCount(numValues, compressed.capacity) match {
+      case Some(e)          => Left(e)
+      case None if base < 0 => Left(InvalidParameter("base", base))
+      case None =>
+        val out = new Array[Long](numValues)
+        val sink = new NibbleSinks.CheckedDeltaSink(out, numValues, 0, base)
+        unpackAllToSink(view(compressed, 0), sink, numValues) match {
+          case Ok if sink.overflowIndex >= 0 => Left(AccumulatorOverflow(sink.overflowIndex))
+          case Ok                            => Right(out)
+          case e: NibbleError                => Left(e)
+        }
+    }
+
+  /**
+   * Rewrites a stream written by NibblePackSigned.packDeltaFromBase so that its deltas run from newBase instead
+   * of the stored base, eg to stitch a chunk onto another which ends at a different value.  The deltas are kept
+   * as they are, so this copies the stream and rewrites the base in its header.  The deltas are read though, to
+   * check that no value from newBase on goes past the range of a Long, taking each delta as the exact difference
+   * between two values.
+   * @return the final position within buf after writing, or AccumulatorOverflow with the index of the first
+   *         value which would overflow, InputTooShort if the stream has fewer values, or another error as from
+   *         NibblePackSigned.unpackDeltaFromBase.  Nothing is written on an error.
+   */
+  final def rebaseDelta(compressed: DirectBuffer, numValues: Int, newBase: Long,
+                        buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] = {
+    val deltas = view(compressed, 0)
+    val res = NibbleFormat.checkCount(numValues, compressed.capacity).getOrElse {
+      NibbleFormat.checkFormat(deltas, NibbleFormat.Format_ZigZag_Base) match {
+        case Ok if deltas.capacity < BaseHeaderBytes - 1 => InputTooShort(BaseHeaderBytes, deltas.capacity + 1)
+        case Ok =>
+          NibblePack.subslice(deltas, BaseHeaderBytes - 1)
+          val sink = new RebaseSink(numValues, newBase)
+          unpackAllToSink(deltas, sink, numValues) match {
+            case Ok if sink.overflowIndex >= 0 => AccumulatorOverflow(sink.overflowIndex)
+            case other                         => other
+          }
+        case e: NibbleError => e
+      }
+    }
+    res match {
+      case Ok =>
+        val numBytes = compressed.capacity - deltas.capacity
+        buf.putBytes(bufindex, compressed, 0, numBytes)
+        buf.putLong(bufindex + 1, newBase, LITTLE_ENDIAN)
+        Right(bufindex + numBytes)
+      case e: NibbleError => Left(e)
+    }
+  }
+
+  private def split(compressed: DirectBuffer, numValues: Int, index: Int,
+                    left: MutableDirectBuffer, leftIndex: Int, right: MutableDirectBuffer, rightIndex: Int,
+                    isDelta: Boolean): Either[NibbleError, (Int, Int)] =
+    if (index < 0 || index > numValues) Left(InvalidParameter("index", index)) else {
+      for {
+        leftEnd   <- truncate(compressed, index, left, leftIndex).right
+        skipBytes <- blocksBytes(compressed, index / 8).right
+        prefix    <- (if (isDelta) NibbleAggregations.sum(view(compressed, 0), index) else Right(0L)).right
+        rightEnd  <- {
+          val rest = view(compressed, skipBytes)
+          val rebase = (value: Long) => value + prefix
+          if (index % 8 == 0) appendAligned(rest, numValues - index, rebase, right, rightIndex)
+          else repackFrom(rest, numValues - index / 8 * 8, index % 8, rebase, right, rightIndex)
+        }.right
+      } yield (leftEnd, rightEnd)
+    }
+
+  // Repacks the values of the stream from the first'th on, rebasing the first of them
+  private def repackFrom(compressed: DirectBuffer, numValues: Int, first: Int, rebase: Long => Long,
+                         buf: MutableDirectBuffer, pos: Int): Either[NibbleError, Int] =
+    rawValues(compressed, numValues).right.map { values =>
+      val packer = new Packer(buf, pos)
+      if (first < values.size) packer.add(rebase(values(first)))
+      for { i <- first + 1 until values.size } { packer.add(values(i)) }
+      packer.finish()
+    }
+
+  private def splice(a: DirectBuffer, numA: Int, b: DirectBuffer, numB: Int,
+                     buf: MutableDirectBuffer, bufindex: Int, isDelta: Boolean): Either[NibbleError, Int] =
+    for {
+      fullBytes <- blocksBytes(a, numA / 8).right
+      lastA     <- (if (isDelta) NibbleAggregations.sum(view(a, 0), numA) else Right(0L)).right
+      tail      <- rawValues(view(a, fullBytes), numA % 8).right
+      endPos    <- {
+        buf.putBytes(bufindex, a, 0, fullBytes)
+        val rebase = (value: Long) => if (!isDelta) value else if (value >= lastA) value - lastA else 0L
+        if (tail.isEmpty) appendAligned(b, numB, rebase, buf, bufindex + fullBytes)
+        else appendRepacked(tail, b, numB, rebase, buf, bufindex + fullBytes)
+      }.right
+    } yield endPos
+
+  // Repacks the first block of b with its first value rebased, then copies the other blocks
+  private def appendAligned(b: DirectBuffer, numB: Int, rebase: Long => Long,
+                            buf: MutableDirectBuffer, pos: Int): Either[NibbleError, Int] =
+    if (numB == 0) Right(pos) else {
+      for {
+        first     <- rawValues(view(b, 0), Math.min(numB, 8)).right
+        firstSize <- blocksBytes(b, 1).right
+        restBytes <- blocksBytes(view(b, firstSize), (numB + 7) / 8 - 1).right
+      } yield {
+        val block = new Array[Long](8)
+        System.arraycopy(first, 0, block, 0, first.size)
+        block(0) = rebase(block(0))
+        val restPos = pack8(block, buf, pos)
+        buf.putBytes(restPos, b, firstSize, restBytes)
+        restPos + restBytes
+      }
+    }
+
+  // Repacks the values at the end of a and all of b, as the blocks of b no longer line up
+  private def appendRepacked(tail: Array[Long], b: DirectBuffer, numB: Int, rebase: Long => Long,
+                             buf: MutableDirectBuffer, pos: Int): Either[NibbleError, Int] =
+    rawValues(view(b, 0), numB).right.map { values =>
+      if (values.nonEmpty) values(0) = rebase(values(0))
+      val packer = new Packer(buf, pos)
+      tail.foreach(packer.add)
+      values.foreach(packer.add)
+      packer.finish()
+    }
+
+  // Returns the number of bytes taken up by the first numBlocks blocks, checking each block header
+  private[format] def blocksBytes(compressed: DirectBuffer, numBlocks: Int): Either[NibbleError, Int] = {
+    var result: Either[NibbleError, Int] = Right(0)
+    var i = 0
+    while (i < numBlocks && result.isRight) {
+      val pos = result.right.get
+      result = if (pos >= compressed.capacity) Left(InputTooShort(1, 0))
+               else NibbleBlocks.parse(compressed, pos).right.map(pos + _.numBytes)
+      i += 1
+    }
+    result
+  }
+
+  // Unpacks the first numValues values as they were packed, without adding up deltas
+  private[format] def rawValues(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Array[Long]] = {
+    val sink = new NibbleSinks.BufferSink(numValues)
+    unpackAllToSink(compressed, sink, numValues) match {
+      case Ok             => Right(sink.values.toArray)
+      case e: NibbleError => Left(e)
+    }
+  }
+
+  // Adds up ZigZag deltas from base without keeping the values, noting the first whose total overflows
+  private final class RebaseSink(numValues: Int, base: Long) extends NibbleSinks.BoundedSink(numValues) {
+    var overflowIndex = -1
+    private var current = base
+    private var i = 0
+    final def processValues(data: Array[Long], numElems: Int): Unit =
+      for { n <- 0 until numElems optimized } {
+        val delta = NibblePackSigned.unzigzag(data(n))
+        val next = current + delta
+        if (((current ^ next) & (delta ^ next)) < 0 && overflowIndex < 0) overflowIndex = i
+        current = next
+        i += 1
+      }
+    override def reset(): Unit = {
+      super.reset()
+      overflowIndex = -1
+      current = base
+      i = 0
+    }
+  }
+
+  private def view(compressed: DirectBuffer, start: Int): DirectBuffer =
+    new UnsafeBuffer(compressed, start, compressed.capacity - start)
+}
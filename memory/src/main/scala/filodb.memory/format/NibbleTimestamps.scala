@@ -0,0 +1,179 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Conveniences for the time index, whose vectors are sorted epoch millisecond timestamps packed either as
+ * increasing deltas (NibblePack.packDeltaCounted) or as delta-of-deltas (NibblePackSigned.packDeltaOfDelta).
+ * Timestamps are plain Longs of UTC millis, so nothing here depends on a timezone.
+ */
+object NibbleTimestamps {
+  import NibbleFormat.{formatOf, Format_Delta_Counted, Format_ZigZag_DoD}
+  import NibblePack.{readCount, unpack8, InputTooShort, InvalidHeader, NibbleError, Ok, Sink, UnexpectedFormat}
+
+  /**
+   * Unpacks the timestamps of a stream from either packDeltaCounted or packDeltaOfDelta, telling them apart by
+   * the format code.  Both record their count.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values, see NibblePack.unpackToSink
+   */
+  final def unpackMillis(compressed: DirectBuffer): Either[NibbleError, Array[Long]] =
+    if (compressed.capacity < 1) {
+      Left(InputTooShort(1, 0))
+    } else {
+      formatOf(compressed) match {
+        case Format_Delta_Counted => NibblePack.unpackDeltaCounted(compressed)
+        case Format_ZigZag_DoD    => NibblePackSigned.unpackDeltaOfDelta(compressed)
+        case other                => Left(UnexpectedFormat(other))
+      }
+    }
+
+  /**
+   * Returns the index of the first timestamp at or after target, or the number of timestamps if they are all
+   * before it, for finding where a query range starts.  The timestamps must be sorted.
+   * For a packDeltaCounted stream blocks are unpacked one at a time, only the last timestamp of each is compared,
+   * and nothing after the block holding the answer is unpacked.  Delta-of-deltas need every earlier value to
+   * find a later one anyway, so such streams are unpacked and then binary searched.
+   * @param compressed NOTE: mutated, see NibblePack.unpackToSink
+   */
+  final def firstAtOrAfter(compressed: DirectBuffer, target: Long): Either[NibbleError, Int] =
+    if (compressed.capacity > 0 && formatOf(compressed) == Format_Delta_Counted) {
+      readCount(compressed).right.flatMap(numValues => searchBlocks(compressed, numValues, target))
+    } else {
+      unpackMillis(compressed).right.map(timestamps => lowerBound(timestamps, target))
+    }
+
+  private def searchBlocks(compressed: DirectBuffer, numValues: Int, target: Long): Either[NibbleError, Int] = {
+    val sink = new TotalsSink
+    var blockStart = 0
+    var found = -1
+    var res: Either[NibbleError, Int] = Right(numValues)
+    while (blockStart < numValues && found < 0 && res.isRight) {
+      if (compressed.capacity < 1) {
+        res = Left(InputTooShort(1, 0))
+      } else {
+        unpack8(compressed, sink, sink.scratch) match {
+          case Ok =>
+            val numElems = Math.min(numValues - blockStart, 8)
+            if (sink.totals(numElems - 1) >= target) {
+              var i = 0
+              while (sink.totals(i) < target) i += 1
+              found = blockStart + i
+              res = Right(found)
+            }
+          case e: NibbleError => res = Left(e)
+        }
+      }
+      blockStart += 8
+    }
+    res
+  }
+
+  /**
+   * Returns the (timestamp, value) samples of a chunk whose timestamps were packed by packDeltaOfDelta and whose
+   * values, eg of a counter, were packed by packDeltaCounted, for sample iterators which would otherwise unpack
+   * both into arrays and zip them.  Both streams are unpacked in lockstep, one block of 8 at a time as samples
+   * are needed.  A malformed stream is returned as an error and ends the iteration.
+   * @param timestamps the packDeltaOfDelta stream.  The buffer is not mutated.
+   * @param values the packDeltaCounted stream.  The buffer is not mutated.
+   * @param numValues the number of samples expected.  If either stream records a different count, the only
+   *                  element is InvalidHeader("numValues", count) with that stream's count
+   */
+  final def samples(timestamps: DirectBuffer, values: DirectBuffer,
+                    numValues: Int): Iterator[Either[NibbleError, (Long, Long)]] =
+    new SampleIterator(new UnsafeBuffer(timestamps, 0, timestamps.capacity),
+                       new UnsafeBuffer(values, 0, values.capacity), numValues)
+
+  // The index of the first value at or after target in sorted values, or values.size if there is none
+  private def lowerBound(values: Array[Long], target: Long): Int = {
+    var low = 0
+    var high = values.size
+    while (low < high) {
+      val mid = (low + high) >>> 1
+      if (values(mid) < target) low = mid + 1 else high = mid
+    }
+    low
+  }
+
+  // Adds up the deltas of each block into timestamps, carrying the running total across blocks
+  private final class TotalsSink extends Sink {
+    val scratch = new Array[Long](8)
+    val totals = new Array[Long](8)
+    private var current = 0L
+    final def process(data: Array[Long]): Unit =
+      for { n <- 0 until 8 optimized } {
+        current += data(n)
+        totals(n) = current
+      }
+  }
+
+  // Adds up the delta-of-deltas of timestamps one block at a time, next to an UnpackIterator over the values
+  private final class SampleIterator(timestamps: DirectBuffer, values: DirectBuffer, numValues: Int)
+  extends Iterator[Either[NibbleError, (Long, Long)]] with Sink {
+    private val header = NibblePackSigned.readDoDHeader(timestamps)
+    private val valueCount = readCount(values)
+    private val valueIter = new NibblePack.UnpackIterator(values, numValues)
+    private val dods = new Array[Long](8)
+    private var i = 0
+    private var timestamp = 0L
+    private var delta = 0L
+    private var error: Option[NibbleError] = (header.right.map(_.numValues), valueCount) match {
+      case (Left(e), _)                            => Some(e)
+      case (_, Left(e))                            => Some(e)
+      case (Right(count), _) if count != numValues => Some(InvalidHeader("numValues", count))
+      case (_, Right(count)) if count != numValues => Some(InvalidHeader("numValues", count))
+      case _                                       => None
+    }
+    private var failed = false
+
+    final def process(data: Array[Long]): Unit = System.arraycopy(data, 0, dods, 0, 8)
+
+    final def hasNext: Boolean = !failed && (error.isDefined || i < numValues)
+
+    final def next(): Either[NibbleError, (Long, Long)] = {
+      if (!hasNext) throw new NoSuchElementException("no more samples")
+      if (error.isEmpty) nextTimestamp(header.right.get)
+      val value = if (error.isEmpty) nextValue() else 0L
+      error match {
+        case Some(e) => failed = true
+                        Left(e)
+        case None    => i += 1
+                        Right((timestamp, value))
+      }
+    }
+
+    private def nextTimestamp(h: NibblePackSigned.DoDHeader): Unit =
+      if (i == 0) {
+        timestamp = h.first
+      } else if (i == 1) {
+        delta = h.firstDelta
+        timestamp += delta
+      } else {
+        if (!h.regular && (i - 2) % 8 == 0) refill()
+        if (!h.regular) delta += NibblePackSigned.unzigzag(dods((i - 2) % 8))
+        timestamp += delta
+      }
+
+    private def refill(): Unit =
+      if (timestamps.capacity < 1) {
+        error = Some(InputTooShort(1, 0))
+      } else {
+        unpack8(timestamps, this) match {
+          case Ok             =>
+          case e: NibbleError => error = Some(e)
+        }
+      }
+
+    private def nextValue(): Long =
+      if (valueIter.hasNext) {
+        valueIter.next()
+      } else {
+        error = Some(valueIter.unpackResult match {
+          case e: NibbleError => e
+          case Ok             => InputTooShort(1, 0)
+        })
+        0L
+      }
+  }
+}
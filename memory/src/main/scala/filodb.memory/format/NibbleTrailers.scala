@@ -0,0 +1,65 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+/**
+ * Optional sections after the values of a self-describing stream, so that newer encoders can add information
+ * which older decoders skip instead of failing on.  Each trailer is a TLV entry:
+ * {{{
+ *   +0   trailer type, 1 byte
+ *   +1   payload length, Int little endian
+ *   +5   payload
+ * }}}
+ * Decoders stop reading a stream once they have unpacked its values, and leave the buffer wrapping whatever
+ * follows (see NibblePack.unpackToSink), so they already ignore trailers;  pass the same buffer to readTrailers
+ * afterwards to get at them.  A stream with a checksum keeps it after the trailers, see NibbleFormat.
+ */
+object NibbleTrailers {
+  import NibblePack.{InputTooShort, InvalidHeader, NibbleError}
+
+  val HeaderBytes = 5
+
+  /**
+   * One trailer entry.
+   * @param payload a view of the payload bytes inside the stream, not a copy
+   */
+  final case class Trailer(trailerType: Byte, payload: DirectBuffer)
+
+  /**
+   * Writes a trailer entry at pos, which should be the end of the stream or of the trailer before it.
+   * @return the final position within the buffer after the trailer
+   */
+  final def appendTrailer(buf: MutableDirectBuffer, pos: Int, trailerType: Byte, payload: Array[Byte]): Int = {
+    buf.putByte(pos, trailerType)
+    buf.putInt(pos + 1, payload.size, LITTLE_ENDIAN)
+    buf.putBytes(pos + HeaderBytes, payload)
+    pos + HeaderBytes + payload.size
+  }
+
+  /**
+   * Returns an iterator over the trailers from the start of the buffer to its end.  A trailer which does not fit
+   * in the buffer is returned as an error and ends the iteration.  The buffer is not mutated.
+   */
+  final def readTrailers(trailers: DirectBuffer): Iterator[Either[NibbleError, Trailer]] =
+    new Iterator[Either[NibbleError, Trailer]] {
+      private var pos = 0
+      final def hasNext: Boolean = pos < trailers.capacity
+      final def next(): Either[NibbleError, Trailer] = {
+        val available = trailers.capacity - pos
+        val result =
+          if (available < HeaderBytes) {
+            Left(InputTooShort(HeaderBytes, available))
+          } else {
+            val length = trailers.getInt(pos + 1, LITTLE_ENDIAN)
+            if (length < 0) Left(InvalidHeader("trailerLength", length))
+            else if (length > available - HeaderBytes) Left(InputTooShort(HeaderBytes + length, available))
+            else Right(Trailer(trailers.getByte(pos), new UnsafeBuffer(trailers, pos + HeaderBytes, length)))
+          }
+        pos = result.fold(_ => trailers.capacity, t => pos + HeaderBytes + t.payload.capacity)
+        result
+      }
+    }
+}
@@ -0,0 +1,150 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer, MutableDirectBuffer}
+
+/**
+ * Re-encodes a self-describing stream from one format to another without going through application code, for
+ * when the access patterns of a series change.  Supported formats are NibbleFormat.Format_Delta_Counted, for
+ * increasing integers (see NibblePack.packDeltaCounted), and NibbleFormat.Format_XOR_Double, for Doubles (see
+ * DoubleXORPack).  Values are never silently changed: a value the target cannot hold exactly is an error.
+ * Whole batches of Long streams, eg every chunk of a column for a compaction job, can be re-encoded with
+ * transcodeBatch.
+ */
+object NibbleTranscode {
+  import NibbleFormat._
+  import NibblePack.{InputTooShort, InvalidHeader, LossyConversion, NibbleError, NonIncreasing, Ok,
+                     UnexpectedFormat}
+
+  // Every integer from -2^53 to 2^53 is exact as a Double
+  val MaxExactLong = 1L << 53
+
+  /**
+   * Decodes src with the codec for its format code, then encodes the values again in targetFormat, keeping the
+   * number of values.  Transcoding to the format src already has is allowed and just repacks it.
+   * @param src NOTE: mutated as it is unpacked, see NibblePack.unpackToSink
+   * @param numValues the number of values in src, as XOR Double streams do not record it.  For a counted stream
+   *                  this must match its count.
+   * @param targetFormat the NibbleFormat code to transcode to
+   * @return the final position within the buffer after packing, or LossyConversion(index) for the first value
+   *         which cannot be held exactly in targetFormat, or NonIncreasing(index) for Doubles which decrease,
+   *         or UnexpectedFormat for an unsupported source or target format
+   */
+  final def transcode(src: DirectBuffer, numValues: Int, targetFormat: Byte,
+                      buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    if (src.capacity < 1) {
+      Left(InputTooShort(1, 0))
+    } else if (numValues < 0) {
+      Left(InvalidHeader("numValues", numValues))
+    } else {
+      NibbleFormat.formatOf(src) match {
+        case Format_Delta_Counted =>
+          NibblePack.unpackDeltaCounted(src).right.flatMap { longs =>
+            if (longs.size != numValues) Left(InvalidHeader("numValues", longs.size))
+            else encodeLongs(longs, targetFormat, buf, bufindex)
+          }
+        case Format_XOR_Double =>
+          val doubles = new Array[Double](numValues)
+          DoubleXORPack.unpack(src, doubles) match {
+            case Ok             => encodeDoubles(doubles, targetFormat, buf, bufindex)
+            case e: NibbleError => Left(e)
+          }
+        case other => Left(UnexpectedFormat(other))
+      }
+    }
+
+  /**
+   * Re-encodes each buffer of a batch in targetFormat, lazily, one buffer as each result is taken, so that a whole
+   * column can be streamed through without holding it all.  Each buffer is decoded with NibbleAny.decode, so it
+   * can be any stream of Longs which records its count, whatever its format, other than an arithmetic sequence,
+   * which NibbleAny.decode leaves unexpanded.  One scratch buffer is packed into
+   * for the whole batch, and only each result is allocated.  The buffers are not mutated.
+   * @param targetFormat the NibbleFormat code to pack each buffer's Longs with: Format_Delta_Counted,
+   *                     Format_Delta_Runs, Format_Delta_Blocks or Format_Delta_Fixed for Longs which never
+   *                     decrease, or Format_FOR, Format_Delta_Hybrid, Format_ZigZag_DoD or Format_Skip_Table
+   *                     for any Longs
+   * @return for each buffer in order, its bytes in targetFormat, or the error decoding it, UnexpectedFormat for
+   *         a buffer which does not hold Longs or an unsupported targetFormat, or NonIncreasing(index) for the
+   *         first value lower than the one before it, or negative, when targetFormat packs increasing deltas
+   */
+  final def transcodeBatch(buffers: Iterator[DirectBuffer],
+                           targetFormat: Byte): Iterator[Either[NibbleError, Array[Byte]]] = {
+    val scratch = new ExpandableArrayBuffer()
+    buffers.map { src =>
+      NibbleAny.decode(src).right.flatMap {
+        case NibbleAny.Decoded.Longs(longs) =>
+          repackLongs(longs, targetFormat, scratch)
+        case _ =>
+          // Decoded without an error, so the format code is there
+          NibbleAny.formatCode(src).right.flatMap(code => Left(UnexpectedFormat(code)))
+      }.right.map { numBytes => java.util.Arrays.copyOf(scratch.byteArray, numBytes) }
+    }
+  }
+
+  private def repackLongs(longs: Array[Long], targetFormat: Byte,
+                          buf: MutableDirectBuffer): Either[NibbleError, Int] = {
+    def increasing(pack: => Int): Either[NibbleError, Int] = firstDrop(longs) match {
+      case -1    => Right(pack)
+      case index => Left(NonIncreasing(index))
+    }
+    targetFormat match {
+      case Format_Delta_Counted => increasing(NibblePack.packDeltaCounted(longs, buf, 0))
+      case Format_Delta_Runs    => increasing(NibbleRuns.packDeltaRuns(longs, buf, 0))
+      case Format_Delta_Blocks  => increasing(NibbleBlockSize.packDelta(longs, buf, 0))
+      case Format_Delta_Fixed   => increasing(NibbleFixedWidth.packDelta(longs, buf, 0))
+      case Format_FOR           => Right(NibbleFOR.packFOR(longs, buf, 0))
+      case Format_Delta_Hybrid  => Right(NibbleHybridDelta.packDelta(longs, buf, 0))
+      case Format_ZigZag_DoD    => Right(NibblePackSigned.packDeltaOfDelta(longs, buf, 0))
+      case Format_Skip_Table    => Right(CompressedVec.encode(longs, buf, 0))
+      case other                => Left(UnexpectedFormat(other))
+    }
+  }
+
+  // The index of the first value lower than the one before it or negative, or -1 if there is none
+  private def firstDrop(longs: Array[Long]): Int = {
+    var last = 0L
+    var i = 0
+    while (i < longs.size && longs(i) >= last) {
+      last = longs(i)
+      i += 1
+    }
+    if (i < longs.size) i else -1
+  }
+
+  private def encodeLongs(longs: Array[Long], targetFormat: Byte,
+                          buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    targetFormat match {
+      case Format_Delta_Counted => Right(NibblePack.packDeltaCounted(longs, buf, bufindex))
+      case Format_XOR_Double =>
+        // Unpacked deltas are never negative, so only the upper bound needs checking
+        val lossy = longs.indexWhere(_ > MaxExactLong)
+        if (lossy >= 0) Left(LossyConversion(lossy))
+        else Right(DoubleXORPack.pack(longs.map(_.toDouble), buf, bufindex))
+      case other => Left(UnexpectedFormat(other))
+    }
+
+  private def encodeDoubles(doubles: Array[Double], targetFormat: Byte,
+                            buf: MutableDirectBuffer, bufindex: Int): Either[NibbleError, Int] =
+    targetFormat match {
+      case Format_XOR_Double => Right(DoubleXORPack.pack(doubles, buf, bufindex))
+      case Format_Delta_Counted =>
+        val lossy = doubles.indexWhere(d => !isExactLong(d))
+        if (lossy >= 0) {
+          Left(LossyConversion(lossy))
+        } else {
+          NibblePack.packDeltaChecked(doubles.map(_.toLong), buf, bufindex + 5).right.map { endPos =>
+            buf.putByte(bufindex, NibbleFormat.versioned(Format_Delta_Counted))
+            buf.putInt(bufindex + 1, doubles.size, LITTLE_ENDIAN)
+            endPos
+          }
+        }
+      case other => Left(UnexpectedFormat(other))
+    }
+
+  // True if the Double is a whole number which converts to a Long and back to exactly the same bits, so that
+  // NaN, infinities, fractions and -0.0 are all refused
+  private def isExactLong(d: Double): Boolean =
+    Math.abs(d) <= MaxExactLong &&
+      java.lang.Double.doubleToRawLongBits(d.toLong.toDouble) == java.lang.Double.doubleToRawLongBits(d)
+}
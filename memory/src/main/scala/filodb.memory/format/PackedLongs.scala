@@ -0,0 +1,127 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+import filodb.memory.MemFactory
+import filodb.memory.format.NibblePack.NibbleError
+
+/**
+ * The bytes of a self-describing stream of Longs, written by NibbleAuto.packAuto.  The stream records its own
+ * count and codec, so nothing else is needed to decode it.
+ */
+final class EncodedLongs(val bytes: Array[Byte]) {
+  /**
+   * Decodes the stream from the start of bytes, which are not mutated.
+   * @return the values, or the NibbleError, eg InputTooShort for a truncated stream
+   */
+  def decode: Either[NibbleError, DecodedLongs] =
+    NibbleAuto.unpackAuto(new UnsafeBuffer(bytes)).right.map(new DecodedLongs(_))
+}
+
+object EncodedLongs {
+  /**
+   * Packs any Longs with NibbleAuto.packAuto, into an array of exactly the packed size.
+   */
+  final def apply(values: Array[Long]): EncodedLongs = {
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = NibbleAuto.packAuto(values, buf, 0)
+    new EncodedLongs(java.util.Arrays.copyOf(buf.byteArray, numBytes))
+  }
+}
+
+/**
+ * Decoded Longs, the other side of EncodedLongs.  values belongs to the caller once decoded.
+ */
+final class DecodedLongs(val values: Array[Long]) {
+  def encode: EncodedLongs = EncodedLongs(values)
+}
+
+/**
+ * Conversions between Longs and their packed bytes, for callers which do not want to deal with buffers or pick
+ * a codec.  Import PackedLongs._ to write eg values.encoded.bytes or bytes.decoded.
+ * {{{
+ *   val bytes = Array(1L, 5L, 9L).encoded.bytes
+ *   val values = bytes.decoded.right.get.values
+ * }}}
+ */
+object PackedLongs {
+  implicit class LongsToEncoded(values: Array[Long]) {
+    def encoded: EncodedLongs = EncodedLongs(values)
+  }
+
+  implicit class BytesToDecoded(bytes: Array[Byte]) {
+    def decoded: Either[NibbleError, DecodedLongs] = new EncodedLongs(bytes).decode
+  }
+
+  /**
+   * Unpacks a packDeltaCounted stream in the first numBytes of bytes like NibblePack.unpackDeltaCountedFromBytes,
+   * but returns the values, their number and the error code together, so bindings need neither pass in a count
+   * and output array nor tell a count from an error by its sign.  The bytes are not mutated.
+   */
+  final def unpackDeltaCountedResult(bytes: Array[Byte], numBytes: Int): NibbleResult =
+    (if (numBytes < 0 || numBytes > bytes.size) Left(NibblePack.InputTooShort(numBytes, bytes.size))
+     else NibblePack.unpackDeltaCounted(new UnsafeBuffer(bytes, 0, numBytes))) match {
+      case Right(values) => NibbleResult(values, values.size, NibblePack.Ok.errorCode)
+      case Left(e)       => NibbleResult(Array.empty[Long], 0, e.errorCode)
+    }
+
+  /**
+   * Unpacks numValues Longs from a packDelta stream in the first numBytes of bytes into native memory allocated
+   * from memFactory, for bindings which hand the values on as a plain pointer, eg to Python through ctypes or
+   * numpy, where neither the JVM's GC nor a reused scratch array can decide when the memory is done with.  The
+   * result is owned by the caller, who must free it, see OwnedLongs.  Nothing is allocated on an error.
+   * The bytes are not mutated.
+   * @return the values, InvalidParameter for more values than one allocation can hold, or the unpacking error
+   */
+  final def unpackDeltaOwned(bytes: Array[Byte], numBytes: Int, numValues: Int,
+                             memFactory: MemFactory): Either[NibbleError, OwnedLongs] =
+    (if (numBytes < 0 || numBytes > bytes.size) Left(NibblePack.InputTooShort(numBytes, bytes.size))
+     else if (numValues > Int.MaxValue / 8) Left(NibblePack.InvalidParameter("numValues", numValues))
+     else NibblePack.unpackDelta(new UnsafeBuffer(bytes, 0, numBytes), numValues)).right.map { values =>
+      // At least one Long, so that empty results too have an address of their own to free
+      val address = memFactory.allocateOffheap(Math.max(values.size, 1) * 8, zero = false)
+      for { i <- 0 until values.size optimized } { UnsafeUtils.setLong(address + i * 8L, values(i)) }
+      new OwnedLongs(address, values.size, memFactory)
+    }
+}
+
+/**
+ * The outcome of PackedLongs.unpackDeltaCountedResult in one value.
+ * @param values the values unpacked, empty on an error
+ * @param length the number of values, 0 on an error
+ * @param errorCode 0 on success, otherwise the negative errorCode of the NibbleError, see NibblePack.UnpackResult
+ */
+final case class NibbleResult(values: Array[Long], length: Int, errorCode: Int)
+
+/**
+ * Longs in native memory owned by the caller, from PackedLongs.unpackDeltaOwned.  The contract is:
+ *  - the memory stays valid, and is never reused by a later unpack, until free or close is called
+ *  - free must be called once the values are done with, or the memory leaks: nothing frees it on GC
+ *  - free gives the memory back to the MemFactory it came from, and calling it again does nothing
+ *  - address must not be read after free, and apply throws IllegalStateException after free
+ * @param address the native address of the first value, with the values as consecutive native order Longs
+ * @param length the number of values
+ */
+final class OwnedLongs private[format] (val address: Long, val length: Int, memFactory: MemFactory)
+extends AutoCloseable {
+  private var freed = false
+
+  def isFreed: Boolean = freed
+
+  def apply(index: Int): Long = {
+    if (freed) throw new IllegalStateException("OwnedLongs read after free")
+    if (index < 0 || index >= length) throw new IndexOutOfBoundsException(s"$index not within 0 until $length")
+    UnsafeUtils.getLong(address + index * 8L)
+  }
+
+  def toArray: Array[Long] = Array.tabulate(length)(apply)
+
+  def free(): Unit = if (!freed) {
+    freed = true
+    memFactory.freeMemory(address)
+  }
+
+  def close(): Unit = free()
+}
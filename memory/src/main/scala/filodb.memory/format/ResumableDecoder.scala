@@ -0,0 +1,108 @@
+package filodb.memory.format
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Decodes a raw NibblePacked stream as its bytes arrive, eg from a network read, instead of waiting for the whole
+ * stream.  Each feed() decodes every block which is now complete and keeps the bytes of an incomplete last block
+ * until the rest of it is fed.  At most one partial block is ever buffered, as complete blocks are decoded.
+ * The stream must start with the first block, like the output of NibblePack.packDelta or packNonIncreasing.
+ * Not thread safe.
+ * @param numValues the number of values which were packed.  Bytes fed after the block holding the last value
+ *                  are ignored.
+ * @param isDelta true for streams written by packDelta, so that the deltas are summed up as they are decoded.
+ *                The running total is checked as in NibblePack.unpackDelta, so that corrupt deltas are returned as
+ *                an AccumulatorOverflow instead of silently wrapping around.
+ */
+final class ResumableDecoder(numValues: Int, isDelta: Boolean) {
+  import NibblePack.{unpack8, AccumulatorOverflow, InputTooShort, NibbleError, Ok, Sink, UnpackResult}
+  require(numValues >= 0)
+
+  private val pending = new ExpandableArrayBuffer()
+  private var pendingBytes = 0
+  private var decoded = 0
+  private var current = 0L
+  private var error: Option[NibbleError] = None
+
+  private val rest = new UnsafeBuffer(pending, 0, 0)
+  private val block = new UnsafeBuffer(pending, 0, 0)
+  private val blockValues = new Array[Long](8)
+  private val sink = new Sink {
+    final def process(data: Array[Long]): Unit = System.arraycopy(data, 0, blockValues, 0, 8)
+  }
+
+  // The number of values decoded so far
+  def numDecoded: Int = decoded
+
+  // The number of bytes of an incomplete block waiting for the rest of it
+  def bufferedBytes: Int = pendingBytes
+
+  /**
+   * Appends chunk to the stream and decodes the blocks it completes.  The chunk is copied, so its buffer can be
+   * reused as soon as feed returns.
+   * @return the values decoded from the newly completed blocks, possibly none, or the NibbleError of a malformed
+   *         block or an AccumulatorOverflow, whose index counts from the start of the stream.  After an error
+   *         every later feed returns the same error.
+   */
+  final def feed(chunk: DirectBuffer): Either[NibbleError, Array[Long]] = {
+    if (error.isEmpty && decoded < numValues) {
+      pending.putBytes(pendingBytes, chunk, 0, chunk.capacity)
+      pendingBytes += chunk.capacity
+    }
+    val out = new collection.mutable.ArrayBuilder.ofLong
+    var pos = 0
+    var waiting = false
+    while (error.isEmpty && !waiting && decoded < numValues && pos < pendingBytes) {
+      rest.wrap(pending, pos, pendingBytes - pos)
+      NibbleBlocks.parse(rest, 0) match {
+        case Right(info) =>
+          // parse checked the header and that the whole block is here, so unpack8 cannot fail
+          block.wrap(pending, pos, info.numBytes)
+          unpack8(block, sink)
+          val numElems = Math.min(numValues - decoded, 8)
+          for { n <- 0 until numElems optimized } {
+            if (isDelta) {
+              // Checked like NibbleSinks.CheckedDeltaSink, across every feed
+              val next = current + blockValues(n)
+              if ((blockValues(n) < 0 || next < 0) && error.isEmpty) error = Some(AccumulatorOverflow(decoded + n))
+              current = next
+            } else {
+              current = blockValues(n)
+            }
+            out += current
+          }
+          decoded += numElems
+          pos += info.numBytes
+        case Left(_: InputTooShort) => waiting = true
+        case Left(e)                => error = Some(e)
+      }
+    }
+    keepTail(pos)
+    error.toLeft(out.result())
+  }
+
+  /**
+   * Checks that the stream fed so far held all numValues values.
+   * @return Ok, the error feed returned, or InputTooShort if the stream ended early, eg inside a block
+   */
+  final def finish(): UnpackResult = error match {
+    case Some(e)                     => e
+    case None if decoded < numValues =>
+      if (pendingBytes == 0) InputTooShort(1, 0)
+      else NibbleBlocks.parse(new UnsafeBuffer(pending, 0, pendingBytes), 0).left.getOrElse(InputTooShort(1, 0))
+    case None                        => Ok
+  }
+
+  // Moves the bytes after pos, an incomplete block if any, to the start of the buffer
+  private def keepTail(pos: Int): Unit =
+    if (decoded >= numValues) {
+      pendingBytes = 0
+    } else if (pos > 0) {
+      val tail = new Array[Byte](pendingBytes - pos)
+      pending.getBytes(pos, tail)
+      pending.putBytes(0, tail)
+      pendingBytes = tail.size
+    }
+}
@@ -0,0 +1,46 @@
+package filodb.memory.format
+
+/**
+ * How a value which falls between two representable ones is settled, for histogram quantiles, which fall within
+ * a bucket, and for fixed-point Doubles, which fall between two multiples of 1 / scale.  Quantiles default to
+ * LinearInterp, as Prometheus' histogram_quantile does, and alerting rules can choose Floor or Ceil for a bound
+ * which is never above or below the true quantile.
+ */
+sealed abstract class Rounding {
+  /**
+   * Settles a value fraction of the way from low to high, fraction being from 0 to 1.
+   */
+  def between(low: Double, high: Double, fraction: Double): Double
+}
+
+object Rounding {
+  // The nearer of low and high, high when halfway
+  case object Nearest extends Rounding {
+    def between(low: Double, high: Double, fraction: Double): Double = if (fraction < 0.5) low else high
+  }
+
+  // Always low, eg the bottom of the bucket
+  case object Floor extends Rounding {
+    def between(low: Double, high: Double, fraction: Double): Double = low
+  }
+
+  // Always high, eg the top of the bucket
+  case object Ceil extends Rounding {
+    def between(low: Double, high: Double, fraction: Double): Double = high
+  }
+
+  // Interpolated fraction of the way from low to high.  Between two Longs this rounds as Nearest, as a Long has
+  // no value in between.
+  case object LinearInterp extends Rounding {
+    def between(low: Double, high: Double, fraction: Double): Double = low + (high - low) * fraction
+  }
+
+  /**
+   * Rounds a Double to a Long, eg a fixed-point value times its scale.
+   */
+  final def toLong(value: Double, rounding: Rounding): Long = rounding match {
+    case Floor                  => Math.floor(value).toLong
+    case Ceil                   => Math.ceil(value).toLong
+    case Nearest | LinearInterp => Math.round(value)
+  }
+}
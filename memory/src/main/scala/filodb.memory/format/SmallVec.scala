@@ -0,0 +1,98 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.DirectBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Unpacks short vectors of up to capacity values, such as the handful of samples in most ingested chunks,
+ * without allocating anything per call: the output, block scratch space, sink and input buffer are all owned by
+ * the SmallVec and reused.  Results are returned as an Int rather than an Either so that the success path does
+ * not allocate either; lastResult has the details of any error.
+ * A SmallVec is not thread safe.  Give each ingest or query thread its own.
+ */
+final class SmallVec(val capacity: Int) {
+  import NibblePack.{subslice, unpackAllToSink, AccumulatorOverflow, InputTooShort, InvalidHeader, NibbleError, Ok,
+                     OutputTooSmall, UnpackResult}
+
+  /**
+   * The output of the last unpack.  Only the first numValues elements are valid.
+   */
+  val values = new Array[Long](capacity)
+  var numValues = 0
+  var lastResult: UnpackResult = Ok
+
+  private val scratch = new Array[Long](8)
+  private val sink = new SmallVec.CountedDeltaSink(values)
+  private val inputBuf = new UnsafeBuffer(NibblePack.empty)
+
+  /**
+   * Unpacks a stream written by NibblePack.packDeltaCounted into values.
+   * @param compressed NOTE: mutated to wrap the bytes after the stream, see NibblePack.unpackToSink
+   * @return the number of values, or the negative errorCode of the error, eg OutputTooSmall if the stream has
+   *         more than capacity values
+   */
+  final def unpackDeltaCounted(compressed: DirectBuffer): Int = {
+    lastResult = unpackCounted(compressed)
+    numValues = if (lastResult == Ok) sink.numValues else 0
+    if (lastResult == Ok) numValues else lastResult.errorCode
+  }
+
+  /**
+   * Like unpackDeltaCounted, for a stream in length bytes of an array starting at offset.
+   */
+  final def unpackDeltaCounted(bytes: Array[Byte], offset: Int, length: Int): Int = {
+    inputBuf.wrap(bytes, offset, length)
+    unpackDeltaCounted(inputBuf)
+  }
+
+  private def unpackCounted(compressed: DirectBuffer): UnpackResult =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_Delta_Counted) match {
+      case Ok if compressed.capacity < 4 => InputTooShort(4, compressed.capacity)
+      case Ok =>
+        val count = compressed.getInt(0, LITTLE_ENDIAN)
+        subslice(compressed, 4)
+        if (count < 0) {
+          InvalidHeader("numValues", count)
+        } else if (count > capacity) {
+          OutputTooSmall(count, capacity)
+        } else {
+          sink.reset(count)
+          unpackAllToSink(compressed, sink, count, scratch) match {
+            case Ok if sink.overflowIndex >= 0 => AccumulatorOverflow(sink.overflowIndex)
+            case other                         => other
+          }
+        }
+      case e: NibbleError => e
+    }
+}
+
+object SmallVec {
+  // Like NibbleSinks.CheckedDeltaSink, but the number of values can be changed for each unpack
+  private final class CountedDeltaSink(outArray: Array[Long]) extends NibblePack.Sink {
+    var numValues = 0
+    var overflowIndex = -1
+    private var current = 0L
+    private var i = 0
+
+    def reset(count: Int): Unit = {
+      numValues = count
+      overflowIndex = -1
+      current = 0L
+      i = 0
+    }
+
+    final def process(data: Array[Long]): Unit = {
+      val numElems = Math.min(numValues - i, 8)
+      for { n <- 0 until numElems optimized } {
+        val next = current + data(n)
+        if ((data(n) < 0 || next < 0) && overflowIndex < 0) overflowIndex = i + n
+        current = next
+        outArray(i + n) = current
+      }
+      i += numElems
+    }
+  }
+}
@@ -0,0 +1,105 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+/**
+ * Doubles with gaps, such as gauges with missing samples marked by NaN.  A presence bitmap records which values
+ * are there, and only those are XOR compressed, so a gap costs one bit instead of a full value, and does not
+ * break up the runs of similar values that XOR compression relies on.
+ *
+ * Layout:
+ * {{{
+ *   +0   NibbleFormat.Format_Extended, then NibbleFormat.Format_Sparse_Double
+ *   +2   numValues, Int
+ *   +6   the presence bitmap, (numValues + 7) / 8 bytes: bit i % 8 of byte i / 8 is set if value i is not NaN
+ *   +..  the present values as a DoubleXORPack stream, with its own format code
+ * }}}
+ * Every NaN is a gap, whatever its payload, and gaps unpack as the standard Double.NaN.
+ */
+object SparseDoublePack {
+  import NibbleFormat.Format_Sparse_Double
+  import NibblePack.{subslice, InputTooShort, InvalidHeader, NibbleError, Ok}
+
+  val HeaderBytes = 6
+
+  /**
+   * Packs the Doubles, leaving out the NaNs, after the format code, count and presence bitmap.
+   * @return the final position within the buffer after packing
+   */
+  final def pack(inputs: Array[Double], buf: MutableDirectBuffer, bufindex: Int): Int = {
+    val countPos = NibbleFormat.putFormat(buf, bufindex, Format_Sparse_Double)
+    buf.putInt(countPos, inputs.size, LITTLE_ENDIAN)
+    val bitmapPos = countPos + 4
+    val present = new collection.mutable.ArrayBuilder.ofDouble
+    for { b <- 0 until bitmapBytes(inputs.size) optimized } {
+      var bits = 0
+      for { i <- b * 8 until Math.min(b * 8 + 8, inputs.size) optimized } {
+        if (!java.lang.Double.isNaN(inputs(i))) {
+          bits |= 1 << (i % 8)
+          present += inputs(i)
+        }
+      }
+      buf.putByte(bitmapPos + b, bits.toByte)
+    }
+    DoubleXORPack.pack(present.result(), buf, bitmapPos + bitmapBytes(inputs.size))
+  }
+
+  /**
+   * Unpacks a stream written by pack, using the count in its header, with Double.NaN at every gap.
+   * @param compressed NOTE: mutated to wrap the bytes after the unpacked values
+   */
+  final def unpack(compressed: DirectBuffer): Either[NibbleError, Array[Double]] =
+    NibbleFormat.checkFormat(compressed, Format_Sparse_Double) match {
+      case Ok if compressed.capacity < HeaderBytes - 2 =>
+        Left(InputTooShort(HeaderBytes - 2, compressed.capacity))
+      case Ok =>
+        val numValues = compressed.getInt(0, LITTLE_ENDIAN)
+        subslice(compressed, HeaderBytes - 2)
+        if (numValues < 0) {
+          Left(InvalidHeader("numValues", numValues))
+        } else if (compressed.capacity < bitmapBytes(numValues)) {
+          Left(InputTooShort(bitmapBytes(numValues), compressed.capacity))
+        } else {
+          unpackPresent(compressed, numValues)
+        }
+      case e: NibbleError => Left(e)
+    }
+
+  // Unpacks the present values after the bitmap, and spreads them out to their positions
+  private def unpackPresent(compressed: DirectBuffer, numValues: Int): Either[NibbleError, Array[Double]] = {
+    val numBitmapBytes = bitmapBytes(numValues)
+    var numPresent = 0
+    for { b <- 0 until numBitmapBytes optimized } {
+      numPresent += java.lang.Integer.bitCount(compressed.getByte(b) & bitsInByte(numValues, b))
+    }
+    val present = new Array[Double](numPresent)
+    val values = new UnsafeBuffer(compressed, numBitmapBytes, compressed.capacity - numBitmapBytes)
+    DoubleXORPack.unpack(values, present) match {
+      case Ok =>
+        val out = new Array[Double](numValues)
+        var p = 0
+        for { i <- 0 until numValues optimized } {
+          if ((compressed.getByte(i / 8) & (1 << (i % 8))) != 0) {
+            out(i) = present(p)
+            p += 1
+          } else {
+            out(i) = Double.NaN
+          }
+        }
+        // DoubleXORPack.unpack left values wrapping the bytes after its stream
+        subslice(compressed, compressed.capacity - values.capacity)
+        Right(out)
+      case e: NibbleError => Left(e)
+    }
+  }
+
+  private def bitmapBytes(numValues: Int): Int = ((numValues.toLong + 7) / 8).toInt
+
+  // The bits of bitmap byte b which are for values, ignoring any set past numValues in the last byte
+  private def bitsInByte(numValues: Int, b: Int): Int =
+    if (numValues - b * 8 >= 8) 0xff else (1 << (numValues - b * 8)) - 1
+}
@@ -1,6 +1,6 @@
 package filodb.memory.format
 
-import java.nio.ByteBuffer
+import java.nio.{ByteBuffer, ByteOrder}
 
 import com.kenai.jffi.MemoryIO
 import org.agrona.DirectBuffer
@@ -82,6 +82,24 @@ object UnsafeUtils {
   final def getDouble(addr: Long): Double = unsafe.getDouble(ZeroPointer, addr)
   final def getFloat(addr: Long): Double = unsafe.getFloat(ZeroPointer, addr)
 
+  /**
+   * Little endian versions of getShort, getLong, getDouble and setShort, for length prefixes and other wire
+   * format fields which must read the same on any host.  On little endian hosts these are identical to the
+   * native versions.
+   */
+  val isBigEndian = ByteOrder.nativeOrder == ByteOrder.BIG_ENDIAN
+  final def getShortLE(obj: Any, offset: Long): Short = {
+    val s = unsafe.getShort(obj, offset)
+    if (isBigEndian) java.lang.Short.reverseBytes(s) else s
+  }
+  final def getLongLE(obj: Any, offset: Long): Long = {
+    val l = unsafe.getLong(obj, offset)
+    if (isBigEndian) java.lang.Long.reverseBytes(l) else l
+  }
+  final def getDoubleLE(obj: Any, offset: Long): Double = java.lang.Double.longBitsToDouble(getLongLE(obj, offset))
+  final def setShortLE(obj: Any, offset: Long, s: Short): Unit =
+    unsafe.putShort(obj, offset, if (isBigEndian) java.lang.Short.reverseBytes(s) else s)
+
   final def setByte(obj: Any, offset: Long, byt: Byte): Unit = unsafe.putByte(obj, offset, byt)
   final def setShort(obj: Any, offset: Long, s: Short): Unit = unsafe.putShort(obj, offset, s)
   final def setInt(obj: Any, offset: Long, i: Int): Unit = unsafe.putInt(obj, offset, i)
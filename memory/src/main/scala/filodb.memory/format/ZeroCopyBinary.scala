@@ -54,7 +54,7 @@ trait ZeroCopyBinary extends Ordered[ZeroCopyBinary] {
     val (newBase, newOffset, _) = factory.allocate(numBytes + 2)
     require(newBase == UnsafeUtils.ZeroPointer, s"Native memory was not allocated, you used factory $factory")
     UnsafeUtils.unsafe.copyMemory(base, offset, newBase, newOffset + 2, numBytes)
-    UnsafeUtils.setShort(newBase, newOffset, numBytes.toShort)
+    UnsafeUtils.setShortLE(newBase, newOffset, numBytes.toShort)
     new UTF8StringMedium(newOffset)
   }
 
@@ -0,0 +1,160 @@
+package filodb.memory.format.vectors
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import debox.Buffer
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+import scalaxy.loops._
+
+import filodb.memory.format.{NibbleFormat, NibblePack, NibblePackSigned}
+import filodb.memory.format.NibblePack.NibbleError
+
+/**
+ * Builds a chunk of histograms over time bucket by bucket instead of histogram by histogram: the values of each
+ * bucket across all the histograms are kept as one column, which changes slowly from one histogram to the next
+ * and so delta encodes much better than each histogram on its own.  A HistColumnAppender is not thread safe.
+ *
+ * Layout written by finish (Ints little endian), read back by HistColumnReader:
+ * {{{
+ *   +0   numBuckets, Int
+ *   +4   numHistograms, Int
+ *   +8   numBuckets Ints: the offset of each bucket's column from +0
+ *   ...  each column as a NibblePackSigned.packDelta stream of numHistograms values
+ * }}}
+ * Columns keep drops, eg from counter resets, as they are ZigZag deltas.
+ * @param numBuckets the number of buckets every histogram appended must have
+ */
+final class HistColumnAppender(val numBuckets: Int) {
+  require(numBuckets > 0, s"numBuckets must be positive, was $numBuckets")
+
+  private val columns = Array.fill(numBuckets)(Buffer.empty[Long])
+
+  // The number of histograms appended so far
+  var numHistograms = 0
+
+  /**
+   * Appends one histogram, one value for each bucket in bucket order.
+   */
+  final def append(bucketValues: Array[Long]): Unit = {
+    require(bucketValues.size == numBuckets, s"Values array size of ${bucketValues.size} != $numBuckets")
+    for { b <- 0 until numBuckets optimized } { columns(b) += bucketValues(b) }
+    numHistograms += 1
+  }
+
+  /**
+   * Writes every histogram appended so far as columns, see the layout above.  More histograms can still be
+   * appended afterwards.
+   * @param out the buffer to write to.  Highly recommended this be an ExpandableArrayBuffer or equiv.
+   * @return the number of bytes written
+   */
+  final def finish(out: MutableDirectBuffer): Int = {
+    out.putInt(0, numBuckets, LITTLE_ENDIAN)
+    out.putInt(4, numHistograms, LITTLE_ENDIAN)
+    var pos = HistColumnReader.HeaderBytes + numBuckets * 4
+    for { b <- 0 until numBuckets optimized } {
+      out.putInt(HistColumnReader.HeaderBytes + b * 4, pos, LITTLE_ENDIAN)
+      pos = NibblePackSigned.packDelta(columns(b).toArray, out, pos)
+    }
+    pos
+  }
+}
+
+object HistColumnAppender {
+  /**
+   * Writes histograms which are all at hand as columns, like appending each to a HistColumnAppender and calling
+   * finish.
+   * @param numBuckets the number of buckets every histogram has, needed as there may be no histograms
+   * @return the number of bytes written
+   */
+  final def pack(numBuckets: Int, histograms: Seq[Array[Long]], out: MutableDirectBuffer): Int = {
+    val appender = new HistColumnAppender(numBuckets)
+    histograms.foreach(appender.append)
+    appender.finish(out)
+  }
+}
+
+/**
+ * Reads back the histograms written by HistColumnAppender.finish.  The buffer is not mutated.
+ * @param buf a buffer wrapping exactly the bytes written by finish
+ */
+final class HistColumnReader(buf: DirectBuffer) {
+  import HistColumnReader._
+  import NibblePack.{InputTooShort, InvalidHeader, InvalidParameter, Ok}
+
+  def numBuckets: Int = if (buf.capacity < HeaderBytes) 0 else buf.getInt(0, LITTLE_ENDIAN)
+  def numHistograms: Int = if (buf.capacity < HeaderBytes) 0 else buf.getInt(4, LITTLE_ENDIAN)
+
+  /**
+   * Reconstructs the i-th histogram appended.  Each column is unpacked only up to the block holding i.
+   * @return the value of each bucket in bucket order, or InvalidParameter for an index out of range, or an
+   *         error for a malformed buffer
+   */
+  final def histogram(i: Int): Either[NibbleError, Array[Long]] =
+    checkHeader(i, numHistograms, "index").right.flatMap { _ =>
+      val values = new Array[Long](numBuckets)
+      val column = new Array[Long](i + 1)
+      var err: Option[NibbleError] = None
+      var b = 0
+      while (b < numBuckets && err.isEmpty) {
+        columnSlice(b) match {
+          case Right(slice) =>
+            unpackColumn(slice, column) match {
+              case Ok             => values(b) = column(i)
+              case e: NibbleError => err = Some(e)
+            }
+          case Left(e) => err = Some(e)
+        }
+        b += 1
+      }
+      err.toLeft(values)
+    }
+
+  /**
+   * Unpacks the values of bucket b in every histogram, in the order they were appended, eg for the time series of
+   * one bucket over a query range.  Only that bucket's column is read.
+   * @return the bucket's values, or InvalidParameter for a bucket out of range, or an error for a malformed buffer
+   */
+  final def bucket(b: Int): Either[NibbleError, Array[Long]] =
+    checkHeader(b, numBuckets, "bucket").right.flatMap { _ =>
+      columnSlice(b).right.flatMap { slice =>
+        NibbleFormat.checkCount(numHistograms, slice.capacity).toLeft(new Array[Long](numHistograms)).right
+          .flatMap { column =>
+            unpackColumn(slice, column) match {
+              case Ok             => Right(column)
+              case e: NibbleError => Left(e)
+            }
+          }
+      }
+    }
+
+  private def checkHeader(index: Int, limit: Int, name: String): Either[NibbleError, Unit] =
+    if (buf.capacity < HeaderBytes) {
+      Left(InputTooShort(HeaderBytes, buf.capacity))
+    } else if (index < 0 || index >= limit) {
+      Left(InvalidParameter(name, index))
+    } else if (numBuckets < 0 || numBuckets > (buf.capacity - HeaderBytes) / 4) {
+      Left(InvalidHeader("numBuckets", numBuckets))
+    } else {
+      Right(())
+    }
+
+  // The bytes of the column of bucket b, from its offset to the next column's
+  private def columnSlice(b: Int): Either[NibbleError, DirectBuffer] = {
+    val start = buf.getInt(HeaderBytes + b * 4, LITTLE_ENDIAN)
+    val end = if (b == numBuckets - 1) buf.capacity else buf.getInt(HeaderBytes + b * 4 + 4, LITTLE_ENDIAN)
+    if (start < HeaderBytes || end < start || end > buf.capacity) Left(InvalidHeader("columnOffset", start))
+    else Right(new UnsafeBuffer(buf, start, end - start))
+  }
+
+  // Unlike NibblePackSigned.unpackDelta, a column holding fewer values than wanted is an error
+  private def unpackColumn(compressed: DirectBuffer, out: Array[Long]): NibblePack.UnpackResult =
+    NibbleFormat.checkFormat(compressed, NibbleFormat.Format_ZigZag_Delta) match {
+      case Ok             => NibblePack.unpackAllToSink(compressed, NibblePackSigned.ZigZagDeltaSink(out), out.size)
+      case e: NibbleError => e
+    }
+}
+
+object HistColumnReader {
+  val HeaderBytes = 8
+}
@@ -52,7 +52,15 @@ trait Histogram extends Ordered[Histogram] {
   /**
    * Calculates histogram quantile based on bucket values using Prometheus scheme (increasing/LE)
    */
-  def quantile(q: Double): Double = {
+  def quantile(q: Double): Double = quantile(q, Rounding.LinearInterp)
+
+  /**
+   * Like quantile(q), but settles the quantile within the bucket holding the rank with the given Rounding:
+   * LinearInterp interpolates as Prometheus does, Floor and Ceil return the bottom and top of the bucket, and
+   * Nearest whichever of them the interpolated quantile is nearer.  A rank in the top bucket still returns the
+   * top of the bucket below it, whatever the rounding.
+   */
+  def quantile(q: Double, rounding: Rounding): Double = {
     val result = if (q < 0) Double.NegativeInfinity
     else if (q > 1) Double.PositiveInfinity
     else if (numBuckets < 2) Double.NaN
@@ -74,7 +82,7 @@ trait Histogram extends Ordered[Histogram] {
           count -= bucketValue(b-1)
           rank -= bucketValue(b-1)
         }
-        bucketStart + (bucketEnd-bucketStart)*(rank/count)
+        rounding.between(bucketStart, bucketEnd, rank/count)
       }
     }
     result
@@ -256,7 +264,7 @@ final case class MaxHistogram(innerHist: MutableHistogram, max: Double) extends
 
   def serialize(intoBuf: Option[MutableDirectBuffer] = None): MutableDirectBuffer = ???
 
-  override def quantile(q: Double): Double = {
+  override def quantile(q: Double, rounding: Rounding): Double = {
     val result = if (q < 0) Double.NegativeInfinity
     else if (q > 1) Double.PositiveInfinity
     else if (numBuckets < 2) Double.NaN
@@ -276,7 +284,7 @@ final case class MaxHistogram(innerHist: MutableHistogram, max: Double) extends
           count -= bucketValue(b-1)
           rank -= bucketValue(b-1)
         }
-        bucketStart + (bucketEnd-bucketStart)*(rank/count)
+        rounding.between(bucketStart, bucketEnd, rank/count)
       }
     }
     result
@@ -348,10 +356,10 @@ object HistogramBuckets {
   }
 
   // Create geometric buckets definition
-  def geometric(bucketsDefBase: Array[Byte], bucketsDefOffset: Long, minusOne: Boolean): HistogramBuckets =
-    GeometricBuckets(UnsafeUtils.getDouble(bucketsDefBase, bucketsDefOffset + OffsetBucketDetails),
-                     UnsafeUtils.getDouble(bucketsDefBase, bucketsDefOffset + OffsetBucketDetails + 8),
-                     UnsafeUtils.getShort(bucketsDefBase, bucketsDefOffset + OffsetNumBuckets).toInt,
+  def geometric(bucketsDefBase: Array[Byte], bucketsDefOffset: Long, minusOne: Boolean): GeometricBuckets =
+    GeometricBuckets(UnsafeUtils.getDoubleLE(bucketsDefBase, bucketsDefOffset + OffsetBucketDetails),
+                     UnsafeUtils.getDoubleLE(bucketsDefBase, bucketsDefOffset + OffsetBucketDetails + 8),
+                     UnsafeUtils.getShortLE(bucketsDefBase, bucketsDefOffset + OffsetNumBuckets) & 0x0ffff,
                      minusOne)
 
   /**
@@ -359,16 +367,32 @@ object HistogramBuckets {
    * @param bucketsDefOffset must point to the 2-byte length prefix of the bucket definition
    */
   def custom(bucketsDefBase: Array[Byte], bucketsDefOffset: Long): CustomBuckets = {
-    val numBuckets = UnsafeUtils.getShort(bucketsDefBase, bucketsDefOffset + 2) & 0x0ffff
+    val numBuckets = UnsafeUtils.getShortLE(bucketsDefBase, bucketsDefOffset + 2) & 0x0ffff
     val les = new Array[Double](numBuckets)
     UnsafeUtils.wrapDirectBuf(bucketsDefBase,
                               bucketsDefOffset + 4,
-                              (UnsafeUtils.getShort(bucketsDefBase, bucketsDefOffset) & 0xffff) - 2,
+                              (UnsafeUtils.getShortLE(bucketsDefBase, bucketsDefOffset) & 0xffff) - 2,
                               valuesBuf)
     require(NibblePack.unpackDoubleXOR(valuesBuf, les) == NibblePack.Ok)
     CustomBuckets(les)
   }
 
+  /**
+   * Returns the most compact bucket definition for the given bucket tops: GeometricBuckets, which is just the
+   * first bucket and multiplier, if every top is within a relative epsilon of a geometric series (with or without
+   * minusOne), otherwise CustomBuckets holding every top.
+   */
+  def fromBounds(les: Array[Double], epsilon: Double = 1e-9): HistogramBuckets = {
+    def matches(buckets: GeometricBuckets): Boolean =
+      checkGeometric(buckets, les.size).isEmpty &&
+      les.indices.forall { i => Math.abs(buckets.bucketTop(i) - les(i)) <= epsilon * Math.abs(les(i)) }
+    val multiplier = if (les.size >= 2) les(1) / les(0) else 2.0
+    val multiplier1 = if (les.size >= 2) (les(1) + 1) / (les(0) + 1) else 2.0
+    Seq(GeometricBuckets(les.headOption.getOrElse(0.0), multiplier, les.size),
+        GeometricBuckets(les.headOption.getOrElse(0.0) + 1, multiplier1, les.size, minusOne = true))
+      .find(matches).getOrElse(CustomBuckets(les))
+  }
+
   // A bucket definition for the bits of a long, ie from 2^0 to 2^63
   // le's = [1, 3, 7, 15, 31, ....]
   val binaryBuckets64 = GeometricBuckets(2.0d, 2.0d, 64, minusOne = true)
@@ -394,7 +418,7 @@ final case class GeometricBuckets(firstBucket: Double,
   final def serialize(buf: MutableDirectBuffer, pos: Int): Int = {
     require(numBuckets < 65536, s"Too many buckets: $numBuckets")
     val numBucketsPos = pos + 2
-    buf.putShort(pos, (2 + 8 + 8).toShort)
+    buf.putShort(pos, (2 + 8 + 8).toShort, LITTLE_ENDIAN)
     buf.putShort(numBucketsPos, numBuckets.toShort, LITTLE_ENDIAN)
     buf.putDouble(numBucketsPos + OffsetBucketDetails, firstBucket, LITTLE_ENDIAN)
     buf.putDouble(numBucketsPos + OffsetBucketDetails + 8, multiplier, LITTLE_ENDIAN)
@@ -412,10 +436,10 @@ final case class CustomBuckets(les: Array[Double]) extends HistogramBuckets {
   def numBuckets: Int = les.size
   def bucketTop(no: Int): Double = les(no)
   final def serialize(buf: MutableDirectBuffer, pos: Int): Int = {
-    buf.putShort(pos + 2, les.size.toShort)
-    val finalPos = NibblePack.packDoubles(les, buf, pos + 4)
+    buf.putShort(pos + 2, les.size.toShort, LITTLE_ENDIAN)
+    val finalPos = NibblePackCompat.packDoubles(les, buf, pos + 4)
     require((finalPos - pos) <= 65535, s"Packing of ${les.size} buckets takes too much space!")
-    buf.putShort(pos, (finalPos - pos - 2).toShort)
+    buf.putShort(pos, (finalPos - pos - 2).toShort, LITTLE_ENDIAN)
     finalPos
   }
 
@@ -85,7 +85,7 @@ object HistogramCompressor extends App with CompressorAnalyzer {
 
   histograms.foreach { h =>
     val buf = h.serialize()
-    val histSize = (buf.getShort(0) & 0x0ffff) + 2
+    val histSize = (buf.getShort(0, java.nio.ByteOrder.LITTLE_ENDIAN) & 0x0ffff) + 2
     numRecords += 1
     binHistBytesMax = Math.max(binHistBytesMax, histSize)
     binHistBytesSum += histSize
@@ -0,0 +1,57 @@
+package filodb.memory.format.vectors
+
+import org.agrona.{DirectBuffer, MutableDirectBuffer}
+import scalaxy.loops._
+
+import filodb.memory.format.NibblePack
+
+/**
+ * Sums many geometric BinaryHistograms with the same buckets into one, such as for rolling up the histograms of
+ * many series on the server.  Each histogram is unpacked into a scratch array owned by the merger and added to
+ * the running bucket counts, so no Histogram objects are created, and the sum is written out as one
+ * BinaryHistogram by finish.  A HistogramMerger is not thread safe.
+ * @param buckets the bucket scheme every histogram added must have
+ * @throws IllegalArgumentException for a bucket scheme which cannot be written, see BinaryHistogram.checkGeometric
+ */
+final class HistogramMerger(val buckets: GeometricBuckets) {
+  BinaryHistogram.checkGeometric(buckets, buckets.numBuckets).foreach { e =>
+    throw new IllegalArgumentException(s"Invalid bucket scheme $buckets: $e")
+  }
+
+  private val totals = new Array[Long](buckets.numBuckets)
+  private val scratch = new Array[Long](buckets.numBuckets)
+  private val sink = NibblePack.DeltaSink(scratch)
+
+  // The number of histograms added so far
+  var numMerged = 0
+
+  /**
+   * Adds the bucket counts of a BinaryHistogram, such as one written by BinaryHistogram.writeDelta, to the sum.
+   * Nothing is added if there is an error.
+   * @param hist a buffer wrapping the BinaryHistogram, starting with its length prefix
+   * @return SchemaMismatch if its buckets are not the merger's, or the error from decoding it
+   */
+  final def add(hist: DirectBuffer): Either[NibblePack.NibbleError, Unit] =
+    BinaryHistogram.geometricValues(hist).right.flatMap { case (histBuckets, values) =>
+      if (histBuckets != buckets) {
+        Left(NibblePack.SchemaMismatch)
+      } else {
+        sink.reset()
+        NibblePack.unpackAllToSink(values, sink, scratch.size) match {
+          case NibblePack.Ok =>
+            for { b <- 0 until totals.size optimized } { totals(b) += scratch(b) }
+            numMerged += 1
+            Right(())
+          case e: NibblePack.NibbleError => Left(e)
+        }
+      }
+    }
+
+  /**
+   * Writes the sum of the histograms added so far as a BinaryHistogram with the merger's buckets.  More
+   * histograms can still be added afterwards.
+   * @param out the buffer to write the histogram to, see BinaryHistogram.writeDelta
+   * @return the number of bytes written, including the length prefix
+   */
+  final def finish(out: MutableDirectBuffer): Int = BinaryHistogram.writeDelta(buckets, totals, out)
+}
@@ -1,6 +1,7 @@
 package filodb.memory.format.vectors
 
 import java.nio.ByteBuffer
+import java.nio.ByteOrder.LITTLE_ENDIAN
 
 import com.typesafe.scalalogging.StrictLogging
 import debox.Buffer
@@ -33,10 +34,10 @@ import filodb.memory.format.BinaryVector.BinaryVectorPtr
 object BinaryHistogram extends StrictLogging {
   // Pass in a buffer which includes the length bytes.  Value class - no allocations.
   case class BinHistogram(buf: DirectBuffer) extends AnyVal {
-    def totalLength: Int = buf.getShort(0).toInt + 2
-    def numBuckets: Int = buf.getShort(5).toInt
+    def totalLength: Int = buf.getShort(0, LITTLE_ENDIAN).toInt + 2
+    def numBuckets: Int = buf.getShort(5, LITTLE_ENDIAN) & 0x0ffff
     def formatCode: Byte = buf.getByte(2)
-    def bucketDefNumBytes: Int = buf.getShort(3).toInt
+    def bucketDefNumBytes: Int = buf.getShort(3, LITTLE_ENDIAN).toInt
     def bucketDefOffset: Long = buf.addressOffset + 5
     def valuesIndex: Int = 2 + 3 + bucketDefNumBytes     // pointer to values bytes
     def valuesNumBytes: Int = totalLength - valuesIndex
@@ -101,27 +102,89 @@ object BinaryHistogram extends StrictLogging {
     (code == HistFormat_Null) || (code == HistFormat_Geometric1_Delta) || (code == HistFormat_Geometric_Delta) ||
     (code == HistFormat_Custom_Delta)
 
+  /**
+   * The formats a BinaryHistogram can be in, one for each format code, for callers which pick a decoder by format
+   * rather than match on the raw code.  Histograms written by writeNonIncreasing have the same geometric codes
+   * as those written by writeDelta, as both decode to increasing values.
+   */
+  sealed abstract class BinHistogramFormat(val code: Byte)
+  object BinHistogramFormat {
+    // An empty histogram, with no buckets or values
+    case object Null extends BinHistogramFormat(HistFormat_Null)
+    // Geometric buckets, see GeometricBuckets, and NibblePacked delta values
+    case object GeometricDelta extends BinHistogramFormat(HistFormat_Geometric_Delta)
+    // Geometric buckets with tops one less than the geometric series, and NibblePacked delta values
+    case object Geometric1Delta extends BinHistogramFormat(HistFormat_Geometric1_Delta)
+    // Custom bucket tops, see CustomBuckets, and NibblePacked delta values
+    case object CustomDelta extends BinHistogramFormat(HistFormat_Custom_Delta)
+
+    val all = Seq(Null, GeometricDelta, Geometric1Delta, CustomDelta)
+
+    def fromCode(code: Byte): Option[BinHistogramFormat] = all.find(_.code == code)
+  }
+
+  /**
+   * Reads the format of a BinaryHistogram from its format code, without decoding the buckets or values.
+   * @param buf a buffer wrapping the BinaryHistogram, starting with its length prefix
+   * @return the format, InputTooShort if there is no format code, or UnexpectedFormat for an unknown code
+   */
+  def readFormat(buf: DirectBuffer): Either[NibblePack.NibbleError, BinHistogramFormat] =
+    if (buf.capacity < 3) Left(NibblePack.InputTooShort(3, buf.capacity))
+    else BinHistogramFormat.fromCode(buf.getByte(2)).toRight(NibblePack.UnexpectedFormat(buf.getByte(2)))
+
   /**
    * Writes binary histogram with geometric bucket definition and data which is non-increasing, but will be
    * decoded as increasing.  Intended only for specific use cases when the source histogram are non increasing
    * buckets, ie each bucket has a count that is independent.
    * @param buf the buffer to write the histogram to.  Highly recommended this be an ExpandableArrayBuffer or equiv.
    *            so it can grow.
+   * @param scratch an array of at least 8 Longs to gather blocks in while packing.  Callers writing many
+   *                histograms from one thread can pass the same array each time instead of the thread local one.
    * @return the number of bytes written, including the length prefix
    */
-  def writeNonIncreasing(buckets: GeometricBuckets, values: Array[Long], buf: MutableDirectBuffer): Int = {
+  def writeNonIncreasing(buckets: GeometricBuckets, values: Array[Long], buf: MutableDirectBuffer,
+                         scratch: Array[Long] = NibblePack.tempArray): Int = {
     require(buckets.numBuckets == values.size, s"Values array size of ${values.size} != ${buckets.numBuckets}")
     val formatCode = if (buckets.minusOne) HistFormat_Geometric1_Delta else HistFormat_Geometric_Delta
 
     buf.putByte(2, formatCode)
     val valuesIndex = buckets.serialize(buf, 3)
-    val finalPos = NibblePack.packNonIncreasing(values, buf, valuesIndex)
+    val finalPos = NibblePackCompat.packNonIncreasing(values, buf, valuesIndex, scratch)
 
     require(finalPos <= 65535, s"Histogram data is too large: $finalPos bytes needed")
-    buf.putShort(0, (finalPos - 2).toShort)
+    buf.putShort(0, (finalPos - 2).toShort, LITTLE_ENDIAN)
     finalPos
   }
 
+  /**
+   * Like writeNonIncreasing, but checks the bucket scheme and values first, returning an error instead of
+   * throwing or silently writing a corrupt histogram.
+   * @return the number of bytes written, including the length prefix, or InvalidParameter for the first bad
+   *         parameter, see checkGeometric
+   */
+  def writeNonIncreasingChecked(buckets: GeometricBuckets, values: Array[Long],
+                                buf: MutableDirectBuffer): Either[NibblePack.NibbleError, Int] =
+    checkGeometric(buckets, values.size).toLeft(writeNonIncreasing(buckets, values, buf))
+
+  /**
+   * Checks that a geometric bucket scheme makes sense and can be serialized: the multiplier must be above 1, the
+   * first bucket above 0, and there must be from 1 to 65535 buckets, one for each of numValues values.  The number
+   * of buckets is stored as an unsigned 16-bit value, so any more could only be written truncated.
+   * @return None if the scheme is fine, or InvalidParameter for the first bad parameter
+   */
+  def checkGeometric(buckets: GeometricBuckets, numValues: Int): Option[NibblePack.NibbleError] =
+    if (!(buckets.multiplier > 1.0)) {
+      Some(NibblePack.InvalidParameter("multiplier", buckets.multiplier))
+    } else if (!(buckets.firstBucket > 0.0)) {
+      Some(NibblePack.InvalidParameter("firstBucket", buckets.firstBucket))
+    } else if (buckets.numBuckets < 1 || buckets.numBuckets > 65535) {
+      Some(NibblePack.InvalidParameter("numBuckets", buckets.numBuckets))
+    } else if (numValues != buckets.numBuckets) {
+      Some(NibblePack.InvalidParameter("numValues", numValues))
+    } else {
+      None
+    }
+
   def writeDelta(buckets: HistogramBuckets, values: Array[Long]): Int =
     writeDelta(buckets, values, histBuf)
 
@@ -143,12 +206,198 @@ object BinaryHistogram extends StrictLogging {
 
     buf.putByte(2, formatCode)
     val valuesIndex = buckets.serialize(buf, 3)
-    val finalPos = NibblePack.packDelta(values, buf, valuesIndex)
+    val finalPos = NibblePackCompat.packDelta(values, buf, valuesIndex)
 
     require(finalPos <= 65535, s"Histogram data is too large: $finalPos bytes needed")
-    buf.putShort(0, (finalPos - 2).toShort)
+    buf.putShort(0, (finalPos - 2).toShort, LITTLE_ENDIAN)
     finalPos
   }
+
+  /**
+   * Like writeDelta, but works out the bucket definition from the bucket tops, so callers need not know whether
+   * they are geometric: geometric tops are written as just the first bucket and multiplier, and any others as
+   * custom buckets.  The format code records which was chosen, see HistogramBuckets.fromBounds.
+   * @return the number of bytes written, including the length prefix
+   */
+  def writeDeltaAuto(les: Array[Double], values: Array[Long], buf: MutableDirectBuffer): Int =
+    writeDelta(HistogramBuckets.fromBounds(les), values, buf)
+
+  /**
+   * Decodes a BinaryHistogram with geometric buckets, as written by writeDelta or writeNonIncreasing.
+   * Unlike BinHistogram.toHistogram, which returns an empty histogram for anything it cannot read, this checks
+   * that the histogram is well formed and reports why it is not.  Corrupt bucket schemes are refused with
+   * InvalidGeometry, rather than decoded into NaN or decreasing bucket tops.
+   * @param buf a buffer wrapping the BinaryHistogram, starting with its length prefix
+   * @return a LongHistogram whose buckets are GeometricBuckets and whose values are the cumulative bucket counts
+   */
+  def decodeGeometric(buf: DirectBuffer): Either[NibblePack.NibbleError, LongHistogram] =
+    geometricValues(buf).right.flatMap { case (buckets, valuesSlice) =>
+      val values = new Array[Long](buckets.numBuckets)
+      NibblePack.unpackAllToSink(valuesSlice, NibblePack.DeltaSink(values), values.size) match {
+        case NibblePack.Ok             => Right(LongHistogram(buckets, values))
+        case e: NibblePack.NibbleError => Left(e)
+      }
+    }
+
+  // Checks that buf holds a well formed geometric BinaryHistogram, returning its buckets and its packed values
+  private[vectors] def geometricValues(buf: DirectBuffer): Either[NibblePack.NibbleError,
+                                                                  (GeometricBuckets, DirectBuffer)] = {
+    import NibblePack._
+    val hist = BinHistogram(buf)
+    val totalLength = if (buf.capacity >= 2) (buf.getShort(0, LITTLE_ENDIAN) & 0x0ffff) + 2 else 0
+    if (buf.capacity < 5) {
+      Left(InputTooShort(5, buf.capacity))
+    } else if (totalLength > buf.capacity) {
+      Left(InputTooShort(totalLength, buf.capacity))
+    } else if (hist.formatCode != HistFormat_Geometric_Delta && hist.formatCode != HistFormat_Geometric1_Delta) {
+      Left(UnexpectedFormat(hist.formatCode))
+    } else if (hist.bucketDefNumBytes != 2 + 8 + 8) {
+      Left(InvalidHeader("bucketDefNumBytes", hist.bucketDefNumBytes))
+    } else if (hist.valuesIndex > totalLength) {
+      Left(InputTooShort(hist.valuesIndex, totalLength))
+    } else {
+      val buckets = HistogramBuckets.geometric(buf.byteArray, hist.bucketDefOffset,
+                                               hist.formatCode == HistFormat_Geometric1_Delta)
+      if (buckets.numBuckets < 0 || buckets.numBuckets > HistogramBuckets.MAX_BUCKETS) {
+        Left(InvalidHeader("numBuckets", buckets.numBuckets))
+      } else if (!(buckets.firstBucket > 0.0) || buckets.firstBucket.isInfinite) {
+        Left(InvalidGeometry("firstBucket", buckets.firstBucket))
+      } else if (!(buckets.multiplier > 1.0) || buckets.multiplier.isInfinite) {
+        Left(InvalidGeometry("multiplier", buckets.multiplier))
+      } else {
+        Right((buckets, new UnsafeBuffer(buf, hist.valuesIndex, totalLength - hist.valuesIndex)))
+      }
+    }
+  }
+
+  /**
+   * Decodes a geometric BinaryHistogram like decodeGeometric, but returns the count in each bucket on its own
+   * rather than cumulative counts, ie bucket(i) - bucket(i - 1) with bucket(-1) = 0.
+   * writeDelta and writeNonIncreasing share the same format codes, so both decode this way; the cumulative counts
+   * must never decrease from one bucket to the next.
+   * @return the per-bucket counts, or NonIncreasing(bucketNo) for the first bucket lower than the one before it
+   */
+  def decodeGeometricPerBucket(buf: DirectBuffer): Either[NibblePack.NibbleError, Array[Long]] =
+    decodeGeometric(buf).right.flatMap { hist =>
+      val counts = new Array[Long](hist.numBuckets)
+      var last = 0L
+      var b = 0
+      while (b < counts.size && hist.values(b) >= last) {
+        counts(b) = hist.values(b) - last
+        last = hist.values(b)
+        b += 1
+      }
+      if (b < counts.size) Left(NibblePack.NonIncreasing(b)) else Right(counts)
+    }
+
+  /**
+   * Computes the q-th quantile of a geometric BinaryHistogram, as histogram_quantile does, by interpolating
+   * within the bucket holding the rank; see Histogram.quantile.  Only the bucket values are unpacked.
+   * Unlike Histogram.quantile, q is clamped to [0, 1] rather than giving an infinite result.
+   * @param rounding how the quantile is settled within its bucket, see Histogram.quantile(q, rounding)
+   * @return the quantile, NaN for a histogram with no counts or fewer than 2 buckets, or InvalidParameter
+   *         if q is NaN
+   */
+  def quantileGeometric(buf: DirectBuffer, q: Double,
+                        rounding: Rounding = Rounding.LinearInterp): Either[NibblePack.NibbleError, Double] =
+    if (q.isNaN) {
+      Left(NibblePack.InvalidParameter("q", q))
+    } else {
+      decodeGeometric(buf).right.map(_.quantile(Math.min(Math.max(q, 0.0), 1.0), rounding))
+    }
+
+  /**
+   * Summary statistics of one histogram, see statsGeometric.
+   * @param count the total number of observations, ie the top cumulative bucket
+   * @param sum the approximate sum of all observations
+   * @param mean sum / count, NaN if there are no observations
+   */
+  final case class HistStats(count: Long, sum: Double, mean: Double)
+
+  /**
+   * Computes the count, approximate sum and mean of a geometric BinaryHistogram without the caller decoding it
+   * into a Histogram.  The sum takes each observation to be at the middle of its bucket, between the top of the
+   * bucket below (0 for the first bucket) and its own top, so the mean is only as precise as the buckets are wide.
+   * @return the stats, count 0 and mean NaN for a histogram with no observations, or an error as from
+   *         decodeGeometricPerBucket
+   */
+  def statsGeometric(buf: DirectBuffer): Either[NibblePack.NibbleError, HistStats] =
+    geometricValues(buf).right.flatMap { case (buckets, _) =>
+      decodeGeometricPerBucket(buf).right.map { counts =>
+        var count = 0L
+        var sum = 0.0
+        for { b <- 0 until counts.size optimized } {
+          val bottom = if (b == 0) 0.0 else buckets.bucketTop(b - 1)
+          count += counts(b)
+          sum += counts(b) * (bottom + buckets.bucketTop(b)) / 2
+        }
+        HistStats(count, sum, if (count == 0) Double.NaN else sum / count)
+      }
+    }
+
+  /**
+   * Computes the per-bucket differences between two geometric BinaryHistograms, such as consecutive samples of
+   * a histogram counter for rate(), and writes them as a new BinaryHistogram with the same buckets.
+   * Avoids the caller decoding both into Histogram objects and serializing the result again.
+   * @param out the buffer to write the difference to, see writeDelta
+   * @return the number of bytes written including the length prefix, SchemaMismatch if the two histograms have
+   *         different buckets, or NonIncreasing(bucketNo) if the differences are not increasing across buckets,
+   *         eg because the counter was reset
+   */
+  def diffGeometric(prev: DirectBuffer, curr: DirectBuffer,
+                    out: MutableDirectBuffer): Either[NibblePack.NibbleError, Int] =
+    for {
+      prevHist <- decodeGeometric(prev).right
+      currHist <- decodeGeometric(curr).right
+      diffs    <- diffValues(prevHist, currHist).right
+    } yield writeDelta(currHist.buckets, diffs, out)
+
+  private def diffValues(prev: LongHistogram, curr: LongHistogram): Either[NibblePack.NibbleError, Array[Long]] =
+    if (prev.buckets != curr.buckets) {
+      Left(NibblePack.SchemaMismatch)
+    } else {
+      val diffs = new Array[Long](curr.numBuckets)
+      var lastDiff = 0L
+      var b = 0
+      while (b < diffs.size && curr.values(b) - prev.values(b) >= lastDiff) {
+        diffs(b) = curr.values(b) - prev.values(b)
+        lastDiff = diffs(b)
+        b += 1
+      }
+      if (b < diffs.size) Left(NibblePack.NonIncreasing(b)) else Right(diffs)
+    }
+
+  /**
+   * Like diffGeometric, but for increase() and rate() over histogram counters which may have been reset.  Each
+   * bucket which is lower in curr than in prev is taken to have been reset, so its increase is its whole count in
+   * curr instead of a negative difference.  This mirrors how Prometheus handles resets of the per-bucket counters
+   * of a histogram.  Buckets reset on their own can leave the increases decreasing across buckets, so as in
+   * Prometheus each increase is raised to at least the one of the bucket below it, keeping the result cumulative.
+   * @param out the buffer to write the increase to, see writeDelta
+   * @return the number of bytes written including the length prefix, or SchemaMismatch if the two histograms have
+   *         different buckets
+   */
+  def increaseGeometric(prev: DirectBuffer, curr: DirectBuffer,
+                        out: MutableDirectBuffer): Either[NibblePack.NibbleError, Int] =
+    for {
+      prevHist  <- decodeGeometric(prev).right
+      currHist  <- decodeGeometric(curr).right
+      increases <- increaseValues(prevHist, currHist).right
+    } yield writeDelta(currHist.buckets, increases, out)
+
+  private def increaseValues(prev: LongHistogram, curr: LongHistogram): Either[NibblePack.NibbleError, Array[Long]] =
+    if (prev.buckets != curr.buckets) {
+      Left(NibblePack.SchemaMismatch)
+    } else {
+      val increases = new Array[Long](curr.numBuckets)
+      var lastIncrease = 0L
+      for { b <- 0 until increases.size optimized } {
+        val reset = curr.values(b) < prev.values(b)
+        lastIncrease = Math.max(lastIncrease, if (reset) curr.values(b) else curr.values(b) - prev.values(b))
+        increases(b) = lastIncrease
+      }
+      Right(increases)
+    }
 }
 
 object HistogramVector {
@@ -171,7 +420,8 @@ object HistogramVector {
   // Note: the format code defines bucket definition format + format of each individual compressed histogram
   final def formatCode(addr: Ptr.U8): Byte = addr.add(OffsetFormatCode).getU8.toByte
   final def afterBucketDefAddr(addr: Ptr.U8): Ptr.U8 = addr + OffsetBucketDef + bucketDefNumBytes(addr)
-  final def bucketDefNumBytes(addr: Ptr.U8): Int = addr.add(OffsetBucketDefSize).asU16.getU16
+  final def bucketDefNumBytes(addr: Ptr.U8): Int =
+    UnsafeUtils.getShortLE(UnsafeUtils.ZeroPointer, addr.add(OffsetBucketDefSize).addr) & 0x0ffff
   final def bucketDefAddr(addr: Ptr.U8): Ptr.U8 = addr + OffsetBucketDef
 
   // Matches the bucket definition whose # bytes is at (base, offset)
@@ -274,7 +524,7 @@ class AppendableHistogramVector(factory: MemFactory,
       // Copy the bucket definition and set the bucket def size
       UnsafeUtils.unsafe.copyMemory(buf.byteArray, h.bucketDefOffset,
                                     UnsafeUtils.ZeroPointer, bucketDefAddr(vectPtr).addr, h.bucketDefNumBytes)
-      UnsafeUtils.setShort(addr + OffsetBucketDefSize, h.bucketDefNumBytes.toShort)
+      UnsafeUtils.setShortLE(UnsafeUtils.ZeroPointer, addr + OffsetBucketDefSize, h.bucketDefNumBytes.toShort)
       UnsafeUtils.setByte(addr + OffsetFormatCode, h.formatCode)
 
       // Initialize the first section
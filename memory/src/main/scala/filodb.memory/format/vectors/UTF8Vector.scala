@@ -308,7 +308,7 @@ class UTF8AppendableVector(val addr: BinaryRegion.NativePointer,
         val firstStr = apply(0)
         ConstVector.make(memFactory, this.length, firstStr.length + 2) { addr =>
           firstStr.copyTo(UnsafeUtils.ZeroPointer, addr + 2)
-          UnsafeUtils.setShort(UnsafeUtils.ZeroPointer, addr, firstStr.length.toShort)
+          UnsafeUtils.setShortLE(UnsafeUtils.ZeroPointer, addr, firstStr.length.toShort)
         }
       } else {
         DictUTF8Vector.makeVector(memFactory, dictInfo)
@@ -379,7 +379,7 @@ object UTF8ConstVector extends ConstVector with UTF8VectorDataReader {
   // TODO: return just a pointer (NativePointer) or a UTF8StringMedium value class
   def apply(vector: BinaryVectorPtr, i: Int): ZeroCopyUTF8String =
     new ZeroCopyUTF8String(UnsafeUtils.ZeroPointer, vector + 14,
-                           UnsafeUtils.getShort(vector + 12) & 0x0ffff)
+                           UnsafeUtils.getShortLE(UnsafeUtils.ZeroPointer, vector + 12) & 0x0ffff)
   def iterate(vector: BinaryVectorPtr, startElement: Int = 0): UTF8Iterator = new UTF8Iterator {
     def next: ZeroCopyUTF8String = apply(vector, 0)
   }
@@ -0,0 +1,113 @@
+package filodb.memory
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.scalatest.{FunSpec, Matchers}
+
+class BinaryRegionTest extends FunSpec with Matchers {
+  describe("safeNumBytes and safeSlice") {
+    it("should return the region when it fits in the array") {
+      // 2-byte little endian length of 3 then the payload, plus one extra byte
+      val bytes = Array[Byte](3, 0, 10, 20, 30, 99)
+      BinaryRegionMedium.safeNumBytes(bytes, 0) shouldEqual Some(3)
+      val slice = BinaryRegionMedium.safeSlice(bytes, 0).get
+      slice.capacity shouldEqual 3
+      slice.getByte(0) shouldEqual 10
+      slice.getByte(2) shouldEqual 30
+
+      // an empty region at the very end of the array
+      BinaryRegionLarge.safeNumBytes(Array[Byte](1, 0, 0, 0, 0), 1) shouldEqual Some(0)
+    }
+
+    it("should return None when the length prefix does not fit") {
+      BinaryRegionMedium.safeNumBytes(Array[Byte](3), 0) shouldEqual None
+      BinaryRegionLarge.safeNumBytes(Array[Byte](3, 0, 0), 0) shouldEqual None
+      BinaryRegionMedium.safeNumBytes(Array[Byte](0, 0), 1) shouldEqual None
+      BinaryRegionMedium.safeNumBytes(Array[Byte](0, 0), -1) shouldEqual None
+    }
+
+    it("should return None when the declared length overruns the array") {
+      BinaryRegionMedium.safeSlice(Array[Byte](4, 0, 10, 20, 30), 0) shouldEqual None
+      BinaryRegionMedium.safeSlice(Array[Byte](-1, -1, 1, 2), 0) shouldEqual None
+      // a negative length from a corrupt 4-byte prefix
+      BinaryRegionLarge.safeSlice(Array[Byte](-1, -1, -1, -1, 1), 0) shouldEqual None
+      BinaryRegionLarge.safeSlice(Array[Byte](0, 0, 0, 0x7f, 1), 0) shouldEqual None
+    }
+  }
+
+  describe("parse") {
+    import BinaryRegion._
+
+    it("should tell medium and large regions apart by which length prefix fits") {
+      val medium = BinaryRegion.parse(Array[Byte](3, 0, 10, 20, 30))
+      medium.right.get shouldBe a[MediumRegion]
+      medium.right.get.payload.capacity shouldEqual 3
+      medium.right.get.payload.getByte(0) shouldEqual 10
+
+      val large = BinaryRegion.parse(Array[Byte](2, 0, 0, 0, 10, 20))
+      large.right.get shouldBe a[LargeRegion]
+      large.right.get.payload.capacity shouldEqual 2
+      large.right.get.payload.getByte(1) shouldEqual 20
+
+      BinaryRegion.parse(Array[Byte](0, 0)).right.get shouldBe a[MediumRegion]
+      BinaryRegion.parse(Array[Byte](0, 0, 0, 0)).right.get shouldBe a[LargeRegion]
+    }
+
+    it("should return errors when no length prefix fits") {
+      BinaryRegion.parse(Array[Byte](1)) shouldEqual Left(format.NibblePack.InputTooShort(2, 1))
+      BinaryRegion.parse(Array[Byte](9, 0, 1, 2)) shouldEqual Left(format.NibblePack.InvalidHeader("length", 9))
+    }
+  }
+
+  describe("length prefixes") {
+    it("should read medium length prefixes as little endian and large ones in host order") {
+      // 0x0102 little endian, then enough payload bytes for a medium region
+      val bytes = Array[Byte](0x02, 0x01, 0x00, 0x00) ++ Array.fill(0x0102)(7.toByte)
+      BinaryRegionMedium.numBytes(bytes, format.UnsafeUtils.arayOffset) shouldEqual 0x0102
+      BinaryRegionMedium.safeSlice(bytes, 0).get.capacity shouldEqual 0x0102
+
+      val large = Array[Byte](0x04, 0x03, 0x02, 0x01)
+      format.UnsafeUtils.getShortLE(large, format.UnsafeUtils.arayOffset) shouldEqual 0x0304
+      format.UnsafeUtils.setInt(large, format.UnsafeUtils.arayOffset, 0x01020304)
+      BinaryRegionLarge.numBytes(large, format.UnsafeUtils.arayOffset) shouldEqual 0x01020304
+    }
+
+    it("should write UTF8StringMedium length prefixes which BinaryRegionMedium reads") {
+      val (base, offset) = UTF8StringMedium("hello")
+      base.asInstanceOf[Array[Byte]].take(2) shouldEqual Array[Byte](5, 0)
+      BinaryRegionMedium.numBytes(base, offset) shouldEqual 5
+      UTF8StringMedium.toString(base, offset) shouldEqual "hello"
+    }
+  }
+
+  describe("writeRegion") {
+    it("should write regions which parse back to the original payload") {
+      val buf = new ExpandableArrayBuffer()
+      Seq(0, 1, 3, 300, 0x0FFFF).foreach { len =>
+        val payload = Array.tabulate(len)(_.toByte)
+        val mediumEnd = BinaryRegionMedium.writeRegion(payload, buf, 0)
+        mediumEnd shouldEqual len + 2
+        val medium = BinaryRegion.parse(buf.byteArray.take(mediumEnd)).right.get
+        medium shouldBe a[BinaryRegion.MediumRegion]
+        medium.payload.byteArray.drop(2) shouldEqual payload
+
+        val largeEnd = BinaryRegionLarge.writeRegion(payload, buf, 1)
+        largeEnd shouldEqual len + 5
+        BinaryRegionLarge.safeSlice(buf.byteArray.take(largeEnd), 1).get shouldEqual new UnsafeBuffer(payload)
+      }
+    }
+
+    it("should copy DirectBuffer slices, such as from safeSlice") {
+      val buf = new ExpandableArrayBuffer()
+      val slice = BinaryRegionMedium.safeSlice(Array[Byte](3, 0, 10, 20, 30, 99), 0).get
+      BinaryRegionLarge.writeRegion(slice, buf, 0) shouldEqual 7
+      buf.byteArray.take(7) shouldEqual Array[Byte](3, 0, 0, 0, 10, 20, 30)
+    }
+
+    it("should refuse payloads the length prefix cannot describe") {
+      intercept[IllegalArgumentException] {
+        BinaryRegionMedium.writeRegion(new Array[Byte](0x10000), new ExpandableArrayBuffer(), 0)
+      }
+    }
+  }
+}
@@ -0,0 +1,82 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+class BitStreamsTest extends FunSpec with Matchers {
+  val buf = new ExpandableArrayBuffer()
+  val patterns = Seq(-1L, 0x5555555555555555L, 0xaaaaaaaaaaaaaaaaL, 0x8000000000000001L, 0x0123456789abcdefL)
+
+  def mask(numBits: Int): Long = if (numBits >= 64) -1L else (1L << numBits) - 1
+
+  // Every start offset within a word and every width, so values start and end at every bit of a byte and word
+  val layouts = for { offset <- 0 until 64; numBits <- 1 to 64; pattern <- patterns } yield (offset, numBits, pattern)
+
+  it("should read back values written least significant bit first at every offset and width") {
+    layouts.foreach { case (offset, numBits, pattern) =>
+      val writer = new LsbBitWriter(buf, 3)
+      if (offset > 0) writer.write(-1L, offset)
+      writer.write(pattern, numBits)
+      writer.write(0x2dL, 7)
+      val end = writer.finish()
+      end shouldEqual 3 + (offset + numBits + 7 + 7) / 8
+
+      val reader = new LsbBitReader(new UnsafeBuffer(buf, 0, end), 3)
+      if (offset > 0) reader.read(offset) shouldEqual mask(offset)
+      reader.read(numBits) shouldEqual (pattern & mask(numBits))
+      reader.read(7) shouldEqual 0x2dL
+      reader.bytesRead shouldEqual end - 3
+      reader.has(1) shouldEqual ((offset + numBits + 7) % 8 != 0)
+    }
+  }
+
+  it("should read back values written most significant bit first at every offset and width") {
+    layouts.foreach { case (offset, numBits, pattern) =>
+      val writer = new BitWriter(buf, 0)
+      if (offset > 0) writer.write(-1L, offset)
+      writer.write(pattern, numBits)
+      writer.write(0x2dL, 7)
+      val end = writer.finish()
+      end shouldEqual (offset + numBits + 7 + 7) / 8
+
+      val reader = new BitReader(new UnsafeBuffer(buf, 0, end))
+      if (offset > 0) reader.read(offset) shouldEqual mask(offset)
+      reader.read(numBits) shouldEqual (pattern & mask(numBits))
+      reader.read(7) shouldEqual 0x2dL
+      reader.bytesRead shouldEqual end
+    }
+  }
+
+  it("should lay out bits least significant first in little-endian words, as NibblePack stores nibbles") {
+    val writer = new LsbBitWriter(buf, 0)
+    writer.write(0x1L, 4)
+    writer.write(0x2L, 4)
+    writer.write(0x345L, 12)
+    writer.finish() shouldEqual 3
+    (0 until 3).map(buf.getByte(_) & 0xff) shouldEqual Seq(0x21, 0x45, 0x03)
+
+    // Only the lowest numBits of a value are written
+    val masked = new LsbBitWriter(buf, 0)
+    masked.write(0xf1L, 4)
+    masked.write(0x0L, 4)
+    masked.finish() shouldEqual 1
+    buf.getByte(0) shouldEqual 0x01
+
+    // A reset writer starts afresh, without bits left over from before
+    masked.write(0x7L, 3)
+    masked.reset(buf, 2)
+    masked.write(0x5L, 4)
+    masked.finish() shouldEqual 3
+    buf.getByte(2) shouldEqual 0x05
+  }
+
+  it("should report how many bytes a read past the end would need") {
+    val reader = new LsbBitReader(new UnsafeBuffer(Array(0x21.toByte, 0x43.toByte)), 0)
+    reader.has(16) shouldEqual true
+    reader.read(12) shouldEqual 0x321L
+    reader.has(5) shouldEqual false
+    reader.tooShort(5) shouldEqual NibblePack.InputTooShort(3, 2)
+  }
+}
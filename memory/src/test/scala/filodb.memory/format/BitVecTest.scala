@@ -0,0 +1,68 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class BitVecTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def pack(values: Array[Boolean]): UnsafeBuffer = new UnsafeBuffer(buf, 0, BitVec.packBools(values, buf, 0))
+
+  def checkAll(values: Array[Boolean]): Unit = {
+    val packed = pack(values)
+    BitVec.unpackBools(packed).right.get shouldEqual values
+    val vec = BitVec(packed).right.get
+    vec.numValues shouldEqual values.size
+    vec.countOnes shouldEqual values.count(identity)
+    values.indices.foreach { i => vec.get(i) shouldEqual Some(values(i)) }
+  }
+
+  it("should pack and unpack alternating, all true and all false values") {
+    val alternating = Array.tabulate(101)(_ % 2 == 0)
+    checkAll(alternating)
+    BitVec(pack(alternating)).right.get.numRuns shouldEqual 101
+
+    checkAll(Array.fill(1000)(true))
+    checkAll(Array.fill(1000)(false))
+    checkAll(Array(true))
+    checkAll(Array.empty[Boolean])
+  }
+
+  it("should pack long constant runs much smaller than NibblePacked 0s and 1s") {
+    val up = Array.fill(10000)(true) ++ Array.fill(5)(false) ++ Array.fill(10000)(true)
+    val bitVecBytes = BitVec.packBools(up, buf, 0)
+    BitVec(new UnsafeBuffer(buf, 0, bitVecBytes)).right.get.numRuns shouldEqual 3
+    bitVecBytes should be < 20
+    bitVecBytes * 50 should be < NibblePack.packNonIncreasing(up.map(b => if (b) 1L else 0L), buf, 0)
+  }
+
+  it("should return None for out of range indices") {
+    val vec = BitVec(pack(Array(true, false, false))).right.get
+    vec.get(-1) shouldEqual None
+    vec.get(3) shouldEqual None
+    BitVec(pack(Array.empty[Boolean])).right.get.get(0) shouldEqual None
+  }
+
+  it("should pack and unpack random Booleans") {
+    forAll { (bools: Seq[Boolean]) => checkAll(bools.toArray) }
+  }
+
+  it("should refuse buffers which are truncated, inconsistent or not a BitVec") {
+    val bytesWritten = BitVec.packBools(Array.tabulate(40)(_ % 3 == 0), buf, 0)
+    BitVec(new UnsafeBuffer(buf, 0, bytesWritten - 1)).left.get shouldBe a[NibblePack.InputTooShort]
+    BitVec(new UnsafeBuffer(buf, 0, 5)) shouldEqual Left(NibblePack.InputTooShort(10, 5))
+
+    // the runs add up to more than numValues
+    buf.putInt(1, 39, java.nio.ByteOrder.LITTLE_ENDIAN)
+    BitVec(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual Left(NibblePack.InvalidHeader("numValues", 39))
+    buf.putInt(1, 10, java.nio.ByteOrder.LITTLE_ENDIAN)
+    BitVec(new UnsafeBuffer(buf, 0, bytesWritten)).left.get shouldEqual NibblePack.InvalidHeader("numRuns", 27)
+
+    val deltaBytes = NibblePackSigned.packDelta(Array(1L, 2L, 3L, 4L, 5L, 6L, 7L, 8L, 9L, 10L), buf, 0)
+    BitVec.unpackBools(new UnsafeBuffer(buf, 0, deltaBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_ZigZag_Delta))
+  }
+}
@@ -0,0 +1,104 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class CompressedVecTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def encode(inputs: Array[Long], blocksPerSkip: Int): CompressedVec = {
+    val bytesWritten = CompressedVec.encode(inputs, buf, 0, blocksPerSkip)
+    CompressedVec(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should get every element using the skip table") {
+    val inputs = Array.tabulate(100) { i => i * 1000L + (i % 7) }
+    Seq(1, 3, 16).foreach { k =>
+      val vec = encode(inputs, k)
+      vec.numValues shouldEqual 100
+      vec.blocksPerSkip shouldEqual k
+      inputs.indices.foreach { i => vec.get(i) shouldEqual Some(inputs(i)) }
+    }
+  }
+
+  it("should return None for out of range indices") {
+    val vec = encode(Array(1L, 2L, 3L), 4)
+    vec.get(-1) shouldEqual None
+    vec.get(3) shouldEqual None
+    encode(Array.empty[Long], 4).get(0) shouldEqual None
+  }
+
+  it("should write one footer entry every K blocks") {
+    val inputs = Array.fill(8 * 10)(12345L)
+    val sizeK1 = CompressedVec.encode(inputs, buf, 0, 1)
+    val sizeK5 = CompressedVec.encode(inputs, buf, 0, 5)
+    (sizeK1 - sizeK5) shouldEqual (10 - 2) * 4
+  }
+
+  it("should refuse buffers which are truncated or not a CompressedVec") {
+    val bytesWritten = CompressedVec.encode(Array.tabulate(20)(_.toLong), buf, 0, 2)
+    CompressedVec(new UnsafeBuffer(buf, 0, bytesWritten - 1)) shouldEqual
+      Left(NibblePack.InputTooShort(bytesWritten, bytesWritten - 1))
+    CompressedVec(new UnsafeBuffer(buf, 0, 5)) shouldEqual Left(NibblePack.InputTooShort(11, 5))
+
+    val deltaBytes = NibblePackSigned.packDelta(Array.tabulate(20)(_.toLong), buf, 0)
+    CompressedVec(new UnsafeBuffer(buf, 0, deltaBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_ZigZag_Delta))
+  }
+
+  it("should find targets in sorted vectors with containsSorted") {
+    val inputs = Array.tabulate(1000)(i => 1546300800000L + i * 10L)
+    Seq(1, 4, 16).foreach { k =>
+      val vec = encode(inputs, k)
+      inputs.foreach { ts => vec.containsSorted(ts) shouldEqual Some(true) }
+      Seq(inputs(0), inputs(7), inputs(8), inputs(999)).foreach { ts => vec.containsSorted(ts) shouldEqual Some(true) }
+      Seq(inputs(0) - 1, inputs(7) + 5, inputs(999) + 1, Long.MinValue, Long.MaxValue).foreach { ts =>
+        vec.containsSorted(ts) shouldEqual Some(false)
+      }
+    }
+    encode(Array.empty[Long], 4).containsSorted(0L) shouldEqual Some(false)
+
+    // Targets at and around the first and last values of blocks, including the partial final block
+    val partial = Array.tabulate(21)(i => i * 2L)
+    val partialVec = encode(partial, 2)
+    Seq(0L, 14L, 16L, 30L, 32L, 40L).foreach { ts => partialVec.containsSorted(ts) shouldEqual Some(true) }
+    Seq(-1L, 15L, 17L, 41L, 42L).foreach { ts => partialVec.containsSorted(ts) shouldEqual Some(false) }
+
+    // 16 nibbles plus 1 trailing nibble in the header of the only block
+    val corrupt = encode(Array.tabulate(8)(i => 1000L + i), 1)
+    buf.putByte(CompressedVec.HeaderBytes + 1, 0xf1.toByte)
+    corrupt.containsSorted(1003L) shouldEqual None
+
+    forAll { (longs: Seq[Long], target: Long) =>
+      val sorted = longs.sorted.toArray
+      val vec = encode(sorted, 3)
+      vec.containsSorted(target) shouldEqual Some(sorted.contains(target))
+      sorted.foreach { value => vec.containsSorted(value) shouldEqual Some(true) }
+    }
+  }
+
+  it("should return ranges of values using the skip table") {
+    val inputs = Array.tabulate(100) { i => i * 1000L + (i % 7) }
+    Seq(1, 3, 16).foreach { k =>
+      val vec = encode(inputs, k)
+      Seq((0, 100), (0, 1), (37, 38), (40, 48), (41, 99), (99, 100), (50, 50)).foreach { case (start, end) =>
+        vec.range(start, end).get shouldEqual inputs.slice(start, end)
+      }
+    }
+    val vec = encode(inputs, 4)
+    vec.range(-1, 5) shouldEqual None
+    vec.range(5, 4) shouldEqual None
+    vec.range(90, 101) shouldEqual None
+  }
+
+  it("should get random elements of random lists of Longs") {
+    forAll { (longs: Seq[Long], k: Byte) =>
+      val inputs = longs.toArray
+      val vec = encode(inputs, (k & 0x0f) + 1)
+      inputs.indices.foreach { i => vec.get(i) shouldEqual Some(inputs(i)) }
+    }
+  }
+}
@@ -0,0 +1,58 @@
+package filodb.memory.format
+
+import org.agrona.DirectBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+class DecodeCacheTest extends FunSpec with Matchers {
+  def packed(values: Array[Long]): DirectBuffer = new UnsafeBuffer(NibblePack.packDeltaToBytes(values))
+
+  val vectors = Array.tabulate(4)(n => Array.tabulate(50)(i => n * 1000L + i * (n + 1)))
+
+  it("should return the same values as unpackDelta, from the cache after the first time") {
+    val cache = new DecodeCache(4)
+    val buf = packed(vectors(0))
+    cache.unpackDelta(buf, 50).right.get shouldEqual vectors(0)
+    val again = cache.unpackDelta(buf, 50).right.get
+    again shouldEqual vectors(0)
+    (cache.hits, cache.misses) shouldEqual ((1L, 1L))
+
+    // Hits are copies, so changing one does not change the cached values
+    again(0) = -1L
+    cache.unpackDelta(buf, 50).right.get shouldEqual vectors(0)
+    buf.capacity shouldEqual NibblePack.packDeltaToBytes(vectors(0)).size     // not mutated
+  }
+
+  it("should evict the least recently used vector once over capacity") {
+    val cache = new DecodeCache(2)
+    val bufs = vectors.map(packed)
+    cache.unpackDelta(bufs(0), 50)
+    cache.unpackDelta(bufs(1), 50)
+    cache.unpackDelta(bufs(0), 50)     // 1 is now the least recently used
+    cache.unpackDelta(bufs(2), 50)
+    cache.size shouldEqual 2
+    (cache.hits, cache.misses) shouldEqual ((1L, 3L))
+
+    cache.unpackDelta(bufs(0), 50).right.get shouldEqual vectors(0)
+    cache.unpackDelta(bufs(2), 50).right.get shouldEqual vectors(2)
+    cache.hits shouldEqual 3L
+    cache.unpackDelta(bufs(1), 50).right.get shouldEqual vectors(1)
+    cache.misses shouldEqual 4L
+
+    cache.clear()
+    cache.size shouldEqual 0
+  }
+
+  it("should key on numValues as well as the bytes, and not cache errors") {
+    val cache = new DecodeCache(4)
+    val buf = packed(vectors(1))
+    cache.unpackDelta(buf, 50).right.get shouldEqual vectors(1)
+    cache.unpackDelta(buf, 20).right.get shouldEqual vectors(1).take(20)
+    cache.size shouldEqual 2
+
+    cache.unpackDelta(new UnsafeBuffer(buf, 0, 3), 50).isLeft shouldEqual true
+    cache.size shouldEqual 2
+    intercept[IllegalArgumentException] { new DecodeCache(0) }
+  }
+}
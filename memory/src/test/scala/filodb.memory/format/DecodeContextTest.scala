@@ -0,0 +1,97 @@
+package filodb.memory.format
+
+import scala.concurrent.{Await, Future}
+import scala.concurrent.ExecutionContext.Implicits.global
+import scala.concurrent.duration._
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class DecodeContextTest extends FunSpec with Matchers with PropertyChecks {
+  def packed(inputs: Array[Long]): UnsafeBuffer = new UnsafeBuffer(NibblePack.packDeltaToBytes(inputs))
+
+  it("should unpack delta vectors into its own array, growing it as needed") {
+    val ctx = new DecodeContext(4)
+    val small = Array(0L, 1000, 1001, 1002, 1003, 2005)
+    ctx.unpackDelta(packed(small), small.size) shouldEqual Right(small.size)
+    ctx.values.take(small.size) shouldEqual small
+
+    val large = Array.tabulate(1000)(i => i * 37L)
+    ctx.unpackDelta(packed(large), large.size) shouldEqual Right(large.size)
+    ctx.values.take(large.size) shouldEqual large
+
+    // the array is reused for a smaller vector
+    val grown = ctx.values
+    ctx.unpackDelta(packed(small), small.size) shouldEqual Right(small.size)
+    ctx.values should be theSameInstanceAs grown
+    ctx.values.take(small.size) shouldEqual small
+  }
+
+  it("should unpack the same as NibblePack.unpackDelta") {
+    val ctx = new DecodeContext()
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.map(_ & 0xffffffffL).sorted.toArray
+      val bytes = NibblePack.packDeltaToBytes(inputs)
+      val expected = NibblePack.unpackDelta(new UnsafeBuffer(bytes), inputs.size).right.get
+      ctx.unpackDelta(new UnsafeBuffer(bytes), inputs.size) shouldEqual Right(inputs.size)
+      ctx.values.take(inputs.size) shouldEqual expected
+    }
+  }
+
+  it("should return errors for truncated input") {
+    val inputs = Array.tabulate(20)(i => i * 1000L)
+    val bytes = NibblePack.packDeltaToBytes(inputs)
+    new DecodeContext().unpackDelta(new UnsafeBuffer(bytes, 0, bytes.size - 1), inputs.size).left.get shouldBe
+      a[NibblePack.InputTooShort]
+
+    // Cut off after the first block, so that the second block is missing rather than zero
+    val firstBlockBytes = NibblePack.blockSize(new UnsafeBuffer(bytes), 0)
+    new DecodeContext().unpackDelta(new UnsafeBuffer(bytes, 0, firstBlockBytes), inputs.size) shouldEqual
+      Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should unpack from inside a Sink with unpackDeltaReentrant, unlike with the thread local scratch array") {
+    val outer = Array.tabulate(16)(i => i + 1L)
+    val inner = Array.tabulate(24)(i => 1000000L + i * 1000L)
+    val outerBuf = new ExpandableArrayBuffer()
+    val outerBytes = java.util.Arrays.copyOf(outerBuf.byteArray, NibblePack.packNonIncreasing(outer, outerBuf, 0))
+
+    // Unpacks the outer vector, unpacking the inner one every block before copying out the outer values
+    def unpackNested(nested: UnsafeBuffer => Either[NibblePack.NibbleError, Array[Long]]): Array[Long] = {
+      val out = new Array[Long](outer.size)
+      var pos = 0
+      val sink = new NibblePack.Sink {
+        def process(data: Array[Long]): Unit = {
+          nested(packed(inner)).right.get shouldEqual inner
+          System.arraycopy(data, 0, out, pos, 8)
+          pos += 8
+        }
+      }
+      NibblePack.unpackToSink(new UnsafeBuffer(outerBytes), sink, outer.size) shouldEqual NibblePack.Ok
+      out
+    }
+
+    unpackNested(DecodeContext.unpackDeltaReentrant(_, inner.size)) shouldEqual outer
+    unpackNested(NibblePack.unpackDelta(_, inner.size)) should not equal outer
+    DecodeContext.unpackDeltaReentrant(packed(inner), 100000).left.get shouldBe a[NibblePack.ImplausibleCount]
+  }
+
+  it("should unpack correctly with one context per concurrent task") {
+    val vectors = (0 until 16).map { n => Array.tabulate(500 + n * 10)(i => i * (n + 1).toLong) }
+    val results = Future.traverse(vectors) { inputs =>
+      Future {
+        val ctx = new DecodeContext()
+        (0 until 20).map { _ =>
+          ctx.unpackDelta(packed(inputs), inputs.size).right.get
+          ctx.values.take(inputs.size).toSeq
+        }
+      }
+    }
+    Await.result(results, 10.seconds).zip(vectors).foreach { case (unpacked, inputs) =>
+      unpacked.foreach { _ shouldEqual inputs.toSeq }
+    }
+  }
+}
@@ -0,0 +1,66 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class DeltaAppenderTest extends FunSpec with Matchers with PropertyChecks {
+  val expectedBuf = new ExpandableArrayBuffer()
+
+  // Checks that the stream holds exactly what packDelta writes for the values, and unpacks to them
+  def checkStream(buf: ExpandableArrayBuffer, start: Int, end: Int, values: Array[Long]): Unit = {
+    val expectedBytes = NibblePack.packDelta(values, expectedBuf, 0)
+    (end - start) shouldEqual expectedBytes
+    (0 until expectedBytes).foreach { i => buf.getByte(start + i) shouldEqual expectedBuf.getByte(i) }
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, start, end - start), values.size).right.get shouldEqual values
+  }
+
+  it("should append values one at a time to a new stream, decoding the same after every push") {
+    val buf = new ExpandableArrayBuffer()
+    val appender = DeltaAppender(buf, 3, 0, 0).right.get
+    val inputs = Array.tabulate(40)(i => 1000L + i * 13 + (i % 3))
+    inputs.indices.foreach { i =>
+      val end = appender.push(inputs(i))
+      appender.position shouldEqual end
+      appender.numValues shouldEqual i + 1
+      checkStream(buf, 3, end, inputs.take(i + 1))
+    }
+    appender.lastValue shouldEqual inputs.last
+  }
+
+  it("should append to an existing stream ending in a partial or a full block") {
+    Seq(5, 8, 16, 21).foreach { numExisting =>
+      val buf = new ExpandableArrayBuffer()
+      val inputs = Array.tabulate(numExisting + 20)(i => i * 1000L + i % 7)
+      val numBytes = NibblePack.packDelta(inputs.take(numExisting), buf, 0)
+      val appender = DeltaAppender(buf, 0, numBytes, numExisting).right.get
+      appender.lastValue shouldEqual inputs(numExisting - 1)
+
+      (numExisting until inputs.size).foreach { i =>
+        checkStream(buf, 0, appender.push(inputs(i)), inputs.take(i + 1))
+      }
+    }
+  }
+
+  it("should append any increasing values, including runs which become constant blocks") {
+    forAll { (longs: Seq[Long], numExisting: Byte) =>
+      val inputs = (longs.map(_ & Long.MaxValue >> 8).sorted ++ Seq.fill(8)(Long.MaxValue >> 4)).toArray
+      val split = Math.min(numExisting & 0x3f, inputs.size)
+      val buf = new ExpandableArrayBuffer()
+      val numBytes = NibblePack.packDelta(inputs.take(split), buf, 0)
+      val appender = DeltaAppender(buf, 0, numBytes, split).right.get
+      inputs.drop(split).foreach(appender.push)
+      checkStream(buf, 0, appender.position, inputs)
+    }
+  }
+
+  it("should refuse streams which do not hold the number of values given") {
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = NibblePack.packDelta(Array.tabulate(20)(i => i * 1000L), buf, 0)
+    DeltaAppender(buf, 0, numBytes - 1, 20).left.get shouldBe a[NibblePack.InputTooShort]
+    DeltaAppender(buf, 0, numBytes, 30).left.get shouldBe a[NibblePack.InputTooShort]
+    DeltaAppender(buf, 0, numBytes, -1).left.get shouldBe a[NibblePack.ImplausibleCount]
+  }
+}
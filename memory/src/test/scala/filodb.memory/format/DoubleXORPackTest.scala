@@ -0,0 +1,78 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class DoubleXORPackTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Double]): Array[Double] = {
+    val bytesWritten = DoubleXORPack.pack(inputs, buf, 0)
+    val out = new Array[Double](inputs.size)
+    DoubleXORPack.unpack(new UnsafeBuffer(buf, 0, bytesWritten), out) shouldEqual NibblePack.Ok
+    out
+  }
+
+  // Compares raw bits, so that NaNs are compared properly too
+  def bits(values: Array[Double]): Seq[Long] = values.map(java.lang.Double.doubleToRawLongBits).toSeq
+
+  it("should store the first value verbatim and repeats in one bit each") {
+    val inputs = Array.fill(17)(21.5)
+    // format code + 8 bytes for the first value + 16 bits of repeats
+    DoubleXORPack.pack(inputs, buf, 0) shouldEqual 11
+    roundTrip(inputs) shouldEqual inputs
+    roundTrip(Array(-3.25)) shouldEqual Array(-3.25)
+    roundTrip(Array.empty[Double]) shouldEqual Array.empty[Double]
+  }
+
+  it("should reuse the previous leading and trailing zero window when the XOR fits in it") {
+    val first = 0x4000000000000000L
+    val second = first ^ 0x00F0000000000000L     // 8 leading zeroes, 4 meaningful bits, 52 trailing zeroes
+    val third = second ^ 0x0060000000000000L     // 9 leading, 53 trailing: fits the window of the second XOR
+    val inputs = Array(first, second, third).map(java.lang.Double.longBitsToDouble)
+    // format code + 64 bits verbatim + 2 + 5 + 6 + 4 bits for a new window + 2 + 4 bits reusing it
+    DoubleXORPack.pack(inputs, buf, 0) shouldEqual 1 + (64 + 17 + 6 + 7) / 8
+    bits(roundTrip(inputs)) shouldEqual bits(inputs)
+  }
+
+  it("should pack and unpack runs of constants and NaNs between changing values") {
+    val inputs = Array.fill(20)(1.5) ++ Array.fill(9)(Double.NaN) ++ Array(2.5, Double.NaN, 2.5) ++
+                 Array.fill(20)(-7.25) ++ Array.tabulate(30)(i => 100.0 + i / 4.0)
+    bits(roundTrip(inputs)) shouldEqual bits(inputs)
+  }
+
+  it("should pack and unpack gauge-like values compactly") {
+    val inputs = Array(20.0, 20.5, 21.0, 21.0, 20.75, 19.5, 19.25, 20.0, 22.125, 22.0)
+    val bytesWritten = DoubleXORPack.pack(inputs, buf, 0)
+    bytesWritten should be < (inputs.size * 8 / 2)
+    roundTrip(inputs) shouldEqual inputs
+  }
+
+  it("should pack and unpack NaN, infinities and extremes") {
+    val inputs = Array(Double.NaN, 1.0, Double.PositiveInfinity, Double.NegativeInfinity, Double.NaN, Double.NaN,
+                       0.0, -0.0, Double.MaxValue, Double.MinPositiveValue, -1.0, java.lang.Double.longBitsToDouble(1L))
+    bits(roundTrip(inputs)) shouldEqual bits(inputs)
+  }
+
+  it("should return errors for truncated or wrong format input") {
+    val inputs = Array(1.5, 2.5, 3.75, 100.125)
+    val bytesWritten = DoubleXORPack.pack(inputs, buf, 0)
+    val out = new Array[Double](inputs.size)
+    DoubleXORPack.unpack(new UnsafeBuffer(buf, 0, bytesWritten - 1), out) shouldBe a[NibblePack.InputTooShort]
+    DoubleXORPack.unpack(new UnsafeBuffer(buf, 0, 5), out) shouldEqual NibblePack.InputTooShort(8, 4)
+
+    val intBytes = NibblePack32.pack(Array(1, 2, 3, 4), buf, 0)
+    DoubleXORPack.unpack(new UnsafeBuffer(buf, 0, intBytes), out) shouldEqual
+      NibblePack.UnexpectedFormat(NibbleFormat.Format_U32)
+  }
+
+  it("should pack and unpack random lists of Doubles") {
+    forAll { (doubles: Seq[Double]) =>
+      val inputs = doubles.toArray
+      bits(roundTrip(inputs)) shouldEqual bits(inputs)
+    }
+  }
+}
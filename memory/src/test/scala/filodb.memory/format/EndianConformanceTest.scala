@@ -0,0 +1,95 @@
+package filodb.memory.format
+
+import java.nio.{ByteBuffer, ByteOrder}
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+import filodb.memory.{BinaryRegionMedium, UTF8StringMedium}
+import filodb.memory.format.vectors.{BinaryHistogram, CustomBuckets, GeometricBuckets, HistogramBuckets}
+
+/**
+ * Checks that encoded buffers have the same little endian bytes whatever the host's byte order is.  Expected
+ * bytes are built independently with a java.nio.ByteBuffer in an explicit byte order, and decoders are fed
+ * those bytes rather than their own output, so a host-order read or write anywhere would be caught on a big
+ * endian host, and the big endian cases below simulate what such a read would see.
+ */
+class EndianConformanceTest extends FunSpec with Matchers {
+  def bytes(buf: ExpandableArrayBuffer, numBytes: Int): Seq[Byte] = buf.byteArray.take(numBytes).toSeq
+
+  def littleEndian(numBytes: Int): ByteBuffer = ByteBuffer.allocate(numBytes).order(ByteOrder.LITTLE_ENDIAN)
+
+  it("should pack NibblePack blocks least significant byte first") {
+    val buf = new ExpandableArrayBuffer()
+    val constant = NibblePack.pack8(Array.fill(8)(0x0102L), buf, 0)
+    bytes(buf, constant) shouldEqual Seq(0xff, NibbleFormat.ConstantBlockMarker | 2, 0x02, 0x01).map(_.toByte)
+
+    val universal = NibblePack.pack8(Array(0x12L, 0x34L, 0, 0, 0, 0, 0, 0), buf, 0)
+    bytes(buf, universal) shouldEqual Seq(0x03, NibbleFormat.nibbleHeader(2, 0), 0x12, 0x34).map(_.toByte)
+
+    val sink = NibblePack.DeltaSink(new Array[Long](8))
+    NibblePack.unpackToSink(new UnsafeBuffer(Array(0xff, 0xf2, 0x02, 0x01).map(_.toByte)), sink, 8)
+    sink.outArray.toSeq shouldEqual Seq.tabulate(8)(i => 0x0102L * (i + 1))
+  }
+
+  it("should write geometric BinaryHistograms with every field little endian") {
+    val values = Array(1L, 3L, 6L, 10L)
+    val packed = NibblePack.packDeltaToBytes(values)
+    val expected = littleEndian(2 + 1 + 2 + 2 + 8 + 8 + packed.size)
+    expected.putShort((expected.capacity - 2).toShort)
+    expected.put(BinaryHistogram.HistFormat_Geometric_Delta)
+    expected.putShort((2 + 8 + 8).toShort)
+    expected.putShort(4.toShort)
+    expected.putDouble(2.5)
+    expected.putDouble(3.0)
+    expected.put(packed)
+
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = BinaryHistogram.writeDelta(GeometricBuckets(2.5, 3.0, 4), values, buf)
+    bytes(buf, numBytes) shouldEqual expected.array.toSeq
+
+    val hist = BinaryHistogram.decodeGeometric(new UnsafeBuffer(expected.array)).right.get
+    hist.buckets shouldEqual GeometricBuckets(2.5, 3.0, 4)
+    hist.values shouldEqual values
+    BinaryHistogram.BinHistogram(new UnsafeBuffer(expected.array)).totalLength shouldEqual expected.capacity
+  }
+
+  it("should write custom bucket definitions with little endian lengths") {
+    val buf = new ExpandableArrayBuffer()
+    val end = CustomBuckets(Array(0.5, 1.0, 10.0)).serialize(buf, 0)
+    val b = ByteBuffer.wrap(buf.byteArray, 0, end).order(ByteOrder.LITTLE_ENDIAN)
+    b.getShort(0) shouldEqual (end - 2).toShort
+    b.getShort(2) shouldEqual 3.toShort
+    HistogramBuckets.custom(buf.byteArray, UnsafeUtils.arayOffset).les.toSeq shouldEqual Seq(0.5, 1.0, 10.0)
+  }
+
+  it("should write and read UTF8StringMedium length prefixes little endian") {
+    val expected = littleEndian(2 + 3).putShort(3.toShort).put("abc".getBytes).array
+    val out = new Array[Byte](5)
+    UTF8StringMedium.copyByteArrayTo("abc".getBytes, out, UnsafeUtils.arayOffset)
+    out.toSeq shouldEqual expected.toSeq
+
+    UTF8StringMedium.numBytes(expected, UnsafeUtils.arayOffset) shouldEqual 3
+    BinaryRegionMedium.numBytes(expected, UnsafeUtils.arayOffset) shouldEqual 3
+    UTF8StringMedium.toString(expected, UnsafeUtils.arayOffset) shouldEqual "abc"
+  }
+
+  it("should read the same values with the little endian UnsafeUtils getters on any host") {
+    val arr = littleEndian(16).putShort(0, 0x0102.toShort).putDouble(8, 1.25).array
+    UnsafeUtils.getShortLE(arr, UnsafeUtils.arayOffset) shouldEqual 0x0102.toShort
+    UnsafeUtils.getDoubleLE(arr, UnsafeUtils.arayOffset + 8) shouldEqual 1.25
+
+    val longBytes = littleEndian(8).putLong(0, 0x0102030405060708L).array
+    UnsafeUtils.getLongLE(longBytes, UnsafeUtils.arayOffset) shouldEqual 0x0102030405060708L
+
+    // What a host order read of the same bytes sees on a big endian host
+    val be = ByteBuffer.wrap(longBytes).order(ByteOrder.BIG_ENDIAN)
+    be.getLong(0) shouldEqual java.lang.Long.reverseBytes(0x0102030405060708L)
+
+    val out = new Array[Byte](2)
+    UnsafeUtils.setShortLE(out, UnsafeUtils.arayOffset, 0x0102.toShort)
+    out.toSeq shouldEqual Seq(0x02, 0x01).map(_.toByte)
+  }
+}
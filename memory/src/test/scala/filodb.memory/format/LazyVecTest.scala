@@ -0,0 +1,54 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.scalacheck.Gen
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class LazyVecTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def view(inputs: Array[Long]): LazyVec = {
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    LazyVec(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size).right.get
+  }
+
+  it("should return the same values from random gets as from iterating") {
+    val inputs = Array.tabulate(1000) { i => (i * 7919L) % 1000 + (if (i % 50 == 0) Long.MaxValue / 3 else 0) }
+    val vec = view(inputs)
+    vec.length shouldEqual 1000
+    vec.iterator.toArray shouldEqual inputs
+
+    forAll(Gen.listOf(Gen.choose(0, 999))) { indices =>
+      indices.map(vec.get) shouldEqual indices.map(i => Some(inputs(i)))
+    }
+    vec.iterator.toArray shouldEqual inputs
+  }
+
+  it("should view any values, including blocks of zeroes and constant blocks") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = (longs ++ Seq.fill(9)(0L) ++ Seq.fill(8)(42L)).toArray
+      val vec = view(inputs)
+      vec.iterator.toArray shouldEqual inputs
+      inputs.indices.reverse.map(vec.get) shouldEqual inputs.reverse.toSeq.map(Some(_))
+    }
+    view(Array.empty[Long]).iterator.isEmpty shouldEqual true
+  }
+
+  it("should return None for out of range indices") {
+    val vec = view(Array(1L, 2L, 3L))
+    vec.get(-1) shouldEqual None
+    vec.get(3) shouldEqual None
+    vec.get(2) shouldEqual Some(3L)
+  }
+
+  it("should refuse streams which are too short for the number of values") {
+    val inputs = Array.tabulate(20)(i => i * 1000L)
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    LazyVec(new UnsafeBuffer(buf, 0, bytesWritten - 1), inputs.size).left.get shouldBe a[NibblePack.InputTooShort]
+    LazyVec(new UnsafeBuffer(buf, 0, bytesWritten), 30) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    LazyVec(new UnsafeBuffer(buf, 0, 2), 1000).left.get shouldBe a[NibblePack.ImplausibleCount]
+  }
+}
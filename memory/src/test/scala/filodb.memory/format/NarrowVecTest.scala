@@ -0,0 +1,41 @@
+package filodb.memory.format
+
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+class NarrowVecTest extends FunSpec with Matchers {
+  import NarrowVec._
+
+  def narrowest(values: Array[Long]): NarrowVec =
+    unpackNarrowest(new UnsafeBuffer(NibblePack.packDeltaToBytes(values)), values.size).right.get
+
+  it("should unpack into the narrowest type which fits the largest value") {
+    val small = Array.tabulate(40)(i => i * 5L)
+    narrowest(small) shouldBe a[U8]
+    narrowest(small :+ 200L) shouldBe a[U8]
+    narrowest(small :+ 255L) shouldBe a[U8]
+    narrowest(small :+ 256L) shouldBe a[U16]
+    narrowest(small :+ 70000L) shouldBe a[U32]
+    narrowest(small :+ 0xffffffffL) shouldBe a[U32]
+    narrowest(small :+ 0x100000000L) shouldBe a[U64]
+    narrowest(Array.empty[Long]).size shouldEqual 0
+  }
+
+  it("should give back the same values as unpackDelta whatever the width") {
+    for { top <- Seq(200L, 60000L, 70000L, 4000000000L, Long.MaxValue) } {
+      val values = Array.tabulate(20)(i => top / 20 * i) :+ top
+      val narrow = narrowest(values)
+      narrow.size shouldEqual values.size
+      (0 until narrow.size).map(narrow(_)) shouldEqual values.toSeq
+    }
+    narrow(Array(5L, -1L, 3L)) match {
+      case U64(values) => values.toSeq shouldEqual Seq(5L, -1L, 3L)
+      case other       => fail(s"Expected U64, got $other")
+    }
+  }
+
+  it("should return errors from unpacking") {
+    unpackNarrowest(new UnsafeBuffer(new Array[Byte](0)), 10).isLeft shouldEqual true
+  }
+}
@@ -0,0 +1,153 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleAggregationsTest extends FunSpec with Matchers with PropertyChecks {
+  import NibbleAggregations._
+
+  val buf = new ExpandableArrayBuffer()
+
+  it("should aggregate delta packed values the same as unpacking first") {
+    val inputs = Array(5L, 1000, 1001, 1001, 1003, 2005, 2005, 3034, 4045, 5056, 6067)
+    val written = NibblePack.packDelta(inputs, buf, 0)
+    def slice: UnsafeBuffer = new UnsafeBuffer(buf, 0, written)
+
+    sumDelta(slice, inputs.size) shouldEqual Right(inputs.sum)
+    minDelta(slice, inputs.size) shouldEqual Right(5L)
+    maxDelta(slice, inputs.size) shouldEqual Right(6067L)
+    // only the first three values
+    sumDelta(slice, 3) shouldEqual Right(2006L)
+  }
+
+  it("should return the identity for no values and errors for truncated input") {
+    sum(new UnsafeBuffer(buf, 0, 0), 0) shouldEqual Right(0L)
+    min(new UnsafeBuffer(buf, 0, 0), 0) shouldEqual Right(Long.MaxValue)
+    max(new UnsafeBuffer(buf, 0, 0), 0) shouldEqual Right(Long.MinValue)
+
+    val written = NibblePack.packNonIncreasing(Array.fill(20)(1234L), buf, 0)
+    sum(new UnsafeBuffer(buf, 0, written - 1), 20).left.get shouldBe a[NibblePack.InputTooShort]
+    sum(new UnsafeBuffer(buf, 0, written), 30) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should cap or widen sums which overflow a Long") {
+    val big = Array.fill(20)(Long.MaxValue / 4)
+    val written = NibblePack.packNonIncreasing(big, buf, 0)
+    def slice: UnsafeBuffer = new UnsafeBuffer(buf, 0, written)
+    val expected = BigInt(Long.MaxValue / 4) * 20
+
+    sumSaturating(slice, 20) shouldEqual Right((Long.MaxValue, true))
+    sumSaturating(slice, 3) shouldEqual Right((Long.MaxValue / 4 * 3, false))
+    val (hi, lo) = sumWide(slice, 20).right.get
+    (BigInt(hi) << 64) + (BigInt(lo) & ((BigInt(1) << 64) - 1)) shouldEqual expected
+
+    // A counter which reaches near Long.MaxValue: the deltas are small, the values are not
+    val counter = Array.tabulate(10)(i => Long.MaxValue - 1000 + i * 100)
+    val deltaWritten = NibblePack.packDelta(counter, buf, 0)
+    def deltaSlice: UnsafeBuffer = new UnsafeBuffer(buf, 0, deltaWritten)
+    sumDeltaSaturating(deltaSlice, 10) shouldEqual Right((Long.MaxValue, true))
+    val (deltaHi, deltaLo) = sumDeltaWide(deltaSlice, 10).right.get
+    (BigInt(deltaHi) << 64) + (BigInt(deltaLo) & ((BigInt(1) << 64) - 1)) shouldEqual counter.map(BigInt(_)).sum
+
+    sumWide(new UnsafeBuffer(buf, 0, deltaWritten - 1), 10).left.get shouldBe a[NibblePack.InputTooShort]
+  }
+
+  it("should sum random lists of Longs exactly in 128 bits, and saturate only when a Long overflows") {
+    forAll { (longs: Seq[Long]) =>
+      val written = NibblePack.packNonIncreasing(longs.toArray, buf, 0)
+      val (hi, lo) = sumWide(new UnsafeBuffer(buf, 0, written), longs.size).right.get
+      (BigInt(hi) << 64) + (BigInt(lo) & ((BigInt(1) << 64) - 1)) shouldEqual longs.map(BigInt(_)).sum
+
+      val (total, saturated) = sumSaturating(new UnsafeBuffer(buf, 0, written), longs.size).right.get
+      val partials = longs.scanLeft(BigInt(0))(_ + _).tail
+      if (partials.forall(t => t.isValidLong)) {
+        (total, saturated) shouldEqual ((longs.sum, false))
+      }
+    }
+  }
+
+  it("should aggregate random lists of Longs the same as a naive unpack and fold") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.toArray
+      val written = NibblePack.packNonIncreasing(inputs, buf, 0)
+      def slice: UnsafeBuffer = new UnsafeBuffer(buf, 0, written)
+      val unpacked = new NibbleSinks.BufferSink(inputs.size)
+      NibblePack.unpackToSink(slice, unpacked, inputs.size)
+      val values = unpacked.values.toArray
+
+      sum(slice, inputs.size) shouldEqual Right(values.sum)
+      min(slice, inputs.size) shouldEqual Right((Long.MaxValue +: values).min)
+      max(slice, inputs.size) shouldEqual Right((Long.MinValue +: values).max)
+    }
+  }
+
+  it("should aggregate random increasing lists the same as unpackDelta then fold") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.map(_ & 0x0000ffffffffffffL).sorted.toArray
+      val written = NibblePack.packDelta(inputs, buf, 0)
+      def slice: UnsafeBuffer = new UnsafeBuffer(buf, 0, written)
+      val values = NibblePack.unpackDelta(slice, inputs.size).right.get
+
+      sumDelta(slice, inputs.size) shouldEqual Right(values.sum)
+      minDelta(slice, inputs.size) shouldEqual Right((Long.MaxValue +: values).min)
+      maxDelta(slice, inputs.size) shouldEqual Right((Long.MinValue +: values).max)
+    }
+  }
+
+  it("should fold arbitrary functions over the values, eg sum the same as unpacking first") {
+    forAll { (longs: List[Long]) =>
+      val inputs = longs.toArray
+      def slice: UnsafeBuffer = new UnsafeBuffer(buf, 0, NibblePack.packNonIncreasing(inputs, buf, 0))
+      foldLeft(slice, inputs.size, 0L)(_ + _) shouldEqual Right(inputs.sum)
+      foldLeft(slice, inputs.size, List.empty[Long])((acc, v) => v :: acc).right.get.reverse shouldEqual longs
+    }
+
+    val increasing = Array(3L, 10, 10, 24, 100, 101)
+    val slice = new UnsafeBuffer(buf, 0, NibblePack.packDelta(increasing, buf, 0))
+    foldLeftDelta(slice, increasing.size, (0L, 0)) { case ((total, evens), v) =>
+      (total + v, if (v % 2 == 0) evens + 1 else evens)
+    } shouldEqual Right((248L, 4))
+  }
+
+  it("should count distinct values exactly for a few, and estimate many within the HyperLogLog error") {
+    val inputs = Array(5L, 3, 5, 0, 3, 3, 1000, 5, 0, 7)
+    countDistinct(new UnsafeBuffer(buf, 0, NibblePack.packNonIncreasing(inputs, buf, 0)), inputs.size) shouldEqual
+      Right(5L)
+    val steps = Array(1L, 1, 2, 2, 2, 9, 9, 10)
+    countDistinctDelta(new UnsafeBuffer(buf, 0, NibblePack.packDelta(steps, buf, 0)), steps.size) shouldEqual
+      Right(4L)
+    countDistinct(new UnsafeBuffer(buf, 0, 0), 0) shouldEqual Right(0L)
+
+    // Each value twice, so that duplicates also come after the switch to the estimate
+    val numDistinct = 100000
+    val many = Array.tabulate(numDistinct * 2)(i => (i % numDistinct) * 7919L)
+    val estimate = countDistinct(new UnsafeBuffer(buf, 0, NibblePack.packNonIncreasing(many, buf, 0)), many.size)
+    estimate.right.get.toDouble shouldEqual numDistinct.toDouble +- numDistinct * 0.05
+  }
+
+  it("should compute the rate of a counter from its value and timestamp chunks, across resets") {
+    val tsBuf = new ExpandableArrayBuffer()
+    def rateOf(values: Array[Long], timestamps: Array[Long], dod: Boolean = false) = {
+      val valueBytes = NibblePackSigned.packDelta(values, buf, 0)
+      val tsBytes = if (dod) NibblePackSigned.packDeltaOfDelta(timestamps, tsBuf, 0)
+                    else NibblePack.packDeltaCounted(timestamps, tsBuf, 0)
+      rate(new UnsafeBuffer(buf, 0, valueBytes), new UnsafeBuffer(tsBuf, 0, tsBytes), values.size)
+    }
+    val timestamps = Array(100000L, 115000L, 130000L, 145000L)
+
+    // 90 over 45 seconds
+    rateOf(Array(10L, 40L, 70L, 100L), timestamps) shouldEqual Right(2.0)
+    rateOf(Array(10L, 40L, 70L, 100L), timestamps, dod = true) shouldEqual Right(2.0)
+    // Reset from 100 to 5: 90 before it, then 5 and 15 after, is 110 over 45 seconds
+    rateOf(Array(10L, 100L, 5L, 20L), timestamps) shouldEqual Right(110.0 / 45)
+    // A reset at the last sample
+    rateOf(Array(10L, 40L, 70L, 3L), timestamps) shouldEqual Right(63.0 / 45)
+
+    rateOf(Array(10L), Array(100000L)).right.get.isNaN shouldEqual true
+    rateOf(Array(10L, 20L), Array(100000L, 100000L)).right.get.isNaN shouldEqual true
+    rateOf(Array(10L, 20L), timestamps) shouldEqual Left(NibblePack.InvalidParameter("numValues", 2))
+  }
+}
@@ -0,0 +1,92 @@
+package filodb.memory.format
+
+import scala.concurrent.Await
+import scala.concurrent.ExecutionContext.Implicits.global
+import scala.concurrent.duration._
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+import filodb.memory.format.vectors.{CustomBuckets, GeometricBuckets, Histogram, LongHistogram}
+
+class NibbleAnyTest extends FunSpec with Matchers {
+  import NibbleAny._
+  import NibbleAny.Decoded._
+
+  val buf = new ExpandableArrayBuffer()
+  val counter = Array.tabulate(100)(i => 1000L + i * 15 + i % 4)
+  val signed = counter.zipWithIndex.map { case (v, i) => if (i % 3 == 1) -v else v }
+  val doubles = counter.map(_ / 1000.0)
+
+  def packed(numBytes: Int): UnsafeBuffer = new UnsafeBuffer(buf, 0, numBytes)
+
+  def longs(numBytes: Int): Array[Long] = decode(packed(numBytes)) match {
+    case Right(Longs(values)) => values
+    case other                => fail(s"Expected Longs, got $other")
+  }
+
+  def doublesOf(numBytes: Int): Array[Double] = decode(packed(numBytes)) match {
+    case Right(Doubles(values)) => values
+    case other                  => fail(s"Expected Doubles, got $other")
+  }
+
+  it("should decode the output of every Long encoder which records its count as Longs") {
+    longs(CompressedVec.encode(signed, buf, 0)) shouldEqual signed
+    longs(NibblePack.packDeltaCounted(counter, buf, 0)) shouldEqual counter
+    longs(NibblePackSigned.packDeltaOfDelta(signed, buf, 0)) shouldEqual signed
+    longs(NibbleFOR.packFOR(signed, buf, 0)) shouldEqual signed
+    longs(NibbleRuns.packDeltaRuns(counter, buf, 0)) shouldEqual counter
+    longs(Await.result(NibbleChunks.packDeltaParallel(counter, buf, 0, 30), 10.seconds)) shouldEqual counter
+    longs(NibbleBlockSize.packDelta(counter, buf, 0, 32)) shouldEqual counter
+    longs(NibbleFixedWidth.packDelta(counter, buf, 0)) shouldEqual counter
+    longs(NibbleHybridDelta.packDelta(signed, buf, 0)) shouldEqual signed
+    longs(NibbleClamped.packDelta(Array(10L, 20L, 19L, 30L), buf, 0).endPos) shouldEqual Array(10L, 20L, 19L, 30L)
+    longs(NibbleAuto.packAuto(signed, buf, 0)) shouldEqual signed
+  }
+
+  it("should decode arithmetic sequences without expanding them") {
+    val seq = NibbleArithmetic.detect(Array(5L, 8L, 11L)).get
+    decode(packed(NibbleArithmetic.pack(seq, buf, 0))) shouldEqual Right(Arithmetic(seq))
+
+    // A header of a few bytes declaring far more values than could be unpacked
+    val huge = NibbleArithmetic.ArithmeticSeq(0L, 1L, Int.MaxValue)
+    decode(packed(NibbleArithmetic.pack(huge, buf, 0))) shouldEqual Right(Arithmetic(huge))
+  }
+
+  it("should decode the output of the Double and Boolean encoders as Doubles and Bools") {
+    doublesOf(NibbleScaled.packScaled(doubles, 1000, buf, 0).right.get) shouldEqual doubles
+    val gaps = doubles.zipWithIndex.map { case (d, i) => if (i % 3 == 2) Double.NaN else d }
+    doublesOf(SparseDoublePack.pack(gaps, buf, 0)).map(java.lang.Double.doubleToLongBits) shouldEqual
+      gaps.map(java.lang.Double.doubleToLongBits)
+
+    val bools = Array.tabulate(100)(i => i % 7 < 3)
+    decode(packed(BitVec.packBools(bools, buf, 0))) match {
+      case Right(Bools(values)) => values shouldEqual bools
+      case other                => fail(s"Expected Bools, got $other")
+    }
+  }
+
+  it("should decode histograms written by packHistogram as a Hist") {
+    val values = Array(0L, 2, 5, 5, 9, 12, 20, 31)
+    for { buckets <- Seq(GeometricBuckets(1.0, 2.0, 8), GeometricBuckets(2.0, 2.0, 8, minusOne = true),
+                         CustomBuckets(Array(0.25, 0.5, 1.0, 2.5, 5.0, 10, 25, Double.PositiveInfinity))) } {
+      decode(packed(packHistogram(buckets, values, buf, 0))) shouldEqual
+        Right(Hist(LongHistogram(buckets, values)))
+    }
+    val numBytes = packHistogram(GeometricBuckets(1.0, 2.0, 8), values, buf, 0)
+    decode(packed(numBytes - 1)).left.get shouldBe a[NibblePack.InputTooShort]
+    decode(packed(packHistogram(Histogram.empty.buckets, Array.empty, buf, 0))).right.get shouldBe a[Hist]
+  }
+
+  it("should refuse streams which cannot be decoded on their own") {
+    decode(packed(NibblePackSigned.packDelta(signed, buf, 0))) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_ZigZag_Delta))
+    decode(packed(DoubleXORPack.pack(doubles, buf, 0))) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_XOR_Double))
+    decode(packed(NibblePatch.diffValues(counter, signed, buf, 0))) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Patch))
+    decode(packed(0)) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+}
@@ -0,0 +1,75 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleArithmeticTest extends FunSpec with Matchers with PropertyChecks {
+  import NibbleArithmetic.ArithmeticSeq
+
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Long]): Array[Long] = {
+    val bytesWritten = NibbleArithmetic.pack(NibbleArithmetic.detect(inputs).get, buf, 0)
+    NibbleArithmetic.unpack(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should store a long regular series in a few bytes, smaller than delta-of-delta") {
+    val timestamps = Array.tabulate(10000)(i => 1000L + i * 10)
+    NibbleArithmetic.detect(timestamps) shouldEqual Some(ArithmeticSeq(1000L, 10L, 10000))
+    val numBytes = NibbleArithmetic.pack(NibbleArithmetic.detect(timestamps).get, buf, 0)
+    numBytes shouldEqual NibbleArithmetic.HeaderBytes + 2 + 1
+    NibbleArithmetic.unpack(new UnsafeBuffer(buf, 0, numBytes)).right.get shouldEqual timestamps
+    NibbleFormat.peekCount(new UnsafeBuffer(buf, 0, numBytes)) shouldEqual Right(10000)
+
+    numBytes should be < NibblePackSigned.packDeltaOfDelta(timestamps, new ExpandableArrayBuffer(), 0)
+  }
+
+  it("should compute values on demand without expanding the sequence") {
+    val seq = NibbleArithmetic.read(new UnsafeBuffer(buf, 0,
+                NibbleArithmetic.pack(ArithmeticSeq(-5L, 3L, Int.MaxValue), buf, 0))).right.get
+    seq shouldEqual ArithmeticSeq(-5L, 3L, Int.MaxValue)
+    seq(0) shouldEqual -5L
+    seq(Int.MaxValue - 1) shouldEqual -5L + (Int.MaxValue - 1) * 3L
+  }
+
+  it("should round trip decreasing, constant, wrapping and short sequences") {
+    Seq(Array.tabulate(100)(i => 500L - i * 7),
+        Array.fill(50)(Long.MinValue),
+        Array.tabulate(20)(i => Long.MaxValue - 5 + i * (1L << 62)),
+        Array.empty[Long], Array(42L), Array(Long.MinValue, Long.MaxValue)).foreach { inputs =>
+      roundTrip(inputs) shouldEqual inputs
+    }
+
+    forAll { (base: Long, step: Long, count: Short) =>
+      val inputs = Array.tabulate(count & 0x3ff)(i => base + i * step)
+      roundTrip(inputs) shouldEqual inputs
+    }
+  }
+
+  it("should detect only sequences whose deltas are all equal") {
+    NibbleArithmetic.detect(Array(1L, 2L, 3L, 5L)) shouldEqual None
+    NibbleArithmetic.detect(Array(1L, 3L, 5L, 7L, 9L, 10L)) shouldEqual None
+    NibbleArithmetic.detect(Array(7L, 3L)) shouldEqual Some(ArithmeticSeq(7L, -4L, 2))
+    NibbleArithmetic.detect(Array.empty[Long]) shouldEqual Some(ArithmeticSeq(0L, 0L, 0))
+  }
+
+  it("should return errors for truncated or malformed streams") {
+    val numBytes = NibbleArithmetic.pack(ArithmeticSeq(1L << 40, 1000L, 100), buf, 0)
+    NibbleArithmetic.read(new UnsafeBuffer(buf, 0, numBytes - 1)).left.get shouldBe a[NibblePack.InputTooShort]
+    NibbleArithmetic.read(new UnsafeBuffer(buf, 0, 3)) shouldEqual Left(NibblePack.InputTooShort(5, 2))
+
+    buf.putByte(NibbleArithmetic.WidthsOffset, 0x29.toByte)
+    NibbleArithmetic.read(new UnsafeBuffer(buf, 0, numBytes)) shouldEqual Left(NibblePack.InvalidHeader("widths", 0x29))
+
+    val huge = NibbleArithmetic.pack(ArithmeticSeq(0L, 1L, NibbleFormat.MaxDecodeValues + 1), buf, 0)
+    NibbleArithmetic.unpack(new UnsafeBuffer(buf, 0, huge)) shouldEqual
+      Left(NibblePack.ImplausibleCount(NibbleFormat.MaxDecodeValues + 1, NibbleFormat.MaxDecodeValues))
+
+    val deltaBytes = NibblePack.packDeltaCounted(Array(1L, 2L), buf, 0)
+    NibbleArithmetic.read(new UnsafeBuffer(buf, 0, deltaBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Delta_Counted))
+  }
+}
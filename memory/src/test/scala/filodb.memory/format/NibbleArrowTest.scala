@@ -0,0 +1,71 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleArrowTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def unpackToArrow(inputs: Array[Long]): (UnsafeBuffer, UnsafeBuffer) = {
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+    val values = new UnsafeBuffer(new Array[Byte](NibbleArrow.valueBufferBytes(inputs.size)))
+    val validity = new UnsafeBuffer(new Array[Byte](NibbleArrow.validityBufferBytes(inputs.size)))
+    NibbleArrow.unpackDeltaToArrow(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size, values, validity) shouldEqual
+      Right(inputs.size)
+    (values, validity)
+  }
+
+  it("should unpack into little-endian Arrow data buffers with every value valid") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val (values, validity) = unpackToArrow(inputs)
+    inputs.indices.map(i => values.getLong(i * 8, LITTLE_ENDIAN)) shouldEqual inputs.toSeq
+    validity.getByte(0) shouldEqual 0xff.toByte
+    validity.getByte(1) shouldEqual 0x0f.toByte
+    validity.getByte(2) shouldEqual 0
+
+    values.capacity shouldEqual 128
+    validity.capacity shouldEqual 64
+    NibbleArrow.valueBufferBytes(0) shouldEqual 0
+  }
+
+  it("should unpack random increasing values like unpackDelta") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.map(_ & 0xffffffffL).sorted.toArray
+      val (values, validity) = unpackToArrow(inputs)
+      inputs.indices.map(i => values.getLong(i * 8, LITTLE_ENDIAN)) shouldEqual inputs.toSeq
+      inputs.indices.forall(i => (validity.getByte(i / 8) & (1 << (i % 8))) != 0) shouldEqual true
+    }
+  }
+
+  it("should return OutputTooSmall for buffers which cannot hold every value") {
+    val bytesWritten = NibblePack.packDelta(Array.tabulate(20)(_ * 10L), buf, 0)
+    val validity = new UnsafeBuffer(new Array[Byte](64))
+    NibbleArrow.unpackDeltaToArrow(new UnsafeBuffer(buf, 0, bytesWritten), 20,
+                                   new UnsafeBuffer(new Array[Byte](152)), validity) shouldEqual
+      Left(NibblePack.OutputTooSmall(160, 152))
+    val values = new UnsafeBuffer(new Array[Byte](160))
+    NibbleArrow.unpackDeltaToArrow(new UnsafeBuffer(buf, 0, bytesWritten), 20,
+                                   values, new UnsafeBuffer(new Array[Byte](2))) shouldEqual
+      Left(NibblePack.OutputTooSmall(3, 2))
+
+    // The bytes needed for more values than fit in an Int of bytes
+    NibbleArrow.unpackDeltaToArrow(new UnsafeBuffer(buf, 0, bytesWritten), Int.MaxValue, values, validity) shouldEqual
+      Left(NibblePack.OutputTooSmall(Int.MaxValue.toLong * 8, 160))
+  }
+
+  it("should return InputTooShort rather than zeros for input which ends early") {
+    val bytesWritten = NibblePack.packDelta(Array.tabulate(20)(_ * 10L + 1), buf, 0)
+    val firstBlockBytes = NibblePack.blockSize(buf, 0)
+    val values = new UnsafeBuffer(new Array[Byte](NibbleArrow.valueBufferBytes(20)))
+    val validity = new UnsafeBuffer(new Array[Byte](NibbleArrow.validityBufferBytes(20)))
+    NibbleArrow.unpackDeltaToArrow(new UnsafeBuffer(buf, 0, firstBlockBytes), 20, values, validity) shouldEqual
+      Left(NibblePack.InputTooShort(1, 0))
+    NibbleArrow.unpackDeltaToArrow(new UnsafeBuffer(buf, 0, bytesWritten - 1), 20, values, validity).left.get shouldBe
+      a[NibblePack.InputTooShort]
+  }
+}
@@ -0,0 +1,100 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleAutoTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Long]): Array[Long] = {
+    val bytesWritten = NibbleAuto.packAuto(inputs, buf, 0)
+    NibbleAuto.unpackAuto(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should pick delta encoding for increasing counters") {
+    val counter = Array.tabulate(5000)(i => 1000000L + i * 15 + i % 4)
+    NibbleAuto.chooseFormat(counter) shouldEqual NibbleFormat.Format_Delta_Counted
+    roundTrip(counter) shouldEqual counter
+    NibbleFormat.formatOf(buf) shouldEqual NibbleFormat.Format_Delta_Counted
+    NibbleAuto.packAuto(counter, buf, 0) shouldEqual NibblePack.packDeltaCounted(counter, buf, 0)
+  }
+
+  it("should pick frame-of-reference for values which cluster within blocks") {
+    // Sorted, so delta encoding is possible, but every block jumps far from the last
+    val clustered = Array.tabulate(5000)(i => (i / 8) * 1000000007L + (i % 8) * 3)
+    NibbleAuto.chooseFormat(clustered) shouldEqual NibbleFormat.Format_FOR
+    roundTrip(clustered) shouldEqual clustered
+    NibbleFormat.formatOf(buf) shouldEqual NibbleFormat.Format_FOR
+  }
+
+  it("should pick frame-of-reference for values which go down, so they round trip exactly") {
+    val gauge = Array(50L, 40L, -3L, 60L, 59L)
+    NibbleAuto.chooseFormat(gauge) shouldEqual NibbleFormat.Format_FOR
+    roundTrip(gauge) shouldEqual gauge
+
+    forAll { (longs: Seq[Long]) =>
+      roundTrip(longs.toArray) shouldEqual longs.toArray
+    }
+    roundTrip(Array.empty[Long]) shouldEqual Array.empty[Long]
+  }
+
+  it("should pick the arithmetic sequence for perfectly regular series, rising or falling") {
+    Seq(Array.tabulate(5000)(i => 1000L + i * 10), Array.tabulate(100)(i => 50L - i * 3)).foreach { regular =>
+      NibbleAuto.chooseFormat(regular) shouldEqual NibbleFormat.Format_Arithmetic
+      roundTrip(regular) shouldEqual regular
+      NibbleFormat.formatOf(buf) shouldEqual NibbleFormat.Format_Arithmetic
+    }
+    // The empty input is smaller as just a count than as a sequence, which also has its widths byte
+    NibbleAuto.chooseFormat(Array.empty[Long]) shouldEqual NibbleFormat.Format_Delta_Counted
+  }
+
+  it("should only unpack the formats it packs") {
+    val bytesWritten = NibblePackSigned.packDelta(Array(1L, 2L), buf, 0)
+    NibbleAuto.unpackAuto(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_ZigZag_Delta))
+    NibbleAuto.unpackAuto(new UnsafeBuffer(buf, 0, 0)) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  // Packs into a buffer of its own, so that several streams can be compared
+  def packed(pack: ExpandableArrayBuffer => Int): UnsafeBuffer = {
+    val out = new ExpandableArrayBuffer()
+    new UnsafeBuffer(out, 0, pack(out))
+  }
+
+  it("should compare streams of the same format by their bytes, leaving out checksums") {
+    val counter = Array.tabulate(100)(i => 1000L + i * 15)
+    val a = packed(NibblePack.packDeltaCounted(counter, _, 0))
+    val withChecksum = packed { out =>
+      NibbleFormat.appendChecksum(out, 0, NibblePack.packDeltaCounted(counter, out, 0))
+    }
+    NibbleAuto.encodedEquals(a, packed(NibbleAuto.packAuto(counter, _, 0))) shouldEqual Right(true)
+    NibbleAuto.encodedEquals(a, withChecksum) shouldEqual Right(true)
+    NibbleAuto.encodedEquals(withChecksum, a) shouldEqual Right(true)
+
+    val changed = counter.updated(50, 1751L)
+    NibbleAuto.encodedEquals(a, packed(NibblePack.packDeltaCounted(changed, _, 0))) shouldEqual Right(false)
+    NibbleAuto.encodedEquals(a, packed(NibblePack.packDeltaCounted(counter.take(99), _, 0))) shouldEqual Right(false)
+  }
+
+  it("should compare streams of different formats by their values") {
+    forAll { (longs: Seq[Long]) =>
+      val increasing = longs.map(_ & Long.MaxValue >> 1).sorted.toArray
+      val delta = packed(NibblePack.packDeltaCounted(increasing, _, 0))
+      val frameOfReference = packed(NibbleFOR.packFOR(increasing, _, 0))
+      NibbleAuto.encodedEquals(delta, frameOfReference) shouldEqual Right(true)
+      NibbleAuto.encodedEquals(delta, packed(NibbleFOR.packFOR(increasing :+ Long.MaxValue, _, 0))) shouldEqual
+        Right(false)
+    }
+  }
+
+  it("should return the error for streams it cannot unpack") {
+    val delta = packed(NibblePack.packDeltaCounted(Array(1L, 2L), _, 0))
+    val signed = packed(NibblePackSigned.packDelta(Array(1L, 2L), _, 0))
+    NibbleAuto.encodedEquals(delta, signed) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_ZigZag_Delta))
+    NibbleAuto.encodedEquals(delta, new UnsafeBuffer(buf, 0, 0)) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+}
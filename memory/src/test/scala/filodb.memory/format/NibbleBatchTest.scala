@@ -0,0 +1,67 @@
+package filodb.memory.format
+
+import scala.concurrent.Await
+import scala.concurrent.ExecutionContext.Implicits.global
+import scala.concurrent.duration._
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleBatchTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  it("should unpack any one vector of a batch using its directory") {
+    val vectors = Seq(Array(1L, 2L, 3L), Array.empty[Long], Array.tabulate(20)(i => i * 1000L), Array(-1L))
+    val bytesWritten = NibbleBatch.pack(vectors, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, bytesWritten)
+    NibbleBatch.numVectors(slice) shouldEqual Right(4)
+    // Out of order, and the buffer is not mutated
+    Seq(2, 0, 3, 1).foreach { n => NibbleBatch.unpackNth(slice, n).right.get shouldEqual vectors(n) }
+    slice.capacity shouldEqual bytesWritten
+
+    NibbleBatch.unpackNth(slice, 4) shouldEqual Left(NibblePack.IndexOutOfRange(4, 5, 4))
+    NibbleBatch.unpackNth(slice, -1) shouldEqual Left(NibblePack.IndexOutOfRange(-1, 0, 4))
+
+    NibbleBatch.numVectors(new UnsafeBuffer(buf, 0, NibbleBatch.pack(Nil, buf, 0))) shouldEqual Right(0)
+  }
+
+  it("should return errors for truncated or corrupt batches") {
+    val vectors = Seq(Array(10L, 20L), Array.tabulate(16)(i => i * 1000L))
+    val bytesWritten = NibbleBatch.pack(vectors, buf, 0)
+    NibbleBatch.numVectors(new UnsafeBuffer(buf, 0, 12)) shouldEqual Left(NibblePack.InputTooShort(21, 12))
+    NibbleBatch.unpackNth(new UnsafeBuffer(buf, 0, bytesWritten - 1), 1).left.get shouldBe a[NibblePack.InputTooShort]
+    // The first vector is still fine
+    NibbleBatch.unpackNth(new UnsafeBuffer(buf, 0, bytesWritten - 1), 0).right.get shouldEqual vectors(0)
+
+    buf.putInt(13, 3)
+    NibbleBatch.unpackNth(new UnsafeBuffer(buf, 0, bytesWritten), 1) shouldEqual
+      Left(NibblePack.InvalidHeader("offset", 3))
+
+    NibblePack.packDeltaCounted(Array(1L), buf, 0)
+    NibbleBatch.numVectors(new UnsafeBuffer(buf, 0, 10)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Delta_Counted))
+  }
+
+  it("should pack and unpack random batches of Longs") {
+    forAll { (vectors: Seq[Seq[Long]]) =>
+      val arrays = vectors.map(_.toArray)
+      val slice = new UnsafeBuffer(buf, 0, NibbleBatch.pack(arrays, buf, 0))
+      arrays.indices.foreach { n => NibbleBatch.unpackNth(slice, n).right.get shouldEqual arrays(n) }
+    }
+  }
+
+  it("should unpack many separately packed vectors in parallel, in order") {
+    val vectors = (0 until 50).map { n => Array.tabulate(n * 7)(i => i * (n + 1) * 10L) }
+    val buffers = vectors.map { v => new UnsafeBuffer(NibblePack.packDeltaToBytes(v)) }
+    val result = Await.result(NibbleBatch.unpackDeltaMany(buffers, vectors.map(_.size)), 10.seconds)
+    result.right.get.zip(vectors).foreach { case (out, expected) => out shouldEqual expected }
+    buffers(10).capacity shouldEqual NibblePack.packDeltaToBytes(vectors(10)).size     // not mutated
+
+    val truncated = buffers.updated(3, new UnsafeBuffer(buffers(3), 0, buffers(3).capacity - 1))
+    Await.result(NibbleBatch.unpackDeltaMany(truncated, vectors.map(_.size)), 10.seconds).left.get shouldBe
+      a[NibblePack.InputTooShort]
+  }
+}
@@ -0,0 +1,98 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleBlockSizeTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+  val blockSizes = Seq(8, 16, 32, 64)
+
+  def roundTrip(inputs: Array[Long], blockSize: Int): Array[Long] = {
+    val bytesWritten = NibbleBlockSize.packDelta(inputs, buf, 0, blockSize)
+    NibbleBlockSize.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should pack and unpack increasing values with every block size") {
+    val inputs = Array.tabulate(300)(i => 100000L + i * 1000 + (i * 37) % 100 + (if (i == 150) 1L << 40 else 0L))
+    blockSizes.foreach { blockSize =>
+      roundTrip(inputs, blockSize) shouldEqual inputs
+      Seq(Array.empty[Long], Array(5L), Array.fill(blockSize + 1)(42L), Array.fill(blockSize * 2)(0L)).foreach {
+        small => roundTrip(small, blockSize) shouldEqual small
+      }
+    }
+  }
+
+  it("should unpack increasing values the same as packDelta does") {
+    forAll { (longs: Seq[Long], sizeIndex: Byte) =>
+      val inputs = longs.map(_ & 0xffffffffL).sorted.toArray
+      val blockSize = blockSizes((sizeIndex & 0xff) % blockSizes.size)
+      val deltaBytes = NibblePack.packDelta(inputs, buf, 0)
+      val expected = NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, deltaBytes), inputs.size).right.get
+      roundTrip(inputs, blockSize) shouldEqual expected
+    }
+  }
+
+  it("should write the blocks of packDelta with a block size of 8, after a header with the size") {
+    // Deltas which are never all equal within a block, so that packDelta writes no constant blocks
+    val inputs = Array.tabulate(50)(i => i.toLong * i * 10 + (if (i % 5 == 0) 0L else 1L))
+    val numBytes = NibbleBlockSize.packDelta(inputs, buf, 0)
+    val deltaBuf = new ExpandableArrayBuffer()
+    val deltaBytes = NibblePack.packDelta(inputs, deltaBuf, 0)
+
+    numBytes shouldEqual NibbleBlockSize.HeaderBytes + deltaBytes
+    buf.getByte(0) shouldEqual NibbleFormat.Format_Delta_Blocks
+    buf.getByte(NibbleBlockSize.BlockSizeOffset) shouldEqual 8.toByte
+    (0 until deltaBytes).foreach { i => buf.getByte(NibbleBlockSize.HeaderBytes + i) shouldEqual deltaBuf.getByte(i) }
+    NibbleFormat.peekCount(new UnsafeBuffer(buf, 0, numBytes)) shouldEqual Right(inputs.size)
+  }
+
+  it("should spend fewer bytes on headers with larger blocks of evenly sized deltas") {
+    val inputs = Array.tabulate(1024)(i => i * 1000L + (i * 7919) % 1000)
+    val sizes = blockSizes.map { blockSize => NibbleBlockSize.packDelta(inputs, buf, 0, blockSize) }
+    sizes shouldEqual sizes.sorted.reverse
+    sizes.last should be < sizes.head
+  }
+
+  it("should refuse block sizes which are not powers of two from 8 to 64") {
+    Seq(0, 4, 12, 24, 128).foreach { blockSize =>
+      intercept[IllegalArgumentException] { NibbleBlockSize.packDelta(Array(1L), buf, 0, blockSize) }
+    }
+  }
+
+  it("should return errors for truncated or malformed streams") {
+    val inputs = Array.tabulate(40)(i => i * 100L + i % 3)
+    val numBytes = NibbleBlockSize.packDelta(inputs, buf, 0, 16)
+    NibbleBlockSize.unpackDelta(new UnsafeBuffer(buf, 0, numBytes - 1)).left.get shouldBe a[NibblePack.InputTooShort]
+    NibbleBlockSize.unpackDelta(new UnsafeBuffer(buf, 0, 3)) shouldEqual Left(NibblePack.InputTooShort(5, 2))
+
+    buf.putByte(NibbleBlockSize.BlockSizeOffset, 12.toByte)
+    NibbleBlockSize.unpackDelta(new UnsafeBuffer(buf, 0, numBytes)) shouldEqual
+      Left(NibblePack.InvalidHeader("blockSize", 12))
+
+    // 16 nibbles plus 15 trailing nibbles cannot fit in a Long
+    buf.putByte(NibbleBlockSize.BlockSizeOffset, 8.toByte)
+    buf.putByte(NibbleBlockSize.HeaderBytes, 0x01.toByte)
+    buf.putByte(NibbleBlockSize.HeaderBytes + 1, 0xff.toByte)
+    NibbleBlockSize.unpackDelta(new UnsafeBuffer(buf, 0, numBytes)) shouldEqual
+      Left(NibblePack.InvalidNibbleWidth(31))
+
+    val countedBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+    NibbleBlockSize.unpackDelta(new UnsafeBuffer(buf, 0, countedBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Delta_Counted))
+  }
+
+  it("should return AccumulatorOverflow for deltas which no increasing input can produce") {
+    // A header for the count, then blocks of 8 raw values written with pack8, which match packDelta's blocks
+    def crafted(count: Int, deltas: Array[Long]): UnsafeBuffer = {
+      NibbleBlockSize.packDelta(Array.fill(count)(0L), buf, 0)
+      new UnsafeBuffer(buf, 0, NibblePack.pack8(deltas, buf, NibbleBlockSize.HeaderBytes))
+    }
+    NibbleBlockSize.unpackDelta(crafted(1, Array(-1L, 0, 0, 0, 0, 0, 0, 0))) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(0))
+    NibbleBlockSize.unpackDelta(crafted(3, Array(5L, Long.MaxValue, 1, 0, 0, 0, 0, 0))) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(1))
+  }
+}
@@ -0,0 +1,147 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleBlocksTest extends FunSpec with Matchers with PropertyChecks {
+  import NibbleBlocks._
+
+  val buf = new ExpandableArrayBuffer()
+
+  it("should walk the headers of every kind of block") {
+    val inputs = Array.fill(8)(-1L) ++ Array.tabulate(8)(_.toLong) ++ Array.fill(8)(0L) ++
+                 Array.fill(8)(7L) ++ Array(42L, 42L, 42L)
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, bytesWritten)
+    val infos = blocks(slice).map(_.right.get).toList
+
+    infos shouldEqual List(BlockInfo(0, 16, 0, 0xff, 8, 10, true),
+                           BlockInfo(10, 1, 0, 0xfe, 7, 6, false),
+                           BlockInfo(16, 0, 0, 0, 0, 1, false),
+                           BlockInfo(17, 2, 0, 0xff, 8, 3, true),
+                           BlockInfo(20, 2, 0, 0x07, 3, 5, false))
+    infos.map(_.numBytes).sum shouldEqual bytesWritten
+    infos.foreach { info => info.numBytes shouldEqual NibblePack.blockSize(slice, info.byteOffset) }
+    slice.capacity shouldEqual bytesWritten     // not mutated
+  }
+
+  it("should report trailing zero nibbles") {
+    val bytesWritten = NibblePack.packNonIncreasing(Array(0x1200L, 0x3400L, 0x5600L), buf, 0)
+    blocks(new UnsafeBuffer(buf, 0, bytesWritten)).toList shouldEqual
+      List(Right(BlockInfo(0, 2, 2, 0x07, 3, 5, false)))
+  }
+
+  it("should stop at the first block which cannot be parsed") {
+    val inputs = Array.tabulate(16)(i => i * 1000L)
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    val results = blocks(new UnsafeBuffer(buf, 0, bytesWritten - 1)).toList
+    results.size shouldEqual 2
+    results.head shouldBe a[Right[_, _]]
+    results.last.left.get shouldBe a[NibblePack.InputTooShort]
+
+    // 16 nibbles plus 1 trailing nibble
+    buf.putByte(0, 0x01)
+    buf.putByte(1, 0xf1.toByte)
+    parse(new UnsafeBuffer(buf, 0, 10), 0) shouldEqual Left(NibblePack.InvalidNibbleWidth(17))
+    blocks(new UnsafeBuffer(buf, 0, 0)).isEmpty shouldEqual true
+  }
+
+  it("should dump every kind of block with its values") {
+    val inputs = Array(0x1200L, 0x3400L, 0x5600L) ++ Array.fill(13)(0L) ++ Array.fill(8)(7L) ++ Array(42L, 42L, 42L)
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    dump(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size) shouldEqual
+      """27 values in 14 bytes
+        |offset  bytes  kind    bitmask  width  trailing  values
+        |     0      5  packed  0x07         2         2  4608 13312 22016 0 0 0 0 0
+        |     5      1  zeroes  0x00         0         0  0 0 0 0 0 0 0 0
+        |     6      3  const   0xff         2         0  7 7 7 7 7 7 7 7
+        |     9      5  packed  0x07         2         0  42 42 42
+        |""".stripMargin
+
+    dump(new UnsafeBuffer(buf, 0, bytesWritten - 1), inputs.size).split("\n").last shouldEqual
+      "error: InputTooShort(5,4)"
+  }
+
+  it("should report compression stats for a packed vector") {
+    val inputs = Array.tabulate(20)(i => 1000L + i * 256)
+    val (endPos, packStats) = packDeltaStats(inputs, buf, 5)
+    // 1000 takes 3 nibbles, the deltas of 256 a constant block of 2 bytes (4 nibbles) and then 1 nibble and
+    // 2 trailing zero nibbles
+    packStats shouldEqual PackStats(160, endPos - 5, 3, (3 + 4 + 1) / 3.0)
+    packStats.outputBytes shouldEqual 14 + 4 + 4
+    packStats.compressionRatio should be > 4.0
+
+    stats(new UnsafeBuffer(buf, 0, 0), 0) shouldEqual PackStats(0, 0, 0, 0.0)
+  }
+
+  it("should flag random data, but not increasing data, as incompressible") {
+    val rand = new scala.util.Random(11)
+    val random = Array.fill(1000)(rand.nextLong)
+    val randomBytes = NibblePack.packNonIncreasing(random, buf, 0)
+    val randomStats = stats(new UnsafeBuffer(buf, 0, randomBytes), random.size)
+    randomStats.incompressible shouldEqual true
+    randomStats.compressionRatio should be < 1.0
+
+    val increasing = Array.tabulate(1000)(i => 1546300800000L + i * 10000L + rand.nextInt(50))
+    packDeltaStats(increasing, buf, 0)._2.incompressible shouldEqual false
+    stats(new UnsafeBuffer(buf, 0, 0), 0).incompressible shouldEqual false
+  }
+
+  it("should report the packed size of values against their entropy") {
+    val rand = new scala.util.Random(5)
+    Seq(Array.fill(1000)(rand.nextInt(100) * 1000L), Array.fill(1000)(rand.nextLong), Array.fill(100)(42L),
+        Array.tabulate(300)(i => 1L << (i % 64))).foreach { values =>
+      val report = compressionReport(values)
+      report.actualBytes shouldEqual NibbleAuto.packAuto(values, buf, 0)
+      report.rawBytes shouldEqual values.size * 8
+      report.entropyBytes should be <= report.actualBytes.toDouble
+      report.bitWidths.sum shouldEqual values.size
+    }
+
+    val constant = compressionReport(Array.fill(100)(42L))
+    constant.entropyBytes shouldEqual 0.0
+    constant.bitWidths(6) shouldEqual 100
+    // 256 values spread evenly over 16 distinct values take 4 bits each
+    compressionReport(Array.tabulate(256)(i => (i % 16).toLong)).entropyBytes shouldEqual 128.0 +- 0.001
+    compressionReport(Array.empty[Long]).entropyBytes shouldEqual 0.0
+  }
+
+  it("should work out exactly the size packNonIncreasing and packDelta will write") {
+    val inputs = Seq(Array.empty[Long], Array(0L), Array.fill(8)(1000L), Array.tabulate(20)(i => 1000L + i * 256),
+                     Array(Long.MinValue, -1L, 0L, 5L, 5L, 3L, Long.MaxValue, 0x1200L, 0x3400L))
+    inputs.foreach { in =>
+      packedSize(in) shouldEqual NibblePack.packNonIncreasing(in, buf, 0)
+      packedSizeDelta(in) shouldEqual NibblePack.packDelta(in, buf, 0)
+    }
+
+    forAll { (longs: Seq[Long]) =>
+      val in = longs.toArray
+      packedSize(in) shouldEqual NibblePack.packNonIncreasing(in, buf, 0)
+      packedSizeDelta(in) shouldEqual NibblePack.packDelta(in, buf, 0)
+    }
+  }
+
+  it("should validate counted streams without unpacking them, reporting truncation and count mismatches") {
+    val inputs = Array.tabulate(30)(i => 5000L + i * 31 + i % 7) ++ Array.fill(16)(6000L)
+    val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, bytesWritten)
+    validate(slice, inputs.size) shouldEqual NibblePack.Ok
+    slice.capacity shouldEqual bytesWritten     // not mutated
+    validate(slice, inputs.size - 1) shouldEqual NibblePack.InvalidHeader("numValues", inputs.size)
+
+    for { n <- 0 until bytesWritten } {
+      val truncated = new UnsafeBuffer(buf, 0, n)
+      validate(truncated, inputs.size) should not equal (NibblePack.Ok)
+      NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, n)).isLeft shouldEqual true
+    }
+
+    val empty = NibblePack.packDeltaCounted(Array.empty[Long], buf, 0)
+    validate(new UnsafeBuffer(buf, 0, empty), 0) shouldEqual NibblePack.Ok
+    val other = NibblePackSigned.packDelta(inputs, buf, 0)
+    validate(new UnsafeBuffer(buf, 0, other), inputs.size) shouldEqual
+      NibblePack.UnexpectedFormat(NibbleFormat.Format_ZigZag_Delta)
+  }
+}
@@ -0,0 +1,43 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+
+import org.scalatest._
+
+class NibbleBufferPoolTest extends FunSpec with Matchers {
+  val inputs = Array.tabulate(100)(i => 1000L + i * 17)
+
+  it("should pack into pooled buffers which unpack the same as any other") {
+    val pool = new NibbleBufferPool()
+    val packed = pool.packDelta(inputs)
+    NibblePack.unpackDelta(packed.slice, inputs.size).right.get shouldEqual inputs
+    packed.numBytes shouldEqual NibblePack.packDelta(inputs, new ExpandableArrayBuffer(), 0)
+    packed.close()
+
+    val plain = pool.packNonIncreasing(inputs)
+    NibbleSelect.unpackRange(plain.slice, inputs.size, 0, inputs.size).right.get shouldEqual inputs
+    plain.close()
+  }
+
+  it("should hand out closed buffers again, cleared, only once each") {
+    val pool = new NibbleBufferPool()
+    val first = pool.packDelta(inputs)
+    val buffer = first.buffer
+    pool.poolSize shouldEqual 0
+    first.close()
+    first.close()
+    pool.poolSize shouldEqual 1
+
+    val checkedOut = pool.checkout()
+    checkedOut should be theSameInstanceAs buffer
+    (0 until first.numBytes + 8).foreach { i => checkedOut.getByte(i) shouldEqual 0.toByte }
+    pool.checkout() should not be theSameInstanceAs (buffer)
+  }
+
+  it("should keep at most maxPooled buffers") {
+    val pool = new NibbleBufferPool(maxPooled = 2)
+    val packed = (0 until 5).map(_ => pool.packDelta(inputs))
+    packed.foreach(_.close())
+    pool.poolSize shouldEqual 2
+  }
+}
@@ -0,0 +1,68 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import scala.concurrent.Await
+import scala.concurrent.ExecutionContext.Implicits.global
+import scala.concurrent.duration._
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleChunksTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def pack(inputs: Array[Long], chunkSize: Int): UnsafeBuffer = {
+    val bytesWritten = Await.result(NibbleChunks.packDeltaParallel(inputs, buf, 0, chunkSize), 10.seconds)
+    new UnsafeBuffer(buf, 0, bytesWritten)
+  }
+
+  it("should pack in parallel and unpack both one chunk at a time and in parallel") {
+    val inputs = Array.tabulate(1000)(i => 5000L + i * 37 + (i % 7))
+    Seq(1, 7, 8, 100, 999, 1000, 5000).foreach { chunkSize =>
+      val packed = pack(inputs, chunkSize)
+      NibbleChunks.unpackDeltaChunks(packed).right.get shouldEqual inputs
+      Await.result(NibbleChunks.unpackDeltaChunksParallel(packed), 10.seconds).right.get shouldEqual inputs
+      packed.getInt(5, LITTLE_ENDIAN) shouldEqual (inputs.size + chunkSize - 1) / chunkSize
+    }
+
+    NibbleChunks.unpackDeltaChunks(pack(Array.empty[Long], 10)).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should store an absolute base in each chunk, so even a drop at a boundary is kept") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.map(_ & Long.MaxValue >> 8).sorted.toArray
+      NibbleChunks.unpackDeltaChunks(pack(inputs, 16)).right.get shouldEqual inputs
+    }
+
+    // 40 is lower than 50, but starts a new chunk
+    val drop = Array(10L, 20L, 50L, 40L, 45L)
+    NibbleChunks.unpackDeltaChunks(pack(drop, 3)).right.get shouldEqual drop
+  }
+
+  it("should return errors for truncated or corrupt directories") {
+    val inputs = Array.tabulate(40)(i => i * 1000L)
+    val packed = pack(inputs, 16)
+    NibbleChunks.unpackDeltaChunks(new UnsafeBuffer(buf, 0, 12)) shouldEqual Left(NibblePack.InputTooShort(33, 12))
+    NibbleChunks.unpackDeltaChunks(new UnsafeBuffer(buf, 0, packed.capacity - 1)).left.get shouldBe
+      a[NibblePack.InputTooShort]
+
+    // The second chunk claims to start inside the directory, where the first could not have written
+    val secondOffset = packed.getInt(17, LITTLE_ENDIAN)
+    packed.putInt(17, 20, LITTLE_ENDIAN)
+    val firstOffset = NibbleChunks.HeaderBytes + 3 * NibbleChunks.DirectoryEntryBytes
+    NibbleChunks.unpackDeltaChunks(packed) shouldEqual Left(NibblePack.InvalidHeader("offset", firstOffset))
+    packed.putInt(17, secondOffset, LITTLE_ENDIAN)
+
+    // More values in a chunk than in the whole stream, and counts which do not add up
+    packed.putInt(13, 1000, LITTLE_ENDIAN)
+    NibbleChunks.unpackDeltaChunks(packed) shouldEqual Left(NibblePack.InvalidHeader("numValues", 1000))
+    packed.putInt(13, 15, LITTLE_ENDIAN)
+    NibbleChunks.unpackDeltaChunks(packed) shouldEqual Left(NibblePack.InvalidHeader("numValues", 40))
+    Await.result(NibbleChunks.unpackDeltaChunksParallel(packed), 10.seconds) shouldEqual
+      Left(NibblePack.InvalidHeader("numValues", 40))
+  }
+}
@@ -0,0 +1,57 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleClampedTest extends FunSpec with Matchers with PropertyChecks {
+  import NibbleClamped.ClampReport
+
+  val buf = new ExpandableArrayBuffer()
+
+  def packed(inputs: Array[Long]): UnsafeBuffer =
+    new UnsafeBuffer(buf, 0, NibbleClamped.packDelta(inputs, buf, 0).endPos)
+
+  it("should clamp a one unit glitch backwards and report it") {
+    val counter = Array.tabulate(20)(i => 1000L + i * 10)
+    counter(7) = counter(6) - 1
+    val report = NibbleClamped.packDelta(counter, buf, 0)
+    report.numClamped shouldEqual 1
+
+    val clamped = NibbleClamped.unpackClamped(new UnsafeBuffer(buf, 0, report.endPos)).right.get
+    clamped shouldEqual counter.updated(7, counter(6))
+    clamped.zip(clamped.tail).forall { case (a, b) => a <= b } shouldEqual true
+    NibbleClamped.unpackOriginal(new UnsafeBuffer(buf, 0, report.endPos)).right.get shouldEqual counter
+
+    // Unlike packDelta, the values after the glitch are not shifted
+    NibblePack.unpackDeltaFromBytes(NibblePack.packDeltaToBytes(counter), 20).right.get(19) shouldEqual
+      counter(19) + 1
+    clamped(19) shouldEqual counter(19)
+  }
+
+  it("should pack increasing values the same as packDelta after the header, with nothing clamped") {
+    val increasing = Array.tabulate(30)(i => i * i.toLong)
+    NibbleClamped.packDelta(increasing, buf, 0) shouldEqual
+      ClampReport(NibbleClamped.HeaderBytes + NibblePack.packDeltaToBytes(increasing).size, 0)
+    NibbleClamped.unpackOriginal(packed(increasing)).right.get shouldEqual increasing
+    NibbleClamped.unpackClamped(packed(Array.empty[Long])).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should round trip random lists of non-negative Longs") {
+    forAll { (longs: List[Long]) =>
+      val inputs = longs.map(_ & Long.MaxValue).toArray
+      NibbleClamped.unpackOriginal(packed(inputs)).right.get shouldEqual inputs
+      val clamped = NibbleClamped.unpackClamped(packed(inputs)).right.get
+      clamped shouldEqual inputs.scanLeft(0L)(Math.max).tail
+      NibbleClamped.packDelta(inputs, buf, 0).numClamped shouldEqual inputs.indices.count(i => inputs(i) < clamped(i))
+    }
+  }
+
+  it("should return errors for a bad clamped count") {
+    val bytes = packed(Array(5L, 4L, 6L))
+    bytes.putInt(6, 4, java.nio.ByteOrder.LITTLE_ENDIAN)
+    NibbleClamped.unpackOriginal(bytes) shouldEqual Left(NibblePack.InvalidHeader("numClamped", 4))
+  }
+}
@@ -0,0 +1,59 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleDownsampleTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def packed(inputs: Array[Long]): UnsafeBuffer = new UnsafeBuffer(buf, 0, NibblePack.packNonIncreasing(inputs, buf, 0))
+
+  it("should downsample a long series to about target points, keeping spikes") {
+    val rand = new scala.util.Random(3)
+    val gauge = Array.tabulate(100000)(i => 1000L + rand.nextInt(50))
+    gauge(54321) = 1000000L
+    val points = NibbleDownsample.unpackDownsampled(packed(gauge), gauge.size, 1000).right.get
+    points.size should be > 990
+    points.size should be <= 1000
+    points.max shouldEqual 1000000L
+    points.min shouldEqual gauge.min
+  }
+
+  it("should keep the min and max of each bucket in the order they occur") {
+    val inputs = Array(5L, 9L, 1L, 4L, 8L, 2L, 7L, 7L)
+    NibbleDownsample.unpackDownsampled(packed(inputs), inputs.size, 4).right.get shouldEqual Array(9L, 1L, 8L, 2L)
+    NibbleDownsample.unpackDownsampled(packed(Array.fill(8)(3L)), 8, 4).right.get shouldEqual Array(3L, 3L)
+  }
+
+  it("should return every value when there are no more than target") {
+    val inputs = Array(5L, 9L, 1L, 4L, 8L)
+    NibbleDownsample.unpackDownsampled(packed(inputs), inputs.size, 5).right.get shouldEqual inputs
+    NibbleDownsample.unpackDownsampled(packed(Array.empty[Long]), 0, 10).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should downsample delta-encoded values like unpacking them first") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.map(_ & 0xffffffL).sorted.toArray
+      val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+      val points = NibbleDownsample.unpackDeltaDownsampled(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size, 10)
+                                   .right.get
+      points.size should be <= 10
+      // Increasing values keep the first and last of each bucket
+      if (inputs.nonEmpty) {
+        points.head shouldEqual inputs.head
+        points.last shouldEqual inputs.last
+      }
+      points.toSeq shouldEqual points.sorted.toSeq
+    }
+  }
+
+  it("should return InvalidParameter for a target below 2 or a negative count") {
+    NibbleDownsample.unpackDownsampled(packed(Array(1L, 2L)), 2, 1) shouldEqual
+      Left(NibblePack.InvalidParameter("target", 1))
+    NibbleDownsample.unpackDownsampled(packed(Array(1L, 2L)), -1, 10) shouldEqual
+      Left(NibblePack.InvalidParameter("numValues", -1))
+  }
+}
@@ -0,0 +1,54 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleFORTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Long]): Array[Long] = {
+    val bytesWritten = NibbleFOR.packFOR(inputs, buf, 0)
+    NibbleFOR.unpackFOR(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should pack and unpack values clustered within blocks") {
+    val clustered = Array.tabulate(100)(i => (i / 8) * 1000000L + (i * 7) % 16)
+    roundTrip(clustered) shouldEqual clustered
+
+    Seq(Array.empty[Long], Array(5L), Array(3L, 2L, 1L), Array.fill(9)(42L)).foreach { inputs =>
+      roundTrip(inputs) shouldEqual inputs
+    }
+  }
+
+  it("should pack block-local clusters smaller than deltas") {
+    val rand = new scala.util.Random(7)
+    val clustered = Array.tabulate(800)(i => rand.nextInt(1 << 30) * 1000L * (i / 8 % 2) + rand.nextInt(16))
+    val forBytes = NibbleFOR.packFOR(clustered, buf, 0)
+    forBytes should be < NibblePackSigned.packDelta(clustered, buf, 0)
+  }
+
+  it("should pack and unpack extreme and random values") {
+    val extremes = Array(Long.MinValue, Long.MaxValue, 0L, -1L, Long.MinValue, 1L, Long.MaxValue, -100L, 3L)
+    roundTrip(extremes) shouldEqual extremes
+
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.toArray
+      roundTrip(inputs) shouldEqual inputs
+    }
+  }
+
+  it("should return errors for truncated or mislabelled streams") {
+    val inputs = Array.tabulate(20)(i => i * 100L)
+    val bytesWritten = NibbleFOR.packFOR(inputs, buf, 0)
+    NibbleFOR.unpackFOR(new UnsafeBuffer(buf, 0, bytesWritten - 1)).left.get shouldBe a[NibblePack.InputTooShort]
+    NibbleFOR.unpackFOR(new UnsafeBuffer(buf, 0, 3)) shouldEqual Left(NibblePack.InputTooShort(4, 2))
+    NibbleFOR.unpackFOR(new UnsafeBuffer(buf, 0, 20)) shouldEqual Left(NibblePack.InvalidHeader("numValues", 20))
+
+    val deltaBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+    NibbleFOR.unpackFOR(new UnsafeBuffer(buf, 0, deltaBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Delta_Counted))
+  }
+}
@@ -0,0 +1,57 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleFixedWidthTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Long]): Array[Long] = {
+    val bytesWritten = NibbleFixedWidth.packDelta(inputs, buf, 0)
+    NibbleFixedWidth.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should pack every delta with the width of the largest") {
+    // The largest delta, 0x1234, takes 4 nibbles
+    val inputs = Array(0L, 0x10L, 0x1244L, 0x1245L, 0x1245L)
+    NibbleFixedWidth.packDelta(inputs, buf, 0) shouldEqual NibbleFixedWidth.HeaderBytes + 5 * 2
+    buf.getByte(NibbleFixedWidth.HeaderBytes - 1) shouldEqual 4.toByte
+    roundTrip(inputs) shouldEqual inputs
+
+    // All zero deltas still take one nibble each
+    NibbleFixedWidth.packDelta(Array.fill(10)(0L), buf, 0) shouldEqual NibbleFixedWidth.HeaderBytes + 5
+    Seq(Array.empty[Long], Array(0L), Array(Long.MaxValue), Array.fill(10)(0L)).foreach { inputs =>
+      roundTrip(inputs) shouldEqual inputs
+    }
+  }
+
+  it("should unpack increasing values the same as packDelta does") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.map(_ & 0xffffffffL).sorted.toArray
+      val deltaBytes = NibblePack.packDelta(inputs, buf, 0)
+      val expected = NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, deltaBytes), inputs.size).right.get
+      roundTrip(inputs) shouldEqual expected
+    }
+  }
+
+  it("should return errors for truncated or malformed streams") {
+    val inputs = Array.tabulate(40)(i => i * 100L + i % 3)
+    val numBytes = NibbleFixedWidth.packDelta(inputs, buf, 0)
+    // 40 deltas of 2 nibbles, one byte each
+    NibbleFixedWidth.unpackDelta(new UnsafeBuffer(buf, 0, numBytes - 1)) shouldEqual
+      Left(NibblePack.InputTooShort(40, 39))
+    NibbleFixedWidth.unpackDelta(new UnsafeBuffer(buf, 0, 4)) shouldEqual Left(NibblePack.InputTooShort(5, 2))
+
+    buf.putByte(NibbleFixedWidth.HeaderBytes - 1, 17.toByte)
+    NibbleFixedWidth.unpackDelta(new UnsafeBuffer(buf, 0, numBytes)) shouldEqual Left(NibblePack.InvalidNibbleWidth(17))
+    buf.putByte(NibbleFixedWidth.HeaderBytes - 1, 0.toByte)
+    NibbleFixedWidth.unpackDelta(new UnsafeBuffer(buf, 0, numBytes)) shouldEqual Left(NibblePack.InvalidNibbleWidth(0))
+
+    val blocksBytes = NibbleBlockSize.packDelta(inputs, buf, 0)
+    NibbleFixedWidth.unpackDelta(new UnsafeBuffer(buf, 0, blocksBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Delta_Blocks))
+  }
+}
@@ -0,0 +1,150 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+class NibbleFormatTest extends FunSpec with Matchers {
+  val buf = new ExpandableArrayBuffer()
+  val inputs = (0 until 21).map(_ * 10L).toArray
+
+  it("should peek at the count of streams which record it without unpacking") {
+    val countedBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, countedBytes)
+    NibbleFormat.peekCount(slice) shouldEqual Right(21)
+    slice.capacity shouldEqual countedBytes     // not mutated
+
+    val vecBytes = CompressedVec.encode(inputs, buf, 0)
+    NibbleFormat.peekCount(new UnsafeBuffer(buf, 0, vecBytes)) shouldEqual Right(21)
+  }
+
+  it("should return errors for streams without a count or too short to have one") {
+    NibbleFormat.peekCount(new UnsafeBuffer(buf, 0, 0)) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    NibblePack.packDeltaCounted(inputs, buf, 0)
+    NibbleFormat.peekCount(new UnsafeBuffer(buf, 0, 3)) shouldEqual Left(NibblePack.InputTooShort(5, 3))
+
+    val int32Bytes = NibblePack32.pack(Array(1, 2, 3), buf, 0)
+    NibbleFormat.peekCount(new UnsafeBuffer(buf, 0, int32Bytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_U32))
+  }
+
+  it("should detect corruption of a stream with a checksum") {
+    val endPos = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val withChecksum = NibbleFormat.appendChecksum(buf, 0, endPos)
+    withChecksum shouldEqual endPos + 4
+
+    val slice = new UnsafeBuffer(buf, 0, withChecksum)
+    NibbleFormat.peekCount(slice) shouldEqual Right(21)
+    NibbleFormat.verifyChecksum(slice) shouldEqual NibblePack.Ok
+    slice.capacity shouldEqual endPos
+    NibblePack.unpackDeltaCounted(slice).right.get shouldEqual inputs
+
+    // Flip a byte in the middle
+    buf.putByte(8, (buf.getByte(8) ^ 0x10).toByte)
+    NibbleFormat.verifyChecksum(new UnsafeBuffer(buf, 0, withChecksum)) shouldBe a[NibblePack.ChecksumMismatch]
+    // Truncated
+    NibbleFormat.verifyChecksum(new UnsafeBuffer(buf, 0, withChecksum - 1)) shouldBe a[NibblePack.ChecksumMismatch]
+  }
+
+  it("should check whole buffers with a CRC32 matching java.util.zip.CRC32") {
+    val check = "123456789".getBytes("UTF-8")
+    NibbleFormat.bufferCrc32(new UnsafeBuffer(check), 0, check.size) shouldEqual 0xCBF43926
+    NibbleFormat.bufferCrc32(new UnsafeBuffer(check), 0, 0) shouldEqual 0
+
+    val endPos = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val crc = new java.util.zip.CRC32
+    crc.update(buf.byteArray, 0, endPos)
+    NibbleFormat.bufferCrc32(buf, 0, endPos) shouldEqual crc.getValue.toInt
+    NibbleFormat.verifyBufferCrc32(buf, 0, endPos, crc.getValue.toInt) shouldEqual NibblePack.Ok
+
+    // Off heap buffers give the same CRC as on heap ones
+    val offheap = new UnsafeBuffer(java.nio.ByteBuffer.allocateDirect(endPos))
+    offheap.putBytes(0, buf, 0, endPos)
+    NibbleFormat.bufferCrc32(offheap, 0, endPos) shouldEqual crc.getValue.toInt
+
+    buf.putByte(3, (buf.getByte(3) ^ 0x01).toByte)
+    NibbleFormat.verifyBufferCrc32(buf, 0, endPos, crc.getValue.toInt) shouldBe a[NibblePack.ChecksumMismatch]
+  }
+
+  it("should leave streams without a checksum alone") {
+    val endPos = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, endPos)
+    NibbleFormat.verifyChecksum(slice) shouldEqual NibblePack.Ok
+    slice.capacity shouldEqual endPos
+  }
+
+  it("should refuse streams written with a newer format version") {
+    val countedBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+    NibbleFormat.versionOf(buf) shouldEqual NibbleFormat.FormatVersion
+    buf.putByte(0, (buf.getByte(0) + ((NibbleFormat.FormatVersion + 1) << 4)).toByte)
+    val unsupported = NibblePack.UnsupportedVersion(NibbleFormat.FormatVersion + 1)
+    NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, countedBytes)) shouldEqual Left(unsupported)
+    NibbleFormat.peekCount(new UnsafeBuffer(buf, 0, countedBytes)) shouldEqual Left(unsupported)
+
+    val vecBytes = CompressedVec.encode(inputs, buf, 0)
+    buf.putByte(0, (buf.getByte(0) + ((NibbleFormat.FormatVersion + 1) << 4)).toByte)
+    CompressedVec(new UnsafeBuffer(buf, 0, vecBytes)) shouldEqual Left(unsupported)
+  }
+
+  it("should keep the version and checksum flag out of the format code") {
+    val endPos = NibblePack.packDeltaCounted(inputs, buf, 0)
+    NibbleFormat.appendChecksum(buf, 0, endPos)
+    NibbleFormat.formatOf(buf) shouldEqual NibbleFormat.Format_Delta_Counted
+    NibbleFormat.versionOf(buf) shouldEqual NibbleFormat.FormatVersion
+  }
+
+  it("should write extended format codes in a second byte and check them there") {
+    NibbleFormat.putFormat(buf, 0, NibbleFormat.Format_Sparse_Double) shouldEqual 2
+    NibbleFormat.formatOf(buf) shouldEqual NibbleFormat.Format_Extended
+    buf.getByte(1) shouldEqual NibbleFormat.Format_Sparse_Double
+    val extended = new UnsafeBuffer(buf, 0, 5)
+    NibbleFormat.checkFormat(extended, NibbleFormat.Format_Sparse_Double) shouldEqual NibblePack.Ok
+    extended.capacity shouldEqual 3
+
+    NibbleFormat.checkFormat(new UnsafeBuffer(buf, 0, 1), NibbleFormat.Format_Sparse_Double) shouldEqual
+      NibblePack.InputTooShort(2, 1)
+    buf.putByte(1, 0x7f.toByte)
+    NibbleFormat.checkFormat(new UnsafeBuffer(buf, 0, 5), NibbleFormat.Format_Sparse_Double) shouldEqual
+      NibblePack.UnexpectedFormat(0x7f)
+    NibbleFormat.checkFormat(new UnsafeBuffer(buf, 0, 5), NibbleFormat.Format_FOR) shouldEqual
+      NibblePack.UnexpectedFormat(NibbleFormat.Format_Extended)
+
+    // Codes which fit in the first byte still take just that
+    NibbleFormat.putFormat(buf, 0, NibbleFormat.Format_FOR) shouldEqual 1
+    NibbleFormat.checkFormat(new UnsafeBuffer(buf, 0, 5), NibbleFormat.Format_Sparse_Double) shouldEqual
+      NibblePack.UnexpectedFormat(NibbleFormat.Format_FOR)
+  }
+
+  // The unsigned bytes at the start of buf
+  def bytesAt(start: Int, len: Int): Seq[Int] = (start until start + len).map(buf.getByte(_) & 0xff)
+
+  it("should write the documented bytes for regular, zero and constant blocks") {
+    // 0x10 and 0x20 then zeroes, a block of all zeroes, and 8 values of 1000
+    val blockInputs = Array(0x10L, 0x20L) ++ new Array[Long](14) ++ Array.fill(8)(1000L)
+    NibblePack.packNonIncreasing(blockInputs, buf, 0) shouldEqual 8
+    // bitmask 0b11, one nibble per value and one trailing zero nibble, then nibbles 1 and 2
+    bytesAt(0, 3) shouldEqual Seq(0x03, 0x01, 0x21)
+    bytesAt(3, NibbleFormat.ZeroBlockBytes) shouldEqual Seq(0x00)
+    // AllNonzeroBitmask, ConstantBlockMarker | 2 bytes, then 1000 little-endian
+    bytesAt(4, 4) shouldEqual Seq(0xff, 0xf2, 0xe8, 0x03)
+    buf.getByte(4 + NibbleFormat.BitmaskOffset) & 0xff shouldEqual NibbleFormat.AllNonzeroBitmask
+    buf.getByte(4 + NibbleFormat.NibbleHeaderOffset) & 0xff shouldEqual (NibbleFormat.ConstantBlockMarker | 2)
+    NibbleFormat.nibbleHeader(1, 1) shouldEqual 0x01
+    NibbleFormat.nibbleWidth(0xf0) shouldEqual 16
+  }
+
+  it("should write the documented header bytes of self-describing streams") {
+    // Format code, count of 3 as a little-endian Int, then one block of the deltas 1, 1, 1
+    NibblePack.packDeltaCounted(Array(1L, 2L, 3L), buf, 0) shouldEqual 9
+    bytesAt(0, 9) shouldEqual Seq(0x06, 0x03, 0x00, 0x00, 0x00, 0x07, 0x00, 0x11, 0x01)
+    buf.getByte(NibbleFormat.CountOffset) shouldEqual 3
+    NibbleFormat.appendChecksum(buf, 0, 9) shouldEqual 9 + NibbleFormat.ChecksumBytes
+    bytesAt(0, 1) shouldEqual Seq(0x86)
+
+    // Format code, count of 21, blocksPerSkip of 16 as a little-endian Short, then the footer offset
+    val vecBytes = CompressedVec.encode(inputs, buf, 0)
+    bytesAt(0, 7) shouldEqual Seq(0x04, 0x15, 0x00, 0x00, 0x00, 0x10, 0x00)
+    buf.getInt(CompressedVec.FooterOffsetOffset, java.nio.ByteOrder.LITTLE_ENDIAN) shouldEqual vecBytes - 4
+  }
+}
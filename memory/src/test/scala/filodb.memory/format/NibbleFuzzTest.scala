@@ -0,0 +1,124 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.scalacheck.{Arbitrary, Gen}
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+/**
+ * Feeds arbitrary bytes and counts to every decoder, checking that none of them throw or read out of bounds:
+ * malformed input must only ever come back as a NibbleError.  Agrona bounds checks are on in tests, so a read
+ * past the end of a buffer throws IndexOutOfBoundsException and fails the test.
+ * It runs 500 cases per decoder by default.  To fuzz for longer, set FuzzRuns in the environment, eg
+ * {{{
+ *   FuzzRuns=1000000 sbt "memory/testOnly filodb.memory.format.NibbleFuzzTest"
+ * }}}
+ */
+class NibbleFuzzTest extends FunSpec with Matchers with PropertyChecks {
+  val numRuns = sys.env.get("FuzzRuns").map(_.toInt).getOrElse(500)
+  implicit override val generatorDrivenConfig = PropertyCheckConfig(minSuccessful = numRuns)
+
+  val formatCodes = Seq(0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E, 0x0F,
+                        0x86, 0x7f, 0xff)
+
+  // Codes from the second byte of a NibbleFormat.Format_Extended stream
+  val extendedCodes = Seq(0x10, 0x11, 0x12, 0x13, 0x14, 0x15, 0x16, 0xff)
+
+  // Mostly streams starting with a real format code and version, so that decoders get past checkFormat
+  val streams: Gen[Array[Byte]] = for {
+    code  <- Gen.frequency((4, Gen.oneOf(formatCodes).map(c => List(NibbleFormat.versioned(c.toByte)))),
+                           (1, Gen.oneOf(extendedCodes).map { c =>
+                             List(NibbleFormat.versioned(NibbleFormat.Format_Extended), c.toByte)
+                           }),
+                           (1, Arbitrary.arbitrary[Byte].map(List(_))))
+    bytes <- Gen.listOf(Arbitrary.arbitrary[Byte])
+  } yield (code ++ bytes).toArray
+
+  def slice(bytes: Array[Byte]): UnsafeBuffer = new UnsafeBuffer(bytes)
+
+  def isNibbleResult(res: Any): Boolean = res match {
+    case NibblePack.Ok | Right(_) | Left(_: NibblePack.NibbleError) | (_: NibblePack.NibbleError) => true
+    case _                                                                                        => false
+  }
+
+  it("should never throw when unpacking raw NibblePacked streams") {
+    forAll(streams, Gen.choose(0, 2000)) { (bytes, numValues) =>
+      isNibbleResult(NibblePack.unpackDelta(slice(bytes), numValues)) shouldEqual true
+      isNibbleResult(NibblePack.unpackDeltaFromBytes(bytes, numValues)) shouldEqual true
+      isNibbleResult(NibblePack.unpackToSink(slice(bytes), NibblePack.DeltaSink(new Array[Long](numValues)),
+                                             numValues)) shouldEqual true
+      isNibbleResult(NibblePack.unpackDoubleXOR(slice(bytes), new Array[Double](numValues))) shouldEqual true
+      isNibbleResult(NibblePack.unpack8(slice(bytes), NibblePack.DeltaSink(new Array[Long](8)))) shouldEqual true
+      new NibblePack.UnpackIterator(slice(bytes), numValues).size should be <= numValues
+      isNibbleResult(new DecodeContext().unpackDelta(slice(bytes), numValues)) shouldEqual true
+      val (recovered, error) = NibbleRecovery.unpackLenient(slice(bytes), numValues)
+      (recovered.size == numValues || error.isDefined) shouldEqual true
+      NibbleBlocks.blocks(slice(bytes)).foreach { info => isNibbleResult(info) shouldEqual true }
+      LazyVec(slice(bytes), numValues).right.foreach { vec => vec.iterator.size shouldEqual numValues }
+      val resumable = new ResumableDecoder(numValues, isDelta = true)
+      isNibbleResult(resumable.feed(slice(bytes))) shouldEqual true
+      isNibbleResult(resumable.finish()) shouldEqual true
+      if (numValues > 0) {
+        isNibbleResult(NibbleSelect.lastValueDelta(slice(bytes), numValues)) shouldEqual true
+        isNibbleResult(NibbleSelect.unpackIndices(slice(bytes), numValues, Array(0, numValues / 2)))
+          .shouldEqual(true)
+      }
+    }
+  }
+
+  it("should never throw when unpacking self-describing streams") {
+    val out = new ExpandableArrayBuffer()
+    forAll(streams, Gen.choose(0, 2000)) { (bytes, numValues) =>
+      isNibbleResult(NibbleFormat.verifyChecksum(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleFormat.peekCount(slice(bytes))) shouldEqual true
+      isNibbleResult(NibblePack.unpackDeltaCounted(slice(bytes))) shouldEqual true
+      isNibbleResult(NibblePack.unpackDeltaCountedInto(slice(bytes), new Array[Long](numValues))) shouldEqual true
+      isNibbleResult(NibblePack32.unpack(slice(bytes), new Array[Int](numValues))) shouldEqual true
+      isNibbleResult(NibblePack128.unpack(slice(bytes), new Array[Long](numValues), new Array[Long](numValues)))
+        .shouldEqual(true)
+      isNibbleResult(NibblePackSigned.unpackDelta(slice(bytes), new Array[Long](numValues))) shouldEqual true
+      isNibbleResult(NibblePackSigned.unpackDeltaFromBase(slice(bytes), new Array[Long](numValues)))
+        .shouldEqual(true)
+      isNibbleResult(NibblePackSigned.unpackDeltaOfDeltaInto(slice(bytes), new Array[Long](numValues)))
+        .shouldEqual(true)
+      isNibbleResult(DoubleXORPack.unpack(slice(bytes), new Array[Double](numValues))) shouldEqual true
+      isNibbleResult(SparseDoublePack.unpack(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleFOR.unpackFOR(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleBlockSize.unpackDelta(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleFixedWidth.unpackDelta(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleHybridDelta.unpackDelta(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleScaled.unpackScaled(slice(bytes))) shouldEqual true
+      isNibbleResult(NibblePatch.patchValues(Array.fill(numValues)(1L), slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleClamped.unpackOriginal(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleAny.decode(slice(bytes))) shouldEqual true
+      // Not unpack, as a short stream can hold a count of up to MaxDecodeValues
+      isNibbleResult(NibbleArithmetic.read(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleRuns.unpackDeltaRuns(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleChunks.unpackDeltaChunks(slice(bytes))) shouldEqual true
+      isNibbleResult(NibbleTranscode.transcode(slice(bytes), numValues, NibbleFormat.Format_Delta_Counted, out, 0))
+        .shouldEqual(true)
+      NibbleTrailers.readTrailers(slice(bytes)).foreach { t => isNibbleResult(t) shouldEqual true }
+
+      CompressedVec(slice(bytes)).right.foreach { vec =>
+        Seq(0, vec.numValues / 2, vec.numValues - 1).foreach(vec.get)
+      }
+      BitVec(slice(bytes)).right.foreach { vec =>
+        vec.countOnes should be <= vec.numValues
+        Seq(0, vec.numValues / 2, vec.numValues - 1).foreach(vec.get)
+      }
+      NibbleBatch.numVectors(slice(bytes)).right.foreach { numVectors =>
+        (0 until Math.min(numVectors, 8)).foreach { n =>
+          isNibbleResult(NibbleBatch.unpackNth(slice(bytes), n)) shouldEqual true
+        }
+      }
+    }
+  }
+
+  it("should return InputTooShort for blocks unpacked from an empty buffer") {
+    NibblePack.unpack8(slice(Array.empty[Byte]), NibblePack.DeltaSink(new Array[Long](8))) shouldEqual
+      NibblePack.InputTooShort(1, 0)
+    NibblePack32.unpack8(slice(Array.empty[Byte]), new Array[Int](8), 0) shouldEqual NibblePack.InputTooShort(1, 0)
+  }
+}
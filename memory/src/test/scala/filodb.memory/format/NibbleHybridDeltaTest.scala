@@ -0,0 +1,57 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleHybridDeltaTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def packed(inputs: Array[Long]): UnsafeBuffer = new UnsafeBuffer(buf, 0, NibbleHybridDelta.packDelta(inputs, buf, 0))
+
+  val counter = Array.tabulate(40)(i => 1000L + i * 12 + i % 3)
+
+  it("should round trip a counter with a dip, ZigZag encoding only the block holding it") {
+    val dipped = counter.clone
+    dipped(20) = 500L
+    NibbleHybridDelta.unpackDelta(packed(dipped)).right.get shouldEqual dipped
+    NibbleHybridDelta.zigzagBlocks(packed(dipped)) shouldEqual Right(Seq(2))
+
+    // A drop from the last value of one block to the first of the next is in the later block
+    val reset = counter.zipWithIndex.map { case (v, i) => if (i >= 24) v - 900 else v }
+    NibbleHybridDelta.unpackDelta(packed(reset)).right.get shouldEqual reset
+    NibbleHybridDelta.zigzagBlocks(packed(reset)) shouldEqual Right(Seq(3))
+  }
+
+  it("should pack increasing values in the same blocks as packDelta plus the flags") {
+    val expected = NibblePack.packDelta(counter, new ExpandableArrayBuffer(), 0)
+    packed(counter).capacity shouldEqual NibbleHybridDelta.HeaderBytes + 1 + expected
+    NibbleHybridDelta.zigzagBlocks(packed(counter)) shouldEqual Right(Nil)
+    NibbleHybridDelta.unpackDelta(packed(counter)).right.get shouldEqual counter
+
+    // 65 values take 9 blocks, so two bytes of flags
+    val longer = Array.tabulate(65)(i => 65L - i)
+    NibbleHybridDelta.unpackDelta(packed(longer)).right.get shouldEqual longer
+    NibbleHybridDelta.zigzagBlocks(packed(longer)) shouldEqual Right(0 until 9)
+    NibbleHybridDelta.unpackDelta(packed(Array.empty[Long])).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should round trip any Longs") {
+    forAll { (longs: Seq[Long]) =>
+      NibbleHybridDelta.unpackDelta(packed(longs.toArray)).right.get shouldEqual longs.toArray
+    }
+  }
+
+  it("should return errors for truncated or mislabeled streams") {
+    val numBytes = packed(counter).capacity
+    for { n <- 0 until numBytes } {
+      NibbleHybridDelta.unpackDelta(new UnsafeBuffer(buf, 0, n)).isLeft shouldEqual true
+    }
+    NibbleHybridDelta.zigzagBlocks(new UnsafeBuffer(buf, 0, NibbleHybridDelta.HeaderBytes)).left.get shouldBe
+      a[NibblePack.InputTooShort]
+    val other = NibbleFixedWidth.packDelta(counter, buf, 0)
+    NibbleHybridDelta.unpackDelta(new UnsafeBuffer(buf, 0, other)).left.get shouldBe a[NibblePack.UnexpectedFormat]
+  }
+}
@@ -0,0 +1,68 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibblePack128Test extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(hi: Array[Long], lo: Array[Long]): (Seq[Long], Seq[Long]) = {
+    val bytesWritten = NibblePack128.pack(hi, lo, buf, 0)
+    val (outHi, outLo) = (new Array[Long](hi.size), new Array[Long](lo.size))
+    NibblePack128.unpack(new UnsafeBuffer(buf, 0, bytesWritten), outHi, outLo) shouldEqual NibblePack.Ok
+    (outHi.toSeq, outLo.toSeq)
+  }
+
+  it("should pack and unpack values on both sides of the 64-bit boundary") {
+    // 2^64 - 1, 2^64, 2^64 + 1, 2^128 - 1, 2^127, 2^63, 0, 1
+    val hi = Array(0L, 1L, 1L, -1L, Long.MinValue, 0L, 0L, 0L)
+    val lo = Array(-1L, 0L, 1L, -1L, 0L, Long.MinValue, 0L, 1L)
+    roundTrip(hi, lo) shouldEqual ((hi.toSeq, lo.toSeq))
+
+    // Only the top nibble of the high half and the bottom of the low half are set, so all 32 nibbles are needed
+    NibblePack128.pack(Array(0x1000000000000000L), Array(1L), buf, 0) shouldEqual 1 + 3 + 16
+    buf.getByte(0) shouldEqual NibbleFormat.Format_U128
+    buf.getByte(2) shouldEqual 31
+  }
+
+  it("should leave out trailing zero nibbles, even across the 64-bit boundary") {
+    // 2^64, 2^65 and 3 * 2^64: the whole low half is trailing zeroes
+    val hi = Array(1L, 2L, 3L)
+    val lo = Array(0L, 0L, 0L)
+    NibblePack128.pack(hi, lo, buf, 0) shouldEqual 1 + 3 + 2
+    buf.getByte(2) shouldEqual 0
+    buf.getByte(3) shouldEqual 16
+    roundTrip(hi, lo) shouldEqual ((hi.toSeq, lo.toSeq))
+  }
+
+  it("should pack and unpack any values and lengths") {
+    forAll { (values: Seq[(Long, Long)]) =>
+      val (hi, lo) = values.unzip
+      roundTrip(hi.toArray, lo.toArray) shouldEqual ((hi, lo))
+    }
+    val zeroes = new Array[Long](16)
+    NibblePack128.pack(zeroes, zeroes, buf, 0) shouldEqual 3
+    roundTrip(Array.empty[Long], Array.empty[Long]) shouldEqual ((Nil, Nil))
+  }
+
+  it("should refuse streams which are not 128-bit, truncated, or have impossible widths") {
+    val longBytes = NibblePack.packDeltaCounted(Array(1L, 2L, 3L), buf, 0)
+    NibblePack128.unpack(new UnsafeBuffer(buf, 0, longBytes), new Array[Long](3), new Array[Long](3)) shouldEqual
+      NibblePack.UnexpectedFormat(NibbleFormat.Format_Delta_Counted)
+
+    val bytesWritten = NibblePack128.pack(Array(5L, 6L), Array(7L, 8L), buf, 0)
+    NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_U128))
+    NibblePack128.unpack(new UnsafeBuffer(buf, 0, bytesWritten - 1), new Array[Long](2), new Array[Long](2))
+      .shouldBe(a[NibblePack.InputTooShort])
+
+    // 20 nibbles plus 13 trailing nibbles
+    buf.putByte(2, 19)
+    buf.putByte(3, 13)
+    NibblePack128.unpack(new UnsafeBuffer(buf, 0, bytesWritten), new Array[Long](2), new Array[Long](2)) shouldEqual
+      NibblePack.InvalidNibbleWidth(33)
+  }
+}
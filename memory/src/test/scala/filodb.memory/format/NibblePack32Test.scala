@@ -0,0 +1,56 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibblePack32Test extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Int]): Array[Int] = {
+    val bytesWritten = NibblePack32.pack(inputs, buf, 0)
+    val out = new Array[Int](inputs.size)
+    NibblePack32.unpack(new UnsafeBuffer(buf, 0, bytesWritten), out) shouldEqual NibblePack.Ok
+    out
+  }
+
+  it("should pack all-zero blocks to a format code plus one byte per block") {
+    val inputs = new Array[Int](16)
+    NibblePack32.pack(inputs, buf, 0) shouldEqual 3
+    buf.getByte(0) shouldEqual NibbleFormat.Format_U32
+    roundTrip(inputs) shouldEqual inputs
+  }
+
+  it("should pack and unpack values near the unsigned 32-bit max") {
+    // -1 is 0xffffffff, the largest unsigned 32-bit value
+    val inputs = Array(-1, -2, Int.MaxValue, Int.MinValue, 0, 1, 0x0fff0000, -16)
+    roundTrip(inputs) shouldEqual inputs
+  }
+
+  it("should pack and unpack arrays whose length is not a multiple of 8") {
+    val inputs = Array(5, 10, 15, 100, 1000, 0, 3, 70000, 123456789, 7, 0)
+    roundTrip(inputs) shouldEqual inputs
+    roundTrip(Array(42)) shouldEqual Array(42)
+  }
+
+  it("should refuse to unpack a stream which is not a 32-bit stream") {
+    val longBytes = NibblePack.packDelta(Array(1L, 2L, 3L), buf, 0)
+    val res = NibblePack32.unpack(new UnsafeBuffer(buf, 0, longBytes), new Array[Int](3))
+    res shouldBe a[NibblePack.UnexpectedFormat]
+  }
+
+  it("should return InvalidNibbleWidth for a header wider than 8 nibbles") {
+    // 8 nibbles plus 1 trailing zero nibble cannot fit in 32 bits
+    val header = Array[Byte](NibbleFormat.Format_U32, 0x01, 0x71, 0x11, 0x11, 0x11, 0x11, 0, 0, 0, 0)
+    NibblePack32.unpack(new UnsafeBuffer(header), new Array[Int](1)) shouldEqual NibblePack.InvalidNibbleWidth(9)
+  }
+
+  it("should pack and unpack random lists of Ints") {
+    forAll { (ints: Seq[Int]) =>
+      val inputs = ints.toArray
+      roundTrip(inputs) shouldEqual inputs
+    }
+  }
+}
@@ -0,0 +1,56 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+import filodb.memory.format.vectors.{BinaryHistogram, CustomBuckets, GeometricBuckets, LongHistogram}
+
+class NibblePackCompatTest extends FunSpec with Matchers {
+  val buf = new ExpandableArrayBuffer()
+
+  def hasConstantBlocks(numBytes: Int, start: Int = 0): Boolean =
+    NibbleBlocks.blocks(new UnsafeBuffer(buf, start, numBytes - start)).exists(_.right.get.constant)
+
+  // A flat counter increasing by 1000 every sample: NibblePack.packDelta packs all but its first block as constant
+  val flatLine = Array.tabulate(64) { i => 5000L + i * 1000 }
+
+  it("should pack without constant blocks, readable by the same decoders") {
+    hasConstantBlocks(NibblePack.packDelta(flatLine, buf, 0)) shouldEqual true
+
+    val deltaBytes = NibblePackCompat.packDelta(flatLine, buf, 0)
+    hasConstantBlocks(deltaBytes) shouldEqual false
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, deltaBytes), flatLine.size).right.get shouldEqual flatLine
+
+    val repeats = Array.fill(24)(42L) ++ Array(1L, 2L, 3L)
+    val rawBytes = NibblePackCompat.packNonIncreasing(repeats, buf, 0)
+    hasConstantBlocks(rawBytes) shouldEqual false
+    val sink = new NibbleSinks.BufferSink(repeats.size)
+    NibblePack.unpackAllToSink(new UnsafeBuffer(buf, 0, rawBytes), sink, repeats.size) shouldEqual NibblePack.Ok
+    sink.values.toArray shouldEqual repeats
+
+    // Doubles whose XORs repeat: alternating between two values
+    val doubles = Array.tabulate(17)(i => if (i % 2 == 0) 1.5 else 2.5)
+    val doubleBytes = NibblePackCompat.packDoubles(doubles, buf, 0)
+    hasConstantBlocks(doubleBytes, 8) shouldEqual false
+    val out = new Array[Double](doubles.size)
+    NibblePack.unpackDoubleXOR(new UnsafeBuffer(buf, 0, doubleBytes), out) shouldEqual NibblePack.Ok
+    out shouldEqual doubles
+
+    // The regular block of 8 equal values, rather than the 4 byte constant block
+    NibblePack.pack8(Array.fill(8)(1000L), buf, 0, constantBlocks = false) shouldEqual 14
+  }
+
+  it("should write histograms without constant blocks") {
+    for { buckets <- Seq(GeometricBuckets(1.0, 2.0, 64), CustomBuckets(Array.tabulate(64)(i => (i + 1) * 0.5))) } {
+      val numBytes = BinaryHistogram.writeDelta(buckets, flatLine, buf)
+      val hist = BinaryHistogram.BinHistogram(new UnsafeBuffer(buf, 0, numBytes))
+      hasConstantBlocks(hist.valuesIndex + hist.valuesNumBytes, hist.valuesIndex) shouldEqual false
+      hist.toHistogram shouldEqual LongHistogram(buckets, flatLine)
+    }
+    val numBytes = BinaryHistogram.writeNonIncreasing(GeometricBuckets(1.0, 2.0, 64), Array.fill(64)(7L), buf)
+    val hist = BinaryHistogram.BinHistogram(new UnsafeBuffer(buf, 0, numBytes))
+    hasConstantBlocks(hist.valuesIndex + hist.valuesNumBytes, hist.valuesIndex) shouldEqual false
+  }
+}
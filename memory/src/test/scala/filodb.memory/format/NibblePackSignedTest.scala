@@ -0,0 +1,172 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibblePackSignedTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Long]): Array[Long] = {
+    val bytesWritten = NibblePackSigned.packDelta(inputs, buf, 0)
+    val out = new Array[Long](inputs.size)
+    NibblePackSigned.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten), out) shouldEqual NibblePack.Ok
+    out
+  }
+
+  it("should zigzag small positive and negative numbers to small unsigned numbers") {
+    Seq(0L, -1L, 1L, -2L, 2L).map(NibblePackSigned.zigzag) shouldEqual Seq(0L, 1L, 2L, 3L, 4L)
+    Seq(0L, -1L, 1L, Long.MinValue, Long.MaxValue).foreach { n =>
+      NibblePackSigned.unzigzag(NibblePackSigned.zigzag(n)) shouldEqual n
+    }
+  }
+
+  it("should pack and unpack decreasing and oscillating series") {
+    val decreasing = Array(1000L, 990, 950, 900, 850, 700, 400, 20, -5, -500)
+    roundTrip(decreasing) shouldEqual decreasing
+
+    val oscillating = Array(215L, 217, 214, 216, 213, 219, 212, 215, 215, 211, 218)
+    roundTrip(oscillating) shouldEqual oscillating
+    buf.getByte(0) shouldEqual NibbleFormat.Format_ZigZag_Delta
+  }
+
+  it("should pack oscillating gauges much smaller than plain delta packing") {
+    val oscillating = (0 until 64).map { i => if (i % 2 == 0) 1000L else 997L }.toArray
+    val signedBytes = NibblePackSigned.packDelta(oscillating, buf, 0)
+    val plainBytes = NibblePack.packNonIncreasing(oscillating, buf, 0)
+    signedBytes should be < plainBytes
+  }
+
+  it("should pack and unpack extreme and random signed values") {
+    val extremes = Array(Long.MinValue, Long.MaxValue, 0L, -1L, Long.MinValue, 1L)
+    roundTrip(extremes) shouldEqual extremes
+
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.toArray
+      roundTrip(inputs) shouldEqual inputs
+    }
+  }
+
+  def roundTripBase(inputs: Array[Long], base: Long): Array[Long] = {
+    val bytesWritten = NibblePackSigned.packDeltaFromBase(inputs, base, buf, 0)
+    val out = new Array[Long](inputs.size)
+    NibblePackSigned.unpackDeltaFromBase(new UnsafeBuffer(buf, 0, bytesWritten), out) shouldEqual NibblePack.Ok
+    out
+  }
+
+  it("should pack and unpack deltas from a base for series which start negative and cross zero") {
+    val crossing = Array(-100L, -50L, 0L, 25L, -10L)
+    roundTripBase(crossing, crossing(0)) shouldEqual crossing
+    roundTripBase(crossing, 0L) shouldEqual crossing
+    buf.getByte(0) shouldEqual NibbleFormat.Format_ZigZag_Base
+    buf.getLong(1, java.nio.ByteOrder.LITTLE_ENDIAN) shouldEqual 0L
+
+    forAll { (longs: Seq[Long], base: Long) =>
+      val inputs = longs.toArray
+      roundTripBase(inputs, base) shouldEqual inputs
+    }
+    roundTripBase(Array.empty[Long], 0L) shouldEqual Array.empty[Long]
+  }
+
+  it("should pack series far below zero smaller from a base than with packDelta") {
+    val farNegative = Array.tabulate(64)(i => -1000000000000L + (i % 5) * 3)
+    val baseBytes = NibblePackSigned.packDeltaFromBase(farNegative, farNegative(0), buf, 0)
+    baseBytes should be < NibblePackSigned.packDelta(farNegative, buf, 0)
+  }
+
+  it("should return errors for truncated streams packed from a base") {
+    val inputs = Array.tabulate(20)(i => -5000L + i * 700)
+    val bytesWritten = NibblePackSigned.packDeltaFromBase(inputs, inputs(0), buf, 0)
+    NibblePackSigned.unpackDeltaFromBase(new UnsafeBuffer(buf, 0, 5), new Array[Long](20)) shouldEqual
+      NibblePack.InputTooShort(NibblePackSigned.BaseHeaderBytes, 5)
+    NibblePackSigned.unpackDeltaFromBase(new UnsafeBuffer(buf, 0, bytesWritten), new Array[Long](30)) shouldEqual
+      NibblePack.InputTooShort(1, 0)
+  }
+
+  def roundTripDoD(inputs: Array[Long]): Array[Long] = {
+    val bytesWritten = NibblePackSigned.packDeltaOfDelta(inputs, buf, 0)
+    NibblePackSigned.unpackDeltaOfDelta(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should pack perfectly regular timestamps as just the delta-of-delta header") {
+    val regular = Array.tabulate(10000)(i => 1546300800000L + i * 10000L)
+    NibblePackSigned.packDeltaOfDelta(regular, buf, 0) shouldEqual NibblePackSigned.DoDHeaderBytes
+    roundTripDoD(regular) shouldEqual regular
+
+    Seq(Array.empty[Long], Array(-5L), Array(100L, 50L), Array(7L, 7L, 7L)).foreach { inputs =>
+      NibblePackSigned.packDeltaOfDelta(inputs, buf, 0) shouldEqual NibblePackSigned.DoDHeaderBytes
+      roundTripDoD(inputs) shouldEqual inputs
+    }
+  }
+
+  it("should pack jittery timestamps much smaller than signed deltas") {
+    val rand = new scala.util.Random(42)
+    val jittery = Array.tabulate(1000)(i => 1546300800000L + i * 10000L + rand.nextInt(20) - 10)
+    val dodBytes = NibblePackSigned.packDeltaOfDelta(jittery, buf, 0)
+    roundTripDoD(jittery) shouldEqual jittery
+    // each delta-of-delta fits in 2 nibbles, against 4 nibbles for the deltas
+    dodBytes should be < NibblePackSigned.packDelta(jittery, buf, 0) * 2 / 3
+  }
+
+  it("should pack and unpack out-of-order and random timestamps with delta-of-deltas") {
+    val outOfOrder = Array(1000L, 2000L, 1500L, 3000L, 2999L, 2998L, 10000L, 9000L, 11000L, 12000L, 0L)
+    roundTripDoD(outOfOrder) shouldEqual outOfOrder
+
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.toArray
+      roundTripDoD(inputs) shouldEqual inputs
+    }
+  }
+
+  it("should return errors for truncated delta-of-delta streams") {
+    val inputs = Array.tabulate(20)(i => i * i * 100L)
+    val bytesWritten = NibblePackSigned.packDeltaOfDelta(inputs, buf, 0)
+    NibblePackSigned.unpackDeltaOfDelta(new UnsafeBuffer(buf, 0, bytesWritten - 1)).left.get shouldBe
+      a[NibblePack.InputTooShort]
+    NibblePackSigned.unpackDeltaOfDelta(new UnsafeBuffer(buf, 0, 10)) shouldEqual
+      Left(NibblePack.InputTooShort(21, 9))
+  }
+
+  it("should unpack delta-of-deltas into an array owned by the caller") {
+    val inputs = Array(1000L, 2000L, 3010L, 3990L, 5000L, 6000L, 7005L, 8000L, 9000L, 10001L)
+    val bytesWritten = NibblePackSigned.packDeltaOfDelta(inputs, buf, 0)
+    val out = new Array[Long](16)
+    NibblePackSigned.unpackDeltaOfDeltaInto(new UnsafeBuffer(buf, 0, bytesWritten), out) shouldEqual Right(10)
+    out.take(10) shouldEqual inputs
+
+    // Unpacking another stream leaves the first output alone
+    val out2 = new Array[Long](10)
+    val regularBytes = NibblePackSigned.packDeltaOfDelta(Array.tabulate(10)(_ * 5L), buf, 0)
+    NibblePackSigned.unpackDeltaOfDeltaInto(new UnsafeBuffer(buf, 0, regularBytes), out2) shouldEqual Right(10)
+    out2 shouldEqual Array.tabulate(10)(_ * 5L)
+    out.take(10) shouldEqual inputs
+
+    NibblePackSigned.unpackDeltaOfDeltaInto(new UnsafeBuffer(buf, 0, regularBytes), new Array[Long](9)) shouldEqual
+      Left(NibblePack.OutputTooSmall(10, 9))
+  }
+
+  it("should unpack counters checked for resets") {
+    val counter = Array(0L, 5, 5, 12, 40, 41, 41, 90, 1000, 1001)
+    val bytesWritten = NibblePackSigned.packDelta(counter, buf, 0)
+    val out = new Array[Long](counter.size)
+    NibblePackSigned.unpackDeltaChecked(new UnsafeBuffer(buf, 0, bytesWritten), out) shouldEqual Right(counter.size)
+    out shouldEqual counter
+
+    // The process restarts after the 6th value, and the counter starts again from 3
+    val reset = counter.take(6) ++ Array(3L, 8, 9)
+    val resetBytes = NibblePackSigned.packDelta(reset, buf, 0)
+    NibblePackSigned.unpackDeltaChecked(new UnsafeBuffer(buf, 0, resetBytes), new Array[Long](reset.size)) shouldEqual
+      Left(NibblePack.CounterReset(6, 41, 3))
+
+    NibblePackSigned.unpackDeltaChecked(new UnsafeBuffer(buf, 0, 0), out) shouldEqual
+      Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should not unpack a stream with a different format code") {
+    val bytesWritten = NibblePack32.pack(Array(1, 2, 3), buf, 0)
+    NibblePackSigned.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten), new Array[Long](3)) shouldEqual
+      NibblePack.UnexpectedFormat(NibbleFormat.Format_U32)
+  }
+}
@@ -73,6 +73,51 @@ class NibblePackTest extends FunSpec with Matchers with PropertyChecks {
     outarray shouldEqual expected
   }
 
+  it("should pack8 and unpack8 blocks of every nibble width and trailing zero count") {
+    val buf = new ExpandableArrayBuffer()
+    for { numNibbles <- 1 to 16
+          trailingNibbles <- 0 to (16 - numNibbles) } {
+      val topNibble = 1L << ((numNibbles + trailingNibbles - 1) * 4)
+      val lowNibble = 1L << (trailingNibbles * 4)
+      // One value has the highest nibble set, one the lowest, so the block is packed at exactly this width
+      val inputs = Array(topNibble, lowNibble, 0L, topNibble | lowNibble, 0L, 0L, lowNibble * 3, topNibble * 7)
+      val bytesWritten = NibblePack.pack8(inputs, buf, 0)
+      buf.getByte(1) shouldEqual (((numNibbles - 1) << 4) | trailingNibbles).toByte
+
+      var out: Array[Long] = Array.empty
+      val sink = new NibblePack.Sink { def process(data: Array[Long]): Unit = { out = data.clone } }
+      NibblePack.unpack8(new UnsafeBuffer(buf, 0, bytesWritten), sink) shouldEqual NibblePack.Ok
+      out shouldEqual inputs
+    }
+  }
+
+  it("should pack blocks of 8 equal values as constant blocks") {
+    val buf = new ExpandableArrayBuffer()
+    // A flat counter increasing by 1000 every sample: all deltas after the first are equal
+    val flatLine = Array.tabulate(64) { i => 5000L + i * 1000 }
+    val bytesWritten = NibblePack.packDelta(flatLine, buf, 0)
+    // each block after the first is the 0xff mask, the constant header and 2 bytes for 1000
+    bytesWritten shouldEqual NibblePack.blockSize(buf, 0) + 7 * 4
+    buf.getByte(bytesWritten - 3) shouldEqual (NibbleFormat.ConstantBlockMarker | 2).toByte
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten), flatLine.size).right.get shouldEqual flatLine
+
+    // A regular block of the same values would take 2 + (8 * 3 nibbles) / 2 = 14 bytes
+    NibblePack.pack8(Array.fill(8)(1000L), buf, 0) shouldEqual 4
+
+    // Constant blocks next to varying blocks, and of the widest values
+    val mixed = Array.fill(8)(-1L) ++ Array.tabulate(8)(_.toLong) ++ Array.fill(8)(7L) ++ Array.fill(3)(42L)
+    val mixedBytes = NibblePack.packNonIncreasing(mixed, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, mixedBytes)
+    NibblePack.blockSize(slice, 0) shouldEqual 2 + 8
+    val sink = new NibbleSinks.BufferSink(mixed.size)
+    NibblePack.unpackAllToSink(slice, sink, mixed.size) shouldEqual NibblePack.Ok
+    sink.values.toArray shouldEqual mixed
+
+    // Truncated constant block
+    NibblePack.unpack8(new UnsafeBuffer(buf, 0, 5), NibblePack.DeltaSink(new Array[Long](8))) shouldEqual
+      NibblePack.InputTooShort(10, 5)
+  }
+
   it("should pack and unpack delta values") {
     val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
     val buf = new ExpandableArrayBuffer()
@@ -95,6 +140,379 @@ class NibblePackTest extends FunSpec with Matchers with PropertyChecks {
     sink2.outArray shouldEqual inputs2
   }
 
+  it("should pack values one at a time with Packer the same as packNonIncreasing") {
+    val buf = new ExpandableArrayBuffer()
+    val expected = new ExpandableArrayBuffer()
+    Seq(0, 1, 7, 8, 9, 16, 21).foreach { n =>
+      val inputs = Array.tabulate(n) { i => i * 0x1234L }
+      val packer = new NibblePack.Packer(buf, 10)
+      inputs.foreach(packer.add)
+      packer.numValues shouldEqual n
+      val endPos = packer.finish()
+
+      val expectedBytes = NibblePack.packNonIncreasing(inputs, expected, 0)
+      endPos shouldEqual 10 + expectedBytes
+      (0 until expectedBytes).foreach { i => buf.getByte(10 + i) shouldEqual expected.getByte(i) }
+    }
+  }
+
+  it("should reject decreasing input with packDeltaChecked") {
+    val buf = new ExpandableArrayBuffer()
+    val inputs = Array(5L, 1000, 1001, 1001, 2005, 2010, 3034, 4045, 5056)
+    val bytesWritten = NibblePack.packDeltaChecked(inputs, buf, 0).right.get
+    bytesWritten shouldEqual NibblePack.packDelta(inputs, buf, 0)
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size).right.get shouldEqual inputs
+
+    NibblePack.packDeltaChecked(Array(10L, 20L, 19L, 30L), buf, 0) shouldEqual Left(NibblePack.NonIncreasing(2))
+    NibblePack.packDeltaChecked(Array(-1L, 20L), buf, 0) shouldEqual Left(NibblePack.NonIncreasing(0))
+    NibblePack.packDeltaChecked(Array.empty[Long], buf, 0) shouldEqual Right(0)
+  }
+
+  it("should reuse one DeltaSink for unpacking vectors of different sizes") {
+    val buf = new ExpandableArrayBuffer()
+    val sink = NibblePack.DeltaSink.withCapacity(64)
+    sink.outArray.size shouldEqual 64
+
+    val inputs = (0 until 50).map(_ * 100L).toArray
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+    NibblePack.unpackToSink(new UnsafeBuffer(buf, 0, bytesWritten), sink, inputs.size) shouldEqual NibblePack.Ok
+    sink.outArray.take(inputs.size) shouldEqual inputs
+
+    // Without reset() the second unpack would continue summing after the first one
+    sink.reset()
+    val inputs2 = Array(5L, 7L, 1000L)
+    val written2 = NibblePack.packDelta(inputs2, buf, 0)
+    NibblePack.unpackToSink(new UnsafeBuffer(buf, 0, written2), sink, inputs2.size) shouldEqual NibblePack.Ok
+    sink.outArray.take(inputs2.size) shouldEqual inputs2
+  }
+
+  it("should unpack delta values into a new array using unpackDelta") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+
+    val out = NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size)
+    out.isRight shouldEqual true
+    out.right.get.size shouldEqual inputs.size
+    out.right.get shouldEqual inputs
+
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, 0), 0).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should unpack several delta streams in order into one array with unpackConcat") {
+    val chunks = Seq(Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078),
+                     Array(5L, 10L, 15L),
+                     Array.tabulate(20)(i => 8000L + i * 17))
+    val buffers = chunks.map { values => (new UnsafeBuffer(NibblePack.packDeltaToBytes(values)), values.size) }
+    NibblePack.unpackConcat(buffers).right.get shouldEqual chunks.flatten.toArray
+    NibblePack.unpackConcat(Nil).right.get shouldEqual Array.empty[Long]
+
+    def fresh(values: Array[Long], numValues: Int) = (new UnsafeBuffer(NibblePack.packDeltaToBytes(values)), numValues)
+    NibblePack.unpackConcat(Seq(fresh(chunks(0), 12), fresh(chunks(1), 9))).left.get shouldBe
+      a[NibblePack.InputTooShort]
+    NibblePack.unpackConcat(Seq(fresh(chunks(1), 3), fresh(chunks(1), -1))) shouldEqual
+      Left(NibblePack.ImplausibleCount(-1, 8 * NibblePack.packDeltaToBytes(chunks(1)).size))
+
+    // Raw deltas which overflow at index 1 of the second stream, which is index 4 of the whole array
+    val deltaBuf = new ExpandableArrayBuffer()
+    val deltaBytes = NibblePack.packNonIncreasing(Array(Long.MaxValue, 1L), deltaBuf, 0)
+    NibblePack.unpackConcat(Seq(fresh(chunks(1), 3), (new UnsafeBuffer(deltaBuf, 0, deltaBytes), 2))) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(4))
+  }
+
+  it("should return AccumulatorOverflow for deltas which add up past Long.MaxValue") {
+    val buf = new ExpandableArrayBuffer()
+    // Raw deltas which no input to packDelta could produce
+    val deltas = Array(10L, Long.MaxValue - 20, 5L, 6L, 100L)
+    val bytesWritten = NibblePack.packNonIncreasing(deltas, buf, 0)
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten), deltas.size) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(3))
+    val bytes = java.util.Arrays.copyOf(buf.byteArray, bytesWritten)
+    NibblePack.unpackDeltaFromBytes(bytes, deltas.size) shouldEqual Left(NibblePack.AccumulatorOverflow(3))
+
+    // The unchecked DeltaSink wraps around
+    val sink = NibblePack.DeltaSink(new Array[Long](deltas.size))
+    NibblePack.unpackToSink(new UnsafeBuffer(buf, 0, bytesWritten), sink, deltas.size)
+    sink.outArray(3) should be < 0L
+
+    // A delta which is already negative, ie more than Long.MaxValue
+    val negBytes = NibblePack.packNonIncreasing(Array(1L, -1L), buf, 0)
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, negBytes), 2) shouldEqual Left(NibblePack.AccumulatorOverflow(1))
+
+    // Values right up to Long.MaxValue are fine
+    val maxBytes = NibblePack.packDelta(Array(0L, Long.MaxValue), buf, 0)
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, maxBytes), 2).right.get shouldEqual Array(0L, Long.MaxValue)
+  }
+
+  it("should pack and unpack delta values with plain byte arrays") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val bytes = NibblePack.packDeltaToBytes(inputs)
+    val buf = new ExpandableArrayBuffer()
+    bytes.size shouldEqual NibblePack.packDelta(inputs, buf, 0)
+    NibblePack.unpackDeltaFromBytes(bytes, inputs.size).right.get shouldEqual inputs
+
+    NibblePack.unpackDeltaFromBytes(bytes.take(bytes.size - 1), inputs.size).left.get shouldBe
+      a[NibblePack.InputTooShort]
+    NibblePack.unpackDeltaFromBytes(NibblePack.packDeltaToBytes(Array.empty[Long]), 0).right.get shouldEqual
+      Array.empty[Long]
+  }
+
+  it("should unpack blocks with every possible bitmask of nonzero values") {
+    val buf = new ExpandableArrayBuffer()
+    Seq(0x1L, 0xabcL, 0x12345600L, -1L).foreach { value =>
+      val inputs = (0 until 256).flatMap { mask =>
+        (0 until 8).map { bit => if ((mask & (1 << bit)) != 0) value + bit else 0L }
+      }.toArray
+      val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+      val sink = new NibbleSinks.BufferSink(inputs.size)
+      NibblePack.unpackToSink(new UnsafeBuffer(buf, 0, bytesWritten), sink, inputs.size) shouldEqual NibblePack.Ok
+      sink.values.toArray shouldEqual inputs
+    }
+    NibblePack.SetBitPositions(0).toSeq shouldEqual Nil
+    NibblePack.SetBitPositions(0xa5).toSeq shouldEqual Seq(0, 2, 5, 7)
+  }
+
+  it("should unpack one nibble wide blocks with every bitmask and trailing zero count") {
+    val buf = new ExpandableArrayBuffer()
+    for { trailingNibbles <- 0 to 15 } {
+      val inputs = (0 until 256).flatMap { mask =>
+        (0 until 8).map { bit => if ((mask & (1 << bit)) != 0) (bit + 8L) << (trailingNibbles * 4) else 0L }
+      }.toArray
+      val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+      NibbleBlocks.blocks(new UnsafeBuffer(buf, 0, bytesWritten)).map(_.right.get).filter(_.numValues > 0)
+        .foreach { info => if (!info.constant) info.nibbleWidth shouldEqual 1 }
+      val sink = new NibbleSinks.BufferSink(inputs.size)
+      NibblePack.unpackToSink(new UnsafeBuffer(buf, 0, bytesWritten), sink, inputs.size) shouldEqual NibblePack.Ok
+      sink.values.toArray shouldEqual inputs
+    }
+  }
+
+  it("should return InputTooShort rather than read out of bounds for input truncated at every offset") {
+    // zero, constant, one nibble wide and wider blocks
+    val inputs = Array.fill(8)(0L) ++ Array.fill(8)(77777L) ++ Array.tabulate(8)(i => i + 1L) ++
+                 Array.tabulate(13)(i => i * 123456789L)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    for { len <- 0 until bytesWritten } {
+      val sink = NibblePack.DeltaSink(new Array[Long](inputs.size))
+      NibblePack.unpackAllToSink(new UnsafeBuffer(buf, 0, len), sink, inputs.size) shouldBe a[NibblePack.InputTooShort]
+      val truncated = new UnsafeBuffer(buf, 0, len)
+      NibbleBlocks.blocks(truncated).foreach(_ => ())
+      NibbleBlocks.parse(truncated, len) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    }
+  }
+
+  it("should return detailed errors when unpacking truncated or malformed input") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+
+    // Chop off the last byte: the second block no longer has all of its bytes
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten - 1), inputs.size) match {
+      case Left(NibblePack.InputTooShort(needed, got)) => needed should be > got
+      case other: Any                                   => fail(s"Unexpected result $other")
+    }
+
+    // Cut off after the first block: the values of the second are missing, not zero
+    val firstBlockBytes = NibblePack.blockSize(buf, 0)
+    NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, firstBlockBytes), inputs.size) shouldEqual
+      Left(NibblePack.InputTooShort(1, 0))
+
+    // Only the bitmask byte of a nonzero block
+    NibblePack.unpack8(new UnsafeBuffer(Array[Byte](0x01)), NibblePack.DeltaSink(new Array[Long](8))) shouldEqual
+      NibblePack.InputTooShort(2, 1)
+
+    // 16 nibbles plus 15 trailing zero nibbles cannot fit in 64 bits
+    val badWidth = Array[Byte](0x01, 0xff.toByte, 0, 0, 0, 0, 0, 0, 0, 0)
+    NibblePack.unpack8(new UnsafeBuffer(badWidth), NibblePack.DeltaSink(new Array[Long](8))) shouldEqual
+      NibblePack.InvalidNibbleWidth(31)
+  }
+
+  it("should return InvalidNibbleWidth for a hand-crafted header one nibble too wide") {
+    // 16 nibbles plus 1 trailing zero nibble, with enough bytes after it for 16 nibbles
+    val header = Array[Byte](0x01, 0xf1.toByte) ++ Array.fill(8)(0x11.toByte)
+    val outArray = Array.fill(8)(-1L)
+    NibblePack.unpack8(new UnsafeBuffer(header), NibblePack.DeltaSink(outArray)) shouldEqual
+      NibblePack.InvalidNibbleWidth(17)
+    outArray shouldEqual Array.fill(8)(-1L)
+    NibblePack.unpackDelta(new UnsafeBuffer(header), 8) shouldEqual Left(NibblePack.InvalidNibbleWidth(17))
+
+    // 16 nibbles with no trailing zeroes is the widest valid block
+    val widest = Array[Byte](0x01, 0xf0.toByte) ++ Array.fill(8)(0x11.toByte)
+    NibblePack.unpackDelta(new UnsafeBuffer(widest), 1).right.get shouldEqual Array(0x1111111111111111L)
+  }
+
+  it("should give every kind of result a distinct, stable errorCode") {
+    import NibblePack._
+    val results = Seq(Ok, InputTooShort(2, 1), InvalidNibbleWidth(17), UnexpectedFormat(3),
+                      InvalidHeader("numValues", -1), NonIncreasing(4), SchemaMismatch,
+                      ChecksumMismatch(1, 2), OutputTooSmall(9, 8), AccumulatorOverflow(3),
+                      UnsupportedVersion(2), LossyConversion(5), InvalidParameter("multiplier", 1.0),
+                      ImplausibleCount(100, 8), CounterReset(3, 10, 2),
+                      InvalidGeometry("multiplier", 0.5), IndexOutOfRange(8, 9, 8))
+    results.map(_.errorCode) shouldEqual Seq(0, -1, -2, -3, -4, -5, -6, -7, -8, -9, -10, -11, -12, -13, -14, -15, -16)
+  }
+
+  it("should refuse counts that a tiny buffer could never hold instead of allocating for them") {
+    val tiny = Array[Byte](0, 0)
+    NibblePack.unpackDelta(new UnsafeBuffer(tiny), 2000000000) shouldEqual
+      Left(NibblePack.ImplausibleCount(2000000000, 16))
+    NibblePack.unpackDeltaFromBytes(tiny, Int.MaxValue) shouldEqual Left(NibblePack.ImplausibleCount(Int.MaxValue, 16))
+    new DecodeContext().unpackDelta(new UnsafeBuffer(tiny), 17) shouldEqual Left(NibblePack.ImplausibleCount(17, 16))
+    NibblePack.unpackDelta(new UnsafeBuffer(tiny), -1) shouldEqual Left(NibblePack.ImplausibleCount(-1, 16))
+    NibblePack.unpackDelta(new UnsafeBuffer(tiny), 16).right.get shouldEqual new Array[Long](16)
+
+    NibbleFormat.checkCount(NibbleFormat.MaxDecodeValues + 1, Int.MaxValue) shouldEqual
+      Some(NibblePack.ImplausibleCount(NibbleFormat.MaxDecodeValues + 1, NibbleFormat.MaxDecodeValues))
+    NibbleFormat.checkCount(NibbleFormat.MaxDecodeValues, Int.MaxValue) shouldEqual None
+  }
+
+  it("should unpack delta values using the count embedded by packDeltaCounted") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+    buf.getByte(0) shouldEqual NibbleFormat.Format_Delta_Counted
+    NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, bytesWritten)).right.get shouldEqual inputs
+
+    val emptyBytes = NibblePack.packDeltaCounted(Array.empty[Long], buf, 0)
+    NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, emptyBytes)).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should return errors from unpackDeltaCounted for malformed input") {
+    val inputs = (0 until 20).map(_ * 1000L).toArray
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+
+    // The last block is missing
+    val truncated = NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, bytesWritten - 1))
+    truncated.left.get shouldBe a[NibblePack.InputTooShort]
+    NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, 3)) shouldEqual Left(NibblePack.InputTooShort(4, 2))
+
+    // A count which could not possibly fit in the remaining bytes
+    buf.putInt(1, 1000000, java.nio.ByteOrder.LITTLE_ENDIAN)
+    NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual
+      Left(NibblePack.InvalidHeader("numValues", 1000000))
+    buf.putInt(1, -1, java.nio.ByteOrder.LITTLE_ENDIAN)
+    NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual
+      Left(NibblePack.InvalidHeader("numValues", -1))
+  }
+
+  it("should unpack counted delta values into an array provided by the caller") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+
+    val out = Array.fill(20)(-1L)
+    NibblePack.unpackDeltaCountedInto(new UnsafeBuffer(buf, 0, bytesWritten), out) shouldEqual Right(inputs.size)
+    out.take(inputs.size) shouldEqual inputs
+    // The rest of the array, including where the last block is padded, is left alone
+    out.drop(inputs.size).forall(_ == -1L) shouldEqual true
+
+    NibblePack.unpackDeltaCountedInto(new UnsafeBuffer(buf, 0, bytesWritten), new Array[Long](11)) shouldEqual
+      Left(NibblePack.OutputTooSmall(12, 11))
+  }
+
+  it("should unpack counted delta values from bytes into an array, returning only an Int") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+    // Longer than the stream, as a binding reusing its byte array would pass
+    val bytes = java.util.Arrays.copyOf(buf.byteArray, bytesWritten + 10)
+
+    val out = new Array[Long](16)
+    NibblePack.unpackDeltaCountedFromBytes(bytes, bytesWritten, out) shouldEqual inputs.size
+    out.take(inputs.size) shouldEqual inputs
+
+    NibblePack.unpackDeltaCountedFromBytes(bytes, bytesWritten, new Array[Long](11)) shouldEqual
+      NibblePack.OutputTooSmall(12, 11).errorCode
+    val tooShort = NibblePack.InputTooShort(0, 0).errorCode
+    NibblePack.unpackDeltaCountedFromBytes(bytes, bytesWritten - 1, out) shouldEqual tooShort
+    NibblePack.unpackDeltaCountedFromBytes(bytes, bytes.size + 1, out) shouldEqual tooShort
+    NibblePack.unpackDeltaCountedFromBytes(bytes, 0, out) shouldEqual tooShort
+  }
+
+  it("should report the bytes consumed so that back to back streams can be unpacked one after the other") {
+    val first = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val second = Array(5L, 10L, 15L)
+    val buf = new ExpandableArrayBuffer()
+    val firstBytes = NibblePack.packDeltaCounted(first, buf, 0)
+    val endPos = NibblePack.packDeltaCounted(second, buf, firstBytes)
+
+    val compressed = new UnsafeBuffer(buf, 0, endPos)
+    val out = new Array[Long](16)
+    NibblePack.unpackDeltaCountedConsumed(compressed, out) shouldEqual Right(NibblePack.Consumed(12, firstBytes))
+    out.take(12) shouldEqual first
+    NibblePack.unpackDeltaCountedConsumed(compressed, out) shouldEqual
+      Right(NibblePack.Consumed(3, endPos - firstBytes))
+    out.take(3) shouldEqual second
+    compressed.capacity shouldEqual 0
+    NibblePack.unpackDeltaCountedConsumed(compressed, out) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should lazily iterate over delta values with UnpackIterator") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+
+    new NibblePack.UnpackIterator(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size).toSeq shouldEqual inputs.toSeq
+
+    // Only the first block needs to be unpacked for this
+    val bufSlice = new UnsafeBuffer(buf, 0, bytesWritten)
+    val it = new NibblePack.UnpackIterator(bufSlice, inputs.size)
+    it.filter(_ > 1000).take(3).toList shouldEqual List(1001, 1002, 1003)
+    bufSlice.capacity should be < bytesWritten
+
+    // Truncated input stops the iteration and reports the error
+    val truncIt = new NibblePack.UnpackIterator(new UnsafeBuffer(buf, 0, bytesWritten - 1), inputs.size)
+    truncIt.toList shouldEqual inputs.take(8).toList
+    truncIt.unpackResult shouldBe a[NibblePack.InputTooShort]
+  }
+
+  it("should scale and offset values from UnpackIterator like unpacking and then mapping") {
+    val buf = new ExpandableArrayBuffer()
+    def iterator(inputs: Array[Long]): NibblePack.UnpackIterator =
+      new NibblePack.UnpackIterator(new UnsafeBuffer(buf, 0, NibblePack.packDelta(inputs, buf, 0)), inputs.size)
+
+    val fixedPoint = Array(1250L, 1300L, 1300L, 1475L, 2000L, 2001L, 2002L, 2500L, 3999L, 4000L)
+    iterator(fixedPoint).mapScale(0.01).toList shouldEqual fixedPoint.map(_ * 0.01).toList
+    iterator(fixedPoint).mapOffset(-1000L).toList shouldEqual fixedPoint.map(_ - 1000L).toList
+
+    forAll { (longs: Seq[Long], factor: Double, delta: Long) =>
+      whenever(!factor.isNaN) {
+        val inputs = longs.map(_ & 0xfffffL).sorted.toArray
+        val baseline = NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, NibblePack.packDelta(inputs, buf, 0)),
+                                              inputs.size).right.get
+        iterator(inputs).mapScale(factor).toList shouldEqual baseline.map(_ * factor).toList
+        iterator(inputs).mapOffset(delta).toList shouldEqual baseline.map(_ + delta).toList
+      }
+    }
+  }
+
+  it("should pack and unpack empty inputs with every codec") {
+    val buf = new ExpandableArrayBuffer()
+    def slice(numBytes: Int): UnsafeBuffer = new UnsafeBuffer(buf, 0, numBytes)
+
+    // Raw formats write nothing at all, exactly as NibblePack writes no partial blocks
+    NibblePack.packNonIncreasing(Array.empty[Long], buf, 0) shouldEqual 0
+    NibblePack.packDelta(Array.empty[Long], buf, 0) shouldEqual 0
+    NibblePack.packDoubles(Array.empty[Double], buf, 0) shouldEqual 0
+    NibblePack.unpackToSink(slice(0), NibblePack.DeltaSink(Array.empty[Long]), 0) shouldEqual NibblePack.Ok
+    NibblePack.unpackDelta(slice(0), 0).right.get shouldEqual Array.empty[Long]
+    NibblePack.unpackDoubleXOR(slice(0), Array.empty[Double]) shouldEqual NibblePack.Ok
+    new NibblePack.UnpackIterator(slice(0), 0).hasNext shouldEqual false
+
+    // Self describing formats write just their header
+    val int32Bytes = NibblePack32.pack(Array.empty[Int], buf, 0)
+    int32Bytes shouldEqual 1
+    NibblePack32.unpack(slice(int32Bytes), Array.empty[Int]) shouldEqual NibblePack.Ok
+    val signedBytes = NibblePackSigned.packDelta(Array.empty[Long], buf, 0)
+    signedBytes shouldEqual 1
+    NibblePackSigned.unpackDelta(slice(signedBytes), Array.empty[Long]) shouldEqual NibblePack.Ok
+    val xorBytes = DoubleXORPack.pack(Array.empty[Double], buf, 0)
+    xorBytes shouldEqual 1
+    DoubleXORPack.unpack(slice(xorBytes), Array.empty[Double]) shouldEqual NibblePack.Ok
+  }
+
   it("should pack and unpack double values") {
     val inputs = Array(0.0, 2.5, 5.0, 7.5, 8, 13.2, 18.9, 89, 101.1, 102.3)
     val buf = new ExpandableArrayBuffer()
@@ -234,6 +652,69 @@ class NibblePackTest extends FunSpec with Matchers with PropertyChecks {
     }
   }
 
+  // Full range Longs in runs of repeats, so that zero blocks, constant blocks and every width of block show up,
+  // and with lengths which are usually not a multiple of 8
+  def mixedLongList: Gen[Seq[Long]] = {
+    val run = for {
+      value     <- Gen.oneOf(Gen.const(0L), Gen.choose(0L, 255L), Gen.choose(Long.MinValue, Long.MaxValue))
+      runLength <- Gen.choose(1, 20)
+    } yield Seq.fill(runLength)(value)
+    Gen.containerOf[Seq, Seq[Long]](run).map(_.flatten)
+  }
+
+  def increasingRunsList: Gen[Seq[Long]] = mixedLongList.map(_.map(_ & 0x0ffffffffL).scanLeft(0L)(_ + _).drop(1))
+
+  it("should never pack incompressible values larger than raw plus the block headers") {
+    val buf = new ExpandableArrayBuffer()
+    val random = new scala.util.Random(7)
+    Seq(1, 8, 63, 1000).foreach { n =>
+      // high bit always set, so that every value needs all 16 nibbles
+      val inputs = Array.fill(n)(random.nextLong | Long.MinValue)
+      val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+      val numBlocks = (n + 7) / 8
+      bytesWritten should be <= (n * 8 + 2 * numBlocks)
+      val sink = new NibbleSinks.BufferSink(n)
+      NibblePack.unpackAllToSink(new UnsafeBuffer(buf, 0, bytesWritten), sink, n) shouldEqual NibblePack.Ok
+      sink.values.toArray shouldEqual inputs
+    }
+
+    forAll { (longs: Seq[Long]) =>
+      NibblePack.packNonIncreasing(longs.toArray, buf, 0) should be <= (longs.size * 8 + 2 * ((longs.size + 7) / 8))
+    }
+  }
+
+  it("should pack and unpack random full range Longs with packNonIncreasing and Packer") {
+    val buf = new ExpandableArrayBuffer()
+    val buf2 = new ExpandableArrayBuffer()
+    forAll(mixedLongList) { longs =>
+      val inputs = longs.toArray
+      val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+      val sink = new NibbleSinks.BufferSink(inputs.size)
+      NibblePack.unpackAllToSink(new UnsafeBuffer(buf, 0, bytesWritten), sink, inputs.size) shouldEqual NibblePack.Ok
+      sink.values.toArray shouldEqual inputs
+
+      val packer = new NibblePack.Packer(buf2, 0)
+      inputs.foreach(packer.add)
+      packer.finish() shouldEqual bytesWritten
+      (0 until bytesWritten).foreach { i => buf2.getByte(i) shouldEqual buf.getByte(i) }
+    }
+  }
+
+  it("should unpack random increasing Longs with runs the same way with every delta decoder") {
+    val buf = new ExpandableArrayBuffer()
+    forAll(increasingRunsList) { longs =>
+      val inputs = longs.toArray
+      val bytesWritten = NibblePack.packDeltaChecked(inputs, buf, 0).right.get
+      def slice: UnsafeBuffer = new UnsafeBuffer(buf, 0, bytesWritten)
+      NibblePack.unpackDelta(slice, inputs.size).right.get shouldEqual inputs
+      new NibblePack.UnpackIterator(slice, inputs.size).toArray shouldEqual inputs
+      NibbleAggregations.sumDelta(slice, inputs.size) shouldEqual Right(inputs.sum)
+
+      val countedBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+      NibblePack.unpackDeltaCounted(new UnsafeBuffer(buf, 0, countedBytes)).right.get shouldEqual inputs
+    }
+  }
+
   def increasingDoubleList: Gen[Seq[Double]] = increasingLongList.map(_.map(_.toDouble)).filter(_.length > 0)
 
   it("should pack and unpack random list of increasing Doubles via XOR") {
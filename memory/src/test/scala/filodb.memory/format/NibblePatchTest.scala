@@ -0,0 +1,71 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibblePatchTest extends FunSpec with Matchers with PropertyChecks {
+  import NibblePack.{InvalidHeader, InvalidParameter}
+
+  val oldBuf = new ExpandableArrayBuffer()
+  val newBuf = new ExpandableArrayBuffer()
+  val patchBuf = new ExpandableArrayBuffer()
+  val outBuf = new ExpandableArrayBuffer()
+
+  def bytes(buf: DirectBuffer, numBytes: Int): Seq[Byte] = (0 until numBytes).map(buf.getByte)
+
+  // Checks that applying the diff of old and updated to old gives the same bytes as packing updated
+  def checkPatch(old: Array[Long], updated: Array[Long]): Int = {
+    val oldSlice = new UnsafeBuffer(oldBuf, 0, NibbleAuto.packAuto(old, oldBuf, 0))
+    val newBytes = NibbleAuto.packAuto(updated, newBuf, 0)
+    val patchBytes = NibblePatch.diff(oldSlice, new UnsafeBuffer(newBuf, 0, newBytes), patchBuf, 0).right.get
+    val outBytes = NibblePatch.applyPatch(oldSlice, new UnsafeBuffer(patchBuf, 0, patchBytes), outBuf, 0).right.get
+    bytes(outBuf, outBytes) shouldEqual bytes(newBuf, newBytes)
+    patchBytes
+  }
+
+  val counter = Array.tabulate(200)(i => 10000L + i * 15 + i % 7)
+
+  it("should patch single changes, many changes, appends and truncations") {
+    val single = counter.clone
+    single(100) += 3
+    checkPatch(counter, single) should be < NibbleAuto.packAuto(single, newBuf, 0) / 4
+    checkPatch(counter, counter)
+
+    val many = counter.zipWithIndex.map { case (v, i) => if (i % 3 == 0) v - 1000 else v }
+    checkPatch(counter, many)
+    checkPatch(counter, counter ++ Array(20000L, 20010L, 19000L))
+    checkPatch(counter, counter.take(150))
+    checkPatch(Array.empty[Long], counter)
+    checkPatch(counter, Array.empty[Long])
+  }
+
+  it("should patch random edits of random lists") {
+    forAll { (old: List[Long], updated: List[Long]) =>
+      checkPatch(old.toArray, updated.toArray)
+      checkPatch(old.toArray, (old ++ updated).toArray)
+    }
+  }
+
+  it("should return errors for a patch made from different old values or with bad changes") {
+    val patchBytes = NibblePatch.diffValues(Array(1L, 2L, 3L), Array(1L, 5L, 3L, 4L), patchBuf, 0)
+    val patch = new UnsafeBuffer(patchBuf, 0, patchBytes)
+    NibblePatch.patchValues(Array(9L, 9L, 9L), new UnsafeBuffer(patch)).right.get shouldEqual Array(9L, 5L, 9L, 4L)
+    NibblePatch.patchValues(Array(1L, 2L), new UnsafeBuffer(patch)) shouldEqual Left(InvalidParameter("old", 2))
+
+    // New counts past the changed indices leave appended values without one
+    patchBuf.putInt(2, 6, LITTLE_ENDIAN)
+    NibblePatch.patchValues(Array(1L, 2L, 3L), new UnsafeBuffer(patch)) shouldEqual
+      Left(InvalidHeader("numValues", 6))
+    patchBuf.putInt(2, 5, LITTLE_ENDIAN)
+    NibblePatch.patchValues(Array(1L, 2L, 3L), new UnsafeBuffer(patch)) shouldEqual
+      Left(InvalidHeader("numChanges", 2))
+    patchBuf.putInt(2, 1, LITTLE_ENDIAN)
+    NibblePatch.patchValues(Array(1L, 2L, 3L), new UnsafeBuffer(patch)) shouldEqual
+      Left(InvalidHeader("numChanges", 2))
+  }
+}
@@ -0,0 +1,121 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleRecoveryTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+  val inputs = Array.tabulate(40)(i => 10000L + i * 997 + (i % 5) * 3)
+  val numBytes = NibblePack.packDelta(inputs, buf, 0)
+
+  // The position of the end of each block, from NibbleBlocks
+  val blockEnds = NibbleBlocks.blocks(new UnsafeBuffer(buf, 0, numBytes)).map(_.right.get)
+                              .map(b => b.byteOffset + b.numBytes).toIndexedSeq
+
+  it("should unpack an undamaged stream like unpackDelta, with no error") {
+    val (values, error) = NibbleRecovery.unpackLenient(new UnsafeBuffer(buf, 0, numBytes), inputs.size)
+    values shouldEqual inputs
+    error shouldEqual None
+  }
+
+  it("should recover the blocks before the point where a stream was truncated") {
+    // Part way into the third block
+    val (values, error) = NibbleRecovery.unpackLenient(new UnsafeBuffer(buf, 0, blockEnds(1) + 3), inputs.size)
+    values shouldEqual inputs.take(16)
+    error.get shouldBe a[NibblePack.InputTooShort]
+
+    (0 to numBytes).foreach { end =>
+      val (prefix, err) = NibbleRecovery.unpackLenient(new UnsafeBuffer(buf, 0, end), inputs.size)
+      prefix shouldEqual inputs.take(8 * blockEnds.count(_ <= end))
+      err.isDefined shouldEqual (end < numBytes)
+    }
+  }
+
+  it("should recover the blocks before a malformed block") {
+    val corrupt = new ExpandableArrayBuffer()
+    corrupt.putBytes(0, buf, 0, numBytes)
+    // 16 nibbles plus 15 trailing nibbles cannot fit in a Long
+    corrupt.putByte(blockEnds(2), 0x01.toByte)
+    corrupt.putByte(blockEnds(2) + 1, 0xff.toByte)
+    val (values, error) = NibbleRecovery.unpackLenient(new UnsafeBuffer(corrupt, 0, numBytes), inputs.size)
+    values shouldEqual inputs.take(24)
+    error shouldEqual Some(NibblePack.InvalidNibbleWidth(31))
+  }
+
+  it("should stop just before a total which overflows") {
+    val deltaBytes = NibblePack.packNonIncreasing(Array(10L, 20L, Long.MaxValue, 5L), buf, 0)
+    NibbleRecovery.unpackLenient(new UnsafeBuffer(buf, 0, deltaBytes), 4) match {
+      case (values, error) =>
+        values shouldEqual Array(10L, 30L)
+        error shouldEqual Some(NibblePack.AccumulatorOverflow(2))
+    }
+    NibbleRecovery.unpackLenient(new UnsafeBuffer(buf, 0, 1), 100)._2.get shouldBe a[NibblePack.ImplausibleCount]
+  }
+
+  def copyOf(from: ExpandableArrayBuffer, numBytes: Int): ExpandableArrayBuffer = {
+    val copy = new ExpandableArrayBuffer()
+    copy.putBytes(0, from, 0, numBytes)
+    copy
+  }
+
+  it("should repair the count of a packDeltaCounted stream from its blocks") {
+    for { n <- Seq(37, 40, 0) } {
+      val counted = new ExpandableArrayBuffer()
+      val countedBytes = NibblePack.packDeltaCounted(inputs.take(n), counted, 0)
+      for { badCount <- Seq(999999, 3, -1) } {
+        val corrupt = copyOf(counted, countedBytes)
+        corrupt.putInt(NibbleFormat.CountOffset, badCount, LITTLE_ENDIAN)
+        val out = new ExpandableArrayBuffer()
+        val repairedBytes = NibbleRecovery.repair(new UnsafeBuffer(corrupt, 0, countedBytes), out, 0).right.get
+        repairedBytes shouldEqual countedBytes
+        NibblePack.unpackDeltaCounted(new UnsafeBuffer(out, 0, repairedBytes)).right.get shouldEqual inputs.take(n)
+      }
+    }
+  }
+
+  it("should repair the count, footer offset and skip table of a CompressedVec") {
+    val vecBuf = new ExpandableArrayBuffer()
+    val vecBytes = CompressedVec.encode(inputs.take(37), vecBuf, 0, blocksPerSkip = 2)
+    val corrupt = copyOf(vecBuf, vecBytes)
+    corrupt.putInt(CompressedVec.NumValuesOffset, 12345, LITTLE_ENDIAN)
+    corrupt.putInt(CompressedVec.FooterOffsetOffset, 3, LITTLE_ENDIAN)
+    corrupt.putInt(vecBytes - 4, -7, LITTLE_ENDIAN)
+    CompressedVec(new UnsafeBuffer(corrupt, 0, vecBytes)).isLeft shouldEqual true
+
+    val out = new ExpandableArrayBuffer()
+    val repairedBytes = NibbleRecovery.repair(new UnsafeBuffer(corrupt, 0, vecBytes), out, 0).right.get
+    (0 until repairedBytes).map(out.getByte) shouldEqual (0 until vecBytes).map(vecBuf.getByte)
+    val vec = CompressedVec(new UnsafeBuffer(out, 0, repairedBytes)).right.get
+    (0 until 37).map(i => vec.get(i).get) shouldEqual inputs.take(37).toSeq
+  }
+
+  it("should append a new checksum to a repaired stream which had one") {
+    val counted = new ExpandableArrayBuffer()
+    val checked = NibbleFormat.appendChecksum(counted, 0, NibblePack.packDeltaCounted(inputs, counted, 0))
+    counted.putInt(NibbleFormat.CountOffset, 7, LITTLE_ENDIAN)
+    NibbleFormat.verifyChecksum(new UnsafeBuffer(counted, 0, checked)) shouldBe a[NibblePack.ChecksumMismatch]
+
+    val out = new ExpandableArrayBuffer()
+    val repairedBytes = NibbleRecovery.repair(new UnsafeBuffer(counted, 0, checked), out, 0).right.get
+    val repaired = new UnsafeBuffer(out, 0, repairedBytes)
+    NibbleFormat.verifyChecksum(repaired) shouldEqual NibblePack.Ok
+    NibblePack.unpackDeltaCounted(repaired).right.get shouldEqual inputs
+  }
+
+  it("should not repair damaged blocks or other formats") {
+    val counted = new ExpandableArrayBuffer()
+    val countedBytes = NibblePack.packDeltaCounted(inputs, counted, 0)
+    val out = new ExpandableArrayBuffer()
+    NibbleRecovery.repair(new UnsafeBuffer(counted, 0, countedBytes - 1), out, 0).left.get shouldBe
+      a[NibblePack.InputTooShort]
+    val forBytes = NibbleFOR.packFOR(inputs, counted, 0)
+    NibbleRecovery.repair(new UnsafeBuffer(counted, 0, forBytes), out, 0) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_FOR))
+    NibbleRecovery.repair(new UnsafeBuffer(counted, 0, 0), out, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+}
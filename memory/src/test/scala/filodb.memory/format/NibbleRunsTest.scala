@@ -0,0 +1,82 @@
+package filodb.memory.format
+
+import java.nio.ByteOrder.LITTLE_ENDIAN
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.scalacheck.Gen
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleRunsTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Long]): Array[Long] = {
+    val bytesWritten = NibbleRuns.packDeltaRuns(inputs, buf, 0)
+    NibbleRuns.unpackDeltaRuns(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  it("should pack a counter flat for thousands of samples into a few bytes") {
+    val flat = Array.tabulate(10000) { i => if (i < 5003) 1000L else 2000L + (i - 5003) / 4000 }
+    roundTrip(flat) shouldEqual flat
+
+    // Three runs, each a segment of 8 bytes plus the block of the one nonzero delta before it
+    val bytesWritten = NibbleRuns.packDeltaRuns(flat, buf, 0)
+    bytesWritten shouldEqual NibbleRuns.HeaderBytes + (8 + 4) + (8 + 4) + (8 + 3)
+    bytesWritten should be < NibblePack.packDelta(flat, buf, 0) / 20
+  }
+
+  it("should round trip runs starting and ending anywhere within blocks") {
+    for { start <- 0 to 9
+          runLength <- Seq(NibbleRuns.MinRunValues - 1, NibbleRuns.MinRunValues, 100, 1001)
+          tail <- Seq(0, 1, 7, 8, 13) } {
+      val inputs = Array.tabulate(start)(i => i * 3L + 1) ++
+                   Array.fill(runLength)(start * 3L + 1) ++
+                   Array.tabulate(tail)(i => start * 3L + 5 + i * 11)
+      roundTrip(inputs) shouldEqual inputs
+    }
+  }
+
+  it("should round trip random counters with flat stretches") {
+    // Each piece is either a single step up, or a flat stretch of up to 300 zero deltas
+    val pieces = Gen.frequency((2, Gen.choose(1L, 5000L).map(Seq(_))),
+                               (1, Gen.choose(0, 300).map(n => Seq.fill(n)(0L))))
+    forAll(Gen.listOf(pieces)) { steps =>
+      val inputs = steps.flatten.scanLeft(0L)(_ + _).drop(1).toArray
+      roundTrip(inputs) shouldEqual inputs
+    }
+    roundTrip(Array.empty[Long]) shouldEqual Array.empty[Long]
+  }
+
+  it("should pack decreasing values as zero deltas, as packDelta does") {
+    val inputs = Array(5L, 10L, 3L, 7L) ++ Array.fill(100)(7L) ++ Array(2L) ++ Array.fill(100)(2L) ++ Array(50L)
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+    val expected = NibblePack.unpackDelta(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size).right.get
+    roundTrip(inputs) shouldEqual expected
+  }
+
+  it("should check the segments before allocating for the count") {
+    val inputs = Array.fill(500)(42L)
+    val bytesWritten = NibbleRuns.packDeltaRuns(inputs, buf, 0)
+    val truncated = new UnsafeBuffer(buf, 0, bytesWritten - 1)
+    NibbleRuns.unpackDeltaRuns(truncated).left.get shouldBe a[NibblePack.InputTooShort]
+    NibbleRuns.unpackDeltaRuns(new UnsafeBuffer(buf, 0, 3)) shouldEqual Left(NibblePack.InputTooShort(4, 2))
+
+    // The 16 bytes of the 500 values, claiming 50 million: the second segment header is missing
+    bytesWritten shouldEqual 16
+    buf.putInt(1, 50000000, LITTLE_ENDIAN)
+    NibbleRuns.unpackDeltaRuns(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual
+      Left(NibblePack.InputTooShort(15, 11))
+    buf.putInt(1, NibbleFormat.MaxDecodeValues + 1, LITTLE_ENDIAN)
+    NibbleRuns.unpackDeltaRuns(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual
+      Left(NibblePack.InvalidHeader("numValues", NibbleFormat.MaxDecodeValues + 1))
+    buf.putInt(1, 400, LITTLE_ENDIAN)
+    NibbleRuns.unpackDeltaRuns(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual
+      Left(NibblePack.InvalidHeader("runLength", 499))
+
+    val deltaBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+    NibbleRuns.unpackDeltaRuns(new UnsafeBuffer(buf, 0, deltaBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Delta_Counted))
+  }
+}
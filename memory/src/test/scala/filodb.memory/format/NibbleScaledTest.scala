@@ -0,0 +1,52 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleScaledTest extends FunSpec with Matchers with PropertyChecks {
+  import NibblePack.{InvalidHeader, InvalidParameter}
+
+  val buf = new ExpandableArrayBuffer()
+
+  def packed(values: Array[Double], scale: Int): UnsafeBuffer =
+    new UnsafeBuffer(buf, 0, NibbleScaled.packScaled(values, scale, buf, 0).right.get)
+
+  it("should round values to the scale, losing finer digits") {
+    NibbleScaled.unpackScaled(packed(Array(3.14159, -2.5, 0.0, 1e-4), 1000)).right.get shouldEqual
+      Array(3.142, -2.5, 0.0, 0.0)
+    NibbleScaled.unpackScaled(packed(Array(0.25, 7.0), 10)).right.get shouldEqual Array(0.3, 7.0)
+    NibbleScaled.unpackScaled(packed(Array.empty[Double], 1)).right.get shouldEqual Array.empty[Double]
+  }
+
+  it("should round values between two multiples of 1 / scale as asked") {
+    def unpacked(rounding: Rounding): Seq[Double] = {
+      val numBytes = NibbleScaled.packScaled(Array(0.25, -0.25, 0.21, 0.5), 10, buf, 0, rounding).right.get
+      NibbleScaled.unpackScaled(new UnsafeBuffer(buf, 0, numBytes)).right.get.toSeq
+    }
+    unpacked(Rounding.Nearest) shouldEqual Seq(0.3, -0.2, 0.2, 0.5)
+    unpacked(Rounding.Floor) shouldEqual Seq(0.2, -0.3, 0.2, 0.5)
+    unpacked(Rounding.Ceil) shouldEqual Seq(0.3, -0.2, 0.3, 0.5)
+    unpacked(Rounding.LinearInterp) shouldEqual unpacked(Rounding.Nearest)
+  }
+
+  it("should round trip values with no digits finer than the scale") {
+    forAll { (cents: List[Int]) =>
+      val values = cents.map(_ / 100.0).toArray
+      NibbleScaled.unpackScaled(packed(values, 100)).right.get shouldEqual values
+    }
+  }
+
+  it("should reject a bad scale or values which do not fit a Long once scaled") {
+    NibbleScaled.packScaled(Array(1.0), 0, buf, 0) shouldEqual Left(InvalidParameter("scale", 0))
+    NibbleScaled.packScaled(Array(1.0, Double.NaN), 10, buf, 0).isLeft shouldEqual true
+    NibbleScaled.packScaled(Array(1e18), 10, buf, 0) shouldEqual Left(InvalidParameter("value", 1e18))
+    NibbleScaled.packScaled(Array(Double.NegativeInfinity), 1, buf, 0).isLeft shouldEqual true
+
+    val bytes = packed(Array(1.5), 10)
+    bytes.putInt(6, -1)
+    NibbleScaled.unpackScaled(bytes) shouldEqual Left(InvalidHeader("scale", -1))
+  }
+}
@@ -0,0 +1,190 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+import org.scalacheck.Gen
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleSelectTest extends FunSpec with Matchers with PropertyChecks {
+  import NibbleSelect._
+
+  val buf = new ExpandableArrayBuffer()
+  val inputs = Array.tabulate(100)(i => i * 1000L + (i % 7))
+
+  it("should unpack only the selected indices of a non-delta vector") {
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, bytesWritten)
+    val indices = Array(0, 7, 8, 8, 50, 99)
+    unpackIndices(slice, inputs.size, indices).right.get shouldEqual indices.map(inputs)
+    unpackIndices(slice, inputs.size, Array.empty[Int]).right.get shouldEqual Array.empty[Long]
+    slice.capacity shouldEqual bytesWritten     // not mutated
+  }
+
+  it("should reconstruct the running sum at the selected indices of a delta vector") {
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, bytesWritten)
+    val indices = Array(3, 42, 42, 97, 99)
+    unpackDeltaIndices(slice, inputs.size, indices).right.get shouldEqual indices.map(inputs)
+  }
+
+  it("should select the same values as a full unpack for random vectors and indices") {
+    val vectorsAndIndices = for {
+      longs   <- Gen.nonEmptyListOf(Gen.choose(0L, Long.MaxValue / 1000))
+      indices <- Gen.listOf(Gen.choose(0, longs.size - 1))
+    } yield (longs.toArray, indices.sorted.toArray)
+    forAll(vectorsAndIndices) { case (longs, indices) =>
+      val written = NibblePack.packNonIncreasing(longs, buf, 0)
+      unpackIndices(new UnsafeBuffer(buf, 0, written), longs.size, indices).right.get shouldEqual indices.map(longs)
+
+      val increasing = longs.scanLeft(0L)(_ + _ % 1000000).tail
+      val deltaWritten = NibblePack.packDelta(increasing, buf, 0)
+      unpackDeltaIndices(new UnsafeBuffer(buf, 0, deltaWritten), longs.size, indices).right.get shouldEqual
+        indices.map(increasing)
+    }
+  }
+
+  it("should only need the blocks up to the last selected index") {
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    val firstBlock = NibblePack.blockSize(buf, 0)
+    unpackIndices(new UnsafeBuffer(buf, 0, firstBlock), inputs.size, Array(1, 6)).right.get shouldEqual
+      Array(inputs(1), inputs(6))
+    unpackIndices(new UnsafeBuffer(buf, 0, bytesWritten - 1), inputs.size, Array(1, 99)).left.get shouldBe
+      a[NibblePack.InputTooShort]
+  }
+
+  it("should return the first and last values of a delta vector") {
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+    lastValueDelta(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size) shouldEqual Right(inputs.last)
+    lastValueDelta(new UnsafeBuffer(buf, 0, bytesWritten), 50) shouldEqual Right(inputs(49))
+    firstValue(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual Right(inputs.head)
+
+    // only the first block is needed for the first value
+    val lateStart = Array(12345L) ++ inputs.map(_ + 12345L)
+    val lateBytes = NibblePack.packDelta(lateStart, buf, 0)
+    firstValue(new UnsafeBuffer(buf, 0, NibblePack.blockSize(buf, 0))) shouldEqual Right(12345L)
+    lastValueDelta(new UnsafeBuffer(buf, 0, lateBytes), lateStart.size) shouldEqual Right(lateStart.last)
+
+    lastValueDelta(new UnsafeBuffer(buf, 0, lateBytes), 0) shouldEqual Left(NibblePack.InvalidHeader("numValues", 0))
+    firstValue(new UnsafeBuffer(buf, 0, 0)) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    lastValueDelta(new UnsafeBuffer(buf, 0, lateBytes - 1), lateStart.size).left.get shouldBe
+      a[NibblePack.InputTooShort]
+  }
+
+  it("should unpack a range of a vector the same as slicing a full unpack") {
+    val ranges = for {
+      longs <- Gen.nonEmptyListOf(Gen.choose(0L, Long.MaxValue / 1000))
+      start <- Gen.choose(0, longs.size)
+      end   <- Gen.choose(start, longs.size)
+    } yield (longs.toArray, start, end)
+    forAll(ranges) { case (longs, start, end) =>
+      val written = NibblePack.packNonIncreasing(longs, buf, 0)
+      unpackRange(new UnsafeBuffer(buf, 0, written), longs.size, start, end).right.get shouldEqual
+        longs.slice(start, end)
+
+      val increasing = longs.scanLeft(0L)(_ + _ % 1000000).tail
+      val deltaWritten = NibblePack.packDelta(increasing, buf, 0)
+      unpackDeltaRange(new UnsafeBuffer(buf, 0, deltaWritten), longs.size, start, end).right.get shouldEqual
+        increasing.slice(start, end)
+    }
+  }
+
+  it("should only need the blocks up to the end of a range") {
+    val bytesWritten = NibblePack.packNonIncreasing(inputs, buf, 0)
+    val firstTwoBlocks = NibblePack.blockSize(buf, 0) + NibblePack.blockSize(buf, NibblePack.blockSize(buf, 0))
+    unpackRange(new UnsafeBuffer(buf, 0, firstTwoBlocks), inputs.size, 5, 16).right.get shouldEqual
+      inputs.slice(5, 16)
+    unpackRange(new UnsafeBuffer(buf, 0, bytesWritten - 1), inputs.size, 90, 100).left.get shouldBe
+      a[NibblePack.InputTooShort]
+    unpackRange(new UnsafeBuffer(buf, 0, 0), inputs.size, 50, 50).right.get shouldEqual Array.empty[Long]
+    unpackRange(new UnsafeBuffer(buf, 0, bytesWritten), inputs.size, 5, 4) shouldEqual
+      Left(NibblePack.IndexOutOfRange(5, 4, 100))
+    unpackDeltaRange(new UnsafeBuffer(buf, 0, bytesWritten), 100, -1, 10) shouldEqual
+      Left(NibblePack.IndexOutOfRange(-1, 10, 100))
+    unpackDeltaRange(new UnsafeBuffer(buf, 0, bytesWritten), 100, 0, 101) shouldEqual
+      Left(NibblePack.IndexOutOfRange(0, 101, 100))
+  }
+
+  it("should refuse unsorted or out of range indices") {
+    val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, bytesWritten)
+    unpackDeltaIndices(slice, inputs.size, Array(5, 4)) shouldEqual Left(NibblePack.IndexOutOfRange(5, 4, 100))
+    unpackDeltaIndices(slice, inputs.size, Array(100)) shouldEqual Left(NibblePack.IndexOutOfRange(100, 101, 100))
+    unpackIndices(slice, inputs.size, Array(3, -1)) shouldEqual Left(NibblePack.IndexOutOfRange(-1, 0, 100))
+  }
+
+  it("should return only the nonzero values of a sparse vector with their indices, in order") {
+    val rand = new scala.util.Random(7)
+    val sparse = Array.fill(1003) { if (rand.nextInt(10) == 0) 1L + rand.nextInt(100000) else 0L }
+    val written = NibblePack.packNonIncreasing(sparse, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, written)
+    val expected = sparse.zipWithIndex.collect { case (v, i) if v != 0 => (i, v) }
+    nonzero(slice, sparse.size).map(_.right.get).toSeq shouldEqual expected.toSeq
+    slice.capacity shouldEqual written     // not mutated
+
+    // constant blocks and the padding of the last block
+    val constant = Array.fill(12)(5L)
+    val constantWritten = NibblePack.packNonIncreasing(constant, buf, 0)
+    nonzero(new UnsafeBuffer(buf, 0, constantWritten), 12).map(_.right.get).toSeq shouldEqual
+      (0 until 12).map(i => (i, 5L))
+    nonzero(new UnsafeBuffer(buf, 0, 0), 0).hasNext shouldEqual false
+  }
+
+  it("should return the same nonzero values as a full unpack for random vectors") {
+    forAll(Gen.listOf(Gen.frequency(9 -> Gen.const(0L), 1 -> Gen.choose(1L, Long.MaxValue)))) { longs =>
+      val written = NibblePack.packNonIncreasing(longs.toArray, buf, 0)
+      nonzero(new UnsafeBuffer(buf, 0, written), longs.size).map(_.right.get).toList shouldEqual
+        longs.zipWithIndex.collect { case (v, i) if v != 0 => (i, v) }
+    }
+  }
+
+  it("should end nonzero with an error for a truncated vector") {
+    val written = NibblePack.packNonIncreasing(inputs, buf, 0)
+    val results = nonzero(new UnsafeBuffer(buf, 0, written - 1), inputs.size).toList
+    results.init.map(_.right.get) shouldEqual inputs.zipWithIndex.map(_.swap).filter(_._2 != 0).take(results.size - 1)
+    results.last.left.get shouldBe a[NibblePack.InputTooShort]
+  }
+
+  def naiveBitmap(values: Seq[Long], matches: Long => Boolean): Seq[Long] = {
+    val bitmap = new Array[Long]((values.size + 63) / 64)
+    values.zipWithIndex.foreach { case (v, i) => if (matches(v)) bitmap(i / 64) |= 1L << (i % 64) }
+    bitmap.toSeq
+  }
+
+  it("should return bitmaps of the values greater than, less than or equal to a threshold") {
+    val values = Array.tabulate(100)(i => (i % 10) * 100L)
+    val written = NibblePack.packNonIncreasing(values, buf, 0)
+    val slice = new UnsafeBuffer(buf, 0, written)
+    selectGt(slice, 100, 700).right.get.toSeq shouldEqual naiveBitmap(values, _ > 700)
+    selectLt(slice, 100, 100).right.get.toSeq shouldEqual naiveBitmap(values, _ < 100)
+    selectEq(slice, 100, 500).right.get.toSeq shouldEqual naiveBitmap(values, _ == 500)
+    selectEq(slice, 100, 500).right.get(0) shouldEqual ((1L << 5) | (1L << 15) | (1L << 25) | (1L << 35) |
+                                                        (1L << 45) | (1L << 55))
+    slice.capacity shouldEqual written     // not mutated
+
+    selectGt(slice, 101, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    selectGt(slice, -1, 0).left.get shouldBe a[NibblePack.ImplausibleCount]
+    selectGt(new UnsafeBuffer(buf, 0, 0), 0, 0).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should select the same positions as unpacking then comparing, for random vectors and thresholds") {
+    forAll { (longs: List[Long], threshold: Long) =>
+      val written = NibblePack.packNonIncreasing(longs.toArray, buf, 0)
+      def slice: UnsafeBuffer = new UnsafeBuffer(buf, 0, written)
+      selectGt(slice, longs.size, threshold).right.get.toSeq shouldEqual naiveBitmap(longs, _ > threshold)
+      selectLt(slice, longs.size, threshold).right.get.toSeq shouldEqual naiveBitmap(longs, _ < threshold)
+      longs.headOption.foreach { v =>
+        selectEq(slice, longs.size, v).right.get.toSeq shouldEqual naiveBitmap(longs, _ == v)
+      }
+
+      val increasing = longs.scanLeft(0L)(_ + (_ & 0x3ff)).tail
+      val deltaWritten = NibblePack.packDelta(increasing.toArray, buf, 0)
+      def deltaSlice: UnsafeBuffer = new UnsafeBuffer(buf, 0, deltaWritten)
+      val mid = increasing.lift(increasing.size / 2).getOrElse(0L)
+      selectGtDelta(deltaSlice, increasing.size, mid).right.get.toSeq shouldEqual naiveBitmap(increasing, _ > mid)
+      selectLtDelta(deltaSlice, increasing.size, mid).right.get.toSeq shouldEqual naiveBitmap(increasing, _ < mid)
+      selectEqDelta(deltaSlice, increasing.size, mid).right.get.toSeq shouldEqual naiveBitmap(increasing, _ == mid)
+    }
+  }
+}
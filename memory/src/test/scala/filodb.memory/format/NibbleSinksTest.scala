@@ -0,0 +1,80 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleSinksTest extends FunSpec with Matchers with PropertyChecks {
+  import NibbleSinks._
+
+  val buf = new ExpandableArrayBuffer()
+  // 11 values so the last block is padded
+  val inputs = Array(0L, 1000, 1001, 1001, 1003, 2005, 2005, 3034, 4045, 5056, 6067)
+  val deltas = inputs.zip(0L +: inputs).map { case (a, b) => a - b }
+  val bytesWritten = NibblePack.packDelta(inputs, buf, 0)
+
+  def unpackTo(sink: NibblePack.Sink): Unit =
+    NibblePack.unpackToSink(new UnsafeBuffer(buf, 0, bytesWritten), sink, inputs.size) shouldEqual NibblePack.Ok
+
+  it("should append the raw values to a Buffer with BufferSink, ignoring block padding") {
+    val sink = new BufferSink(inputs.size)
+    unpackTo(sink)
+    sink.values.toArray shouldEqual deltas
+
+    sink.reset()
+    unpackTo(sink)
+    sink.values.toArray shouldEqual deltas
+  }
+
+  it("should sum up raw values with SumSink") {
+    val sink = new SumSink(inputs.size)
+    unpackTo(sink)
+    // the sum of the deltas is the last value
+    sink.sum shouldEqual inputs.last
+
+    sink.reset()
+    sink.sum shouldEqual 0L
+  }
+
+  it("should count nonzero values with CountNonZeroSink") {
+    val sink = new CountNonZeroSink(inputs.size)
+    unpackTo(sink)
+    sink.count shouldEqual deltas.count(_ != 0)
+  }
+
+  it("should unpack deltas as Doubles with DoubleDeltaSink, the same as DeltaSink") {
+    val outArray = new Array[Double](inputs.size)
+    val doubleSink = new DoubleDeltaSink(outArray, inputs.size)
+    unpackTo(doubleSink)
+    val longs = new Array[Long](inputs.size)
+    unpackTo(NibblePack.DeltaSink(longs))
+    outArray shouldEqual longs.map(_.toDouble)
+    outArray shouldEqual inputs.map(_.toDouble)
+
+    val larger = new Array[Double](inputs.size + 2)
+    unpackTo(new DoubleDeltaSink(larger, inputs.size))
+    larger.take(inputs.size) shouldEqual outArray
+    larger.drop(inputs.size) shouldEqual Array(0.0, 0.0)
+
+    // every integer up to 2^53 converts exactly
+    val big = Array(0L, 1L << 52, (1L << 53) - 1, 1L << 53)
+    val bigBuf = new ExpandableArrayBuffer()
+    val written = NibblePack.packDelta(big, bigBuf, 0)
+    val bigOut = new Array[Double](big.size)
+    val bigSink = new DoubleDeltaSink(bigOut, big.size)
+    NibblePack.unpackToSink(new UnsafeBuffer(bigBuf, 0, written), bigSink, big.size) shouldEqual NibblePack.Ok
+    bigOut.map(_.toLong) shouldEqual big
+  }
+
+  it("should sum random lists of Longs the same as summing the unpacked values") {
+    forAll { (longs: Seq[Long]) =>
+      val inputs = longs.toArray
+      val written = NibblePack.packNonIncreasing(inputs, buf, 0)
+      val sink = new SumSink(inputs.size)
+      NibblePack.unpackToSink(new UnsafeBuffer(buf, 0, written), sink, inputs.size) shouldEqual NibblePack.Ok
+      sink.sum shouldEqual inputs.sum
+    }
+  }
+}
@@ -0,0 +1,156 @@
+package filodb.memory.format
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleSpliceTest extends FunSpec with Matchers with PropertyChecks {
+  val aBuf = new ExpandableArrayBuffer()
+  val bBuf = new ExpandableArrayBuffer()
+  val outBuf = new ExpandableArrayBuffer()
+  val expectedBuf = new ExpandableArrayBuffer()
+
+  // Lengths which line up with blocks, and ones which force b to be realigned
+  val lengths = Seq(0, 1, 7, 8, 9, 16, 21)
+
+  def bytes(buf: DirectBuffer, numBytes: Int): Seq[Byte] = (0 until numBytes).map(buf.getByte)
+
+  def checkConcat(a: Array[Long], b: Array[Long], isDelta: Boolean): Unit = {
+    val pack = (in: Array[Long], buf: ExpandableArrayBuffer) =>
+      if (isDelta) NibblePack.packDelta(in, buf, 0) else NibblePack.packNonIncreasing(in, buf, 0)
+    val aSlice = new UnsafeBuffer(aBuf, 0, pack(a, aBuf))
+    val bSlice = new UnsafeBuffer(bBuf, 0, pack(b, bBuf))
+    val expectedBytes = pack(a ++ b, expectedBuf)
+
+    val result = if (isDelta) NibbleSplice.concatDelta(aSlice, a.size, bSlice, b.size, outBuf, 0)
+                 else NibbleSplice.concat(aSlice, a.size, bSlice, b.size, outBuf, 0)
+    val numBytes = result.right.get
+    bytes(outBuf, numBytes) shouldEqual bytes(expectedBuf, expectedBytes)
+    aSlice.capacity shouldEqual pack(a, aBuf)     // not mutated
+  }
+
+  it("should concatenate streams from packNonIncreasing with every alignment") {
+    for { numA <- lengths; numB <- lengths } {
+      checkConcat(Array.tabulate(numA)(i => -5L * i), Array.tabulate(numB)(i => i * 1000L + 7), false)
+    }
+  }
+
+  it("should concatenate streams from packDelta with every alignment, rebasing the first delta of b") {
+    for { numA <- lengths; numB <- lengths } {
+      val a = Array.tabulate(numA)(i => 100L + i * 10)
+      checkConcat(a, Array.tabulate(numB)(i => 5000L + i * 1000), true)
+      // b starts lower than the end of a, which packDelta packs as a 0 delta
+      checkConcat(a, Array.tabulate(numB)(i => 50L + i * 3), true)
+    }
+  }
+
+  it("should return errors for streams with fewer values than given") {
+    val aBytes = NibblePack.packNonIncreasing(Array.tabulate(16)(_.toLong), aBuf, 0)
+    val bBytes = NibblePack.packNonIncreasing(Array(1L, 2L, 3L), bBuf, 0)
+    val aSlice = new UnsafeBuffer(aBuf, 0, aBytes)
+    val bSlice = new UnsafeBuffer(bBuf, 0, bBytes)
+    NibbleSplice.concat(aSlice, 24, bSlice, 3, outBuf, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    NibbleSplice.concat(aSlice, 16, bSlice, 9, outBuf, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    NibbleSplice.concat(aSlice, 19, bSlice, 3, outBuf, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should truncate streams to the first values the same as packing only those") {
+    val inputs = Array.tabulate(21)(i => 100L + i * 10)
+    val bytesWritten = NibblePack.packDelta(inputs, aBuf, 0)
+    val slice = new UnsafeBuffer(aBuf, 0, bytesWritten)
+    for { keep <- 0 to inputs.size } {
+      val numBytes = NibbleSplice.truncate(slice, keep, outBuf, 0).right.get
+      val expectedBytes = NibblePack.packDelta(inputs.take(keep), expectedBuf, 0)
+      bytes(outBuf, numBytes) shouldEqual bytes(expectedBuf, expectedBytes)
+      NibblePack.unpackDelta(new UnsafeBuffer(outBuf, 0, numBytes), keep).right.get shouldEqual inputs.take(keep)
+    }
+    slice.capacity shouldEqual bytesWritten     // not mutated
+
+    // Past the padding of the last block
+    NibbleSplice.truncate(slice, 25, outBuf, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    NibbleSplice.truncate(slice, 32, outBuf, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should split streams at every index into halves packed the same as each part on its own") {
+    val inputs = Array.tabulate(21)(i => 100L + i * 10 + (i % 3))
+    for { isDelta <- Seq(false, true); index <- 0 to inputs.size } {
+      val pack = (in: Array[Long], buf: ExpandableArrayBuffer) =>
+        if (isDelta) NibblePack.packDelta(in, buf, 0) else NibblePack.packNonIncreasing(in, buf, 0)
+      val slice = new UnsafeBuffer(aBuf, 0, pack(inputs, aBuf))
+      val (leftEnd, rightEnd) =
+        if (isDelta) NibbleSplice.splitAtDelta(slice, inputs.size, index, outBuf, 0, bBuf, 0).right.get
+        else NibbleSplice.splitAt(slice, inputs.size, index, outBuf, 0, bBuf, 0).right.get
+      bytes(outBuf, leftEnd) shouldEqual bytes(expectedBuf, pack(inputs.take(index), expectedBuf))
+      bytes(bBuf, rightEnd) shouldEqual bytes(expectedBuf, pack(inputs.drop(index), expectedBuf))
+    }
+  }
+
+  it("should return errors when splitting at an index outside the stream") {
+    val slice = new UnsafeBuffer(aBuf, 0, NibblePack.packDelta(Array.tabulate(16)(_.toLong), aBuf, 0))
+    NibbleSplice.splitAt(slice, 16, 17, outBuf, 0, bBuf, 0) shouldEqual Left(NibblePack.InvalidParameter("index", 17))
+    NibbleSplice.splitAt(slice, 16, -1, outBuf, 0, bBuf, 0) shouldEqual Left(NibblePack.InvalidParameter("index", -1))
+    NibbleSplice.splitAtDelta(slice, 24, 10, outBuf, 0, bBuf, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should unpack continuation chunks the same as the tail of unpacking the whole vector") {
+    val inputs = Array.tabulate(37)(i => 1000L + i * 25 + (i % 4))
+    val whole = NibblePack.unpackDelta(new UnsafeBuffer(aBuf, 0, NibblePack.packDelta(inputs, aBuf, 0)), 37).right.get
+    for { index <- 1 until inputs.size } {
+      val tail = inputs.drop(index)
+      val numBytes = NibbleSplice.packDeltaContinued(tail, inputs(index - 1), bBuf, 0)
+      numBytes should be <= NibblePack.packDelta(tail, expectedBuf, 0)
+      val slice = new UnsafeBuffer(bBuf, 0, numBytes)
+      NibbleSplice.unpackDeltaContinued(slice, tail.size, inputs(index - 1)).right.get shouldEqual whole.drop(index)
+      slice.capacity shouldEqual numBytes     // not mutated
+    }
+
+    // Aligned on a block, the continuation is the same bytes as the rest of packDelta of the whole vector
+    val blocksBytes = NibblePack.packDelta(inputs.take(16), expectedBuf, 0)
+    val numBytes = NibbleSplice.packDeltaContinued(inputs.drop(16), inputs(15), bBuf, 0)
+    bytes(bBuf, numBytes) shouldEqual bytes(aBuf, NibblePack.packDelta(inputs, aBuf, 0)).drop(blocksBytes)
+  }
+
+  it("should return errors for continuation chunks with a bad base or fewer values than given") {
+    val slice = new UnsafeBuffer(bBuf, 0, NibbleSplice.packDeltaContinued(Array(10L, 20L, 30L), 5L, bBuf, 0))
+    NibbleSplice.unpackDeltaContinued(slice, 3, -1L) shouldEqual Left(NibblePack.InvalidParameter("base", -1))
+    NibbleSplice.unpackDeltaContinued(slice, 3, Long.MaxValue - 10) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(1))
+    NibbleSplice.unpackDeltaContinued(slice, 9, 5L).left.get shouldBe a[NibblePack.InputTooShort]
+  }
+
+  it("should rebase a stream with a stored base to decode the same deltas from the new base") {
+    val inputs = Array.tabulate(21)(i => 1000L + i * 37 - (i % 4) * 50)
+    val numBytes = NibblePackSigned.packDeltaFromBase(inputs, 1000L, aBuf, 0)
+    val slice = new UnsafeBuffer(aBuf, 0, numBytes)
+    NibbleSplice.rebaseDelta(slice, inputs.size, -5000L, outBuf, 0) shouldEqual Right(numBytes)
+    slice.capacity shouldEqual numBytes     // not mutated
+
+    val out = new Array[Long](inputs.size)
+    NibblePackSigned.unpackDeltaFromBase(new UnsafeBuffer(outBuf, 0, numBytes), out) shouldEqual NibblePack.Ok
+    out shouldEqual inputs.map(_ - 1000L - 5000L)
+    bytes(outBuf, numBytes).drop(NibblePackSigned.BaseHeaderBytes) shouldEqual
+      bytes(aBuf, numBytes).drop(NibblePackSigned.BaseHeaderBytes)
+  }
+
+  it("should return errors when a rebased stream would overflow or has fewer values than given") {
+    val slice = new UnsafeBuffer(aBuf, 0, NibblePackSigned.packDeltaFromBase(Array(10L, 20L, 5L), 0L, aBuf, 0))
+    NibbleSplice.rebaseDelta(slice, 3, Long.MaxValue - 15, outBuf, 0) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(1))
+    NibbleSplice.rebaseDelta(slice, 3, Long.MinValue, outBuf, 0) shouldEqual Right(slice.capacity)
+    NibbleSplice.rebaseDelta(slice, 9, 0L, outBuf, 0).left.get shouldBe a[NibblePack.InputTooShort]
+
+    val drop = new UnsafeBuffer(aBuf, 0, NibblePackSigned.packDeltaFromBase(Array(-1L), 0L, aBuf, 0))
+    NibbleSplice.rebaseDelta(drop, 1, Long.MinValue, outBuf, 0) shouldEqual Left(NibblePack.AccumulatorOverflow(0))
+  }
+
+  it("should concatenate random lists of increasing Longs the same as packing them together") {
+    forAll { (x: Seq[Short], y: Seq[Short]) =>
+      val a = x.map(v => Math.abs(v.toLong)).scanLeft(0L)(_ + _).drop(1).toArray
+      val b = y.map(v => Math.abs(v.toLong)).scanLeft(a.lastOption.getOrElse(0L))(_ + _).drop(1).toArray
+      checkConcat(a, b, true)
+      checkConcat(x.map(_.toLong).toArray, y.map(_.toLong).toArray, false)
+    }
+  }
+}
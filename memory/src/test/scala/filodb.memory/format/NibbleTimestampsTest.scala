@@ -0,0 +1,98 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleTimestampsTest extends FunSpec with Matchers with PropertyChecks {
+  val start = 1546300800000L     // 2019-01-01T00:00:00Z
+  // An hour at one second intervals, with a few samples a little late
+  val timestamps = Array.tabulate(3600)(i => start + i * 1000L + (if (i % 97 == 50) 3L else 0L))
+
+  // Packs the timestamps both ways, each in a buffer of its own
+  def packedBothWays(values: Array[Long]): Seq[UnsafeBuffer] =
+    Seq((NibblePack.packDeltaCounted _), (NibblePackSigned.packDeltaOfDelta _)).map { pack =>
+      val buf = new ExpandableArrayBuffer()
+      new UnsafeBuffer(buf, 0, pack(values, buf, 0))
+    }
+
+  def copy(compressed: UnsafeBuffer): UnsafeBuffer = new UnsafeBuffer(compressed, 0, compressed.capacity)
+
+  it("should unpack timestamps packed as deltas or as delta-of-deltas") {
+    packedBothWays(timestamps).foreach { compressed =>
+      NibbleTimestamps.unpackMillis(compressed).right.get shouldEqual timestamps
+    }
+    val buf = new ExpandableArrayBuffer()
+    val xorBytes = DoubleXORPack.pack(Array(1.0), buf, 0)
+    NibbleTimestamps.unpackMillis(new UnsafeBuffer(buf, 0, xorBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_XOR_Double))
+    NibbleTimestamps.unpackMillis(new UnsafeBuffer(buf, 0, 0)) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  it("should find the first timestamp at or after a time, at and around block boundaries") {
+    packedBothWays(timestamps).foreach { compressed =>
+      def first(target: Long): Int = NibbleTimestamps.firstAtOrAfter(copy(compressed), target).right.get
+      first(Long.MinValue) shouldEqual 0
+      first(start) shouldEqual 0
+      first(start + 1) shouldEqual 1
+      first(start + 7000) shouldEqual 7
+      first(start + 7001) shouldEqual 8
+      first(start + 8000) shouldEqual 8
+      first(start + 50000 + 3) shouldEqual 50
+      first(start + 50000 + 4) shouldEqual 51
+      first(timestamps.last) shouldEqual 3599
+      first(timestamps.last + 1) shouldEqual 3600
+    }
+  }
+
+  it("should find the same index as a linear search for any sorted timestamps") {
+    forAll { (offsets: Seq[Int], target: Int) =>
+      val values = offsets.map(start + _.abs.toLong).sorted.toArray
+      val expected = values.indexWhere(_ >= start + target)
+      packedBothWays(values).foreach { compressed =>
+        NibbleTimestamps.firstAtOrAfter(compressed, start + target) shouldEqual
+          Right(if (expected < 0) values.size else expected)
+      }
+    }
+  }
+
+  it("should return errors for truncated streams") {
+    packedBothWays(timestamps).foreach { compressed =>
+      val truncated = new UnsafeBuffer(compressed, 0, compressed.capacity - 1)
+      NibbleTimestamps.firstAtOrAfter(truncated, timestamps.last).left.get shouldBe a[NibblePack.InputTooShort]
+    }
+  }
+
+  def packed(pack: (Array[Long], ExpandableArrayBuffer, Int) => Int, values: Array[Long]): UnsafeBuffer = {
+    val buf = new ExpandableArrayBuffer()
+    new UnsafeBuffer(buf, 0, pack(values, buf, 0))
+  }
+
+  it("should return the samples of timestamps and values unpacked in lockstep") {
+    val counter = Array.tabulate(timestamps.size)(i => i * 10L + i % 3)
+    val regular = Array.tabulate(timestamps.size)(i => start + i * 1000L)
+    for { times <- Seq(timestamps, regular)
+          n     <- Seq(0, 1, 2, 3, 10, 17, 3600) } {
+      val ts = packed(NibblePackSigned.packDeltaOfDelta, times.take(n))
+      val values = packed(NibblePack.packDeltaCounted, counter.take(n))
+      NibbleTimestamps.samples(ts, values, n).map(_.right.get).toSeq shouldEqual times.take(n).zip(counter.take(n))
+      // The buffers are not mutated
+      NibbleTimestamps.samples(ts, values, n).size shouldEqual n
+    }
+  }
+
+  it("should return an error for sample streams which disagree on their length or are truncated") {
+    val ts = packed(NibblePackSigned.packDeltaOfDelta, timestamps.take(100))
+    val values = packed(NibblePack.packDeltaCounted, timestamps.take(99))
+    NibbleTimestamps.samples(ts, values, 100).toSeq shouldEqual Seq(Left(NibblePack.InvalidHeader("numValues", 99)))
+    NibbleTimestamps.samples(ts, values, 99).toSeq shouldEqual Seq(Left(NibblePack.InvalidHeader("numValues", 100)))
+
+    val fullValues = packed(NibblePack.packDeltaCounted, timestamps.take(100))
+    val truncated = new UnsafeBuffer(fullValues, 0, fullValues.capacity - 1)
+    val samples = NibbleTimestamps.samples(ts, truncated, 100).toSeq
+    samples.init.forall(_.isRight) shouldEqual true
+    samples.last.left.get shouldBe a[NibblePack.InputTooShort]
+  }
+}
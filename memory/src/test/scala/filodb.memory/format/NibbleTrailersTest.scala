@@ -0,0 +1,64 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+class NibbleTrailersTest extends FunSpec with Matchers {
+  import NibbleTrailers._
+
+  val buf = new ExpandableArrayBuffer()
+  val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+
+  def payloadBytes(t: Trailer): Seq[Byte] = (0 until t.payload.capacity).map(t.payload.getByte)
+
+  it("should decode streams with trailers after them, then read the trailers") {
+    var pos = NibblePack.packDeltaCounted(inputs, buf, 0)
+    pos = appendTrailer(buf, pos, 0x42, Array[Byte](1, 2, 3))
+    pos = appendTrailer(buf, pos, 0x7f, Array.empty[Byte])
+    pos = appendTrailer(buf, pos, 0x01, Array[Byte](9))
+
+    val compressed = new UnsafeBuffer(buf, 0, pos)
+    NibblePack.unpackDeltaCounted(compressed).right.get shouldEqual inputs
+    val trailers = readTrailers(compressed).map(_.right.get).toList
+    trailers.map(_.trailerType) shouldEqual List(0x42, 0x7f, 0x01)
+    trailers.map(payloadBytes) shouldEqual List(Seq[Byte](1, 2, 3), Nil, Seq[Byte](9))
+  }
+
+  it("should let XOR Double and delta-of-delta decoders skip trailers") {
+    val doubles = Array(1.5, 2.5, 2.5, -3.0)
+    val xorPos = appendTrailer(buf, DoubleXORPack.pack(doubles, buf, 0), 0x10, Array[Byte](5, 6))
+    val xorCompressed = new UnsafeBuffer(buf, 0, xorPos)
+    val out = new Array[Double](doubles.size)
+    DoubleXORPack.unpack(xorCompressed, out) shouldEqual NibblePack.Ok
+    out shouldEqual doubles
+    readTrailers(xorCompressed).map(_.right.get.trailerType).toList shouldEqual List(0x10)
+
+    val timestamps = Array(1000L, 2000L, 3010L, 3990L, 5000L)
+    val dodPos = appendTrailer(buf, NibblePackSigned.packDeltaOfDelta(timestamps, buf, 0), 0x11, Array[Byte](7))
+    val dodCompressed = new UnsafeBuffer(buf, 0, dodPos)
+    NibblePackSigned.unpackDeltaOfDelta(dodCompressed).right.get shouldEqual timestamps
+    readTrailers(dodCompressed).map(t => payloadBytes(t.right.get)).toList shouldEqual List(Seq[Byte](7))
+  }
+
+  it("should keep the checksum after the trailers") {
+    val trailerPos = appendTrailer(buf, NibblePack.packDeltaCounted(inputs, buf, 0), 0x42, Array[Byte](1, 2))
+    val endPos = NibbleFormat.appendChecksum(buf, 0, trailerPos)
+    val compressed = new UnsafeBuffer(buf, 0, endPos)
+    NibbleFormat.verifyChecksum(compressed) shouldEqual NibblePack.Ok
+    NibblePack.unpackDeltaCounted(compressed).right.get shouldEqual inputs
+    readTrailers(compressed).map(_.right.get.trailerType).toList shouldEqual List(0x42)
+  }
+
+  it("should return an error for a trailer which does not fit, and stop") {
+    val pos = appendTrailer(buf, 0, 0x42, Array[Byte](1, 2, 3))
+    readTrailers(new UnsafeBuffer(buf, 0, pos - 1)).toList shouldEqual List(Left(NibblePack.InputTooShort(8, 7)))
+    readTrailers(new UnsafeBuffer(buf, 0, 3)).toList shouldEqual List(Left(NibblePack.InputTooShort(5, 3)))
+
+    buf.putInt(1, -1, java.nio.ByteOrder.LITTLE_ENDIAN)
+    readTrailers(new UnsafeBuffer(buf, 0, pos)).toList shouldEqual
+      List(Left(NibblePack.InvalidHeader("trailerLength", -1)))
+    readTrailers(new UnsafeBuffer(buf, 0, 0)).isEmpty shouldEqual true
+  }
+}
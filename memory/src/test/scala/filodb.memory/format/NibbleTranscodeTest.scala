@@ -0,0 +1,121 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class NibbleTranscodeTest extends FunSpec with Matchers with PropertyChecks {
+  import NibbleFormat.{Format_Delta_Counted, Format_XOR_Double}
+  import NibbleTranscode._
+
+  val buf = new ExpandableArrayBuffer()
+  val buf2 = new ExpandableArrayBuffer()
+
+  def slice(buffer: ExpandableArrayBuffer, numBytes: Int): UnsafeBuffer = new UnsafeBuffer(buffer, 0, numBytes)
+
+  it("should transcode increasing Longs to XOR Doubles and back") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, MaxExactLong)
+    val countedBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val xorBytes = transcode(slice(buf, countedBytes), inputs.size, Format_XOR_Double, buf2, 0).right.get
+    buf2.getByte(0) shouldEqual Format_XOR_Double
+
+    val doubles = new Array[Double](inputs.size)
+    DoubleXORPack.unpack(slice(buf2, xorBytes), doubles) shouldEqual NibblePack.Ok
+    doubles shouldEqual inputs.map(_.toDouble)
+
+    val backBytes = transcode(slice(buf2, xorBytes), inputs.size, Format_Delta_Counted, buf, 0).right.get
+    backBytes shouldEqual countedBytes
+    NibblePack.unpackDeltaCounted(slice(buf, backBytes)).right.get shouldEqual inputs
+  }
+
+  it("should transcode random increasing values there and back") {
+    forAll { (deltas: Seq[Int]) =>
+      val inputs = deltas.map(d => Math.abs(d.toLong)).scanLeft(0L)(_ + _).toArray
+      val countedBytes = NibblePack.packDeltaCounted(inputs, buf, 0)
+      val xorBytes = transcode(slice(buf, countedBytes), inputs.size, Format_XOR_Double, buf2, 0).right.get
+      val backBytes = transcode(slice(buf2, xorBytes), inputs.size, Format_Delta_Counted, buf, 0).right.get
+      NibblePack.unpackDeltaCounted(slice(buf, backBytes)).right.get shouldEqual inputs
+    }
+  }
+
+  it("should repack a stream transcoded to its own format") {
+    val doubles = Array(1.5, 2.25, 2.25, -7.0, Double.NaN)
+    val xorBytes = DoubleXORPack.pack(doubles, buf, 0)
+    transcode(slice(buf, xorBytes), doubles.size, Format_XOR_Double, buf2, 0) shouldEqual Right(xorBytes)
+    (0 until xorBytes).map(buf2.getByte) shouldEqual (0 until xorBytes).map(buf.getByte)
+  }
+
+  it("should refuse values which the target format cannot hold exactly") {
+    val countedBytes = NibblePack.packDeltaCounted(Array(5L, MaxExactLong + 1), buf, 0)
+    transcode(slice(buf, countedBytes), 2, Format_XOR_Double, buf2, 0) shouldEqual
+      Left(NibblePack.LossyConversion(1))
+
+    Seq(Array(1.0, 2.5) -> 1, Array(1.0, Double.NaN) -> 1, Array(-0.0) -> 0,
+        Array(Double.PositiveInfinity) -> 0, Array(0.0, 1e17) -> 1).foreach { case (doubles, index) =>
+      val xorBytes = DoubleXORPack.pack(doubles, buf, 0)
+      transcode(slice(buf, xorBytes), doubles.size, Format_Delta_Counted, buf2, 0) shouldEqual
+        Left(NibblePack.LossyConversion(index))
+    }
+
+    val decreasing = Array(10.0, 20.0, 15.0)
+    val xorBytes = DoubleXORPack.pack(decreasing, buf, 0)
+    transcode(slice(buf, xorBytes), 3, Format_Delta_Counted, buf2, 0) shouldEqual Left(NibblePack.NonIncreasing(2))
+  }
+
+  it("should return errors for unsupported formats and mismatched counts") {
+    val countedBytes = NibblePack.packDeltaCounted(Array(1L, 2L, 3L), buf, 0)
+    transcode(slice(buf, countedBytes), 4, Format_XOR_Double, buf2, 0) shouldEqual
+      Left(NibblePack.InvalidHeader("numValues", 3))
+    transcode(slice(buf, countedBytes), 3, NibbleFormat.Format_U32, buf2, 0) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_U32))
+
+    val u32Bytes = NibblePack32.pack(Array(1, 2, 3), buf, 0)
+    transcode(slice(buf, u32Bytes), 3, Format_XOR_Double, buf2, 0) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_U32))
+    transcode(slice(buf, 0), 0, Format_XOR_Double, buf2, 0) shouldEqual Left(NibblePack.InputTooShort(1, 0))
+  }
+
+  // Packs each of the inputs into a buffer of its own
+  def packedEach(inputs: Seq[Array[Long]], pack: (Array[Long], ExpandableArrayBuffer, Int) => Int): Seq[UnsafeBuffer] =
+    inputs.map { values =>
+      val own = new ExpandableArrayBuffer()
+      new UnsafeBuffer(own, 0, pack(values, own, 0))
+    }
+
+  it("should transcode a batch of delta streams to frame-of-reference, keeping every buffer's values") {
+    val inputs = Seq.tabulate(20)(n => Array.tabulate(n * 13)(i => 1000L * n + i * 7 + i % 3))
+    val results = transcodeBatch(packedEach(inputs, NibblePack.packDeltaCounted).iterator, NibbleFormat.Format_FOR)
+    results.zip(inputs.iterator).foreach { case (result, values) =>
+      val bytes = result.right.get
+      NibbleFormat.formatOf(new UnsafeBuffer(bytes)) shouldEqual NibbleFormat.Format_FOR
+      NibbleFOR.unpackFOR(new UnsafeBuffer(bytes)).right.get shouldEqual values
+    }
+
+    // And back to deltas, from any mix of formats
+    val mixed = packedEach(inputs.take(3), NibbleFOR.packFOR) ++ packedEach(inputs.drop(3), NibbleRuns.packDeltaRuns)
+    val back = transcodeBatch(mixed.iterator, Format_Delta_Counted).map(_.right.get).toSeq
+    back.map(b => NibblePack.unpackDeltaCounted(new UnsafeBuffer(b)).right.get.toSeq) shouldEqual inputs.map(_.toSeq)
+  }
+
+  it("should transcode lazily, returning the error of each buffer which cannot be transcoded in its place") {
+    val drops = Array(5L, 9L, 7L)
+    val doubles = SparseDoublePack.pack(Array(1.5, Double.NaN), buf, 0)
+    val buffers = packedEach(Seq(Array(1L, 2L), drops), NibbleFOR.packFOR) :+
+                  new UnsafeBuffer(java.util.Arrays.copyOf(buf.byteArray, doubles)) :+ new UnsafeBuffer(buf, 0, 0)
+    var taken = 0
+    val results = transcodeBatch(buffers.iterator.map { b => taken += 1; b }, Format_Delta_Counted)
+    taken shouldEqual 0
+    results.next().isRight shouldEqual true
+    taken shouldEqual 1
+    results.toSeq shouldEqual Seq(Left(NibblePack.NonIncreasing(2)),
+                                  Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_Sparse_Double)),
+                                  Left(NibblePack.InputTooShort(1, 0)))
+
+    transcodeBatch(buffers.take(2).iterator, Format_XOR_Double).toSeq shouldEqual
+      Seq.fill(2)(Left(NibblePack.UnexpectedFormat(Format_XOR_Double)))
+    transcodeBatch(buffers.take(2).iterator, NibbleFormat.Format_Delta_Hybrid).map(_.isRight).toSeq shouldEqual
+      Seq(true, true)
+  }
+}
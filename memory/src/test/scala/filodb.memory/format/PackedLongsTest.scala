@@ -0,0 +1,90 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+import filodb.memory.NativeMemoryManager
+
+class PackedLongsTest extends FunSpec with Matchers with PropertyChecks {
+  import PackedLongs._
+
+  def packAutoBytes(values: Array[Long]): Array[Byte] = {
+    val buf = new ExpandableArrayBuffer()
+    java.util.Arrays.copyOf(buf.byteArray, NibbleAuto.packAuto(values, buf, 0))
+  }
+
+  it("should encode Longs to the bytes of packAuto and decode them back") {
+    val counter = Array.tabulate(100)(i => 1000L + i * 15 + i % 4)
+    val encoded = counter.encoded
+    encoded.bytes shouldEqual packAutoBytes(counter)
+    encoded.decode.right.get.values shouldEqual counter
+    encoded.bytes.decoded.right.get.values shouldEqual counter
+    new DecodedLongs(counter).encode.bytes shouldEqual encoded.bytes
+  }
+
+  it("should round trip any Longs, including ones which go down") {
+    forAll { (longs: Seq[Long]) =>
+      longs.toArray.encoded.bytes.decoded.right.get.values shouldEqual longs.toArray
+    }
+    Array.empty[Long].encoded.bytes.decoded.right.get.values shouldEqual Array.empty[Long]
+  }
+
+  it("should return the error for truncated or foreign bytes") {
+    val bytes = Array.tabulate(100)(i => i * 1000L + i % 7).encoded.bytes
+    bytes.take(bytes.size - 1).decoded.left.get shouldBe a[NibblePack.InputTooShort]
+    Array.empty[Byte].decoded shouldEqual Left(NibblePack.InputTooShort(1, 0))
+    NibblePack.packDeltaToBytes(Array(1L, 2L)).decoded.isLeft shouldEqual true
+  }
+
+  it("should return the values, their number and the error code together from unpackDeltaCountedResult") {
+    val inputs = Array(0L, 1000, 1001, 1002, 1003, 2005, 2010, 3034, 4045, 5056, 6067, 7078)
+    val buf = new ExpandableArrayBuffer()
+    val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val bytes = java.util.Arrays.copyOf(buf.byteArray, bytesWritten + 10)
+
+    val result = unpackDeltaCountedResult(bytes, bytesWritten)
+    result.values shouldEqual inputs
+    (result.length, result.errorCode) shouldEqual ((inputs.size, 0))
+
+    val tooShort = NibblePack.InputTooShort(0, 0).errorCode
+    for { numBytes <- Seq(bytesWritten - 1, bytes.size + 1, 0, -1) } {
+      val failed = unpackDeltaCountedResult(bytes, numBytes)
+      (failed.values.size, failed.length, failed.errorCode) shouldEqual ((0, 0, tooShort))
+    }
+  }
+
+  it("should unpack into native memory owned by the caller, which is all given back on free") {
+    val memFactory = new NativeMemoryManager(1000000)
+    val inputs = Array.tabulate(1000)(i => i * 100L + i % 9)
+    val bytes = NibblePack.packDeltaToBytes(inputs)
+
+    for { n <- Seq(1000, 17, 0) } {
+      val owned = unpackDeltaOwned(bytes, bytes.size, n, memFactory).right.get
+      owned.length shouldEqual n
+      owned.toArray shouldEqual inputs.take(n)
+      memFactory.usedMemory should be > 0L
+      owned.free()
+      owned.isFreed shouldEqual true
+      memFactory.usedMemory shouldEqual 0
+      owned.free()
+      memFactory.usedMemory shouldEqual 0
+      intercept[IllegalStateException] { owned(0) }
+    }
+
+    val owned = unpackDeltaOwned(bytes, bytes.size, 10, memFactory).right.get
+    intercept[IndexOutOfBoundsException] { owned(10) }
+    owned.close()
+    memFactory.usedMemory shouldEqual 0
+  }
+
+  it("should allocate nothing when unpackDeltaOwned fails") {
+    val memFactory = new NativeMemoryManager(1000000)
+    val bytes = NibblePack.packDeltaToBytes(Array.tabulate(100)(_ * 3L))
+    unpackDeltaOwned(bytes, bytes.size + 1, 100, memFactory).left.get shouldBe a[NibblePack.InputTooShort]
+    unpackDeltaOwned(bytes, bytes.size / 2, 100, memFactory).isLeft shouldEqual true
+    unpackDeltaOwned(bytes, bytes.size, Int.MaxValue, memFactory).left.get shouldBe a[NibblePack.InvalidParameter]
+    memFactory.usedMemory shouldEqual 0
+  }
+}
@@ -0,0 +1,105 @@
+package filodb.memory.format
+
+import scala.concurrent.Await
+import scala.concurrent.ExecutionContext.Implicits.global
+import scala.concurrent.duration._
+
+import org.agrona.{DirectBuffer, ExpandableArrayBuffer}
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+/**
+ * Checks the partial final block convention, see doc/compression.md, for every codec at every length mod 8:
+ * the final block is padded with zeroes, and decoders emit exactly the count, from the header or from the caller,
+ * never the padding.
+ */
+class PartialBlockTest extends FunSpec with Matchers {
+  import NibblePack.Ok
+
+  val buf = new ExpandableArrayBuffer()
+
+  // Up to 2 full blocks plus 0 to 7 values, with no two values alike so that dropped or extra values show
+  val lengths = 0 to 23
+
+  def inputs(n: Int): Array[Long] = Array.tabulate(n)(i => 1000L * (i + 1) + (i * 37) % 11)
+  def signed(n: Int): Array[Long] = inputs(n).zipWithIndex.map { case (v, i) => if (i % 3 == 1) -v else v }
+
+  def packed(numBytes: Int): UnsafeBuffer = new UnsafeBuffer(buf, 0, numBytes)
+  def bytes(buffer: DirectBuffer): Seq[Byte] = (0 until buffer.capacity).map(buffer.getByte)
+
+  it("should pad the final block with zeroes") {
+    lengths.foreach { n =>
+      val padded = java.util.Arrays.copyOf(inputs(n), (n + 7) / 8 * 8)
+      bytes(packed(NibblePack.packNonIncreasing(inputs(n), buf, 0))) shouldEqual
+        bytes(packed(NibblePack.packNonIncreasing(padded, new ExpandableArrayBuffer(), 0)))
+
+      // As deltas, padding with zeroes is the same as repeating the last value
+      val repeated = padded.zipWithIndex.map { case (v, i) => if (i < n) v else inputs(n).last }
+      if (n > 0) bytes(packed(NibblePack.packDelta(inputs(n), buf, 0))) shouldEqual
+        bytes(packed(NibblePack.packDelta(repeated, new ExpandableArrayBuffer(), 0)))
+    }
+  }
+
+  it("should unpack exactly the count given to decoders of streams without one") {
+    lengths.foreach { n =>
+      NibbleSplice.rawValues(packed(NibblePack.packNonIncreasing(inputs(n), buf, 0)), n).right.get shouldEqual
+        inputs(n)
+      NibblePack.unpackDelta(packed(NibblePack.packDelta(inputs(n), buf, 0)), n).right.get shouldEqual inputs(n)
+
+      val out = new Array[Long](n)
+      NibblePackSigned.unpackDelta(packed(NibblePackSigned.packDelta(signed(n), buf, 0)), out) shouldEqual Ok
+      out shouldEqual signed(n)
+      NibblePackSigned.unpackDeltaFromBase(packed(NibblePackSigned.packDeltaFromBase(signed(n), 7L, buf, 0)), out)
+        .shouldEqual(Ok)
+      out shouldEqual signed(n)
+
+      val ints = new Array[Int](n)
+      NibblePack32.unpack(packed(NibblePack32.pack(inputs(n).map(_.toInt), buf, 0)), ints) shouldEqual Ok
+      ints shouldEqual inputs(n).map(_.toInt)
+
+      val (hi, lo) = (new Array[Long](n), new Array[Long](n))
+      NibblePack128.unpack(packed(NibblePack128.pack(inputs(n), signed(n), buf, 0)), hi, lo) shouldEqual Ok
+      hi shouldEqual inputs(n)
+      lo shouldEqual signed(n)
+
+      val doubles = new Array[Double](n)
+      DoubleXORPack.unpack(packed(DoubleXORPack.pack(inputs(n).map(_ / 8.0), buf, 0)), doubles) shouldEqual Ok
+      doubles shouldEqual inputs(n).map(_ / 8.0)
+    }
+  }
+
+  it("should unpack exactly the count in the header from every counted codec") {
+    lengths.foreach { n =>
+      val in = inputs(n)
+      NibblePack.unpackDeltaCounted(packed(NibblePack.packDeltaCounted(in, buf, 0))).right.get shouldEqual in
+      NibbleFOR.unpackFOR(packed(NibbleFOR.packFOR(signed(n), buf, 0))).right.get shouldEqual signed(n)
+      Seq(8, 16, 32, 64).foreach { blockSize =>
+        NibbleBlockSize.unpackDelta(packed(NibbleBlockSize.packDelta(in, buf, 0, blockSize))).right.get shouldEqual in
+      }
+      NibbleFixedWidth.unpackDelta(packed(NibbleFixedWidth.packDelta(in, buf, 0))).right.get shouldEqual in
+      NibbleRuns.unpackDeltaRuns(packed(NibbleRuns.packDeltaRuns(in, buf, 0))).right.get shouldEqual in
+      NibbleHybridDelta.unpackDelta(packed(NibbleHybridDelta.packDelta(signed(n), buf, 0))).right.get shouldEqual
+        signed(n)
+      NibbleClamped.unpackOriginal(packed(NibbleClamped.packDelta(in, buf, 0).endPos)).right.get shouldEqual in
+      NibblePackSigned.unpackDeltaOfDelta(packed(NibblePackSigned.packDeltaOfDelta(in, buf, 0))).right.get shouldEqual
+        in
+      NibbleAuto.unpackAuto(packed(NibbleAuto.packAuto(signed(n), buf, 0))).right.get shouldEqual signed(n)
+
+      // Chunks of 5 values, so that chunks end part way through blocks too
+      val chunksBytes = Await.result(NibbleChunks.packDeltaParallel(in, buf, 0, 5), 10.seconds)
+      NibbleChunks.unpackDeltaChunks(packed(chunksBytes)).right.get shouldEqual in
+
+      val vec = CompressedVec(packed(CompressedVec.encode(in, buf, 0))).right.get
+      vec.range(0, n).get shouldEqual in
+      vec.get(n) shouldEqual None
+
+      val doubles = in.map(_ / 1000.0)
+      NibbleScaled.unpackScaled(packed(NibbleScaled.packScaled(doubles, 1000, buf, 0).right.get)).right.get
+        .shouldEqual(doubles)
+      val gaps = doubles.zipWithIndex.map { case (d, i) => if (i % 3 == 2) Double.NaN else d }
+      SparseDoublePack.unpack(packed(SparseDoublePack.pack(gaps, buf, 0))).right.get
+        .map(java.lang.Double.doubleToLongBits) shouldEqual gaps.map(java.lang.Double.doubleToLongBits)
+    }
+  }
+}
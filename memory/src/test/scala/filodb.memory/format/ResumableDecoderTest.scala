@@ -0,0 +1,91 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class ResumableDecoderTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  // Feeds the bytes of the stream in chunks of chunkSize, returning all the values decoded along the way
+  def feedAll(decoder: ResumableDecoder, bytes: Array[Byte], chunkSize: Int): Seq[Long] =
+    bytes.grouped(chunkSize).flatMap { chunk => decoder.feed(new UnsafeBuffer(chunk)).right.get }.toList
+
+  def packedBytes(numBytes: Int): Array[Byte] = {
+    val bytes = new Array[Byte](numBytes)
+    buf.getBytes(0, bytes)
+    bytes
+  }
+
+  it("should decode a stream fed one byte at a time the same as all at once") {
+    val inputs = Array.tabulate(100)(i => 10000L + i * 997 + (if (i % 9 == 0) 1L << 40 else 0L))
+    val bytes = packedBytes(NibblePack.packDelta(inputs, buf, 0))
+
+    val decoder = new ResumableDecoder(inputs.size, isDelta = true)
+    feedAll(decoder, bytes, 1) shouldEqual NibblePack.unpackDelta(new UnsafeBuffer(bytes), inputs.size).right.get.toSeq
+    decoder.numDecoded shouldEqual inputs.size
+    decoder.bufferedBytes shouldEqual 0
+    decoder.finish() shouldEqual NibblePack.Ok
+  }
+
+  it("should decode any values fed in any size of chunk") {
+    forAll { (longs: Seq[Long], chunkSize: Byte) =>
+      val inputs = (longs ++ Seq.fill(9)(0L) ++ Seq.fill(8)(42L)).toArray
+      val bytes = packedBytes(NibblePack.packNonIncreasing(inputs, buf, 0))
+      val decoder = new ResumableDecoder(inputs.size, isDelta = false)
+      feedAll(decoder, bytes, (chunkSize & 0x3f) + 1) shouldEqual inputs.toSeq
+      decoder.finish() shouldEqual NibblePack.Ok
+    }
+  }
+
+  it("should keep an incomplete block until the rest of it is fed") {
+    val inputs = Array.tabulate(16)(i => i * 1000L)
+    val bytes = packedBytes(NibblePack.packNonIncreasing(inputs, buf, 0))
+    val firstBlockBytes = NibblePack.blockSize(new UnsafeBuffer(bytes), 0)
+
+    val decoder = new ResumableDecoder(inputs.size, isDelta = false)
+    decoder.feed(new UnsafeBuffer(bytes, 0, firstBlockBytes + 3)).right.get shouldEqual inputs.take(8)
+    decoder.bufferedBytes shouldEqual 3
+    decoder.feed(new UnsafeBuffer(bytes, firstBlockBytes + 3, bytes.size - firstBlockBytes - 3))
+      .right.get shouldEqual inputs.drop(8)
+    decoder.bufferedBytes shouldEqual 0
+  }
+
+  it("should return errors for streams which end early or have malformed blocks") {
+    val inputs = Array.tabulate(20)(i => i * 1000L)
+    val bytes = packedBytes(NibblePack.packDelta(inputs, buf, 0))
+
+    val truncated = new ResumableDecoder(inputs.size, isDelta = true)
+    truncated.feed(new UnsafeBuffer(bytes, 0, bytes.size - 1)).isRight shouldEqual true
+    truncated.finish() shouldBe a[NibblePack.InputTooShort]
+    new ResumableDecoder(30, isDelta = true).finish() shouldEqual NibblePack.InputTooShort(1, 0)
+
+    // 16 nibbles plus 15 trailing nibbles cannot fit in a Long
+    val malformed = new ResumableDecoder(8, isDelta = true)
+    malformed.feed(new UnsafeBuffer(Array(0x01.toByte, 0xff.toByte))) shouldEqual
+      Left(NibblePack.InvalidNibbleWidth(31))
+    malformed.feed(new UnsafeBuffer(Array(0x00.toByte))) shouldEqual Left(NibblePack.InvalidNibbleWidth(31))
+    malformed.finish() shouldEqual NibblePack.InvalidNibbleWidth(31)
+  }
+
+  it("should return AccumulatorOverflow when the deltas add up past Long.MaxValue, even across feeds") {
+    // Raw values read as deltas: the second block takes the total of the first past Long.MaxValue
+    val inputs = Array.fill(8)(Long.MaxValue / 8) ++ Array(Long.MaxValue / 2)
+    val bytes = packedBytes(NibblePack.packNonIncreasing(inputs, buf, 0))
+    val firstBlockBytes = NibblePack.blockSize(new UnsafeBuffer(bytes), 0)
+    NibblePack.unpackDelta(new UnsafeBuffer(bytes), inputs.size) shouldEqual Left(NibblePack.AccumulatorOverflow(8))
+
+    val decoder = new ResumableDecoder(inputs.size, isDelta = true)
+    decoder.feed(new UnsafeBuffer(bytes, 0, firstBlockBytes)).right.get.size shouldEqual 8
+    decoder.feed(new UnsafeBuffer(bytes, firstBlockBytes, bytes.size - firstBlockBytes)) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(8))
+    decoder.finish() shouldEqual NibblePack.AccumulatorOverflow(8)
+
+    // A negative delta is never written by packDelta
+    val negative = packedBytes(NibblePack.packNonIncreasing(Array(3L, -1L), buf, 0))
+    new ResumableDecoder(2, isDelta = true).feed(new UnsafeBuffer(negative)) shouldEqual
+      Left(NibblePack.AccumulatorOverflow(1))
+  }
+}
@@ -0,0 +1,45 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+class SmallVecTest extends FunSpec with Matchers {
+  val buf = new ExpandableArrayBuffer()
+
+  it("should unpack vectors of up to its capacity, reusing the same output") {
+    val vec = new SmallVec(16)
+    Seq(16, 0, 8, 5, 16).foreach { n =>
+      val inputs = Array.tabulate(n)(i => 1000L + i * i)
+      val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+      vec.unpackDeltaCounted(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual n
+      vec.numValues shouldEqual n
+      vec.lastResult shouldEqual NibblePack.Ok
+      vec.values.take(n) shouldEqual inputs
+    }
+  }
+
+  it("should unpack from a slice of a byte array") {
+    val vec = new SmallVec(16)
+    val inputs = Array(3L, 5L, 8L)
+    val bytesWritten = NibblePack.packDeltaCounted(inputs, buf, 0)
+    val bytes = Array[Byte](99, 99) ++ buf.byteArray.take(bytesWritten)
+    vec.unpackDeltaCounted(bytes, 2, bytes.size - 2) shouldEqual 3
+    vec.values.take(3) shouldEqual inputs
+  }
+
+  it("should return error codes for vectors longer than its capacity and for bad input") {
+    val vec = new SmallVec(16)
+    val bytesWritten = NibblePack.packDeltaCounted(Array.tabulate(17)(_.toLong), buf, 0)
+    vec.unpackDeltaCounted(new UnsafeBuffer(buf, 0, bytesWritten)) shouldEqual -8
+    vec.lastResult shouldEqual NibblePack.OutputTooSmall(17, 16)
+    vec.numValues shouldEqual 0
+
+    val shortBytes = NibblePack.packDeltaCounted(Array.tabulate(12)(_ * 1000L), buf, 0)
+    vec.unpackDeltaCounted(new UnsafeBuffer(buf, 0, shortBytes - 1)) shouldEqual -1
+    vec.lastResult shouldBe a[NibblePack.InputTooShort]
+    vec.unpackDeltaCounted(new UnsafeBuffer(buf, 0, 3)) shouldEqual -1
+    vec.unpackDeltaCounted(new UnsafeBuffer(buf, 1, 4)) shouldEqual NibblePack.UnexpectedFormat(12).errorCode
+  }
+}
@@ -0,0 +1,61 @@
+package filodb.memory.format
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+import org.scalatest.prop.PropertyChecks
+
+class SparseDoublePackTest extends FunSpec with Matchers with PropertyChecks {
+  val buf = new ExpandableArrayBuffer()
+
+  def roundTrip(inputs: Array[Double]): Array[Double] = {
+    val bytesWritten = SparseDoublePack.pack(inputs, buf, 0)
+    SparseDoublePack.unpack(new UnsafeBuffer(buf, 0, bytesWritten)).right.get
+  }
+
+  // Compares raw bits, so that NaNs are compared properly too
+  def bits(values: Array[Double]): Seq[Long] = values.map(java.lang.Double.doubleToRawLongBits).toSeq
+
+  it("should store a half NaN gauge as a bitmap plus the present values, putting the NaNs back in place") {
+    val rand = new scala.util.Random(5)
+    val gauge = Array.tabulate(1000)(i => if (rand.nextBoolean()) Double.NaN else 20.0 + (i % 16) * 0.25)
+    val present = gauge.filterNot(_.isNaN)
+    present.size.toDouble / gauge.size shouldEqual 0.5 +- 0.05
+
+    val numBytes = SparseDoublePack.pack(gauge, buf, 0)
+    numBytes shouldEqual SparseDoublePack.HeaderBytes + 1000 / 8 +
+                         DoubleXORPack.pack(present, new ExpandableArrayBuffer(), 0)
+    numBytes should be < DoubleXORPack.pack(gauge, buf, 0)
+
+    val out = roundTrip(gauge)
+    out.indices.filter(i => out(i).isNaN) shouldEqual gauge.indices.filter(i => gauge(i).isNaN)
+    out.filterNot(_.isNaN) shouldEqual present
+  }
+
+  it("should round trip any Doubles, unpacking every NaN as the standard one") {
+    val cases = Seq(Array.empty[Double], Array(Double.NaN), Array.fill(9)(Double.NaN), Array(1.5),
+                    Array(Double.NaN, -0.0))
+    cases.foreach { values => bits(roundTrip(values)) shouldEqual bits(values) }
+
+    val oddNaN = java.lang.Double.longBitsToDouble(0x7ff0000000000001L)
+    bits(roundTrip(Array(oddNaN, 2.0))) shouldEqual bits(Array(Double.NaN, 2.0))
+
+    forAll { (doubles: Seq[Double], gaps: Seq[Boolean]) =>
+      val inputs = doubles.zipAll(gaps, 0.0, false).map { case (d, gap) => if (gap) Double.NaN else d }.toArray
+      bits(roundTrip(inputs)) shouldEqual bits(inputs.map(d => if (d.isNaN) Double.NaN else d))
+    }
+  }
+
+  it("should return errors for truncated or mislabelled streams") {
+    val inputs = Array.tabulate(30)(i => if (i % 3 == 0) Double.NaN else i * 1.5)
+    val numBytes = SparseDoublePack.pack(inputs, buf, 0)
+    SparseDoublePack.unpack(new UnsafeBuffer(buf, 0, numBytes - 1)).left.get shouldBe a[NibblePack.InputTooShort]
+    SparseDoublePack.unpack(new UnsafeBuffer(buf, 0, 8)) shouldEqual Left(NibblePack.InputTooShort(4, 2))
+    SparseDoublePack.unpack(new UnsafeBuffer(buf, 0, 3)) shouldEqual Left(NibblePack.InputTooShort(4, 1))
+
+    val xorBytes = DoubleXORPack.pack(inputs, buf, 0)
+    SparseDoublePack.unpack(new UnsafeBuffer(buf, 0, xorBytes)) shouldEqual
+      Left(NibblePack.UnexpectedFormat(NibbleFormat.Format_XOR_Double))
+  }
+}
@@ -0,0 +1,66 @@
+package filodb.memory.format.vectors
+
+import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import org.scalatest._
+
+import filodb.memory.format.NibblePack
+
+class HistColumnsTest extends FunSpec with Matchers {
+  // 100 cumulative histograms over time, each bucket slowly growing, with a counter reset halfway
+  val histograms = Array.tabulate(100) { t =>
+    val sinceReset = if (t < 50) t + 50 else t - 50
+    Array.tabulate(16)(b => sinceReset * 10L * (b + 1) + b)
+  }
+
+  def appended(hists: Seq[Array[Long]]): UnsafeBuffer = {
+    val appender = new HistColumnAppender(16)
+    hists.foreach(appender.append)
+    val buf = new ExpandableArrayBuffer()
+    new UnsafeBuffer(buf, 0, appender.finish(buf))
+  }
+
+  it("should read back each histogram appended") {
+    val reader = new HistColumnReader(appended(histograms))
+    reader.numBuckets shouldEqual 16
+    reader.numHistograms shouldEqual 100
+    histograms.indices.foreach { i => reader.histogram(i).right.get shouldEqual histograms(i) }
+  }
+
+  it("should read back each bucket over time, and pack the same bytes as appending") {
+    val columns = appended(histograms)
+    val reader = new HistColumnReader(columns)
+    (0 until 16).foreach { b => reader.bucket(b).right.get shouldEqual histograms.map(_(b)) }
+
+    val buf = new ExpandableArrayBuffer()
+    val numBytes = HistColumnAppender.pack(16, histograms, buf)
+    numBytes shouldEqual columns.capacity
+    (0 until numBytes).foreach { i => buf.getByte(i) shouldEqual columns.getByte(i) }
+
+    reader.bucket(16) shouldEqual Left(NibblePack.InvalidParameter("bucket", 16))
+    new HistColumnReader(appended(Nil)).bucket(3).right.get shouldEqual Array.empty[Long]
+  }
+
+  it("should store the bucket columns in fewer bytes than the histograms one by one") {
+    val buf = new ExpandableArrayBuffer()
+    val rowBytes = histograms.map { h => BinaryHistogram.writeDelta(GeometricBuckets(1.0, 2.0, 16), h, buf) }.sum
+    appended(histograms).capacity should be < rowBytes
+  }
+
+  it("should return errors for indices out of range and malformed buffers") {
+    val columns = appended(histograms)
+    val reader = new HistColumnReader(columns)
+    reader.histogram(100) shouldEqual Left(NibblePack.InvalidParameter("index", 100))
+    reader.histogram(-1) shouldEqual Left(NibblePack.InvalidParameter("index", -1))
+    new HistColumnReader(new UnsafeBuffer(columns, 0, 4)).histogram(0) shouldEqual
+      Left(NibblePack.InputTooShort(8, 4))
+    new HistColumnReader(new UnsafeBuffer(columns, 0, 20)).histogram(0) shouldEqual
+      Left(NibblePack.InvalidHeader("numBuckets", 16))
+    new HistColumnReader(new UnsafeBuffer(columns, 0, columns.capacity - 1)).histogram(99).left.get shouldBe
+      a[NibblePack.InputTooShort]
+
+    new HistColumnReader(appended(Nil)).histogram(0) shouldEqual Left(NibblePack.InvalidParameter("index", 0))
+    intercept[IllegalArgumentException] { new HistColumnAppender(16).append(new Array[Long](8)) }
+  }
+}
@@ -1,6 +1,9 @@
 package filodb.memory.format.vectors
 
 import org.agrona.ExpandableArrayBuffer
+import org.agrona.concurrent.UnsafeBuffer
+
+import filodb.memory.format.NibblePack
 
 object HistogramTest {
   val bucketScheme = GeometricBuckets(1.0, 2.0, 8)
@@ -54,6 +57,10 @@ class HistogramTest extends NativeVectorTest {
 
       customScheme.serialize(writeBuf, 0) shouldEqual 26
       HistogramBuckets(writeBuf, HistFormat_Custom_Delta) shouldEqual customScheme
+
+      val noBuckets = CustomBuckets(Array.empty[Double])
+      noBuckets.serialize(writeBuf, 0) shouldEqual 4
+      HistogramBuckets(writeBuf, HistFormat_Custom_Delta) shouldEqual noBuckets
     }
   }
 
@@ -108,6 +115,321 @@ class HistogramTest extends NativeVectorTest {
       hist2.hashCode shouldEqual longHist.hashCode
     }
 
+    it("should pick geometric or custom buckets from the bucket tops with writeDeltaAuto") {
+      val buf = new ExpandableArrayBuffer()
+      val values = Array[Long](10, 15, 17, 20, 25, 34, 76, 82)
+
+      val geometricBytes = BinaryHistogram.writeDeltaAuto(bucketScheme.allBucketTops, values, buf)
+      BinaryHistogram.BinHistogram(buf).formatCode shouldEqual BinaryHistogram.HistFormat_Geometric_Delta
+      BinaryHistogram.BinHistogram(buf).toHistogram shouldEqual LongHistogram(bucketScheme, values)
+
+      // Prometheus style tops of 2^n - 1, slightly off as if parsed from text
+      val minusOneTops = HistogramBuckets.binaryBuckets64.allBucketTops.take(8).map(_ * (1 + 1e-12))
+      BinaryHistogram.writeDeltaAuto(minusOneTops, values, buf)
+      BinaryHistogram.BinHistogram(buf).formatCode shouldEqual BinaryHistogram.HistFormat_Geometric1_Delta
+      BinaryHistogram.BinHistogram(buf).toHistogram.asInstanceOf[LongHistogram].buckets.allBucketTops
+        .zip(minusOneTops).foreach { case (top, expected) => top shouldEqual expected +- 1e-6 }
+
+      val irregular = Array(0.25, 0.5, 1.0, 2.5, 5.0, 10, 25, Double.PositiveInfinity)
+      val customBytes = BinaryHistogram.writeDeltaAuto(irregular, values, buf)
+      BinaryHistogram.BinHistogram(buf).formatCode shouldEqual BinaryHistogram.HistFormat_Custom_Delta
+      BinaryHistogram.BinHistogram(buf).toHistogram shouldEqual LongHistogram(CustomBuckets(irregular), values)
+      geometricBytes should be < customBytes
+
+      HistogramBuckets.fromBounds(Array(3.0)) shouldEqual GeometricBuckets(3.0, 2.0, 1)
+      HistogramBuckets.fromBounds(Array(1.0, 2.0, 4.0, 8.0001)) shouldEqual CustomBuckets(Array(1.0, 2.0, 4.0, 8.0001))
+      HistogramBuckets.fromBounds(Array.empty[Double]) shouldEqual CustomBuckets(Array.empty[Double])
+    }
+
+    it("should read the format each writer uses with readFormat") {
+      val buf = new ExpandableArrayBuffer()
+      BinaryHistogram.writeDelta(bucketScheme, rawLongBuckets.head, buf)
+      BinaryHistogram.readFormat(buf) shouldEqual Right(BinHistogramFormat.GeometricDelta)
+      BinaryHistogram.writeNonIncreasing(HistogramBuckets.binaryBuckets64, new Array[Long](64), buf)
+      BinaryHistogram.readFormat(buf) shouldEqual Right(BinHistogramFormat.Geometric1Delta)
+      BinaryHistogram.writeDelta(customScheme, rawLongBuckets.head.take(customScheme.numBuckets), buf)
+      BinaryHistogram.readFormat(buf) shouldEqual Right(BinHistogramFormat.CustomDelta)
+      BinaryHistogram.readFormat(buf).right.get.code shouldEqual HistFormat_Custom_Delta
+
+      buf.putByte(2, HistFormat_Null)
+      BinaryHistogram.readFormat(buf) shouldEqual Right(BinHistogramFormat.Null)
+      buf.putByte(2, 0x7f.toByte)
+      BinaryHistogram.readFormat(buf) shouldEqual Left(NibblePack.UnexpectedFormat(0x7f))
+      BinaryHistogram.readFormat(new UnsafeBuffer(buf, 0, 2)) shouldEqual Left(NibblePack.InputTooShort(3, 2))
+      BinHistogramFormat.all.map(_.code).forall(isValidFormatCode) shouldEqual true
+    }
+
+    it("should decode geometric BinaryHistograms with decodeGeometric") {
+      val buf = new ExpandableArrayBuffer()
+      rawLongBuckets.foreach { rawBuckets =>
+        val increasing = rawBuckets.scanLeft(0L)(_ + _).drop(1)
+        BinaryHistogram.writeDelta(bucketScheme, increasing, buf)
+        val LongHistogram(buckets: GeometricBuckets, values) = BinaryHistogram.decodeGeometric(buf).right.get
+        buckets shouldEqual bucketScheme
+        buckets.allBucketTops shouldEqual (0 until 8).map(i => 1.0 * Math.pow(2.0, i)).toArray
+        values shouldEqual increasing
+
+        // non increasing histograms are decoded as increasing
+        BinaryHistogram.writeNonIncreasing(GeometricBuckets(2.0, 2.0, 8, minusOne = true), rawBuckets, buf)
+        val hist2 = BinaryHistogram.decodeGeometric(buf).right.get
+        hist2.buckets shouldEqual GeometricBuckets(2.0, 2.0, 8, minusOne = true)
+        hist2.values shouldEqual increasing
+      }
+    }
+
+    it("should write non increasing histograms with writeNonIncreasingChecked only for valid bucket schemes") {
+      val buf = new ExpandableArrayBuffer()
+      val values = rawLongBuckets.head
+      val numBytes = BinaryHistogram.writeNonIncreasingChecked(bucketScheme, values, buf).right.get
+      BinaryHistogram.decodeGeometricPerBucket(new UnsafeBuffer(buf, 0, numBytes)).right.get shouldEqual values
+
+      val invalid = Seq(GeometricBuckets(1.0, 1.0, 8) -> NibblePack.InvalidParameter("multiplier", 1.0),
+                        GeometricBuckets(1.0, 0.5, 8) -> NibblePack.InvalidParameter("multiplier", 0.5),
+                        GeometricBuckets(0.0, 2.0, 8) -> NibblePack.InvalidParameter("firstBucket", 0.0),
+                        GeometricBuckets(-1.0, 2.0, 8) -> NibblePack.InvalidParameter("firstBucket", -1.0),
+                        GeometricBuckets(1.0, 2.0, 0) -> NibblePack.InvalidParameter("numBuckets", 0),
+                        GeometricBuckets(1.0, 2.0, 65536) -> NibblePack.InvalidParameter("numBuckets", 65536),
+                        GeometricBuckets(1.0, 2.0, 9) -> NibblePack.InvalidParameter("numValues", 8))
+      invalid.foreach { case (scheme, error) =>
+        BinaryHistogram.writeNonIncreasingChecked(scheme, values, buf) shouldEqual Left(error)
+      }
+      BinaryHistogram.writeNonIncreasingChecked(GeometricBuckets(Double.NaN, 2.0, 8), values, buf).left.get shouldBe
+        a[NibblePack.InvalidParameter]
+    }
+
+    it("should write the same non increasing histograms with a caller provided scratch array") {
+      val buf = new ExpandableArrayBuffer()
+      val scratchBuf = new ExpandableArrayBuffer()
+      val scratch = new Array[Long](8)
+      val scheme = GeometricBuckets(2.0, 2.0, 20)
+      rawLongBuckets.foreach { rawBuckets =>
+        // 20 buckets, so that the last block is a partial one and the scratch array is used for more than one block
+        val values = Array.tabulate(20)(i => rawBuckets(i % rawBuckets.size))
+        val numBytes = BinaryHistogram.writeNonIncreasing(scheme, values, buf)
+        BinaryHistogram.writeNonIncreasing(scheme, values, scratchBuf, scratch) shouldEqual numBytes
+        (0 until numBytes).foreach { i => scratchBuf.getByte(i) shouldEqual buf.getByte(i) }
+      }
+    }
+
+    it("should refuse histograms with more buckets than the 16-bit count can hold instead of truncating") {
+      val buf = new ExpandableArrayBuffer()
+      val values = new Array[Long](70000)
+      val scheme = GeometricBuckets(1.0, 1.001, 70000)
+      BinaryHistogram.writeNonIncreasingChecked(scheme, values, buf) shouldEqual
+        Left(NibblePack.InvalidParameter("numBuckets", 70000))
+      intercept[IllegalArgumentException] { BinaryHistogram.writeNonIncreasing(scheme, values, buf) }
+
+      // Counts from 32768 to 65535 do not read back as negative
+      val wide = GeometricBuckets(1.0, 1.001, 40000)
+      BinaryHistogram.writeNonIncreasingChecked(wide, new Array[Long](40000), buf).isRight shouldEqual true
+      BinaryHistogram.BinHistogram(buf).numBuckets shouldEqual 40000
+      HistogramBuckets(new UnsafeBuffer(buf, 3, 20), BinaryHistogram.HistFormat_Geometric_Delta) shouldEqual wide
+    }
+
+    it("should return errors from decodeGeometric for malformed or non geometric histograms") {
+      val buf = new ExpandableArrayBuffer()
+      val numBytes = BinaryHistogram.writeDelta(bucketScheme, rawLongBuckets.head, buf)
+      val truncated = BinaryHistogram.decodeGeometric(new UnsafeBuffer(buf, 0, numBytes - 1))
+      truncated shouldEqual Left(NibblePack.InputTooShort(numBytes, numBytes - 1))
+
+      BinaryHistogram.writeDelta(customScheme, rawLongBuckets.head.take(customScheme.numBuckets), buf)
+      BinaryHistogram.decodeGeometric(buf) shouldEqual Left(NibblePack.UnexpectedFormat(HistFormat_Custom_Delta))
+
+      // The values end before all the buckets (shortened length prefix)
+      BinaryHistogram.writeDelta(bucketScheme, rawLongBuckets.head, buf)
+      buf.putShort(0, (5 + 18 - 2).toShort)
+      BinaryHistogram.decodeGeometric(buf).left.get shouldBe a[NibblePack.InputTooShort]
+    }
+
+    it("should refuse geometric BinaryHistograms with a corrupt first bucket or multiplier") {
+      val buf = new ExpandableArrayBuffer()
+      // The bucket definition at +5 is the number of buckets, then the first bucket and multiplier as Doubles
+      BinaryHistogram.writeDelta(bucketScheme, rawLongBuckets.head, buf)
+      buf.putDouble(15, 0.5)
+      BinaryHistogram.decodeGeometric(buf) shouldEqual Left(NibblePack.InvalidGeometry("multiplier", 0.5))
+      buf.putDouble(15, Double.PositiveInfinity)
+      BinaryHistogram.decodeGeometric(buf) shouldEqual
+        Left(NibblePack.InvalidGeometry("multiplier", Double.PositiveInfinity))
+
+      BinaryHistogram.writeDelta(bucketScheme, rawLongBuckets.head, buf)
+      buf.putDouble(7, Double.NaN)
+      val nanFirst = BinaryHistogram.decodeGeometric(buf).left.get.asInstanceOf[NibblePack.InvalidGeometry]
+      nanFirst.name shouldEqual "firstBucket"
+      nanFirst.value.isNaN shouldEqual true
+      buf.putDouble(7, -1.0)
+      BinaryHistogram.decodeGeometric(buf) shouldEqual Left(NibblePack.InvalidGeometry("firstBucket", -1.0))
+    }
+
+    it("should decode per-bucket counts from both geometric formats with decodeGeometricPerBucket") {
+      val buf = new ExpandableArrayBuffer()
+      rawLongBuckets.foreach { rawBuckets =>
+        val increasing = rawBuckets.scanLeft(0L)(_ + _).drop(1)
+        BinaryHistogram.writeDelta(bucketScheme, increasing, buf)
+        buf.getByte(2) shouldEqual HistFormat_Geometric_Delta
+        BinaryHistogram.decodeGeometricPerBucket(buf).right.get shouldEqual rawBuckets
+
+        BinaryHistogram.writeNonIncreasing(GeometricBuckets(2.0, 2.0, 8, minusOne = true), rawBuckets, buf)
+        buf.getByte(2) shouldEqual HistFormat_Geometric1_Delta
+        BinaryHistogram.decodeGeometricPerBucket(buf).right.get shouldEqual rawBuckets
+      }
+    }
+
+    it("should return NonIncreasing from decodeGeometricPerBucket for decreasing cumulative counts") {
+      val buf = new ExpandableArrayBuffer()
+      // Written raw, so that the negative count makes the decoded cumulative counts go down at bucket 3
+      BinaryHistogram.writeNonIncreasing(bucketScheme, Array(1L, 2L, 3L, -4L, 5L, 6L, 7L, 8L), buf)
+      BinaryHistogram.decodeGeometricPerBucket(buf) shouldEqual Left(NibblePack.NonIncreasing(3))
+
+      BinaryHistogram.writeDelta(customScheme, rawLongBuckets.head.take(customScheme.numBuckets), buf)
+      BinaryHistogram.decodeGeometricPerBucket(buf) shouldEqual
+        Left(NibblePack.UnexpectedFormat(HistFormat_Custom_Delta))
+    }
+
+    it("should compute count, sum and mean of geometric histograms with statsGeometric") {
+      val buf = new ExpandableArrayBuffer()
+      // Buckets 0-1, 1-2, 2-4, ... 64-128 with midpoints 0.5, 1.5, 3, ... 96
+      val populations = Array(4L, 0L, 2L, 0L, 0L, 1L, 0L, 1L)
+      BinaryHistogram.writeDelta(bucketScheme, populations.scanLeft(0L)(_ + _).drop(1), buf)
+      val stats = BinaryHistogram.statsGeometric(buf).right.get
+      stats shouldEqual BinaryHistogram.HistStats(8, 128.0, 16.0)
+
+      // Observations anywhere within their buckets have a mean between that of the bucket bottoms and tops
+      val bottoms = populations.zipWithIndex.tail.map { case (n, b) => n * bucketScheme.bucketTop(b - 1) }
+      val tops = populations.zipWithIndex.map { case (n, b) => n * bucketScheme.bucketTop(b) }
+      stats.mean should (be >= bottoms.sum / 8 and be <= tops.sum / 8)
+
+      BinaryHistogram.writeDelta(bucketScheme, new Array[Long](8), buf)
+      val empty = BinaryHistogram.statsGeometric(buf).right.get
+      (empty.count, empty.sum) shouldEqual ((0L, 0.0))
+      empty.mean.isNaN shouldEqual true
+
+      BinaryHistogram.writeDelta(customScheme, rawLongBuckets.head.take(customScheme.numBuckets), buf)
+      BinaryHistogram.statsGeometric(buf) shouldEqual Left(NibblePack.UnexpectedFormat(HistFormat_Custom_Delta))
+    }
+
+    it("should compute the difference between two geometric histograms with diffGeometric") {
+      val prevBuf = new ExpandableArrayBuffer()
+      val currBuf = new ExpandableArrayBuffer()
+      val outBuf = new ExpandableArrayBuffer()
+      val prev = incrHistBuckets(0).map(_.toLong)
+      val curr = incrHistBuckets(1).map(_.toLong)
+      BinaryHistogram.writeDelta(bucketScheme, prev, prevBuf)
+      BinaryHistogram.writeDelta(bucketScheme, curr, currBuf)
+
+      val numBytes = BinaryHistogram.diffGeometric(prevBuf, currBuf, outBuf).right.get
+      BinaryHistogram.BinHistogram(outBuf).totalLength shouldEqual numBytes
+      val diff = BinaryHistogram.decodeGeometric(outBuf).right.get
+      diff.buckets shouldEqual bucketScheme
+      diff.values shouldEqual curr.zip(prev).map { case (c, p) => c - p }
+    }
+
+    it("should return errors from diffGeometric for different buckets or decreasing differences") {
+      val prevBuf = new ExpandableArrayBuffer()
+      val currBuf = new ExpandableArrayBuffer()
+      val outBuf = new ExpandableArrayBuffer()
+      val values = incrHistBuckets(1).map(_.toLong)
+      BinaryHistogram.writeDelta(bucketScheme, values, prevBuf)
+      BinaryHistogram.writeDelta(GeometricBuckets(1.0, 2.0, 8, minusOne = true), values, currBuf)
+      BinaryHistogram.diffGeometric(prevBuf, currBuf, outBuf) shouldEqual Left(NibblePack.SchemaMismatch)
+
+      // A counter reset: the current histogram is lower than the previous one
+      BinaryHistogram.writeDelta(bucketScheme, incrHistBuckets(0).map(_.toLong), currBuf)
+      BinaryHistogram.diffGeometric(prevBuf, currBuf, outBuf) shouldEqual Left(NibblePack.NonIncreasing(0))
+    }
+
+    it("should compute increases with increaseGeometric, treating buckets which dropped as reset") {
+      val prevBuf = new ExpandableArrayBuffer()
+      val currBuf = new ExpandableArrayBuffer()
+      val outBuf = new ExpandableArrayBuffer()
+      def increase(prev: Array[Long], curr: Array[Long]): Array[Long] = {
+        BinaryHistogram.writeDelta(bucketScheme, prev, prevBuf)
+        BinaryHistogram.writeDelta(bucketScheme, curr, currBuf)
+        val numBytes = BinaryHistogram.increaseGeometric(prevBuf, currBuf, outBuf).right.get
+        BinaryHistogram.BinHistogram(outBuf).totalLength shouldEqual numBytes
+        BinaryHistogram.decodeGeometric(outBuf).right.get.values
+      }
+
+      // Without a reset it is the same as diffGeometric
+      val prev = incrHistBuckets(0).map(_.toLong)
+      val curr = incrHistBuckets(1).map(_.toLong)
+      increase(prev, curr) shouldEqual curr.zip(prev).map { case (c, p) => c - p }
+
+      // The third bucket dropped, so its increase is its whole count, and the ones above are kept from going lower
+      increase(Array(5L, 10, 20, 30, 40, 50, 60, 70), Array(6L, 12, 4, 40, 50, 60, 70, 80)) shouldEqual
+        Array(1L, 2, 4, 10, 10, 10, 10, 10)
+      // A reset of the whole histogram
+      increase(curr, prev) shouldEqual prev
+
+      BinaryHistogram.writeDelta(GeometricBuckets(1.0, 2.0, 8, minusOne = true), curr, currBuf)
+      BinaryHistogram.increaseGeometric(prevBuf, currBuf, outBuf) shouldEqual Left(NibblePack.SchemaMismatch)
+    }
+
+    it("should sum geometric histograms with HistogramMerger and refuse ones with other buckets") {
+      val merger = new HistogramMerger(bucketScheme)
+      val histBufs = incrHistBuckets.take(3).map { buckets =>
+        val histBuf = new ExpandableArrayBuffer()
+        BinaryHistogram.writeDelta(bucketScheme, buckets.map(_.toLong), histBuf)
+        histBuf
+      }
+      histBufs.foreach { histBuf => merger.add(histBuf) shouldEqual Right(()) }
+
+      val otherBuf = new ExpandableArrayBuffer()
+      BinaryHistogram.writeDelta(GeometricBuckets(1.0, 2.0, 8, minusOne = true), Array.fill(8)(100L), otherBuf)
+      merger.add(otherBuf) shouldEqual Left(NibblePack.SchemaMismatch)
+      merger.add(new UnsafeBuffer(histBufs.head, 0, 4)) shouldEqual Left(NibblePack.InputTooShort(5, 4))
+      merger.numMerged shouldEqual 3
+
+      val outBuf = new ExpandableArrayBuffer()
+      val numBytes = merger.finish(outBuf)
+      BinaryHistogram.BinHistogram(outBuf).totalLength shouldEqual numBytes
+      val sum = BinaryHistogram.decodeGeometric(outBuf).right.get
+      sum.buckets shouldEqual bucketScheme
+      sum.values shouldEqual incrHistBuckets.take(3).map(_.map(_.toLong)).reduce { (a, b) =>
+        a.zip(b).map { case (x, y) => x + y }
+      }
+
+      intercept[IllegalArgumentException] { new HistogramMerger(GeometricBuckets(1.0, 1.0, 8)) }
+    }
+
+    it("should compute quantiles of geometric BinaryHistograms with quantileGeometric") {
+      // Bucket tops are 1, 2, 4, ... 128, with 8 counts in each bucket
+      BinaryHistogram.writeDelta(bucketScheme, Array.tabulate(8)(b => (b + 1) * 8L), writeBuf)
+      // rank 32 is the top of the 4th bucket, (4, 8]
+      BinaryHistogram.quantileGeometric(writeBuf, 0.5) shouldEqual Right(8.0)
+      // rank 20 is 4 of the 8 counts in (2, 4]
+      BinaryHistogram.quantileGeometric(writeBuf, 0.3125) shouldEqual Right(3.0)
+      BinaryHistogram.quantileGeometric(writeBuf, 0.0) shouldEqual Right(0.0)
+      // The top bucket cannot be interpolated, so its bottom is returned
+      BinaryHistogram.quantileGeometric(writeBuf, 1.0) shouldEqual Right(64.0)
+      BinaryHistogram.quantileGeometric(writeBuf, 1.5) shouldEqual Right(64.0)
+      BinaryHistogram.quantileGeometric(writeBuf, -0.5) shouldEqual Right(0.0)
+      BinaryHistogram.quantileGeometric(writeBuf, Double.NaN).left.get shouldBe a[NibblePack.InvalidParameter]
+
+      BinaryHistogram.writeDelta(bucketScheme, Array.fill(8)(0L), writeBuf)
+      BinaryHistogram.quantileGeometric(writeBuf, 0.5).right.get.isNaN shouldEqual true
+
+      BinaryHistogram.writeDelta(customScheme, Array.fill(customScheme.numBuckets)(5L), writeBuf)
+      BinaryHistogram.quantileGeometric(writeBuf, 0.5).left.get shouldBe a[NibblePack.UnexpectedFormat]
+    }
+
+    it("should settle quantiles within their bucket with each Rounding") {
+      import filodb.memory.format.Rounding._
+      // Bucket tops are 1, 2, 4, ... 128, with 8 counts in each bucket
+      val hist = LongHistogram(bucketScheme, Array.tabulate(8)(b => (b + 1) * 8L))
+      // rank 32 is exactly the top of the 4th bucket, (4, 8]
+      Seq(LinearInterp, Floor, Ceil, Nearest).map(hist.quantile(0.5, _)) shouldEqual Seq(8.0, 4.0, 8.0, 8.0)
+      // rank 20 is halfway through (2, 4], and rank 18 a quarter of the way
+      Seq(LinearInterp, Floor, Ceil, Nearest).map(hist.quantile(0.3125, _)) shouldEqual Seq(3.0, 2.0, 4.0, 4.0)
+      Seq(LinearInterp, Floor, Ceil, Nearest).map(hist.quantile(0.28125, _)) shouldEqual Seq(2.5, 2.0, 4.0, 2.0)
+      hist.quantile(0.3125) shouldEqual hist.quantile(0.3125, LinearInterp)
+      // The top bucket cannot be settled within, so its bottom is returned whatever the rounding
+      Seq(LinearInterp, Floor, Ceil, Nearest).map(hist.quantile(1.0, _)) shouldEqual Seq.fill(4)(64.0)
+
+      MaxHistogram(mutableHistograms(0), 90).quantile(0.95, Ceil) shouldEqual 90.0
+      BinaryHistogram.writeDelta(bucketScheme, Array.tabulate(8)(b => (b + 1) * 8L), writeBuf)
+      BinaryHistogram.quantileGeometric(writeBuf, 0.5, Floor) shouldEqual Right(4.0)
+    }
+
     it("should serialize to and from an empty Histogram") {
       val binEmptyHist = BinaryHistogram.BinHistogram(Histogram.empty.serialize())
       binEmptyHist.numBuckets shouldEqual 0